@@ -0,0 +1,157 @@
+//! A local stand-in for a Flashbots compatible relay, to try the private txs against a dev node.
+//!
+//! It checks the `X-Flashbots-Signature` of every request, then forwards the txs of
+//! `eth_sendPrivateTransaction`, `eth_sendBundle` and `mev_sendBundle` to the node with
+//! `eth_sendRawTransaction`. No searcher backruns the MEV-Share bundles, so their rebates are 0.
+//!
+//! ```sh
+//! anvil &
+//! cargo run -p tg-api --example mock_relay
+//! ETH_RELAY_URL=http://127.0.0.1:8555 cargo run
+//! ```
+//!
+//! `MOCK_RELAY_ADDR` (default 127.0.0.1:8555) is the address it listens on and `MOCK_RELAY_NODE`
+//! (default http://127.0.0.1:8545) the node it forwards to. `MOCK_RELAY_MODE=reject` refuses
+//! every tx and `MOCK_RELAY_MODE=drop` accepts them without forwarding, to exercise the public
+//! fallback after the relay fails or doesn't include the tx.
+
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    types::{Address, Bytes, Signature, H256},
+    utils::keccak256,
+};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Forward,
+    Reject,
+    Drop,
+}
+
+/// The address which signed the body, from the `X-Flashbots-Signature` header
+fn signer(header: &str, body: &[u8]) -> anyhow::Result<Address> {
+    let (address, signature) = header
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("malformed signature header"))?;
+    let address = Address::from_str(address)?;
+    let signature = Signature::from_str(signature)?;
+    let digest = format!("{:#x}", H256::from(keccak256(body)));
+    if signature.recover(digest)? != address {
+        return Err(anyhow::anyhow!("signature doesn't match {:#x}", address));
+    }
+    Ok(address)
+}
+
+/// The raw txs of a relay request
+fn raw_txs(method: &str, params: &Value) -> anyhow::Result<Vec<Bytes>> {
+    let param = params
+        .get(0)
+        .ok_or_else(|| anyhow::anyhow!("missing params"))?;
+    let txs = match method {
+        "eth_sendPrivateTransaction" => vec![param["tx"].clone()],
+        "eth_sendBundle" => param["txs"]
+            .as_array()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("missing txs"))?,
+        "mev_sendBundle" => param["body"]
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("missing body"))?
+            .iter()
+            .map(|item| item["tx"].clone())
+            .collect(),
+        _ => return Err(anyhow::anyhow!("unsupported method {}", method)),
+    };
+    txs.into_iter()
+        .map(|tx| Ok(serde_json::from_value(tx)?))
+        .collect()
+}
+
+async fn relay(
+    node: &Provider<Http>,
+    mode: Mode,
+    header: Option<String>,
+    body: &[u8],
+) -> anyhow::Result<Value> {
+    let header = header.ok_or_else(|| anyhow::anyhow!("missing X-Flashbots-Signature"))?;
+    let searcher = signer(&header, body)?;
+    let request: Value = serde_json::from_slice(body)?;
+    let method = request["method"].as_str().unwrap_or_default();
+    let txs = raw_txs(method, &request["params"])?;
+    println!("{} of {} tx(s) from {:#x}", method, txs.len(), searcher);
+    if mode == Mode::Reject {
+        return Err(anyhow::anyhow!("rejected by the mock relay"));
+    }
+
+    let hashes: Vec<H256> = txs.iter().map(|tx| H256::from(keccak256(tx))).collect();
+    if mode == Mode::Forward {
+        for tx in txs {
+            // every block of a bundle window carries the same tx
+            if let Err(err) = node.send_raw_transaction(tx).await {
+                println!("  not forwarded: {}", err);
+            }
+        }
+    }
+    Ok(match method {
+        "eth_sendBundle" | "mev_sendBundle" => {
+            let hashes: Vec<u8> = hashes.iter().flat_map(|hash| hash.0).collect();
+            json!({ "bundleHash": H256::from(keccak256(hashes)) })
+        }
+        _ => json!(hashes[0]),
+    })
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let addr: SocketAddr = std::env::var("MOCK_RELAY_ADDR")
+        .unwrap_or_else(|_| "127.0.0.1:8555".to_string())
+        .parse()?;
+    let node = std::env::var("MOCK_RELAY_NODE").unwrap_or_else(|_| "http://127.0.0.1:8545".into());
+    let node = Arc::new(Provider::<Http>::try_from(node)?);
+    let mode = match std::env::var("MOCK_RELAY_MODE").as_deref() {
+        Ok("reject") => Mode::Reject,
+        Ok("drop") => Mode::Drop,
+        _ => Mode::Forward,
+    };
+
+    let service = make_service_fn(move |_| {
+        let node = node.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let node = node.clone();
+                async move {
+                    let header = request
+                        .headers()
+                        .get("X-Flashbots-Signature")
+                        .and_then(|header| header.to_str().ok())
+                        .map(str::to_string);
+                    let body = hyper::body::to_bytes(request.into_body())
+                        .await
+                        .unwrap_or_default();
+                    let response = match relay(&node, mode, header, &body).await {
+                        Ok(result) => json!({ "jsonrpc": "2.0", "id": 1, "result": result }),
+                        Err(err) => {
+                            println!("  refused: {}", err);
+                            json!({
+                                "jsonrpc": "2.0",
+                                "id": 1,
+                                "error": { "code": -32000, "message": err.to_string() },
+                            })
+                        }
+                    };
+                    Ok::<_, Infallible>(Response::new(Body::from(response.to_string())))
+                }
+            }))
+        }
+    });
+
+    println!("Mock relay ({:?}) listening on http://{}", mode, addr);
+    Server::bind(&addr).serve(service).await?;
+    Ok(())
+}
@@ -1,32 +1,59 @@
-use crate::consts::{BUY, CLOSE, MAIN_MENU};
+use crate::config::{live_menu_interval_from_env, RelayConfig};
+use crate::handlers::admin_handlers::rpc_health_command;
 use crate::handlers::callback_handlers::{
-    handle_buy_amount_callback, handle_buy_callback, handle_buy_token_callback,
-    handle_close_callback, handle_menu_callback, handle_private_tx_callback,
-    handle_rebate_callback, handle_receive_token_callback, handle_send_tx_callback,
-    handle_wallet_callback,
+    handle_abort_swap_callback, handle_buy_amount_callback, handle_buy_callback,
+    handle_buy_token_callback, handle_chain_callback, handle_close_callback,
+    handle_confirm_swap_callback, handle_cycle_gas_mode_callback, handle_limit_buy_callback,
+    handle_limit_field_callback, handle_limit_kind_callback, handle_limit_sell_callback,
+    handle_menu_callback, handle_private_tx_callback, handle_rebate_callback,
+    handle_receive_token_callback, handle_select_token_callback, handle_sell_amount_callback,
+    handle_sell_callback, handle_sell_percentage_callback, handle_sell_percentage_preset_callback,
+    handle_sell_receive_token_callback, handle_sell_token_callback, handle_send_tx_callback,
+    handle_slippage_callback, handle_trigger_unit_callback, handle_wallet_callback,
 };
 use crate::handlers::dialogue_handlers::{
-    buy_address_dialogue_handler, buy_address_or_token_handler, buy_amount_dialogue_handler,
-    PromptDialogueState,
+    address_or_token_handler, buy_address_dialogue_handler, buy_amount_dialogue_handler,
+    limit_dialogue_handler, sell_amount_dialogue_handler, sell_percentage_dialogue_handler,
+    slippage_dialogue_handler, BuyAddressPromptDialogue, PromptDialogueState,
 };
-use crate::handlers::{delete_previous_messages, matching_sub_menu, SubMenuType};
-use crate::keyboards::buy_buttons::BuyButtons;
+use crate::handlers::estimate_handlers::spawn_estimate_refresh;
+use crate::handlers::history_handlers::{handle_history_callback, history_command};
+use crate::handlers::limit_order_handlers::{
+    handle_place_limit_order_callback, spawn_limit_order_watcher,
+};
+use crate::handlers::live_menu_handlers::spawn_live_main_menus;
+use crate::handlers::orders_handlers::{
+    handle_open_orders_callback, handle_orders_callback, order_dialogue_handler, orders_command,
+};
+use crate::handlers::rebate_handlers::rebates_command;
+use crate::handlers::settings_handlers::{
+    handle_settings_callback, settings_command, settings_dialogue_handler,
+};
+use crate::handlers::wallet_handlers::{
+    handle_wallets_callback, wallet_import_dialogue_handler, wallet_rename_dialogue_handler,
+    wallets_command,
+};
+use crate::handlers::{delete_previous_messages, is_current_draft, matching_sub_menu, SubMenuType};
+use crate::keyboards::callback_data::{CallbackAction, CallbackData, MenuType};
 use crate::keyboards::menu_keyboard;
 use crate::requests::on_chain;
-use crate::storages::{TgMessage, TgMessageStorage, GLOBAL_MAIN_MENU_STORAGE};
+use crate::requests::providers::ProviderRegistry;
+use crate::storages::{
+    dialogue_storage, init_storages, spawn_menu_eviction, DraftKey, MenuKind, OrderSide, TgMessage,
+    GLOBAL_CHAIN_REGISTRY, GLOBAL_MENU_STORE,
+};
 use std::sync::Arc;
 use teloxide::dispatching::HandlerExt;
 use teloxide::{
-    dispatching::{dialogue::InMemStorage, UpdateFilterExt},
+    dispatching::{dialogue::ErasedStorage, UpdateFilterExt, UpdateHandler},
     dptree,
     error_handlers::LoggingErrorHandler,
-    payloads::SendMessageSetters,
+    payloads::{AnswerCallbackQuerySetters, SendMessageSetters},
     prelude::{Dispatcher, Requester},
     types::{CallbackQuery, Message, ParseMode, Update},
     utils::command::BotCommands,
     Bot,
 };
-use tokio::time::{sleep, Duration};
 
 use std::fmt;
 use teloxide::dispatching::dialogue::InMemStorageError;
@@ -38,10 +65,11 @@ pub enum TgError {
     Parse(String),
     TeloxideRequest(teloxide::RequestError),
     TeloxideInMemStorageError(InMemStorageError),
-    UnmatchedQuery(teloxide::types::CallbackQuery),
-    NoQueryData(teloxide::types::CallbackQuery),
-    NoQueryMessage(teloxide::types::CallbackQuery),
-    UserNotFound(teloxide::types::Message),
+    DialogueStorage(Box<dyn std::error::Error + Send + Sync>),
+    UnmatchedQuery(Box<teloxide::types::CallbackQuery>),
+    NoQueryData(Box<teloxide::types::CallbackQuery>),
+    NoQueryMessage(Box<teloxide::types::CallbackQuery>),
+    UserNotFound(Box<teloxide::types::Message>),
 }
 
 #[derive(BotCommands, Clone)]
@@ -57,6 +85,14 @@ enum Command {
     Start,
     #[command(description = "Display Trade History")]
     History,
+    #[command(description = "Display the Open Orders")]
+    Orders,
+    #[command(description = "Display the Rebates of the Trades")]
+    Rebates,
+    #[command(description = "Slippage, Deadline, Gas and Defaults of the Orders")]
+    Settings,
+    #[command(description = "Display the health of the RPC endpoints (admins only)")]
+    Rpc,
 }
 
 #[derive(Clone, Debug)]
@@ -64,6 +100,12 @@ pub struct TgBot {
     bot: Bot,
 }
 
+impl Default for TgBot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TgBot {
     pub fn new() -> Self {
         let bot = Bot::from_env();
@@ -71,29 +113,115 @@ impl TgBot {
     }
 
     pub async fn init(self) -> Result<(), TgError> {
+        init_storages();
+        spawn_menu_eviction();
+        let providers = Arc::new(ProviderRegistry::new(
+            &GLOBAL_CHAIN_REGISTRY,
+            &RelayConfig::from_env()?,
+        )?);
+        providers.clone().spawn_status_refresh().await;
+        providers.clone().spawn_new_heads_subscriptions();
+        if let Some(every) = live_menu_interval_from_env()? {
+            spawn_live_main_menus(self.bot.clone(), providers.clone(), every);
+        }
+        spawn_estimate_refresh(self.bot.clone(), providers.clone());
+        spawn_limit_order_watcher(self.bot.clone(), providers.clone());
         let handler = dptree::entry()
-            .branch(Update::filter_message().filter_command::<Command>().endpoint(command_callback))
+            .branch(
+                Update::filter_message()
+                    .filter_command::<Command>()
+                    .endpoint(command_callback),
+            )
             .branch(Update::filter_callback_query().endpoint(button_callback))
             .branch(
-                 Update::filter_message()
-                     .enter_dialogue::<Message,InMemStorage<PromptDialogueState>,PromptDialogueState>()
-                         .branch(dptree::case![PromptDialogueState::BuyStartAddressPrompt]
-                             .endpoint(buy_address_dialogue_handler))
-                         .branch(dptree::case![PromptDialogueState::BuyAddressReceived]
-                             .endpoint(buy_address_or_token_handler))
-                         .branch(dptree::case![PromptDialogueState::ReceiveStartAddressPrompt]
-                             .endpoint(buy_address_dialogue_handler))
-                         .branch(dptree::case![PromptDialogueState::ReceiveAddressReceived]
-                             .endpoint(buy_address_or_token_handler))
-                         .branch(dptree::case![PromptDialogueState::BuyAmountReceived]
-                             .endpoint(buy_amount_dialogue_handler))
+                Update::filter_message()
+                    .chain(enter_user_dialogue())
+                    .branch(
+                        dptree::case![PromptDialogueState::BuyStartAddressPrompt]
+                            .endpoint(buy_address_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::BuyAddressReceived]
+                            .endpoint(address_or_token_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::BuyTokenNameReceived]
+                            .endpoint(address_or_token_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::ReceiveStartAddressPrompt]
+                            .endpoint(buy_address_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::ReceiveAddressReceived]
+                            .endpoint(address_or_token_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::ReceiveTokenNameReceived]
+                            .endpoint(address_or_token_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::BuyAmountReceived]
+                            .endpoint(buy_amount_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::SellAddressReceived]
+                            .endpoint(address_or_token_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::SellTokenNameReceived]
+                            .endpoint(address_or_token_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::SellReceiveAddressReceived]
+                            .endpoint(address_or_token_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::SellReceiveTokenNameReceived]
+                            .endpoint(address_or_token_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::SellAmountReceived]
+                            .endpoint(sell_amount_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::SellPercentageReceived]
+                            .endpoint(sell_percentage_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::WalletRenameReceived(address)]
+                            .endpoint(wallet_rename_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::WalletImportReceived]
+                            .endpoint(wallet_import_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::SettingReceived(field)]
+                            .endpoint(settings_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::SlippageReceived(side)]
+                            .endpoint(slippage_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::LimitFieldReceived(field)]
+                            .endpoint(limit_dialogue_handler),
+                    )
+                    .branch(
+                        dptree::case![PromptDialogueState::OrderFieldReceived { id, field }]
+                            .endpoint(order_dialogue_handler),
+                    ),
             );
 
         Dispatcher::builder(self.bot, handler)
             .error_handler(LoggingErrorHandler::with_custom_text(
                 "An error has occurred in the dispatcher",
             ))
-            .dependencies(dptree::deps![InMemStorage::<PromptDialogueState>::new()])
+            .dependencies(dptree::deps![
+                dialogue_storage::<PromptDialogueState>(),
+                providers
+            ])
             .enable_ctrlc_handler()
             .build()
             .dispatch()
@@ -102,7 +230,32 @@ impl TgBot {
     }
 }
 
-async fn command_callback(bot: Bot, cmd: Command, msg: Message) -> Result<(), TgError> {
+/// Same as [HandlerExt::enter_dialogue], except the dialogue is the one of the sender in the
+/// chat, see [DraftKey::dialogue_id]
+fn enter_user_dialogue() -> UpdateHandler<TgError> {
+    dptree::filter_map(
+        |storage: Arc<ErasedStorage<PromptDialogueState>>, msg: Message| {
+            let key = DraftKey::from_message(&msg)?;
+            Some(BuyAddressPromptDialogue::new(storage, key.dialogue_id()))
+        },
+    )
+    .filter_map_async(|dialogue: BuyAddressPromptDialogue| async move {
+        match dialogue.get_or_default().await {
+            Ok(state) => Some(state),
+            Err(err) => {
+                log::error!("Unable to read the dialogue state: {:?}", err);
+                None
+            }
+        }
+    })
+}
+
+async fn command_callback(
+    bot: Bot,
+    cmd: Command,
+    msg: Message,
+    providers: Arc<ProviderRegistry>,
+) -> Result<(), TgError> {
     match cmd {
         Command::Help => {
             let _ = bot
@@ -110,8 +263,8 @@ async fn command_callback(bot: Bot, cmd: Command, msg: Message) -> Result<(), Tg
                 .await?;
         }
         Command::Menu => {
-            let keyboard = menu_keyboard();
-            let menu_msg = on_chain::get_on_chain_info().await?;
+            let keyboard = menu_keyboard()?;
+            let menu_msg = on_chain::get_on_chain_info(&providers);
 
             // send the new message
             let message_sent = bot
@@ -121,30 +274,29 @@ async fn command_callback(bot: Bot, cmd: Command, msg: Message) -> Result<(), Tg
                 .await?;
             let message_sent = Arc::new(message_sent);
 
-            // Updates the GLOBAL_MAIN_MENU_STORAGE
-            let _user_name = message_sent
-                .clone()
-                .from()
-                .and_then(|user| user.username.as_ref())
-                .and_then(|user_name| {
-                    let message = TgMessage {
-                        chat_id: message_sent.chat.id,
-                        message_id: message_sent.id,
-                        message: message_sent.clone(),
-                    };
-                    GLOBAL_MAIN_MENU_STORAGE.insert(user_name.to_string(), message);
-                    Some(user_name)
-                });
+            // Updates the GLOBAL_MENU_STORE
+            if let Some(key) = DraftKey::from_message(&msg) {
+                let message = TgMessage {
+                    chat_id: message_sent.chat.id,
+                    message_id: message_sent.id,
+                    message: message_sent.clone(),
+                };
+                GLOBAL_MENU_STORE
+                    .insert(MenuKind::Main, key, message)
+                    .await?;
+            }
 
             // delete previous messages
             let last_message_id = message_sent.id;
-            let _ =
-                delete_previous_messages(&bot, msg.chat.id.0, last_message_id.0 - 1, 20).await?;
+            delete_previous_messages(&bot, msg.chat.id.0, last_message_id.0 - 1, 20).await?;
         }
         Command::Start => {
-            sleep(Duration::from_secs(3)).await;
-            let keyboard = menu_keyboard();
-            let menu_msg = on_chain::get_on_chain_info_start().await?;
+            let user_id = match msg.from() {
+                Some(user) => user.id,
+                None => return Err(TgError::UserNotFound(Box::new(msg))),
+            };
+            let keyboard = menu_keyboard()?;
+            let menu_msg = on_chain::get_on_chain_info_start(&providers, user_id).await?;
 
             // send the new message
             let _message_sent = bot
@@ -154,10 +306,22 @@ async fn command_callback(bot: Bot, cmd: Command, msg: Message) -> Result<(), Tg
                 .await?;
         }
         Command::Wallets => {
-            todo!()
+            wallets_command(&bot, &msg, &providers).await?;
         }
         Command::History => {
-            todo!()
+            history_command(&bot, &msg).await?;
+        }
+        Command::Orders => {
+            orders_command(&bot, &msg, &providers).await?;
+        }
+        Command::Rebates => {
+            rebates_command(&bot, &msg).await?;
+        }
+        Command::Settings => {
+            settings_command(&bot, &msg).await?;
+        }
+        Command::Rpc => {
+            rpc_health_command(&bot, &msg, &providers).await?;
         }
     }
     Ok(())
@@ -166,60 +330,203 @@ async fn command_callback(bot: Bot, cmd: Command, msg: Message) -> Result<(), Tg
 async fn button_callback(
     bot: Bot,
     q: CallbackQuery,
-    storage: Arc<InMemStorage<PromptDialogueState>>,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+    providers: Arc<ProviderRegistry>,
 ) -> Result<(), TgError> {
+    let providers = providers.as_ref();
     if let Some(action) = &q.data {
-        match action.as_str() {
+        let data = match CallbackData::decode(action) {
+            Ok(data) => data,
+            Err(err) => {
+                // buttons of menus sent before an incompatible change of the encoding
+                log::warn!("Unable to decode callback data {}: {}", action, err);
+                bot.answer_callback_query(&q.id)
+                    .text("This menu is outdated, please open a new one")
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let sub_menu = matching_sub_menu(&q);
+        if sub_menu.is_some() && !is_current_draft(&q, &data).await {
+            bot.answer_callback_query(&q.id)
+                .text("This menu is outdated, please open a new one")
+                .await?;
+            return Ok(());
+        }
+
+        match (&data.action, sub_menu) {
             // main-menu
-            BUY => handle_buy_callback(&bot, &q).await?,
-            MAIN_MENU => handle_menu_callback(&bot, &q).await?,
-            CLOSE => handle_close_callback(&bot, &q).await?,
+            (CallbackAction::Buy, _) => handle_buy_callback(&bot, &q, providers).await?,
+            (CallbackAction::Sell, _) => handle_sell_callback(&bot, &q, providers).await?,
+            (CallbackAction::LimitBuy, _) => handle_limit_buy_callback(&bot, &q, providers).await?,
+            (CallbackAction::LimitSell, _) => {
+                handle_limit_sell_callback(&bot, &q, providers).await?
+            }
+            (CallbackAction::OpenOrders, _) => {
+                handle_open_orders_callback(&bot, &q, providers).await?
+            }
+            (CallbackAction::MainMenu, _) => handle_menu_callback(&bot, &q, providers).await?,
+            (CallbackAction::Close, _) => handle_close_callback(&bot, &q).await?,
+
+            // settings
+            (CallbackAction::Settings, _) => {
+                handle_settings_callback(&bot, &q, &data, storage).await?
+            }
+            _ if data.menu == MenuType::Settings => {
+                handle_settings_callback(&bot, &q, &data, storage).await?
+            }
+
+            // wallets
+            (
+                CallbackAction::RenameWallet(_)
+                | CallbackAction::SetDefaultWallet { .. }
+                | CallbackAction::ExportWallet(_)
+                | CallbackAction::DeleteWallet(_)
+                | CallbackAction::ConfirmDeleteWallet(_)
+                | CallbackAction::ImportWallet,
+                None,
+            ) => handle_wallets_callback(&bot, &q, providers, &data, storage).await?,
+
+            // history
+            (
+                CallbackAction::PreviousHistoryPage(_)
+                | CallbackAction::NextHistoryPage(_)
+                | CallbackAction::BackToHistory(_)
+                | CallbackAction::TradeDetail { .. },
+                None,
+            ) => handle_history_callback(&bot, &q, &data).await?,
+
+            // open orders
+            (
+                CallbackAction::PreviousOrdersPage(_)
+                | CallbackAction::NextOrdersPage(_)
+                | CallbackAction::BackToOrders(_)
+                | CallbackAction::EditOrder { .. }
+                | CallbackAction::CancelOrder { .. }
+                | CallbackAction::EditTrigger { .. }
+                | CallbackAction::EditExpiry { .. },
+                None,
+            ) => handle_orders_callback(&bot, &q, providers, &data, storage).await?,
 
             // sub-menus
-            _ => match matching_sub_menu(&bot, &q) {
-                Some(SubMenuType::SendBuyTx) => match BuyButtons::new(action) {
-                    BuyButtons::SendBuyTx => handle_send_tx_callback(&bot, &q).await?,
-                    BuyButtons::PrivateTx(_) => handle_private_tx_callback(&bot, &q).await?,
-                    BuyButtons::Rebate(_) => handle_rebate_callback(&bot, &q).await?,
-                    BuyButtons::Wallet1(_) | BuyButtons::Wallet2(_) | BuyButtons::Wallet3(_) => {
-                        handle_wallet_callback(&bot, &q).await?
-                    }
-                    BuyButtons::BuyToken => {
-                        handle_buy_token_callback(
-                            &bot,
-                            PromptDialogueState::BuyStartAddressPrompt,
-                            &q,
-                            storage,
-                        )
-                        .await?
-                    }
-                    BuyButtons::ReceiveToken => {
-                        handle_receive_token_callback(
-                            &bot,
-                            PromptDialogueState::ReceiveStartAddressPrompt,
-                            &q,
-                            storage,
-                        )
-                        .await?
-                    }
-                    BuyButtons::BuyAmount => {
-                        handle_buy_amount_callback(
-                            &bot,
-                            PromptDialogueState::StartBuyAmountPrompt,
-                            &q,
-                            storage,
-                        )
-                        .await?
-                    }
-                    _ => {}
-                },
-                Some(SubMenuType::SendSellTx) => {
-                    todo!()
-                }
-                _ => {}
-            },
+            (CallbackAction::SendBuyTx, Some(SubMenuType::SendBuyTx))
+            | (CallbackAction::SendSellTx, Some(SubMenuType::SendSellTx)) => {
+                handle_send_tx_callback(&bot, &q, providers).await?
+            }
+            (CallbackAction::ConfirmSwap(_), Some(_)) => {
+                handle_confirm_swap_callback(&bot, &q, providers, &data).await?
+            }
+            (CallbackAction::AbortSwap(_), Some(_)) => {
+                handle_abort_swap_callback(&bot, &q, &data).await?
+            }
+            (CallbackAction::PlaceLimitOrder, Some(_)) => {
+                handle_place_limit_order_callback(&bot, &q, providers).await?
+            }
+            (CallbackAction::LimitKind { .. }, Some(SubMenuType::SendSellTx)) => {
+                handle_limit_kind_callback(&bot, &q, providers, &data).await?
+            }
+            (CallbackAction::TriggerUnit { .. }, Some(_)) => {
+                handle_trigger_unit_callback(&bot, &q, providers, &data).await?
+            }
+            (CallbackAction::Trigger { .. } | CallbackAction::Expiry(_), Some(_)) => {
+                handle_limit_field_callback(&bot, &q, &data, storage).await?
+            }
+            (CallbackAction::Chain { .. }, Some(_)) => {
+                handle_chain_callback(&bot, &q, providers, &data).await?
+            }
+            (CallbackAction::PrivateTx(_), Some(_)) => {
+                handle_private_tx_callback(&bot, &q, providers).await?
+            }
+            (CallbackAction::Rebate(_), Some(_)) => {
+                handle_rebate_callback(&bot, &q, providers).await?
+            }
+            (CallbackAction::Slippage(_), Some(SubMenuType::SendBuyTx)) => {
+                handle_slippage_callback(&bot, OrderSide::Buy, &q, storage).await?
+            }
+            (CallbackAction::Slippage(_), Some(SubMenuType::SendSellTx)) => {
+                handle_slippage_callback(&bot, OrderSide::Sell, &q, storage).await?
+            }
+            (CallbackAction::CycleGasMode(_), Some(_)) => {
+                handle_cycle_gas_mode_callback(&bot, &q, providers).await?
+            }
+            (CallbackAction::Wallet { .. }, Some(_)) => {
+                handle_wallet_callback(&bot, &q, providers, &data).await?
+            }
+            (CallbackAction::BuyToken(_), Some(SubMenuType::SendBuyTx)) => {
+                handle_buy_token_callback(
+                    &bot,
+                    PromptDialogueState::BuyStartAddressPrompt,
+                    &q,
+                    storage,
+                )
+                .await?
+            }
+            (CallbackAction::ReceiveToken(_), Some(SubMenuType::SendBuyTx)) => {
+                handle_receive_token_callback(
+                    &bot,
+                    PromptDialogueState::ReceiveStartAddressPrompt,
+                    &q,
+                    storage,
+                )
+                .await?
+            }
+            (CallbackAction::BuyAmount(_), Some(SubMenuType::SendBuyTx)) => {
+                handle_buy_amount_callback(
+                    &bot,
+                    PromptDialogueState::StartBuyAmountPrompt,
+                    &q,
+                    storage,
+                )
+                .await?
+            }
+            (CallbackAction::SellToken(_), Some(SubMenuType::SendSellTx)) => {
+                handle_sell_token_callback(
+                    &bot,
+                    PromptDialogueState::SellStartAddressPrompt,
+                    &q,
+                    storage,
+                )
+                .await?
+            }
+            (CallbackAction::ReceiveToken(_), Some(SubMenuType::SendSellTx)) => {
+                handle_sell_receive_token_callback(
+                    &bot,
+                    PromptDialogueState::SellReceiveStartAddressPrompt,
+                    &q,
+                    storage,
+                )
+                .await?
+            }
+            (CallbackAction::SellAmount(_), Some(SubMenuType::SendSellTx)) => {
+                handle_sell_amount_callback(
+                    &bot,
+                    PromptDialogueState::StartSellAmountPrompt,
+                    &q,
+                    storage,
+                )
+                .await?
+            }
+            (CallbackAction::SellPercentage(_), Some(SubMenuType::SendSellTx)) => {
+                handle_sell_percentage_callback(
+                    &bot,
+                    PromptDialogueState::StartSellPercentagePrompt,
+                    &q,
+                    storage,
+                )
+                .await?
+            }
+            (CallbackAction::PresetPercentage { .. }, Some(SubMenuType::SendSellTx)) => {
+                handle_sell_percentage_preset_callback(&bot, &q, providers, &data).await?
+            }
+            (CallbackAction::SelectToken(_), Some(_)) => {
+                handle_select_token_callback(&bot, &q, providers, &data, storage).await?
+            }
+            _ => {
+                bot.answer_callback_query(&q.id).await?;
+            }
         }
-        log::info!("You chose: {}", action);
+        log::debug!("You chose: {:?}", data.action);
     }
     Ok(())
 }
@@ -234,6 +541,7 @@ impl fmt::Display for TgError {
             Self::TeloxideInMemStorageError(ref err) => {
                 write!(f, "InMemStorage error: {}", err)
             }
+            Self::DialogueStorage(ref err) => write!(f, "Dialogue storage error: {}", err),
             Self::UnmatchedQuery(ref cb_query) => {
                 write!(f, "Could not match callback query: {:?}", cb_query)
             }
@@ -263,6 +571,12 @@ impl From<InMemStorageError> for TgError {
     }
 }
 
+impl From<Box<dyn std::error::Error + Send + Sync>> for TgError {
+    fn from(err: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Self::DialogueStorage(err)
+    }
+}
+
 impl From<anyhow::Error> for TgError {
     fn from(err: anyhow::Error) -> Self {
         Self::AnyhowError(err)
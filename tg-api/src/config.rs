@@ -0,0 +1,447 @@
+use dotenv::dotenv;
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use teloxide::types::UserId;
+
+const DEFAULT_SQLITE_PATH: &str = "koi-bot.sqlite";
+
+/// Backend of the menu, order draft and dialogue storages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StorageBackend {
+    /// Everything is lost on restart
+    Memory,
+    /// Path of the SQLite database file
+    Sqlite(String),
+}
+
+impl StorageBackend {
+    /// Reads `STORAGE_BACKEND` ("memory" or "sqlite", defaults to memory) and `SQLITE_PATH`
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        dotenv().ok();
+        let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "memory".to_string());
+        match backend.to_lowercase().as_str() {
+            "memory" => Ok(Self::Memory),
+            "sqlite" => Ok(Self::Sqlite(
+                std::env::var("SQLITE_PATH").unwrap_or_else(|_| DEFAULT_SQLITE_PATH.to_string()),
+            )),
+            _ => Err(anyhow::anyhow!("Unsupported storage backend: {}", backend)),
+        }
+    }
+}
+
+/// Telegram doesn't let bots delete messages older than 48 hours, such menus are forgotten
+const DEFAULT_MENU_TTL_SECS: u64 = 48 * 60 * 60;
+
+/// Reads `MENU_TTL_SECS`, how long a menu message is kept in the menu store
+pub(crate) fn menu_ttl_from_env() -> anyhow::Result<Duration> {
+    dotenv().ok();
+    match std::env::var("MENU_TTL_SECS") {
+        Ok(ttl) => Ok(Duration::from_secs(ttl.parse()?)),
+        Err(_) => Ok(Duration::from_secs(DEFAULT_MENU_TTL_SECS)),
+    }
+}
+
+/// Telegram allows about one edit per second and chat, the live menus are edited far less often
+const DEFAULT_LIVE_MENU_INTERVAL_SECS: u64 = 12;
+
+/// Reads `LIVE_MENU_INTERVAL_SECS`, how often the open main menus are re-rendered with the latest
+/// gas and block, None when set to 0
+pub(crate) fn live_menu_interval_from_env() -> anyhow::Result<Option<Duration>> {
+    dotenv().ok();
+    let secs = match std::env::var("LIVE_MENU_INTERVAL_SECS") {
+        Ok(secs) => secs.parse()?,
+        Err(_) => DEFAULT_LIVE_MENU_INTERVAL_SECS,
+    };
+    Ok((secs > 0).then(|| Duration::from_secs(secs)))
+}
+
+/// Reads `ADMIN_USER_IDS`, the comma separated Telegram ids of the users allowed to run the admin
+/// commands, none by default
+pub(crate) fn admin_ids_from_env() -> anyhow::Result<Vec<UserId>> {
+    dotenv().ok();
+    std::env::var("ADMIN_USER_IDS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| Ok(UserId(id.parse()?)))
+        .collect()
+}
+
+const DEFAULT_KEYSTORE_DIR: &str = "keystore";
+
+/// Location and master passphrase of the users' encrypted wallets
+#[derive(Clone)]
+pub(crate) struct WalletConfig {
+    pub(crate) keystore_dir: String,
+    pub(crate) master_passphrase: String,
+}
+
+/// The passphrase is never printed
+impl std::fmt::Debug for WalletConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WalletConfig")
+            .field("keystore_dir", &self.keystore_dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WalletConfig {
+    /// Reads `KEYSTORE_DIR` and the required `WALLET_PASSPHRASE`
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        dotenv().ok();
+        let master_passphrase = std::env::var("WALLET_PASSPHRASE")
+            .map_err(|_| anyhow::anyhow!("WALLET_PASSPHRASE must be set to encrypt the wallets"))?;
+        if master_passphrase.is_empty() {
+            return Err(anyhow::anyhow!("WALLET_PASSPHRASE must not be empty"));
+        }
+        Ok(Self {
+            keystore_dir: std::env::var("KEYSTORE_DIR")
+                .unwrap_or_else(|_| DEFAULT_KEYSTORE_DIR.to_string()),
+            master_passphrase,
+        })
+    }
+}
+
+const DEFAULT_SLIPPAGE_BPS: u64 = 100;
+const DEFAULT_SWAP_DEADLINE_SECS: u64 = 300;
+
+/// How the fees of the swaps are priced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum GasMode {
+    Slow,
+    #[default]
+    Normal,
+    Fast,
+    /// The max fee and priority fee set by the user
+    Custom,
+}
+
+impl GasMode {
+    pub(crate) const ALL: [Self; 4] = [Self::Slow, Self::Normal, Self::Fast, Self::Custom];
+
+    /// Percentage of the node's EIP-1559 fee estimate paid
+    pub(crate) fn fee_percent(&self) -> u64 {
+        match self {
+            Self::Slow => 90,
+            Self::Normal | Self::Custom => 100,
+            Self::Fast => 150,
+        }
+    }
+}
+
+/// Fees of the swaps, the custom fees are only used in [GasMode::Custom]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct GasSettings {
+    pub(crate) mode: GasMode,
+    /// EIP-1559 max fee per gas, in Gwei
+    pub(crate) max_fee_gwei: Option<f64>,
+    /// EIP-1559 max priority fee per gas, in Gwei
+    pub(crate) priority_fee_gwei: Option<f64>,
+}
+
+/// Parameters of the swaps sent for the users' orders, the routers are configured per chain
+#[derive(Debug, Clone)]
+pub(crate) struct SwapConfig {
+    /// Tolerated difference between the quoted and the received amount, in basis points
+    pub(crate) slippage_bps: u64,
+    /// Seconds after the latest block the swap stays valid for
+    pub(crate) deadline_secs: u64,
+    pub(crate) gas: GasSettings,
+}
+
+impl SwapConfig {
+    /// Reads `SLIPPAGE_BPS` and `SWAP_DEADLINE_SECS`
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        dotenv().ok();
+        let slippage_bps = match std::env::var("SLIPPAGE_BPS") {
+            Ok(slippage) => slippage.parse()?,
+            Err(_) => DEFAULT_SLIPPAGE_BPS,
+        };
+        if slippage_bps >= 10_000 {
+            return Err(anyhow::anyhow!("SLIPPAGE_BPS must be below 10000"));
+        }
+        let deadline_secs = match std::env::var("SWAP_DEADLINE_SECS") {
+            Ok(deadline) => deadline.parse()?,
+            Err(_) => DEFAULT_SWAP_DEADLINE_SECS,
+        };
+        Ok(Self {
+            slippage_bps,
+            deadline_secs,
+            gas: GasSettings::default(),
+        })
+    }
+}
+
+/// How the private txs are handed to the relay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RelayMethod {
+    /// `eth_sendPrivateTransaction`, the relay retries the tx until `max_blocks` are mined
+    PrivateTransaction,
+    /// `eth_sendBundle`, a single tx bundle is sent for each of the next `max_blocks` blocks
+    Bundle,
+}
+
+const DEFAULT_RELAY_MAX_BLOCKS: u64 = 25;
+
+/// Parameters of the private txs, the relay of each chain is configured in chains.json
+#[derive(Clone)]
+pub(crate) struct RelayConfig {
+    pub(crate) method: RelayMethod,
+    /// Key the relay requests are signed with, it only identifies the bot to the relay and must
+    /// not hold funds, a random one is generated when unset
+    pub(crate) signing_key: Option<String>,
+    /// Blocks the relay has to include the tx
+    pub(crate) max_blocks: u64,
+    /// Whether the tx is broadcast publicly when the relay fails or doesn't include it in time
+    pub(crate) public_fallback: bool,
+    pub(crate) rebate: RebateConfig,
+}
+
+/// The signing key is never printed
+impl std::fmt::Debug for RelayConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RelayConfig")
+            .field("method", &self.method)
+            .field("max_blocks", &self.max_blocks)
+            .field("public_fallback", &self.public_fallback)
+            .field("rebate", &self.rebate)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RelayConfig {
+    /// Reads `RELAY_METHOD` ("private" or "bundle", defaults to private), `RELAY_SIGNING_KEY`,
+    /// `RELAY_MAX_BLOCKS` and `RELAY_PUBLIC_FALLBACK` (defaults to true)
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        dotenv().ok();
+        let method = std::env::var("RELAY_METHOD").unwrap_or_else(|_| "private".to_string());
+        let method = match method.to_lowercase().as_str() {
+            "private" => RelayMethod::PrivateTransaction,
+            "bundle" => RelayMethod::Bundle,
+            _ => return Err(anyhow::anyhow!("Unsupported relay method: {}", method)),
+        };
+        let max_blocks = match std::env::var("RELAY_MAX_BLOCKS") {
+            Ok(blocks) => blocks.parse()?,
+            Err(_) => DEFAULT_RELAY_MAX_BLOCKS,
+        };
+        if max_blocks == 0 {
+            return Err(anyhow::anyhow!("RELAY_MAX_BLOCKS must be positive"));
+        }
+        let public_fallback = match std::env::var("RELAY_PUBLIC_FALLBACK") {
+            Ok(fallback) => fallback.parse()?,
+            Err(_) => true,
+        };
+        Ok(Self {
+            method,
+            signing_key: std::env::var("RELAY_SIGNING_KEY")
+                .ok()
+                .filter(|key| !key.trim().is_empty()),
+            max_blocks,
+            public_fallback,
+            rebate: RebateConfig::from_env()?,
+        })
+    }
+}
+
+/// Hints MEV-Share can share with the searchers
+const REBATE_HINTS: [&str; 6] = [
+    "calldata",
+    "contract_address",
+    "logs",
+    "function_selector",
+    "hash",
+    "tx_hash",
+];
+const DEFAULT_REBATE_HINTS: &str = "hash,contract_address,function_selector,logs";
+const DEFAULT_REBATE_REFUND_PERCENT: u64 = 90;
+
+/// Policy of the rebate orders, their swap is sent to the relay as a MEV-Share bundle and the
+/// searchers backrunning it refund part of their profit to the wallet
+#[derive(Debug, Clone)]
+pub(crate) struct RebateConfig {
+    /// Parts of the tx revealed to the searchers, more hints get more backruns but less privacy
+    pub(crate) hints: Vec<String>,
+    /// Share of the backrun profit refunded by the relay, in percent, only used to estimate the
+    /// expected rebate
+    pub(crate) refund_percent: u64,
+}
+
+impl RebateConfig {
+    /// Reads the comma separated `REBATE_HINTS` and `REBATE_REFUND_PERCENT` (defaults to 90)
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        dotenv().ok();
+        let hints = std::env::var("REBATE_HINTS")
+            .ok()
+            .filter(|hints| !hints.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_REBATE_HINTS.to_string())
+            .split(',')
+            .map(|hint| hint.trim().to_lowercase())
+            .filter(|hint| !hint.is_empty())
+            .map(|hint| match REBATE_HINTS.contains(&hint.as_str()) {
+                true => Ok(hint),
+                false => Err(anyhow::anyhow!("Unsupported rebate hint: {}", hint)),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let refund_percent = match std::env::var("REBATE_REFUND_PERCENT") {
+            Ok(percent) => percent.parse()?,
+            Err(_) => DEFAULT_REBATE_REFUND_PERCENT,
+        };
+        if refund_percent > 100 {
+            return Err(anyhow::anyhow!("REBATE_REFUND_PERCENT can't exceed 100"));
+        }
+        Ok(Self {
+            hints,
+            refund_percent,
+        })
+    }
+}
+
+/// Chains the bot knows about, `CHAINS_FILE` replaces them
+const BUNDLED_CHAINS: &str = include_str!("../chains.json");
+const DEFAULT_ENABLED_CHAINS: &str = "1";
+/// Multicall3 is deployed at the same address on most chains
+const DEFAULT_MULTICALL: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+
+fn default_multicall() -> Address {
+    DEFAULT_MULTICALL.parse().expect("valid multicall address")
+}
+
+/// A chain the bot can query and swap on
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ChainConfig {
+    pub(crate) chain_id: u64,
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) rpc_urls: Vec<String>,
+    /// Environment variable holding comma separated RPC URLs, tried before `rpc_urls`
+    #[serde(default)]
+    rpc_urls_env: Option<String>,
+    /// WebSocket URL new heads are subscribed through, the status is polled over HTTP without it
+    #[serde(default)]
+    pub(crate) ws_url: Option<String>,
+    /// Environment variable holding the WebSocket URL, preferred over `ws_url`
+    #[serde(default)]
+    ws_url_env: Option<String>,
+    /// Flashbots compatible relay the private txs are sent to, they go public without it
+    #[serde(default)]
+    pub(crate) relay_url: Option<String>,
+    /// Environment variable holding the relay URL, preferred over `relay_url`
+    #[serde(default)]
+    relay_url_env: Option<String>,
+    pub(crate) native_symbol: String,
+    pub(crate) native_name: String,
+    /// Stands in for the native token in the pools
+    pub(crate) wrapped_native: Address,
+    /// Uniswap V2 style router
+    pub(crate) router: Address,
+    /// Uniswap V3 SwapRouter
+    pub(crate) v3_router: Address,
+    /// Uniswap V3 Quoter the V3 routes are quoted with
+    pub(crate) quoter: Address,
+    /// Tokens two hop routes may go through, the wrapped native token always is
+    #[serde(default)]
+    pub(crate) routing_tokens: Vec<Address>,
+    /// Multicall3 contract whose code runs the pre-trade simulations
+    #[serde(default = "default_multicall")]
+    pub(crate) multicall: Address,
+    /// Stablecoin the gas cost of a swap is valued in
+    #[serde(default)]
+    pub(crate) usd_token: Option<Address>,
+    pub(crate) explorer_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChainList {
+    chains: Vec<ChainConfig>,
+}
+
+/// The enabled chains, in the order they are displayed
+#[derive(Debug, Clone)]
+pub(crate) struct ChainRegistry {
+    chains: Vec<ChainConfig>,
+}
+
+impl ChainRegistry {
+    /// Reads the chains of `CHAINS_FILE` (the bundled chains.json by default) and keeps the ones
+    /// listed in `ENABLED_CHAINS` (comma separated chain ids, defaults to 1)
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        dotenv().ok();
+        let list = match std::env::var("CHAINS_FILE") {
+            Ok(path) => std::fs::read_to_string(&path)
+                .map_err(|err| anyhow::anyhow!("Unable to read {}: {}", path, err))?,
+            Err(_) => BUNDLED_CHAINS.to_string(),
+        };
+        let mut known = serde_json::from_str::<ChainList>(&list)?.chains;
+
+        let mut chains = Vec::new();
+        for chain_id in std::env::var("ENABLED_CHAINS")
+            .unwrap_or_else(|_| DEFAULT_ENABLED_CHAINS.to_string())
+            .split(',')
+            .map(str::trim)
+            .filter(|chain_id| !chain_id.is_empty())
+        {
+            let chain_id: u64 = chain_id.parse()?;
+            let position = known
+                .iter()
+                .position(|chain| chain.chain_id == chain_id)
+                .ok_or_else(|| anyhow::anyhow!("Unknown chain id: {}", chain_id))?;
+            let mut chain = known.remove(position);
+            if let Some(urls) = chain
+                .rpc_urls_env
+                .as_ref()
+                .and_then(|key| std::env::var(key).ok())
+            {
+                let mut rpc_urls: Vec<String> = urls
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                rpc_urls.append(&mut chain.rpc_urls);
+                chain.rpc_urls = rpc_urls;
+            }
+            if let Some(url) = chain
+                .ws_url_env
+                .as_ref()
+                .and_then(|key| std::env::var(key).ok())
+                .filter(|url| !url.trim().is_empty())
+            {
+                chain.ws_url = Some(url.trim().to_string());
+            }
+            if let Some(url) = chain
+                .relay_url_env
+                .as_ref()
+                .and_then(|key| std::env::var(key).ok())
+                .filter(|url| !url.trim().is_empty())
+            {
+                chain.relay_url = Some(url.trim().to_string());
+            }
+            if chain.rpc_urls.is_empty() {
+                return Err(anyhow::anyhow!("No RPC URL configured for {}", chain.name));
+            }
+            chains.push(chain);
+        }
+        if chains.is_empty() {
+            return Err(anyhow::anyhow!(
+                "ENABLED_CHAINS must list at least one chain"
+            ));
+        }
+        Ok(Self { chains })
+    }
+
+    pub(crate) fn get(&self, chain_id: u64) -> Option<&ChainConfig> {
+        self.chains.iter().find(|chain| chain.chain_id == chain_id)
+    }
+
+    /// Chain new orders start on
+    pub(crate) fn default_chain(&self) -> &ChainConfig {
+        &self.chains[0]
+    }
+
+    pub(crate) fn chains(&self) -> &[ChainConfig] {
+        &self.chains
+    }
+}
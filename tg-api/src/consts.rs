@@ -8,10 +8,55 @@ pub const WALLET_1: &str = "Wallet 1";
 pub const WALLET_2: &str = "Wallet 2";
 pub const WALLET_3: &str = "Wallet 3";
 pub const BUY: &str = "Buy";
+pub const SELL: &str = "Sell";
+pub const LIMIT_BUY: &str = "Limit Buy";
+pub const LIMIT_SELL: &str = "Limit Sell";
+pub const SELECT_WALLET: &str = "=Select Wallet=";
 #[allow(dead_code)]
 pub const RECEIVE: &str = "Receive";
 pub const BUY_AMOUNT: &str = "Buy Amount";
 pub const ESTIMATED_RECEIVED_AMOUNT: &str = "Estimated Received Amount";
+pub const ESTIMATE_UNAVAILABLE: &str = "Estimate Unavailable";
 pub const BUY_TOKEN: &str = "Buy Token";
 pub const RECEIVE_TOKEN: &str = "Receive Token";
-pub const BOT_NAME: &str = "NishikigoiBot";
+pub const SELL_TOKEN: &str = "Sell Token";
+pub const SELL_AMOUNT: &str = "Sell Amount";
+pub const SELL_PERCENTAGE: &str = "Sell %";
+pub const RENAME_WALLET: &str = "Rename";
+pub const SET_DEFAULT_WALLET: &str = "Set Default";
+pub const DEFAULT_WALLET: &str = "Default";
+pub const EXPORT_WALLET: &str = "Export Key";
+pub const IMPORT_WALLET: &str = "Import Wallet";
+pub const DELETE_WALLET: &str = "Delete";
+pub const CONFIRM_DELETE_WALLET: &str = "Confirm Delete";
+pub const PREVIOUS_PAGE: &str = "Prev";
+pub const NEXT_PAGE: &str = "Next";
+pub const BACK: &str = "Back";
+pub const SETTINGS: &str = "Settings";
+pub const SLIPPAGE: &str = "Slippage";
+pub const DEADLINE: &str = "Deadline";
+pub const GAS: &str = "Gas";
+pub const GAS_SLOW: &str = "Slow";
+pub const GAS_NORMAL: &str = "Normal";
+pub const GAS_FAST: &str = "Fast";
+pub const GAS_CUSTOM: &str = "Custom";
+pub const MAX_FEE: &str = "Max Fee";
+pub const PRIORITY_FEE: &str = "Priority Fee";
+pub const DEFAULT_BUY_AMOUNT: &str = "Default Buy Amount";
+pub const EXPIRY: &str = "Expiry";
+pub const PLACE_LIMIT_ORDER: &str = "Place Limit Order";
+pub const LIMIT: &str = "Limit";
+pub const STOP_LOSS: &str = "Stop Loss";
+pub const TRAILING_STOP: &str = "Trailing Stop";
+pub const PRICE: &str = "Price";
+pub const ENTRY_PERCENT: &str = "% From Entry";
+pub const MARKET_CAP: &str = "Market Cap";
+pub const TRAIL_PERCENT: &str = "Trail %";
+pub const TRIGGER: &str = "Trigger";
+pub const OPEN_ORDERS: &str = "Open Orders";
+pub const CANCEL_ORDER: &str = "Cancel";
+pub const EDIT_ORDER: &str = "Edit";
+pub const EDIT_TRIGGER: &str = "Edit Trigger";
+pub const EDIT_EXPIRY: &str = "Edit Expiry";
+pub const CONFIRM_SWAP: &str = "Confirm";
+pub const ABORT_SWAP: &str = "Abort";
@@ -0,0 +1,48 @@
+use crate::bot::TgError;
+use crate::config::admin_ids_from_env;
+use crate::requests::providers::ProviderRegistry;
+use teloxide::{prelude::Requester, types::Message, Bot};
+
+/// Lists the RPC endpoints of every chain along with their health, admins only
+pub(crate) async fn rpc_health_command(
+    bot: &Bot,
+    msg: &Message,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    let user_id = match msg.from() {
+        Some(user) => user.id,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+    if !admin_ids_from_env()?.contains(&user_id) {
+        bot.send_message(msg.chat.id, "This command is reserved to the admins")
+            .await?;
+        return Ok(());
+    }
+
+    let mut text = String::from("RPC Endpoints");
+    for (chain, endpoints) in providers.endpoint_health() {
+        text.push_str(&format!("\n\n{}", chain.name));
+        for (position, (host, health)) in endpoints.iter().enumerate() {
+            text.push_str(&format!(
+                "\n{}. {}\nScore: {:.0}  ═  Latency: {:.0} ms  ═  Errors: {:.1}%\nHead: {}  ═  Lag: {}  ═  Requests: {} ({} failed)",
+                position + 1,
+                host,
+                health.score().max(0.0),
+                health.latency_ms,
+                health.error_rate * 100.0,
+                health
+                    .head
+                    .map(|head| head.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                health.lag,
+                health.requests,
+                health.failures
+            ));
+            if let Some(err) = &health.last_error {
+                text.push_str(&format!("\nLast Error: {}", err));
+            }
+        }
+    }
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
@@ -1,31 +1,49 @@
 use crate::bot::TgError;
-use crate::handlers::dialogue_handlers::PromptDialogueState;
+use crate::handlers::dialogue_handlers::{PromptDialogueState, TokenField};
+use crate::handlers::limit_order_handlers::LimitField;
+use crate::handlers::preview_handlers::confirmation_card;
 use crate::handlers::{
-    delete_previous_messages, find_keyboard_from_callback, find_sub_menu_type_from_callback,
-    SubMenuType,
+    delete_previous_messages, delete_up_to_messages, edit_draft_menu, format_eth, native_symbol,
 };
-use crate::keyboards::buy_buttons::{buy_keyboard, BuyButtons};
-use crate::keyboards::menu_keyboard;
-use crate::requests::on_chain;
-use crate::storages::{TgMessage, TgMessageStorage};
-use crate::storages::{GLOBAL_BUY_MENU_STORAGE, GLOBAL_MAIN_MENU_STORAGE};
+use crate::keyboards::callback_data::{new_session_id, CallbackAction, CallbackData};
+use crate::keyboards::{confirm_swap_keyboard, draft_keyboard, menu_keyboard, DraftDisplay};
+use crate::requests::failover::RpcProvider;
+use crate::requests::on_chain::{self, explorer_tx_url};
+use crate::requests::providers::ProviderRegistry;
+use crate::requests::relay::PrivateSubmission;
+use crate::requests::server::{SendBuyTxRequest, SendSellTxRequest};
+use crate::requests::swap::{
+    recheck_swap, submit_swap, wait_for_swap, PreparedSwap, SubmittedSwap, SwapOrder,
+};
+use crate::storages::{
+    DraftKey, LimitTrigger, MenuKind, OrderDraft, OrderSide, SwapSettings, TakenSwap, TgMessage,
+    GLOBAL_SWAP_CONFIG,
+};
+use crate::storages::{TradeRecord, TradeStatus};
+use crate::storages::{
+    GLOBAL_CHAIN_REGISTRY, GLOBAL_MENU_STORE, GLOBAL_ORDER_DRAFT_STORAGE, GLOBAL_PENDING_SWAPS,
+    GLOBAL_SETTINGS_STORE, GLOBAL_TRADE_JOURNAL, GLOBAL_WALLET_STORE,
+};
+use ethers::types::Address;
 use std::sync::Arc;
-use teloxide::dispatching::dialogue::InMemStorage;
+use teloxide::dispatching::dialogue::ErasedStorage;
 use teloxide::{
-    dispatching::dialogue::Storage,
-    payloads::{EditMessageTextSetters, SendMessageSetters},
+    payloads::{AnswerCallbackQuerySetters, SendMessageSetters},
     prelude::Requester,
-    types::{CallbackQuery, InlineKeyboardButtonKind, Message, ParseMode},
+    types::{CallbackQuery, ChatId, Message, ParseMode},
     Bot,
 };
-use tokio::time::{sleep, Duration};
 
 /// Upon a user clicks the "Main Menu", it'll clear the text and show the menu again
-pub(crate) async fn handle_menu_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
-    let keyboard = menu_keyboard();
+pub(crate) async fn handle_menu_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    let keyboard = menu_keyboard()?;
     bot.answer_callback_query(&q.id).await?;
     if let Some(Message { chat, .. }) = &q.message {
-        let menu_msg = on_chain::get_on_chain_info().await?;
+        let menu_msg = on_chain::get_on_chain_info(providers);
 
         let message_sent = bot
             .send_message(chat.id, menu_msg)
@@ -35,311 +53,920 @@ pub(crate) async fn handle_menu_callback(bot: &Bot, q: &CallbackQuery) -> Result
         let message_sent = Arc::new(message_sent);
 
         // Updates the GLOBAL_STORAGE
-        let _user_name = message_sent
-            .clone()
-            .from()
-            .and_then(|user| user.username.as_ref())
-            .and_then(|user_name| {
-                let message = TgMessage {
-                    chat_id: message_sent.chat.id,
-                    message_id: message_sent.id,
-                    message: message_sent.clone(),
-                };
-                GLOBAL_MAIN_MENU_STORAGE.insert(user_name.to_string(), message);
-                Some(user_name)
-            });
+        if let Some(key) = DraftKey::from_callback(q) {
+            let message = TgMessage {
+                chat_id: message_sent.chat.id,
+                message_id: message_sent.id,
+                message: message_sent.clone(),
+            };
+            GLOBAL_MENU_STORE
+                .insert(MenuKind::Main, key, message)
+                .await?;
+        }
 
         let last_message_id = message_sent.id;
-        let _ = delete_previous_messages(bot, chat.id.0, last_message_id.0 - 1, 20).await?;
+        delete_previous_messages(bot, chat.id.0, last_message_id.0 - 1, 20).await?;
     };
     Ok(())
 }
 
-pub(crate) async fn handle_buy_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
-    let keyboard = buy_keyboard(true, false, true, false, false)?;
+/// Starts a new draft of the given side for the user and sends its menu, a limit draft places
+/// a limit order instead of swapping right away
+async fn open_draft_menu(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    side: OrderSide,
+    limit: Option<LimitTrigger>,
+) -> Result<(), TgError> {
     bot.answer_callback_query(&q.id).await?;
-    if let Some(Message { id: _id, chat, .. }) = &q.message {
-        let menu_msg = on_chain::get_on_chain_info().await?;
-        // todo: add custom info for buy
-        let _ = bot
+    if let (Some(key), Some(Message { chat, .. })) = (DraftKey::from_callback(q), &q.message) {
+        // a new draft makes the buttons of the user's previous buy or sell menu stale
+        let settings = GLOBAL_SETTINGS_STORE.get(key.user_id).await?;
+        let mut draft = OrderDraft::new(side, new_session_id()).with_settings(&settings);
+        let wallets = GLOBAL_WALLET_STORE.wallets(key.user_id).await?;
+        let default = GLOBAL_WALLET_STORE.default_wallet(key.user_id).await?;
+        draft.wallet = wallets.get(default as usize).map(|wallet| wallet.address);
+        draft.limit = limit;
+        let display = DraftDisplay {
+            wallets: wallets.iter().map(|wallet| wallet.address).collect(),
+            ..DraftDisplay::default()
+        };
+        let keyboard = draft_keyboard(&draft, &display)?;
+        GLOBAL_ORDER_DRAFT_STORAGE.insert(key, draft).await;
+
+        let menu_msg = on_chain::get_on_chain_info(providers);
+        let message_sent = bot
             .send_message(chat.id, menu_msg)
             .parse_mode(ParseMode::MarkdownV2)
             .reply_markup(keyboard)
             .await?;
+
+        // the open menu is tracked, so its quote can be refreshed on new blocks
+        let menu = match side {
+            OrderSide::Buy => MenuKind::Buy,
+            OrderSide::Sell => MenuKind::Sell,
+        };
+        let message = TgMessage {
+            chat_id: message_sent.chat.id,
+            message_id: message_sent.id,
+            message: Arc::new(message_sent),
+        };
+        GLOBAL_MENU_STORE.insert(menu, key, message).await?;
     }
     Ok(())
 }
 
+pub(crate) async fn handle_buy_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    open_draft_menu(bot, q, providers, OrderSide::Buy, None).await
+}
+
+/// Opens a buy menu whose order waits for its trigger price
+pub(crate) async fn handle_limit_buy_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    open_draft_menu(
+        bot,
+        q,
+        providers,
+        OrderSide::Buy,
+        Some(LimitTrigger::default()),
+    )
+    .await
+}
+
+pub(crate) async fn handle_sell_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    open_draft_menu(bot, q, providers, OrderSide::Sell, None).await
+}
+
+/// Opens a sell menu whose order waits for its limit, stop-loss or trailing stop trigger
+pub(crate) async fn handle_limit_sell_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    open_draft_menu(
+        bot,
+        q,
+        providers,
+        OrderSide::Sell,
+        Some(LimitTrigger::default()),
+    )
+    .await
+}
+
 pub(crate) async fn handle_close_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
     bot.answer_callback_query(&q.id).await?;
     if let Some(Message { id, chat, .. }) = &q.message {
-        let _ = bot.delete_message(chat.id, *id).await?;
+        bot.delete_message(chat.id, *id).await?;
     };
     Ok(())
 }
 
-pub(crate) async fn handle_wallet_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
-    bot.answer_callback_query(&q.id).await?;
-
-    if let (Some(button), Some(Message { id, chat, .. })) = (&q.data, &q.message) {
-        let menu_msg = on_chain::get_on_chain_info().await?;
-        // let sub_menu_type = find_sub_menu_type_from_callback(q)?;
-        let buy_button = BuyButtons::new(button);
-        let new_button_text = buy_button.toggle();
-
-        //if let SubMenuType::SendBuyTx = sub_menu_type {
-        let mut keyboard = find_keyboard_from_callback(q)?.clone();
-
-        // Determine the index based on the button type
-        let index = match buy_button {
-            BuyButtons::Wallet1(_) => 0,
-            BuyButtons::Wallet2(_) => 1,
-            BuyButtons::Wallet3(_) => 2,
-            _ => return Ok(()), // Return early if no match
-        };
+/// Helper function to apply `f` to the user's draft and re-render the menu the callback came from
+async fn update_draft(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    f: impl FnOnce(&mut OrderDraft) + Send,
+) -> Result<(), TgError> {
+    if let (Some(key), Some(Message { id, .. })) = (DraftKey::from_callback(q), &q.message) {
+        if let Some(draft) = GLOBAL_ORDER_DRAFT_STORAGE.update(&key, f).await {
+            edit_draft_menu(bot, providers, key, *id, &draft).await?;
+        }
+    }
+    Ok(())
+}
 
-        // Update the button text and kind
-        if let Some(button) = keyboard
-            .inline_keyboard
-            .get_mut(3)
-            .and_then(|row| row.get_mut(index))
-        {
-            button.text = new_button_text.to_string();
-            button.kind = InlineKeyboardButtonKind::CallbackData(new_button_text.to_string());
+pub(crate) async fn handle_wallet_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    data: &CallbackData,
+) -> Result<(), TgError> {
+    let clicked = match data.action {
+        CallbackAction::Wallet { index, .. } => index,
+        _ => return Ok(()), // Return early if no match
+    };
+    // wallets are generated on /start
+    let wallets = GLOBAL_WALLET_STORE.wallets(q.from.id).await?;
+    let address = match wallets.get(clicked as usize) {
+        Some(wallet) => wallet.address,
+        None => {
+            bot.answer_callback_query(&q.id)
+                .text(format!(
+                    "Wallet {} not found, use /start to create your wallets",
+                    clicked + 1
+                ))
+                .await?;
+            return Ok(());
         }
+    };
+    bot.answer_callback_query(&q.id).await?;
+    update_draft(bot, q, providers, |draft| draft.wallet = Some(address)).await
+}
 
-        // Edit the message with the updated keyboard
-        bot.edit_message_text(chat.id, *id, menu_msg)
-            .parse_mode(ParseMode::MarkdownV2)
-            .reply_markup(keyboard)
+/// Moves the draft to the clicked chain, its tokens are cleared as they belong to the previous one
+pub(crate) async fn handle_chain_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    data: &CallbackData,
+) -> Result<(), TgError> {
+    let chain_id = match data.action {
+        CallbackAction::Chain { chain_id, .. } => chain_id,
+        _ => return Ok(()),
+    };
+    if GLOBAL_CHAIN_REGISTRY.get(chain_id).is_none() {
+        bot.answer_callback_query(&q.id)
+            .text("This chain is no longer enabled")
             .await?;
-        //} else if let SubMenuType::SendSellTx = sub_menu_type {
-        //    todo!(); // Handle the SendSellTx case here
-        //}
+        return Ok(());
     }
-
-    Ok(())
+    bot.answer_callback_query(&q.id).await?;
+    update_draft(bot, q, providers, |draft| draft.set_chain(chain_id)).await
 }
 
-// Note: any value changed to the keyboard layout will affect this function
 pub(crate) async fn handle_private_tx_callback(
     bot: &Bot,
     q: &CallbackQuery,
+    providers: &ProviderRegistry,
 ) -> Result<(), TgError> {
     bot.answer_callback_query(&q.id).await?;
-    match find_sub_menu_type_from_callback(q)? {
-        SubMenuType::SendBuyTx => {
-            if let Some(button) = &q.data {
-                if let Some(Message { id, chat, .. }) = &q.message {
-                    let menu_msg = on_chain::get_on_chain_info().await?;
-
-                    // Gets current keyboard layout
-                    let keyboard = find_keyboard_from_callback(q)?.clone();
-                    let mut new_keyboard = keyboard.clone();
-                    let button = BuyButtons::new(button);
-                    let new_button_text = button.toggle();
-
-                    // Change the text to toggled value
-                    if let Some(button) = new_keyboard
-                        .inline_keyboard
-                        .get_mut(1)
-                        .and_then(|row| row.get_mut(0))
-                    {
-                        button.text = new_button_text.to_string();
-                        button.kind =
-                            InlineKeyboardButtonKind::CallbackData(new_button_text.to_string());
-                    }
-
-                    // Edit the message with the new keyboard
-                    bot.edit_message_text(chat.id, *id, menu_msg)
-                        .parse_mode(ParseMode::MarkdownV2)
-                        .reply_markup(new_keyboard)
-                        .await?;
-                }
-            }
+    update_draft(bot, q, providers, |draft| {
+        draft.private_tx = !draft.private_tx
+    })
+    .await
+}
+
+pub(crate) async fn handle_rebate_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
+    update_draft(bot, q, providers, |draft| draft.rebate = !draft.rebate).await
+}
+
+/// Switches the draft to the next gas mode
+pub(crate) async fn handle_cycle_gas_mode_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
+    update_draft(bot, q, providers, |draft| draft.swap.cycle_gas_mode()).await
+}
+
+/// Selects one of the preset percentages in the sell menu, which clears the exact sell amount,
+/// clicking the selected preset again deselects it
+pub(crate) async fn handle_sell_percentage_preset_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    data: &CallbackData,
+) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
+    let clicked = match data.action {
+        CallbackAction::PresetPercentage { percentage, .. } => percentage as f64,
+        _ => return Ok(()),
+    };
+    update_draft(bot, q, providers, |draft| {
+        draft.amount = None;
+        draft.sell_percentage = match draft.sell_percentage {
+            Some(percentage) if percentage == clicked => None,
+            _ => Some(clicked),
+        };
+    })
+    .await
+}
+
+/// Dry runs the swap of the draft and shows its confirmation card, the swap is sent once it's
+/// confirmed
+pub(crate) async fn handle_send_tx_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
+    let (key, chat_id) = match (DraftKey::from_callback(q), &q.message) {
+        (Some(key), Some(Message { chat, .. })) => (key, chat.id),
+        _ => return Ok(()),
+    };
+    let draft = GLOBAL_ORDER_DRAFT_STORAGE
+        .get(&key)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("No order draft found for {}", key))?;
+    send_confirmation_card(bot, providers, key, chat_id, &draft).await
+}
+
+/// Helper function to dry run the swap of the draft and send its confirmation card, the swap
+/// that was dry run is kept for the card's Confirm button
+async fn send_confirmation_card(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    key: DraftKey,
+    chat_id: ChatId,
+    draft: &OrderDraft,
+) -> Result<(), TgError> {
+    match confirmation_card(providers, key.user_id, draft).await {
+        Ok((card, swap)) => {
+            let nonce = GLOBAL_PENDING_SWAPS.issue(key, draft.fingerprint(), swap);
+            bot.send_message(chat_id, card)
+                .reply_markup(confirm_swap_keyboard(draft, nonce)?)
+                .disable_web_page_preview(true)
+                .await?;
         }
-        SubMenuType::SendSellTx => {
-            todo!()
+        Err(err) => {
+            let side = match draft.side {
+                OrderSide::Buy => "buy",
+                OrderSide::Sell => "sell",
+            };
+            bot.send_message(chat_id, format!("Unable to send {} tx: {}", side, err))
+                .await?;
         }
     }
     Ok(())
 }
 
-pub(crate) async fn handle_rebate_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
+/// Sends the swap of the confirmation card as it was dry run, once only and unless its draft was
+/// edited since the card was shown. A swap that no longer fills the minimum shown on the card
+/// isn't sent, a new card is shown instead
+pub(crate) async fn handle_confirm_swap_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    data: &CallbackData,
+) -> Result<(), TgError> {
+    let nonce = match data.action {
+        CallbackAction::ConfirmSwap(nonce) => nonce,
+        _ => return Ok(()),
+    };
+    let (key, chat_id, message_id) = match (DraftKey::from_callback(q), &q.message) {
+        (Some(key), Some(Message { id, chat, .. })) => (key, chat.id, *id),
+        _ => return Ok(()),
+    };
+    let pending = match GLOBAL_PENDING_SWAPS.take(&key, nonce) {
+        TakenSwap::Confirmed(pending) => pending,
+        TakenSwap::Replaced => {
+            bot.answer_callback_query(&q.id)
+                .text("A newer confirmation was shown, confirm that one")
+                .await?;
+            return Ok(());
+        }
+        TakenSwap::Missing => {
+            bot.answer_callback_query(&q.id)
+                .text("This swap was already sent or aborted")
+                .await?;
+            return Ok(());
+        }
+    };
+    let draft = match GLOBAL_ORDER_DRAFT_STORAGE.get(&key).await {
+        Some(draft) if draft.fingerprint() == pending.fingerprint => draft,
+        _ => {
+            bot.answer_callback_query(&q.id)
+                .text("The order changed since it was checked, press Send again")
+                .await?;
+            return Ok(());
+        }
+    };
+    let swap = match pending.swap {
+        Some(swap) => swap,
+        None => {
+            bot.answer_callback_query(&q.id)
+                .text("The swap couldn't be checked, press Send again")
+                .await?;
+            return Ok(());
+        }
+    };
     bot.answer_callback_query(&q.id).await?;
-    match find_sub_menu_type_from_callback(q)? {
-        SubMenuType::SendBuyTx => {
-            if let Some(button) = &q.data {
-                if let Some(Message { id, chat, .. }) = &q.message {
-                    let menu_msg = on_chain::get_on_chain_info().await?;
-
-                    // Gets current keyboard layout
-                    let keyboard = find_keyboard_from_callback(q)?.clone();
-                    let mut new_keyboard = keyboard.clone();
-                    let button = BuyButtons::new(button);
-                    let new_button_text = button.toggle();
-
-                    // Change the text to toggled value
-                    if let Some(button) = new_keyboard
-                        .inline_keyboard
-                        .get_mut(1)
-                        .and_then(|row| row.get_mut(1))
-                    {
-                        button.text = new_button_text.to_string();
-                        button.kind =
-                            InlineKeyboardButtonKind::CallbackData(new_button_text.to_string());
-                    }
-
-                    // Edit the message with the new keyboard
-                    bot.edit_message_text(chat.id, *id, menu_msg)
-                        .parse_mode(ParseMode::MarkdownV2)
-                        .reply_markup(new_keyboard)
-                        .await?;
-                }
-            }
+    // the card keeps its text, its buttons are of no use anymore
+    bot.edit_message_reply_markup(chat_id, message_id).await?;
+
+    let moved = async {
+        let query = providers.query(draft.chain_id)?;
+        let order = match draft.side {
+            OrderSide::Buy => SendBuyTxRequest::new(&draft)?.swap_order(),
+            OrderSide::Sell => SendSellTxRequest::new(&draft)?.swap_order(),
+        };
+        recheck_swap(&Arc::new(query.provider()), query.chain(), &order, &swap).await
+    }
+    .await;
+    match moved {
+        Ok(None) => broadcast_draft(bot, providers, key, &draft, swap).await,
+        Ok(Some(reason)) => {
+            bot.send_message(
+                chat_id,
+                format!("Not sent, {} since the card was shown. New quote:", reason),
+            )
+            .await?;
+            send_confirmation_card(bot, providers, key, chat_id, &draft).await
         }
-        SubMenuType::SendSellTx => {
-            todo!()
+        Err(err) => {
+            bot.send_message(chat_id, format!("Unable to check the swap again: {}", err))
+                .await?;
+            Ok(())
         }
     }
+}
+
+pub(crate) async fn handle_abort_swap_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    data: &CallbackData,
+) -> Result<(), TgError> {
+    if let (CallbackAction::AbortSwap(nonce), Some(key)) =
+        (&data.action, DraftKey::from_callback(q))
+    {
+        GLOBAL_PENDING_SWAPS.take(&key, *nonce);
+    }
+    bot.answer_callback_query(&q.id)
+        .text("Swap aborted")
+        .await?;
+    if let Some(Message { id, chat, .. }) = &q.message {
+        bot.delete_message(chat.id, *id).await?;
+    }
     Ok(())
 }
 
-pub(crate) async fn handle_send_tx_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
-    bot.answer_callback_query(&q.id).await?;
-    match find_sub_menu_type_from_callback(q)? {
-        SubMenuType::SendBuyTx => {
-            sleep(Duration::from_secs(3)).await;
-            bot.answer_callback_query(&q.id).await?;
-            if let Some(Message { id: _id, chat, .. }) = &q.message {
-                // todo: add custom info for buy
-                let _ = bot
-                    .send_message(chat.id, "Tx Sent\nTx Hash: 0x39ee32a72c11b78e4d190158682e9e3f3ec7cf1aeb849644355475bc778eacba")
-                    .parse_mode(ParseMode::MarkdownV2)
+/// Validates the draft and sends the `swap` prepared for it
+async fn broadcast_draft(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    key: DraftKey,
+    draft: &OrderDraft,
+    swap: PreparedSwap,
+) -> Result<(), TgError> {
+    match draft.side {
+        OrderSide::Buy => match SendBuyTxRequest::new(draft) {
+            Ok(request) => {
+                log::info!("buy request: {:?}", request);
+                let summary = format!(
+                    "Buy: {:#x}\nPay: {} of {:#x}",
+                    request.buy, request.buy_amount, request.receive
+                );
+                send_swap(
+                    bot,
+                    providers,
+                    key,
+                    (request.chain_id, request.wallet),
+                    (request.swap_order(), draft.swap, Some(swap)),
+                    request.trade_record(key.user_id),
+                    ("Buy", summary),
+                )
+                .await?;
+            }
+            Err(err) => {
+                bot.send_message(key.chat_id, format!("Unable to send buy tx: {}", err))
+                    .await?;
+            }
+        },
+        OrderSide::Sell => match SendSellTxRequest::new(draft) {
+            Ok(request) => {
+                log::info!("sell request: {:?}", request);
+                let amount = match (request.sell_amount, request.sell_percentage) {
+                    (Some(amount), _) => amount.to_string(),
+                    (_, Some(percentage)) => format!("{}% of balance", percentage),
+                    _ => unreachable!("validated in SendSellTxRequest::new"),
+                };
+                let summary = format!(
+                    "Sell: {} of {:#x}\nReceive: {:#x}",
+                    amount, request.sell, request.receive
+                );
+                send_swap(
+                    bot,
+                    providers,
+                    key,
+                    (request.chain_id, request.wallet),
+                    (request.swap_order(), draft.swap, Some(swap)),
+                    request.trade_record(key.user_id),
+                    ("Sell", summary),
+                )
+                .await?;
+            }
+            Err(err) => {
+                bot.send_message(key.chat_id, format!("Unable to send sell tx: {}", err))
                     .await?;
             }
+        },
+    }
+
+    Ok(())
+}
+
+/// Helper function to journal the order, sign its swap with the selected wallet and broadcast
+/// it, the user is notified again once the swap is mined. The `prepared` swap is sent as is,
+/// None prepares it along the best route. Returns the id of the trade once its swap is broadcast
+pub(crate) async fn send_swap(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    DraftKey { chat_id, user_id }: DraftKey,
+    (chain_id, address): (u64, Address),
+    (order, swap_settings, prepared): (SwapOrder, SwapSettings, Option<PreparedSwap>),
+    trade_record: TradeRecord,
+    (side, summary): (&str, String),
+) -> Result<Option<u64>, TgError> {
+    let wallet = GLOBAL_WALLET_STORE
+        .find(user_id, address)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Wallet {:#x} not found", address))?;
+    let trade_id = GLOBAL_TRADE_JOURNAL.record(trade_record).await?;
+
+    let submitted = async {
+        let query = providers.query(chain_id)?;
+        let provider = query.provider();
+        let signer = GLOBAL_WALLET_STORE.signer(user_id, address).await?;
+        let relay = providers.relay(chain_id)?;
+        let swap = submit_swap(
+            provider.clone(),
+            signer,
+            query.chain(),
+            &swap_settings.apply(&GLOBAL_SWAP_CONFIG),
+            &order,
+            prepared,
+            relay.as_ref(),
+        )
+        .await?;
+        let sent = match &swap.private {
+            Some(PrivateSubmission::Relayed(relayed)) if relayed.rebate => {
+                format!(
+                    "Via MEV-Share on {}, backrun refunds go to the wallet",
+                    relayed.relay
+                )
+            }
+            Some(PrivateSubmission::Relayed(relayed)) => format!("Privately via {}", relayed.relay),
+            Some(PrivateSubmission::FellBack { relay, reason }) => {
+                format!("Publicly, {} refused it: {}", relay, reason)
+            }
+            None if order.private || order.rebate => {
+                format!("Publicly, {} has no private relay", query.chain().name)
+            }
+            None => "Publicly".to_string(),
+        };
+        anyhow::Ok((provider, swap, sent))
+    }
+    .await;
+    let (provider, swap, sent) = match submitted {
+        Ok(submitted) => submitted,
+        Err(err) => {
+            log::warn!("Unable to send the swap of trade #{}: {}", trade_id, err);
+            GLOBAL_TRADE_JOURNAL
+                .update(trade_id, |trade| trade.status = TradeStatus::Failed)
+                .await?;
+            bot.send_message(
+                chat_id,
+                format!("Unable to send {} tx: {}", side.to_lowercase(), err),
+            )
+            .await?;
+            return Ok(None);
         }
-        SubMenuType::SendSellTx => {
-            todo!()
+    };
+
+    let (tx_hash, chain_id, expected_rebate) = (swap.tx_hash, swap.chain_id, swap.expected_rebate);
+    GLOBAL_TRADE_JOURNAL
+        .update(trade_id, move |trade| {
+            trade.tx_hash = Some(tx_hash);
+            trade.chain_id = chain_id;
+            trade.expected_rebate = expected_rebate;
+        })
+        .await?;
+    let tx = explorer_tx_url(chain_id, tx_hash).unwrap_or_else(|| format!("{:#x}", tx_hash));
+    bot.send_message(
+        chat_id,
+        format!(
+            "{} Tx Submitted\n{}\nRoute: {}\nMin Received: {}\nWallet: {} ({:#x})\nSent: {}\nTrade: #{}\nTx: {}",
+            side,
+            summary,
+            swap.route,
+            swap.min_received().unwrap_or_default(),
+            wallet.name,
+            wallet.address,
+            sent,
+            trade_id,
+            tx
+        ),
+    )
+    .disable_web_page_preview(true)
+    .await?;
+
+    let bot = bot.clone();
+    tokio::spawn(async move {
+        if let Err(err) = track_swap(&bot, chat_id, trade_id, &provider, &swap).await {
+            log::warn!("Unable to track the swap of trade #{}: {}", trade_id, err);
         }
-    }
+    });
+    Ok(Some(trade_id))
+}
+
+/// Waits for the swap to be mined, records its outcome in the journal and notifies the user
+async fn track_swap(
+    bot: &Bot,
+    chat_id: ChatId,
+    trade_id: u64,
+    provider: &RpcProvider,
+    swap: &SubmittedSwap,
+) -> Result<(), TgError> {
+    let receipt = wait_for_swap(provider, swap).await;
+    let trade = GLOBAL_TRADE_JOURNAL
+        .update(trade_id, {
+            let receipt = receipt.as_ref().ok().cloned();
+            move |trade| match receipt {
+                Some(receipt) => {
+                    trade.status = if receipt.success {
+                        TradeStatus::Confirmed
+                    } else {
+                        TradeStatus::Failed
+                    };
+                    trade.gas_paid = receipt.gas_paid;
+                    trade.filled_in = receipt.filled_in;
+                    trade.filled_out = receipt.filled_out;
+                    trade.rebate_received = receipt.rebate;
+                }
+                None => trade.status = TradeStatus::Failed,
+            }
+        })
+        .await?;
 
+    let text = match receipt {
+        Ok(receipt) if receipt.success => {
+            let mut text = format!(
+                "Trade #{} confirmed\nPaid: {}\nReceived: {}",
+                trade.id,
+                trade
+                    .filled_in
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                trade
+                    .filled_out
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            );
+            if let Some(rebate) = trade.rebate_received {
+                text.push_str(&format!(
+                    "\nRebate: {} {}",
+                    format_eth(rebate),
+                    native_symbol(trade.chain_id)
+                ));
+            }
+            text
+        }
+        Ok(_) => format!("Trade #{} reverted", trade.id),
+        Err(err) => format!("Trade #{} failed: {}", trade.id, err),
+    };
+    bot.send_message(chat_id, text).await?;
     Ok(())
 }
 
-pub(crate) async fn handle_buy_token_callback(
+/// Fills the token field of the pending prompt with the token picked among the ones matching
+/// the name the user entered
+pub(crate) async fn handle_select_token_callback(
     bot: &Bot,
-    state: PromptDialogueState,
     q: &CallbackQuery,
-    storage: Arc<InMemStorage<PromptDialogueState>>,
+    providers: &ProviderRegistry,
+    data: &CallbackData,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
 ) -> Result<(), TgError> {
+    let address = match data.action {
+        CallbackAction::SelectToken(address) => address,
+        _ => return Ok(()),
+    };
+    let (key, msg) = match (DraftKey::from_callback(q), &q.message) {
+        (Some(key), Some(msg)) => (key, msg),
+        _ => return Err(TgError::NoQueryMessage(Box::new(q.clone()))),
+    };
+    let state = storage.clone().get_dialogue(key.dialogue_id()).await?;
+    let field = match state.as_ref().and_then(TokenField::from_state) {
+        Some(field) => field,
+        None => {
+            bot.answer_callback_query(&q.id)
+                .text("This choice has expired, please enter the token again")
+                .await?;
+            return Ok(());
+        }
+    };
     bot.answer_callback_query(&q.id).await?;
 
-    // Updates the GLOBAL_BUY_MENU_STORAGE
-    if let Some(msg) = &q.message {
-        let msg = Arc::new(msg);
-        let _user_name = msg
-            .clone()
-            .from()
-            .and_then(|user| user.username.as_ref())
-            .and_then(|user_name| {
-                let message = TgMessage {
-                    chat_id: msg.chat.id,
-                    message_id: msg.id,
-                    message: (*msg).clone().into(),
-                };
-                GLOBAL_BUY_MENU_STORAGE.insert(user_name.to_string(), message);
-                Some(user_name)
-            });
-    }
-
-    if let Some(Message { chat, .. }) = &q.message {
-        storage.clone().update_dialogue(chat.id, state).await?;
-        bot.send_message(
-            chat.id,
-            "Enter the address or name of the token you want to buy",
-        )
-        .await?;
-        storage
-            .update_dialogue(chat.id, PromptDialogueState::BuyAddressReceived)
-            .await?;
+    match (
+        GLOBAL_MENU_STORE.get(field.menu(), &key).await?,
+        GLOBAL_ORDER_DRAFT_STORAGE
+            .update(&key, |draft| field.set(draft, address))
+            .await,
+    ) {
+        (Some(menu), Some(draft)) => {
+            edit_draft_menu(bot, providers, key, menu.message_id, &draft).await?;
+            storage.remove_dialogue(key.dialogue_id()).await?;
+
+            delete_up_to_messages(bot, msg.chat.id.0, msg.id.0, menu.message_id.0).await?;
+        }
+        _ => log::warn!("message not found"),
     }
     Ok(())
 }
 
-pub(crate) async fn handle_receive_token_callback(
+/// Helper function to store the sub menu the prompt was sent from and move the dialogue
+/// to the state awaiting the user's input
+async fn prompt_dialogue_input(
     bot: &Bot,
-    state: PromptDialogueState,
     q: &CallbackQuery,
-    storage: Arc<InMemStorage<PromptDialogueState>>,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+    menu: MenuKind,
+    state: PromptDialogueState,
+    next_state: PromptDialogueState,
+    prompt: &str,
 ) -> Result<(), TgError> {
     bot.answer_callback_query(&q.id).await?;
 
-    // Updates the GLOBAL_BUY_MENU_STORAGE
-    if let Some(msg) = &q.message {
-        let msg = Arc::new(msg);
-        let _user_name = msg
-            .clone()
-            .from()
-            .and_then(|user| user.username.as_ref())
-            .and_then(|user_name| {
-                let message = TgMessage {
-                    chat_id: msg.chat.id,
-                    message_id: msg.id,
-                    message: (*msg).clone().into(),
-                };
-                GLOBAL_BUY_MENU_STORAGE.insert(user_name.to_string(), message);
-                Some(user_name)
-            });
-    }
+    // Updates the menu storage, so the dialogue handlers can find the menu of this user
+    if let (Some(key), Some(msg)) = (DraftKey::from_callback(q), &q.message) {
+        let message = TgMessage {
+            chat_id: msg.chat.id,
+            message_id: msg.id,
+            message: Arc::new(msg.clone()),
+        };
+        GLOBAL_MENU_STORE.insert(menu, key, message).await?;
 
-    if let Some(Message { chat, .. }) = &q.message {
-        storage.clone().update_dialogue(chat.id, state).await?;
-        bot.send_message(
-            chat.id,
-            "Enter the address or name of the token you want to sell",
-        )
-        .await?;
         storage
-            .update_dialogue(chat.id, PromptDialogueState::ReceiveAddressReceived)
+            .clone()
+            .update_dialogue(key.dialogue_id(), state)
+            .await?;
+        bot.send_message(msg.chat.id, prompt).await?;
+        storage
+            .update_dialogue(key.dialogue_id(), next_state)
             .await?;
     }
     Ok(())
 }
 
+pub(crate) async fn handle_buy_token_callback(
+    bot: &Bot,
+    state: PromptDialogueState,
+    q: &CallbackQuery,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    prompt_dialogue_input(
+        bot,
+        q,
+        storage,
+        MenuKind::Buy,
+        state,
+        PromptDialogueState::BuyAddressReceived,
+        "Enter the address or name of the token you want to buy",
+    )
+    .await
+}
+
+pub(crate) async fn handle_receive_token_callback(
+    bot: &Bot,
+    state: PromptDialogueState,
+    q: &CallbackQuery,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    prompt_dialogue_input(
+        bot,
+        q,
+        storage,
+        MenuKind::Buy,
+        state,
+        PromptDialogueState::ReceiveAddressReceived,
+        "Enter the address or name of the token you want to sell",
+    )
+    .await
+}
+
 pub(crate) async fn handle_buy_amount_callback(
     bot: &Bot,
     state: PromptDialogueState,
     q: &CallbackQuery,
-    storage: Arc<InMemStorage<PromptDialogueState>>,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
 ) -> Result<(), TgError> {
+    prompt_dialogue_input(
+        bot,
+        q,
+        storage,
+        MenuKind::Buy,
+        state,
+        PromptDialogueState::BuyAmountReceived,
+        "Enter the amount you want to trade",
+    )
+    .await
+}
+
+pub(crate) async fn handle_sell_token_callback(
+    bot: &Bot,
+    state: PromptDialogueState,
+    q: &CallbackQuery,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    prompt_dialogue_input(
+        bot,
+        q,
+        storage,
+        MenuKind::Sell,
+        state,
+        PromptDialogueState::SellAddressReceived,
+        "Enter the address or name of the token you want to sell",
+    )
+    .await
+}
+
+pub(crate) async fn handle_sell_receive_token_callback(
+    bot: &Bot,
+    state: PromptDialogueState,
+    q: &CallbackQuery,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    prompt_dialogue_input(
+        bot,
+        q,
+        storage,
+        MenuKind::Sell,
+        state,
+        PromptDialogueState::SellReceiveAddressReceived,
+        "Enter the address or name of the token you want to receive",
+    )
+    .await
+}
+
+pub(crate) async fn handle_sell_amount_callback(
+    bot: &Bot,
+    state: PromptDialogueState,
+    q: &CallbackQuery,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    prompt_dialogue_input(
+        bot,
+        q,
+        storage,
+        MenuKind::Sell,
+        state,
+        PromptDialogueState::SellAmountReceived,
+        "Enter the amount you want to sell",
+    )
+    .await
+}
+
+pub(crate) async fn handle_slippage_callback(
+    bot: &Bot,
+    side: OrderSide,
+    q: &CallbackQuery,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    let menu = match side {
+        OrderSide::Buy => MenuKind::Buy,
+        OrderSide::Sell => MenuKind::Sell,
+    };
+    prompt_dialogue_input(
+        bot,
+        q,
+        storage,
+        menu,
+        PromptDialogueState::SlippageReceived(side),
+        PromptDialogueState::SlippageReceived(side),
+        "Enter the slippage of this order in percent, like 0.5",
+    )
+    .await
+}
+
+pub(crate) async fn handle_sell_percentage_callback(
+    bot: &Bot,
+    state: PromptDialogueState,
+    q: &CallbackQuery,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    prompt_dialogue_input(
+        bot,
+        q,
+        storage,
+        MenuKind::Sell,
+        state,
+        PromptDialogueState::SellPercentageReceived,
+        "Enter the percentage of your balance you want to sell",
+    )
+    .await
+}
+
+/// Switches the kind of the limit sell draft between limit, stop-loss and trailing stop
+pub(crate) async fn handle_limit_kind_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    data: &CallbackData,
+) -> Result<(), TgError> {
+    let kind = match data.action {
+        CallbackAction::LimitKind { kind, .. } => kind,
+        _ => return Ok(()),
+    };
     bot.answer_callback_query(&q.id).await?;
+    update_draft(bot, q, providers, |draft| {
+        if let Some(limit) = draft.limit.as_mut() {
+            limit.set_kind(kind)
+        }
+    })
+    .await
+}
 
-    // Updates the GLOBAL_BUY_MENU_STORAGE
-    if let Some(msg) = &q.message {
-        let msg = Arc::new(msg);
-        let _user_name = msg
-            .clone()
-            .from()
-            .and_then(|user| user.username.as_ref())
-            .and_then(|user_name| {
-                let message = TgMessage {
-                    chat_id: msg.chat.id,
-                    message_id: msg.id,
-                    message: (*msg).clone().into(),
-                };
-                GLOBAL_BUY_MENU_STORAGE.insert(user_name.to_string(), message);
-                Some(user_name)
-            });
-    }
+/// Switches the unit the trigger of the limit draft is entered in
+pub(crate) async fn handle_trigger_unit_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    data: &CallbackData,
+) -> Result<(), TgError> {
+    let unit = match data.action {
+        CallbackAction::TriggerUnit { unit, .. } => unit,
+        _ => return Ok(()),
+    };
+    bot.answer_callback_query(&q.id).await?;
+    update_draft(bot, q, providers, |draft| {
+        if let Some(limit) = draft.limit.as_mut() {
+            limit.set_unit(unit)
+        }
+    })
+    .await
+}
 
-    if let Some(Message { chat, .. }) = &q.message {
-        storage.clone().update_dialogue(chat.id, state).await?;
-        bot.send_message(chat.id, "Enter the amount you want to trade")
-            .await?;
-        storage
-            .update_dialogue(chat.id, PromptDialogueState::BuyAmountReceived)
-            .await?;
-    }
-    Ok(())
+/// Prompts for the trigger or the expiry of the limit draft
+pub(crate) async fn handle_limit_field_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    data: &CallbackData,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    let field = match LimitField::from_action(&data.action) {
+        Some(field) => field,
+        None => return Ok(()),
+    };
+    let draft = match DraftKey::from_callback(q) {
+        Some(key) => GLOBAL_ORDER_DRAFT_STORAGE.get(&key).await,
+        None => None,
+    };
+    let draft = match draft {
+        Some(draft) => draft,
+        None => return Ok(()),
+    };
+    let (side, limit) = (draft.side, draft.limit.unwrap_or_default());
+    let menu = match side {
+        OrderSide::Buy => MenuKind::Buy,
+        OrderSide::Sell => MenuKind::Sell,
+    };
+    prompt_dialogue_input(
+        bot,
+        q,
+        storage,
+        menu,
+        PromptDialogueState::LimitFieldReceived(field),
+        PromptDialogueState::LimitFieldReceived(field),
+        &field.prompt(side, &limit),
+    )
+    .await
 }
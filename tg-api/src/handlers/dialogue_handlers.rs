@@ -1,25 +1,33 @@
 use crate::bot::TgError;
-use crate::consts::{BOT_NAME, BUY_TOKEN, RECEIVE_TOKEN};
-use crate::handlers::delete_up_to_messages;
-use crate::handlers::find_keyboard_from_message;
-use crate::requests::on_chain;
-use crate::storages::{TgMessageStorage, GLOBAL_BUY_MENU_STORAGE};
+use crate::handlers::limit_order_handlers::LimitField;
+use crate::handlers::settings_handlers::{parse_slippage, SettingField};
+use crate::handlers::{delete_up_to_messages, edit_draft_menu};
+use crate::keyboards::callback_data::MenuType;
+use crate::keyboards::token_buttons::token_choice_keyboard;
+use crate::requests::on_chain::token_metadata;
+use crate::requests::providers::ProviderRegistry;
+use crate::storages::{
+    DraftKey, MenuKind, OrderDraft, OrderSide, TokenEntry, GLOBAL_MENU_STORE,
+    GLOBAL_ORDER_DRAFT_STORAGE, GLOBAL_TOKEN_REGISTRY,
+};
 use ethers::types::Address;
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
+use std::sync::Arc;
 use teloxide::{
-    dispatching::dialogue::{Dialogue, InMemStorage},
-    payloads::EditMessageTextSetters,
+    dispatching::dialogue::{Dialogue, ErasedStorage},
+    payloads::SendMessageSetters,
     requests::Requester,
-    types::{InlineKeyboardButtonKind, Message, ParseMode},
+    types::Message,
     Bot,
 };
 
 pub(crate) type BuyAddressPromptDialogue =
-    Dialogue<PromptDialogueState, InMemStorage<PromptDialogueState>>;
+    Dialogue<PromptDialogueState, ErasedStorage<PromptDialogueState>>;
 
 /// Dialogue state
 #[allow(dead_code)]
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub(crate) enum PromptDialogueState {
     #[default]
     /// Represents state when the buy menu buy token button clicked
@@ -38,6 +46,38 @@ pub(crate) enum PromptDialogueState {
     StartBuyAmountPrompt,
     /// Represents state when the buy amount is received
     BuyAmountReceived,
+    /// Represents state when the sell menu sell token button clicked
+    SellStartAddressPrompt,
+    /// Represents state when the sell menu sell token address is received
+    SellAddressReceived,
+    /// Represents state when the sell menu sell token name is received
+    SellTokenNameReceived,
+    /// Represents state when the sell menu receive token button clicked
+    SellReceiveStartAddressPrompt,
+    /// Represents state when the sell menu receive token address is received
+    SellReceiveAddressReceived,
+    /// Represents state when the sell menu receive token name is received
+    SellReceiveTokenNameReceived,
+    /// Represents state when the sell amount button is clicked
+    StartSellAmountPrompt,
+    /// Represents state when the sell amount is received
+    SellAmountReceived,
+    /// Represents state when the sell percentage button is clicked
+    StartSellPercentagePrompt,
+    /// Represents state when the percentage of the balance to sell is received
+    SellPercentageReceived,
+    /// Represents state when the new name of the wallet is awaited
+    WalletRenameReceived(Address),
+    /// Represents state when the private key of the wallet to import is awaited
+    WalletImportReceived,
+    /// Represents state when a setting of the settings menu is awaited
+    SettingReceived(SettingField),
+    /// Represents state when the slippage of the buy or sell draft is awaited
+    SlippageReceived(OrderSide),
+    /// Represents state when the trigger or the expiry of the limit draft is awaited
+    LimitFieldReceived(LimitField),
+    /// Represents state when the new trigger or expiry of an open order is awaited
+    OrderFieldReceived { id: u64, field: LimitField },
 }
 
 pub(crate) async fn buy_address_dialogue_handler(
@@ -58,82 +98,230 @@ pub(crate) async fn buy_address_dialogue_handler(
     Ok(())
 }
 
-pub(crate) async fn buy_address_or_token_handler(
+/// Helper function to apply `f` to the user's draft and re-render the stored buy or sell menu,
+/// then clean up the prompt messages and exit the dialogue
+async fn edit_stored_menu(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    dialogue: &BuyAddressPromptDialogue,
+    msg: &Message,
+    menu: MenuKind,
+    f: impl FnOnce(&mut OrderDraft) + Send,
+) -> Result<(), TgError> {
+    let key = match DraftKey::from_message(msg) {
+        Some(key) => key,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+
+    match (
+        GLOBAL_MENU_STORE.get(menu, &key).await?,
+        GLOBAL_ORDER_DRAFT_STORAGE.update(&key, f).await,
+    ) {
+        (Some(menu), Some(draft)) => {
+            edit_draft_menu(bot, providers, key, menu.message_id, &draft).await?;
+            dialogue.exit().await?;
+
+            delete_up_to_messages(bot, msg.chat.id.0, msg.id.0, menu.message_id.0).await?;
+        }
+        _ => log::warn!("message not found"),
+    }
+    Ok(())
+}
+
+/// Token field of the draft a token prompt fills
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum TokenField {
+    Buy,
+    BuyReceive,
+    Sell,
+    SellReceive,
+}
+
+impl TokenField {
+    /// The field a dialogue state awaits, entered by address or by name
+    pub(crate) fn from_state(state: &PromptDialogueState) -> Option<Self> {
+        match state {
+            PromptDialogueState::BuyAddressReceived | PromptDialogueState::BuyTokenNameReceived => {
+                Some(Self::Buy)
+            }
+            PromptDialogueState::ReceiveAddressReceived
+            | PromptDialogueState::ReceiveTokenNameReceived => Some(Self::BuyReceive),
+            PromptDialogueState::SellAddressReceived
+            | PromptDialogueState::SellTokenNameReceived => Some(Self::Sell),
+            PromptDialogueState::SellReceiveAddressReceived
+            | PromptDialogueState::SellReceiveTokenNameReceived => Some(Self::SellReceive),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn menu(self) -> MenuKind {
+        match self {
+            Self::Buy | Self::BuyReceive => MenuKind::Buy,
+            Self::Sell | Self::SellReceive => MenuKind::Sell,
+        }
+    }
+
+    fn menu_type(self) -> MenuType {
+        match self {
+            Self::Buy | Self::BuyReceive => MenuType::Buy,
+            Self::Sell | Self::SellReceive => MenuType::Sell,
+        }
+    }
+
+    /// The state awaiting the choice among several tokens matching the name
+    fn name_state(self) -> PromptDialogueState {
+        match self {
+            Self::Buy => PromptDialogueState::BuyTokenNameReceived,
+            Self::BuyReceive => PromptDialogueState::ReceiveTokenNameReceived,
+            Self::Sell => PromptDialogueState::SellTokenNameReceived,
+            Self::SellReceive => PromptDialogueState::SellReceiveTokenNameReceived,
+        }
+    }
+
+    pub(crate) fn set(self, draft: &mut OrderDraft, address: Address) {
+        match self {
+            Self::Buy => draft.buy_token = Some(address),
+            Self::Sell => draft.sell_token = Some(address),
+            Self::BuyReceive | Self::SellReceive => draft.receive_token = Some(address),
+        }
+    }
+}
+
+/// Fills the token field awaited by the dialogue, the token is entered by address, or by name or
+/// symbol in which case the user picks among the matching tokens when there are several
+pub(crate) async fn address_or_token_handler(
     bot: Bot,
     dialogue: BuyAddressPromptDialogue,
     msg: Message,
+    providers: Arc<ProviderRegistry>,
 ) -> Result<(), TgError> {
     let text = match msg.text() {
-        Some(t) => t,
+        Some(t) => t.trim(),
         _ => {
             bot.send_message(msg.chat.id, "Send me plain text.").await?;
             return Ok(());
         }
     };
+    let field = match dialogue
+        .get()
+        .await?
+        .as_ref()
+        .and_then(TokenField::from_state)
+    {
+        Some(field) => field,
+        None => {
+            log::warn!("No dialogue found");
+            return Ok(());
+        }
+    };
+    let key = match DraftKey::from_message(&msg) {
+        Some(key) => key,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+    // tokens are looked up on the draft's chain
+    let draft = GLOBAL_ORDER_DRAFT_STORAGE
+        .get(&key)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("No order draft found for {}", key))?;
 
-    // Checks if it's valid address
-    if text.starts_with("0x") && Address::from_str(text).is_ok() {
-        let menu_msg = on_chain::get_on_chain_info().await?;
-
-        if let Some(menu) = GLOBAL_BUY_MENU_STORAGE.get(BOT_NAME.to_string()) {
-            let buy_sell_msg = menu.message;
-            let buy_sell_msg_id = menu.message_id;
-            let keyboard = find_keyboard_from_message(&buy_sell_msg)?;
-            let mut new_keyboard = keyboard.clone();
-
-            // Gets the dialogue state
-            match dialogue.get().await? {
-                Some(PromptDialogueState::BuyAddressReceived) => {
-                    let new_button_text = format!("{}", text);
-                    if let Some(button) = new_keyboard
-                        .inline_keyboard
-                        .get_mut(4)
-                        .and_then(|row| row.get_mut(0))
-                    {
-                        button.text = new_button_text.to_string();
-                        button.kind = InlineKeyboardButtonKind::CallbackData(BUY_TOKEN.to_string());
-                    };
-                }
-                Some(PromptDialogueState::ReceiveAddressReceived) => {
-                    let new_button_text = format!("{}", text);
-                    if let Some(button) = new_keyboard
-                        .inline_keyboard
-                        .get_mut(4)
-                        .and_then(|row| row.get_mut(1))
-                    {
-                        button.text = new_button_text.to_string();
-                        button.kind =
-                            InlineKeyboardButtonKind::CallbackData(RECEIVE_TOKEN.to_string());
-                    };
-                }
-                _ => {
-                    log::warn!("No dialogue found")
-                }
+    // Checks if it's valid address of a token
+    if let (true, Ok(address)) = (text.starts_with("0x"), Address::from_str(text)) {
+        let metadata = match token_metadata(&providers, draft.chain_id, address).await {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                bot.send_message(
+                    msg.chat.id,
+                    format!("Please enter the address of a token: {}", err),
+                )
+                .await?;
+                return Ok(());
             }
+        };
+        // the token can be found by name next time
+        if let Err(err) = GLOBAL_TOKEN_REGISTRY
+            .add(key.user_id, TokenEntry::from(&metadata))
+            .await
+        {
+            log::warn!("Unable to add the token {:#x}: {}", address, err);
+        }
+        return edit_stored_menu(&bot, &providers, &dialogue, &msg, field.menu(), |draft| {
+            field.set(draft, address)
+        })
+        .await;
+    }
 
-            // Edit the message with the new keyboard
-            bot.edit_message_text(msg.chat.id, buy_sell_msg_id, menu_msg)
-                .parse_mode(ParseMode::MarkdownV2)
-                .reply_markup(new_keyboard)
-                .await?;
-            dialogue.exit().await?;
+    let tokens = GLOBAL_TOKEN_REGISTRY
+        .search(key.user_id, draft.chain_id, text)
+        .await?;
+    match tokens.as_slice() {
+        [] => {
+            bot.send_message(
+                msg.chat.id,
+                format!("No token found matching {}, please enter its address", text),
+            )
+            .await?;
+        }
+        [token] => {
+            let address = token.address;
+            edit_stored_menu(&bot, &providers, &dialogue, &msg, field.menu(), |draft| {
+                field.set(draft, address)
+            })
+            .await?;
+        }
+        _ => {
+            bot.send_message(
+                msg.chat.id,
+                format!("Several tokens match {}, pick one", text),
+            )
+            .reply_markup(token_choice_keyboard(
+                field.menu_type(),
+                draft.session,
+                &tokens,
+            )?)
+            .await?;
+            dialogue.update(field.name_state()).await?;
+        }
+    }
 
-            let _ = delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, buy_sell_msg_id.0).await?;
-        } else {
-            log::warn!("message not found");
+    Ok(())
+}
+
+pub(crate) async fn buy_amount_dialogue_handler(
+    bot: Bot,
+    dialogue: BuyAddressPromptDialogue,
+    msg: Message,
+    providers: Arc<ProviderRegistry>,
+) -> Result<(), TgError> {
+    let text = match msg.text() {
+        Some(t) => t,
+        _ => {
+            bot.send_message(msg.chat.id, "Send me plain text.").await?;
+            return Ok(());
         }
-    } else {
-        bot.send_message(msg.chat.id, "Please enter valid address")
+    };
+
+    // Checks if it's is numeric value
+    match text.parse::<f64>() {
+        Ok(amount) => {
+            edit_stored_menu(&bot, &providers, &dialogue, &msg, MenuKind::Buy, |draft| {
+                draft.amount = Some(amount)
+            })
             .await?;
+        }
+        _ => {
+            bot.send_message(msg.chat.id, "Please enter numeric value")
+                .await?;
+        }
     };
 
     Ok(())
 }
 
-pub(crate) async fn buy_amount_dialogue_handler(
+pub(crate) async fn sell_amount_dialogue_handler(
     bot: Bot,
     dialogue: BuyAddressPromptDialogue,
     msg: Message,
+    providers: Arc<ProviderRegistry>,
 ) -> Result<(), TgError> {
     let text = match msg.text() {
         Some(t) => t,
@@ -142,43 +330,134 @@ pub(crate) async fn buy_amount_dialogue_handler(
             return Ok(());
         }
     };
-    let is_numeric =
-        |value: &str| -> bool { value.parse::<f64>().is_ok() || value.parse::<i64>().is_ok() };
 
     // Checks if it's is numeric value
-    if is_numeric(text) {
-        let menu_msg = on_chain::get_on_chain_info().await?;
-
-        if let Some(menu) = GLOBAL_BUY_MENU_STORAGE.get(BOT_NAME.to_string()) {
-            let buy_sell_msg = menu.message;
-            let buy_sell_msg_id = menu.message_id;
-            let keyboard = find_keyboard_from_message(&buy_sell_msg)?;
-            let mut new_keyboard = keyboard.clone();
-
-            // Gets the dialogue state
-            let new_button_text = format!("{}", text);
-            if let Some(button) = new_keyboard
-                .inline_keyboard
-                .get_mut(5)
-                .and_then(|row| row.get_mut(0))
-            {
-                button.text = new_button_text.to_string();
-                button.kind = InlineKeyboardButtonKind::CallbackData(BUY_TOKEN.to_string());
-            };
-            // Edit the message with the new keyboard
-            bot.edit_message_text(msg.chat.id, buy_sell_msg_id, menu_msg)
-                .parse_mode(ParseMode::MarkdownV2)
-                .reply_markup(new_keyboard)
+    // The exact amount and the percentage are mutually exclusive
+    match text.parse::<f64>() {
+        Ok(amount) => {
+            edit_stored_menu(&bot, &providers, &dialogue, &msg, MenuKind::Sell, |draft| {
+                draft.amount = Some(amount);
+                draft.sell_percentage = None;
+            })
+            .await?;
+        }
+        _ => {
+            bot.send_message(msg.chat.id, "Please enter numeric value")
                 .await?;
-            dialogue.exit().await?;
+        }
+    };
+
+    Ok(())
+}
+
+pub(crate) async fn sell_percentage_dialogue_handler(
+    bot: Bot,
+    dialogue: BuyAddressPromptDialogue,
+    msg: Message,
+    providers: Arc<ProviderRegistry>,
+) -> Result<(), TgError> {
+    let text = match msg.text() {
+        Some(t) => t.trim().trim_end_matches('%'),
+        _ => {
+            bot.send_message(msg.chat.id, "Send me plain text.").await?;
+            return Ok(());
+        }
+    };
+
+    // Checks if it's a percentage in (0, 100]
+    match text.parse::<f64>() {
+        Ok(sell_percentage) if sell_percentage > 0.0 && sell_percentage <= 100.0 => {
+            edit_stored_menu(&bot, &providers, &dialogue, &msg, MenuKind::Sell, |draft| {
+                draft.amount = None;
+                draft.sell_percentage = Some(sell_percentage);
+            })
+            .await?;
+        }
+        _ => {
+            bot.send_message(msg.chat.id, "Please enter a percentage between 0 and 100")
+                .await?;
+        }
+    };
+
+    Ok(())
+}
+
+/// Sets the slippage of the buy or sell draft, its menu is re-rendered
+pub(crate) async fn slippage_dialogue_handler(
+    bot: Bot,
+    dialogue: BuyAddressPromptDialogue,
+    side: OrderSide,
+    msg: Message,
+    providers: Arc<ProviderRegistry>,
+) -> Result<(), TgError> {
+    let text = match msg.text() {
+        Some(t) => t,
+        _ => {
+            bot.send_message(msg.chat.id, "Send me plain text.").await?;
+            return Ok(());
+        }
+    };
+
+    match parse_slippage(text) {
+        Ok(slippage_bps) => {
+            let menu = match side {
+                OrderSide::Buy => MenuKind::Buy,
+                OrderSide::Sell => MenuKind::Sell,
+            };
+            edit_stored_menu(&bot, &providers, &dialogue, &msg, menu, |draft| {
+                draft.swap.slippage_bps = Some(slippage_bps)
+            })
+            .await?;
+        }
+        Err(err) => {
+            bot.send_message(msg.chat.id, err).await?;
+        }
+    };
+
+    Ok(())
+}
 
-            let _ = delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, buy_sell_msg_id.0).await?;
-        } else {
-            log::warn!("message not found");
+/// Sets the trigger or the expiry of the limit draft, its menu is re-rendered
+pub(crate) async fn limit_dialogue_handler(
+    bot: Bot,
+    dialogue: BuyAddressPromptDialogue,
+    field: LimitField,
+    msg: Message,
+    providers: Arc<ProviderRegistry>,
+) -> Result<(), TgError> {
+    let text = match msg.text() {
+        Some(t) => t,
+        _ => {
+            bot.send_message(msg.chat.id, "Send me plain text.").await?;
+            return Ok(());
         }
-    } else {
-        bot.send_message(msg.chat.id, "Please enter numeric value")
+    };
+
+    let key = match DraftKey::from_message(&msg) {
+        Some(key) => key,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+    let draft = GLOBAL_ORDER_DRAFT_STORAGE
+        .get(&key)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("No order draft found for {}", key))?;
+    let mut limit = draft
+        .limit
+        .ok_or_else(|| anyhow::anyhow!("No limit draft found for {}", key))?;
+    let menu = match draft.side {
+        OrderSide::Buy => MenuKind::Buy,
+        OrderSide::Sell => MenuKind::Sell,
+    };
+    match field.apply(&mut limit, text) {
+        Ok(()) => {
+            edit_stored_menu(&bot, &providers, &dialogue, &msg, menu, |draft| {
+                draft.limit = Some(limit)
+            })
             .await?;
+        }
+        Err(err) => {
+            bot.send_message(msg.chat.id, err).await?;
+        }
     };
 
     Ok(())
@@ -0,0 +1,240 @@
+use crate::consts::ESTIMATE_UNAVAILABLE;
+use crate::handlers::draft_menu;
+use crate::keyboards::callback_data::decode_keyboard;
+use crate::requests::providers::ProviderRegistry;
+use crate::requests::swap::quote_swap;
+use crate::storages::{
+    DraftKey, MenuKind, OrderDraft, OrderSide, GLOBAL_CHAIN_REGISTRY, GLOBAL_MENU_STORE,
+    GLOBAL_ORDER_DRAFT_STORAGE, GLOBAL_SWAP_CONFIG,
+};
+use ethers::providers::Middleware;
+use ethers::types::Address;
+use futures::{stream, StreamExt};
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use teloxide::{
+    payloads::EditMessageTextSetters,
+    prelude::Requester,
+    types::{MessageId, ParseMode},
+    ApiError, Bot, RequestError,
+};
+use tokio::time::{sleep, Duration};
+
+/// How long to wait before watching the new blocks again once the watcher failed
+const WATCH_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Buy menus quoted and edited at once on every new block
+const MAX_CONCURRENT_REFRESHES: usize = 8;
+
+/// The tokens and amount of a buy draft, once they are all set
+fn quotable(draft: &OrderDraft) -> Option<(Address, Address, f64)> {
+    match draft.side {
+        // the buy amount is paid in the receive token
+        OrderSide::Buy => Some((draft.receive_token?, draft.buy_token?, draft.amount?)),
+        OrderSide::Sell => None,
+    }
+}
+
+/// Rounds an amount to a readable number of digits
+pub(crate) fn format_amount(amount: f64) -> String {
+    let digits = if amount >= 1.0 { 4 } else { 8 };
+    let amount = format!("{:.*}", digits, amount);
+    amount
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Quotes the buy draft, rendered into its estimated received amount row, None until the tokens
+/// and the amount are set
+pub(crate) async fn estimate_received(
+    providers: &ProviderRegistry,
+    draft: &OrderDraft,
+) -> Option<String> {
+    let (token_in, token_out, amount) = quotable(draft)?;
+    let quote = async {
+        let query = providers.query(draft.chain_id)?;
+        quote_swap(
+            Arc::new(query.provider()),
+            query.chain(),
+            &draft.swap.apply(&GLOBAL_SWAP_CONFIG),
+            token_in,
+            token_out,
+            amount,
+        )
+        .await
+    }
+    .await;
+    match quote {
+        // limit orders compare the price to their trigger
+        Ok(quote) if draft.limit.is_some() => Some(format!(
+            "≈ {} | Price {} | Impact {:.2}%",
+            format_amount(quote.amount_out),
+            format_amount(amount / quote.amount_out),
+            quote.price_impact
+        )),
+        Ok(quote) => Some(format!(
+            "≈ {} | Impact {:.2}% | Min {}",
+            format_amount(quote.amount_out),
+            quote.price_impact,
+            format_amount(quote.min_received)
+        )),
+        Err(err) => {
+            log::warn!(
+                "Unable to quote {} of {:#x} for {:#x}: {}",
+                amount,
+                token_in,
+                token_out,
+                err
+            );
+            Some(ESTIMATE_UNAVAILABLE.to_string())
+        }
+    }
+}
+
+/// Text last rendered into the buy menu of each draft, an edit to the same text is skipped
+type RenderedMenus = Mutex<HashMap<DraftKey, (MessageId, String)>>;
+
+/// Outcome of refreshing a single buy menu
+enum Refresh {
+    Done,
+    /// Telegram asked to wait before the next edit
+    Throttled(Duration),
+}
+
+/// Re-renders the buy menu of the draft unless its text didn't change, a menu that no longer
+/// exists or can't be edited anymore is forgotten
+async fn refresh_estimate(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    rendered: &RenderedMenus,
+    key: DraftKey,
+    draft: OrderDraft,
+) -> Refresh {
+    let menu = match GLOBAL_MENU_STORE.get(MenuKind::Buy, &key).await {
+        Ok(Some(menu)) => menu,
+        Ok(None) => return Refresh::Done,
+        Err(err) => {
+            log::warn!("Unable to get the buy menu of {}: {}", key, err);
+            return Refresh::Done;
+        }
+    };
+    // the menus of replaced drafts are stale
+    let session = menu
+        .message
+        .reply_markup()
+        .and_then(|keyboard| decode_keyboard(keyboard).first().map(|data| data.session));
+    if session != Some(draft.session) {
+        return Refresh::Done;
+    }
+    let (text, keyboard) = match draft_menu(providers, key.user_id, &draft).await {
+        Ok(menu) => menu,
+        Err(err) => {
+            log::warn!("Unable to render the buy menu of {}: {}", key, err);
+            return Refresh::Done;
+        }
+    };
+    let unchanged = rendered
+        .lock()
+        .get(&key)
+        .is_some_and(|(message_id, last)| *message_id == menu.message_id && *last == text);
+    if unchanged {
+        return Refresh::Done;
+    }
+    let edit = bot
+        .edit_message_text(menu.chat_id, menu.message_id, text.clone())
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(keyboard)
+        .await;
+    match edit {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {
+            rendered.lock().insert(key, (menu.message_id, text));
+        }
+        Err(RequestError::RetryAfter(delay)) => return Refresh::Throttled(delay),
+        Err(
+            err @ RequestError::Api(
+                ApiError::MessageToEditNotFound
+                | ApiError::MessageCantBeEdited
+                | ApiError::MessageIdInvalid,
+            ),
+        ) => {
+            // deleted or too old, it's not refreshed again
+            log::warn!("Unable to refresh the buy menu of {}: {}", key, err);
+            rendered.lock().remove(&key);
+            let _ = GLOBAL_MENU_STORE.remove(MenuKind::Buy, &key).await;
+        }
+        // the menu is refreshed again on the next block
+        Err(err) => log::warn!("Unable to refresh the buy menu of {}: {}", key, err),
+    }
+    Refresh::Done
+}
+
+/// Re-renders the open buy menus with a complete draft on the chain, so their quotes follow it.
+/// Returns how long Telegram asked to wait when it throttled the edits
+async fn refresh_estimates(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    rendered: &RenderedMenus,
+    chain_id: u64,
+) -> Option<Duration> {
+    let drafts = GLOBAL_ORDER_DRAFT_STORAGE.entries();
+    rendered
+        .lock()
+        .retain(|key, _| drafts.iter().any(|(draft_key, _)| draft_key == key));
+    let throttled = Mutex::new(None);
+    stream::iter(drafts)
+        .filter(|(_, draft)| {
+            futures::future::ready(draft.chain_id == chain_id && quotable(draft).is_some())
+        })
+        .for_each_concurrent(MAX_CONCURRENT_REFRESHES, |(key, draft)| {
+            let throttled = &throttled;
+            async move {
+                // the remaining menus wait for the next block
+                if throttled.lock().is_some() {
+                    return;
+                }
+                if let Refresh::Throttled(delay) =
+                    refresh_estimate(bot, providers, rendered, key, draft).await
+                {
+                    *throttled.lock() = Some(delay);
+                }
+            }
+        })
+        .await;
+    throttled.into_inner()
+}
+
+async fn watch_new_blocks(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    rendered: &RenderedMenus,
+    chain_id: u64,
+) -> anyhow::Result<()> {
+    let provider = providers.query(chain_id)?.provider();
+    let mut blocks = provider.watch_blocks().await?;
+    while blocks.next().await.is_some() {
+        if let Some(delay) = refresh_estimates(bot, providers, rendered, chain_id).await {
+            log::warn!("Telegram throttled the buy menu refresh for {:?}", delay);
+            sleep(delay).await;
+        }
+    }
+    Ok(())
+}
+
+/// Refreshes the quotes of the open buy menus on every new block of their chain
+pub(crate) fn spawn_estimate_refresh(bot: Bot, providers: Arc<ProviderRegistry>) {
+    for chain in GLOBAL_CHAIN_REGISTRY.chains() {
+        let (bot, providers) = (bot.clone(), providers.clone());
+        let (chain_id, name) = (chain.chain_id, chain.name.clone());
+        tokio::spawn(async move {
+            let rendered = RenderedMenus::default();
+            loop {
+                if let Err(err) = watch_new_blocks(&bot, &providers, &rendered, chain_id).await {
+                    log::warn!("Unable to watch the new blocks of {}: {}", name, err);
+                }
+                sleep(WATCH_RETRY_DELAY).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,181 @@
+use crate::bot::TgError;
+use crate::handlers::{format_eth, native_symbol};
+use crate::keyboards::callback_data::{new_session_id, CallbackAction, CallbackData};
+use crate::keyboards::history_buttons::{history_keyboard, trade_detail_keyboard};
+use crate::requests::on_chain::{chain_name, explorer_tx_url};
+use crate::storages::{OrderSide, TradeRecord, GLOBAL_TRADE_JOURNAL};
+use chrono::{TimeZone, Utc};
+use ethers::types::U256;
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    prelude::Requester,
+    types::{CallbackQuery, InlineKeyboardMarkup, Message, UserId},
+    Bot,
+};
+
+/// Number of trades per page of the /history listing
+const HISTORY_PAGE_SIZE: usize = 5;
+
+pub(crate) fn format_date(timestamp: i64) -> String {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|date| date.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+fn format_side(side: OrderSide) -> &'static str {
+    match side {
+        OrderSide::Buy => "Buy",
+        OrderSide::Sell => "Sell",
+    }
+}
+
+/// What the user asked to pay, an amount or a percentage of the balance
+fn format_requested(trade: &TradeRecord) -> String {
+    match (trade.amount, trade.sell_percentage) {
+        (Some(amount), _) => format!("{} of {:#x}", amount, trade.token_in),
+        (_, Some(percentage)) => format!("{}% of {:#x}", percentage, trade.token_in),
+        _ => format!("{:#x}", trade.token_in),
+    }
+}
+
+/// Renders a page of the listing along with its keyboard, `page` is clamped to the last page
+async fn history_page(
+    user_id: UserId,
+    session: u32,
+    page: u32,
+) -> anyhow::Result<(String, InlineKeyboardMarkup)> {
+    let count = GLOBAL_TRADE_JOURNAL.count(user_id).await?;
+    let pages = count.div_ceil(HISTORY_PAGE_SIZE).max(1) as u32;
+    let page = page.min(pages - 1);
+    let trades = GLOBAL_TRADE_JOURNAL
+        .page(
+            user_id,
+            page as usize * HISTORY_PAGE_SIZE,
+            HISTORY_PAGE_SIZE,
+        )
+        .await?;
+
+    if trades.is_empty() {
+        return Ok((
+            "No trades yet".to_string(),
+            history_keyboard(session, &[], 0, 1)?,
+        ));
+    }
+
+    let mut text = format!("Trade History ({}/{})\n", page + 1, pages);
+    for trade in trades.iter() {
+        text.push_str(&format!(
+            "\n#{} {} | {} | {}\nPay: {}\nReceive: {:#x}\n",
+            trade.id,
+            format_side(trade.side),
+            trade.status,
+            format_date(trade.created_at),
+            format_requested(trade),
+            trade.token_out
+        ));
+    }
+    Ok((text, history_keyboard(session, &trades, page, pages)?))
+}
+
+/// Whether the trade was sent for rebates, with its expected and received rebates
+fn rebate_detail(trade: &TradeRecord, native_symbol: &str) -> String {
+    if !trade.rebate {
+        return "no".to_string();
+    }
+    let format = |rebate: Option<U256>| {
+        rebate
+            .map(|rebate| format!("{} {}", format_eth(rebate), native_symbol))
+            .unwrap_or_else(|| "-".to_string())
+    };
+    format!(
+        "yes (expected ~{}, received {})",
+        format(trade.expected_rebate),
+        format(trade.rebate_received)
+    )
+}
+
+fn trade_detail(trade: &TradeRecord) -> String {
+    let or_pending = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let yes_no = |on: bool| if on { "yes" } else { "no" };
+
+    let native_symbol = native_symbol(trade.chain_id);
+
+    let mut text = format!(
+        "Trade #{}\nChain: {}\nSide: {}\nStatus: {}\nDate: {}\nWallet: {:#x}\nPay: {}\nReceive: {:#x}\nPrivate Tx: {}\nRebate: {}\nPaid: {}\nReceived: {}\nGas Paid: {}",
+        trade.id,
+        chain_name(trade.chain_id),
+        format_side(trade.side),
+        trade.status,
+        format_date(trade.created_at),
+        trade.wallet,
+        format_requested(trade),
+        trade.token_out,
+        yes_no(trade.private_tx),
+        rebate_detail(trade, native_symbol),
+        or_pending(trade.filled_in.map(|amount| amount.to_string())),
+        or_pending(trade.filled_out.map(|amount| amount.to_string())),
+        or_pending(trade.gas_paid.map(|gas| format!("{} {}", format_eth(gas), native_symbol))),
+    );
+    if let Some(tx_hash) = trade.tx_hash {
+        match explorer_tx_url(trade.chain_id, tx_hash) {
+            Some(url) => text.push_str(&format!("\nTx: {}", url)),
+            None => text.push_str(&format!("\nTx: {:#x}", tx_hash)),
+        }
+    }
+    text
+}
+
+/// Shows the first page of the user's trades
+pub(crate) async fn history_command(bot: &Bot, msg: &Message) -> Result<(), TgError> {
+    let user_id = match msg.from() {
+        Some(user) => user.id,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+    let (text, keyboard) = history_page(user_id, new_session_id(), 0).await?;
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+/// Handles the page navigation and the detail views of the /history listing
+pub(crate) async fn handle_history_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    data: &CallbackData,
+) -> Result<(), TgError> {
+    let msg = match &q.message {
+        Some(msg) => msg,
+        None => return Err(TgError::NoQueryMessage(Box::new(q.clone()))),
+    };
+    let user_id = q.from.id;
+
+    let (text, keyboard) = match data.action {
+        CallbackAction::PreviousHistoryPage(page)
+        | CallbackAction::NextHistoryPage(page)
+        | CallbackAction::BackToHistory(page) => history_page(user_id, data.session, page).await?,
+        CallbackAction::TradeDetail { id, page } => {
+            match GLOBAL_TRADE_JOURNAL.get(user_id, id).await? {
+                Some(trade) => (
+                    trade_detail(&trade),
+                    trade_detail_keyboard(data.session, page)?,
+                ),
+                None => {
+                    bot.answer_callback_query(&q.id)
+                        .text(format!("Trade #{} not found", id))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        _ => return Ok(()),
+    };
+
+    bot.answer_callback_query(&q.id).await?;
+    bot.edit_message_text(msg.chat.id, msg.id, text)
+        .reply_markup(keyboard)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
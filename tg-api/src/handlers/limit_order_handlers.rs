@@ -0,0 +1,692 @@
+use crate::bot::TgError;
+use crate::handlers::callback_handlers::send_swap;
+use crate::handlers::estimate_handlers::format_amount;
+use crate::keyboards::callback_data::{format_duration, limit_kind_label, CallbackAction};
+use crate::requests::on_chain::token_metadata;
+use crate::requests::providers::ProviderRegistry;
+use crate::requests::server::{SendBuyTxRequest, SendSellTxRequest};
+use crate::requests::swap::{quote_amount_out, token_balance};
+use crate::storages::{
+    DraftKey, LimitKind, LimitOrder, LimitOrderStatus, LimitTrigger, OrderSide, TriggerMarket,
+    TriggerUnit, GLOBAL_CHAIN_REGISTRY, GLOBAL_ORDER_BOOK, GLOBAL_ORDER_DRAFT_STORAGE,
+    GLOBAL_TRADE_JOURNAL, GLOBAL_WALLET_STORE, MAX_EXPIRY_SECS,
+};
+use ethers::providers::Middleware;
+use ethers::types::Address;
+use ethers::utils::format_units;
+use futures::{stream, StreamExt};
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use teloxide::{
+    payloads::AnswerCallbackQuerySetters,
+    prelude::Requester,
+    types::{CallbackQuery, Message},
+    Bot,
+};
+use tokio::time::{sleep, Duration};
+
+/// How long to wait before watching the new blocks again once the watcher failed
+const WATCH_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Orders checked, and quotes fetched, at once on every new block
+const MAX_CONCURRENT_CHECKS: usize = 8;
+
+/// Entering this as the expiry keeps the order open until it triggers
+const NO_EXPIRY: &str = "none";
+
+/// Field of a limit draft a prompt awaits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum LimitField {
+    Trigger,
+    Expiry,
+}
+
+impl LimitField {
+    pub(crate) fn from_action(action: &CallbackAction) -> Option<Self> {
+        match action {
+            CallbackAction::Trigger { .. } => Some(Self::Trigger),
+            CallbackAction::Expiry(_) => Some(Self::Expiry),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn prompt(self, side: OrderSide, limit: &LimitTrigger) -> String {
+        let token = match side {
+            OrderSide::Buy => "buy",
+            OrderSide::Sell => "sell",
+        };
+        match (self, limit.unit, limit.kind) {
+            (Self::Expiry, _, _) => format!(
+                "Enter how long the order stays open, like 30m, 12h or 7d, or \"{}\"",
+                NO_EXPIRY
+            ),
+            (Self::Trigger, TriggerUnit::Price, _) => format!(
+                "Enter the trigger price, in the receive token per {} token",
+                token
+            ),
+            (Self::Trigger, TriggerUnit::MarketCap, _) => format!(
+                "Enter the market cap of the {} token to trigger at, in the receive token",
+                token
+            ),
+            (Self::Trigger, TriggerUnit::TrailPercent, _) => {
+                "Enter how far below its highest price to sell, in percent".to_string()
+            }
+            (Self::Trigger, TriggerUnit::EntryPercent, _) if side == OrderSide::Buy => {
+                "Enter how far below the current price to buy, in percent".to_string()
+            }
+            (Self::Trigger, TriggerUnit::EntryPercent, LimitKind::Limit) => {
+                "Enter the gain from the entry price to sell at, in percent".to_string()
+            }
+            (Self::Trigger, TriggerUnit::EntryPercent, _) => {
+                "Enter the loss from the entry price to sell at, in percent".to_string()
+            }
+        }
+    }
+
+    /// Parses and validates the entered value into the trigger
+    pub(crate) fn apply(self, limit: &mut LimitTrigger, text: &str) -> Result<(), String> {
+        match self {
+            Self::Trigger => limit.value = Some(parse_trigger(limit.unit, text)?),
+            Self::Expiry => limit.expiry_secs = parse_expiry(text)?,
+        }
+        Ok(())
+    }
+}
+
+/// Parses a positive trigger value, a trail percentage must be below 100
+fn parse_trigger(unit: TriggerUnit, text: &str) -> Result<f64, String> {
+    match text.trim().trim_end_matches('%').parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => match unit {
+            TriggerUnit::TrailPercent if value >= 100.0 => {
+                Err("Please enter a percentage between 0 and 100".to_string())
+            }
+            _ => Ok(value),
+        },
+        _ => Err("Please enter a value above 0".to_string()),
+    }
+}
+
+/// Parses an expiry like 30m, 12h or 7d into seconds, "none" for no expiry. The expiry can't
+/// exceed [MAX_EXPIRY_SECS]
+fn parse_expiry(text: &str) -> Result<Option<u64>, String> {
+    let text = text.trim().to_lowercase();
+    if text == NO_EXPIRY {
+        return Ok(None);
+    }
+    let invalid = || "Please enter a duration like 30m, 12h or 7d".to_string();
+    // the unit is the last char, which may not be a single byte
+    let split = text.char_indices().last().map(|(index, _)| index);
+    let (number, unit) = text.split_at(split.ok_or_else(invalid)?);
+    let unit_secs: u64 = match unit {
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        _ => return Err(invalid()),
+    };
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 => match number.checked_mul(unit_secs) {
+            Some(secs) if secs <= MAX_EXPIRY_SECS => Ok(Some(secs)),
+            _ => Err(format!(
+                "Please enter an expiry of at most {}",
+                format_duration(MAX_EXPIRY_SECS)
+            )),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// Name of the order's kind and side, like Limit Sell or Stop Loss
+pub(crate) fn order_name(order: &LimitOrder) -> String {
+    match (order.kind, order.side) {
+        (LimitKind::Limit, OrderSide::Buy) => "Limit Buy".to_string(),
+        (LimitKind::Limit, OrderSide::Sell) => "Limit Sell".to_string(),
+        (kind, _) => limit_kind_label(kind).to_string(),
+    }
+}
+
+/// Describes the trigger of the order along with the price it resolved to
+pub(crate) fn trigger_text(order: &LimitOrder) -> String {
+    let direction = match order.triggers_above() {
+        true => "at or above",
+        false => "at or below",
+    };
+    let price = format!("price {} {}", direction, format_amount(order.trigger_price));
+    let entry = order.entry_price.map(format_amount).unwrap_or_default();
+    match order.unit {
+        TriggerUnit::Price => price,
+        TriggerUnit::MarketCap => {
+            format!("market cap {} {} ({})", direction, order.value, price)
+        }
+        TriggerUnit::EntryPercent if order.side == OrderSide::Buy => {
+            format!("{}% below {} ({})", order.value, entry, price)
+        }
+        TriggerUnit::EntryPercent => {
+            let sign = if order.triggers_above() { "+" } else { "-" };
+            format!("{}{}% from entry {} ({})", sign, order.value, entry, price)
+        }
+        TriggerUnit::TrailPercent => format!(
+            "{}% below the peak {} ({})",
+            order.value,
+            order.peak_price.map(format_amount).unwrap_or_default(),
+            price
+        ),
+    }
+}
+
+/// Describes what the order pays
+pub(crate) fn amount_text(order: &LimitOrder) -> String {
+    match (order.amount, order.sell_percentage) {
+        (Some(amount), _) => format!("{} of {:#x}", amount, order.token_in),
+        (_, Some(percentage)) => format!("{}% of the {:#x} balance", percentage, order.token_in),
+        _ => format!("{:#x}", order.token_in),
+    }
+}
+
+/// Amount of `token_in` the order swaps, the share of the wallet's balance for a percentage
+async fn order_amount(providers: &ProviderRegistry, order: &LimitOrder) -> anyhow::Result<f64> {
+    let amount = match (order.amount, order.sell_percentage) {
+        (Some(amount), _) => amount,
+        (_, Some(percentage)) => {
+            let query = providers.query(order.chain_id)?;
+            token_balance(Arc::new(query.provider()), order.token_in, order.wallet).await?
+                * percentage
+                / 100.0
+        }
+        _ => return Err(anyhow::anyhow!("No amount found")),
+    };
+    if amount <= 0.0 {
+        return Err(anyhow::anyhow!("Nothing to sell, the balance is empty"));
+    }
+    Ok(amount)
+}
+
+/// Price of the order's position token, quoted for swapping `amount` of its `token_in`. Runs
+/// for every armed order on every block, so the price impact is left to the swap once the
+/// order fires
+async fn quote_price(
+    providers: &ProviderRegistry,
+    order: &LimitOrder,
+    amount: f64,
+) -> anyhow::Result<f64> {
+    let query = providers.query(order.chain_id)?;
+    let amount_out = quote_amount_out(
+        Arc::new(query.provider()),
+        query.chain(),
+        order.token_in,
+        order.token_out,
+        amount,
+    )
+    .await?;
+    if amount_out <= 0.0 {
+        return Err(anyhow::anyhow!("No liquidity for the order"));
+    }
+    Ok(match order.side {
+        OrderSide::Buy => amount / amount_out,
+        OrderSide::Sell => amount_out / amount,
+    })
+}
+
+/// Current price of the order's position token, quoted for the amount the order swaps
+pub(crate) async fn current_price(
+    providers: &ProviderRegistry,
+    order: &LimitOrder,
+) -> anyhow::Result<f64> {
+    let amount = order_amount(providers, order).await?;
+    quote_price(providers, order, amount).await
+}
+
+/// Average price the wallet paid for the sold token, in the receive token, from the confirmed
+/// buys of the journal
+async fn entry_price(order: &LimitOrder, wallet: Address) -> anyhow::Result<Option<f64>> {
+    let buys = GLOBAL_TRADE_JOURNAL
+        .token_buys(order.user_id, order.token_in)
+        .await?;
+    let (paid, bought) = buys
+        .iter()
+        .filter(|trade| {
+            trade.chain_id == order.chain_id
+                && trade.wallet == wallet
+                && trade.token_in == order.token_out
+        })
+        .filter_map(|trade| Some((trade.filled_in?, trade.filled_out?)))
+        .fold((0.0, 0.0), |(paid, bought), (filled_in, filled_out)| {
+            (paid + filled_in, bought + filled_out)
+        });
+    Ok(Some(paid / bought).filter(|_| bought > 0.0))
+}
+
+/// What the trigger of the order is resolved from, a sell order needs the wallet to hold the
+/// position
+pub(crate) async fn trigger_market(
+    providers: &ProviderRegistry,
+    order: &LimitOrder,
+) -> anyhow::Result<TriggerMarket> {
+    let mut market = TriggerMarket {
+        price: 0.0,
+        entry_price: None,
+        supply: None,
+    };
+    if order.side == OrderSide::Sell {
+        let wallet = GLOBAL_WALLET_STORE
+            .find(order.user_id, order.wallet)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Wallet {:#x} not found", order.wallet))?;
+        let query = providers.query(order.chain_id)?;
+        let held =
+            token_balance(Arc::new(query.provider()), order.token_in, wallet.address).await?;
+        match order.amount {
+            _ if held <= 0.0 => {
+                return Err(anyhow::anyhow!(
+                    "{} holds no {:#x}",
+                    wallet.name,
+                    order.token_in
+                ))
+            }
+            Some(amount) if amount > held => {
+                return Err(anyhow::anyhow!("{} only holds {}", wallet.name, held))
+            }
+            _ => {}
+        }
+        market.entry_price = entry_price(order, wallet.address).await?;
+    }
+    if order.unit == TriggerUnit::MarketCap {
+        let token = token_metadata(providers, order.chain_id, order.position_token()).await?;
+        market.supply = match token.total_supply {
+            Some(supply) => Some(format_units(supply, token.decimals as u32)?.parse()?),
+            None => None,
+        };
+    }
+    market.price = current_price(providers, order).await?;
+    Ok(market)
+}
+
+/// Places the limit order of the user's draft in the order book
+pub(crate) async fn handle_place_limit_order_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    let (key, chat_id) = match (DraftKey::from_callback(q), &q.message) {
+        (Some(key), Some(Message { chat, .. })) => (key, chat.id),
+        _ => return Ok(()),
+    };
+    let draft = GLOBAL_ORDER_DRAFT_STORAGE
+        .get(&key)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("No order draft found for {}", key))?;
+    // wallets are generated on /start
+    let wallet = match draft.wallet {
+        Some(address) => GLOBAL_WALLET_STORE.find(key.user_id, address).await?,
+        None => None,
+    };
+    if wallet.is_none() {
+        bot.answer_callback_query(&q.id)
+            .text("Wallet not found, use /start to create your wallets")
+            .await?;
+        return Ok(());
+    }
+    let mut order = match LimitOrder::from_draft(key, &draft) {
+        Ok(order) => order,
+        Err(err) => {
+            bot.answer_callback_query(&q.id)
+                .text(format!("Unable to place the order: {}", err))
+                .await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&q.id).await?;
+
+    let armed = async {
+        let market = trigger_market(providers, &order).await?;
+        order.arm(market)?;
+        anyhow::Ok(market.price)
+    }
+    .await;
+    let price = match armed {
+        Ok(price) => price,
+        Err(err) => {
+            bot.send_message(chat_id, format!("Unable to place the order: {}", err))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let id = GLOBAL_ORDER_BOOK.place(order.clone()).await?;
+    let expiry = draft
+        .limit
+        .and_then(|limit| limit.expiry_secs)
+        .map(|secs| format!("in {}", format_duration(secs)))
+        .unwrap_or_else(|| "never".to_string());
+    bot.send_message(
+        chat_id,
+        format!(
+            "{} #{} placed\nPay: {}\nReceive: {:#x}\nPrice now: {}\nTrigger: {}\nExpires: {}\n\nIt's checked on every new block",
+            order_name(&order),
+            id,
+            amount_text(&order),
+            order.token_out,
+            format_amount(price),
+            trigger_text(&order),
+            expiry
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Sends the swap of the triggered order and records its outcome in the order book
+async fn execute(bot: &Bot, providers: &ProviderRegistry, order: LimitOrder, price: f64) {
+    let key = order.key();
+    let name = order_name(&order);
+    let summary = format!(
+        "{} #{} triggered at price {}, {}\nPay: {}\nReceive: {:#x}",
+        name,
+        order.id,
+        format_amount(price),
+        trigger_text(&order),
+        amount_text(&order),
+        order.token_out
+    );
+    let sent = match order.side {
+        OrderSide::Buy => {
+            let request = SendBuyTxRequest {
+                chain_id: order.chain_id,
+                wallet: order.wallet,
+                private_tx: order.private_tx,
+                rebate: order.rebate,
+                buy: order.token_out,
+                receive: order.token_in,
+                buy_amount: order.amount.unwrap_or_default(),
+            };
+            send_swap(
+                bot,
+                providers,
+                key,
+                (request.chain_id, request.wallet),
+                (request.swap_order(), order.swap, None),
+                request.trade_record(key.user_id),
+                (&name, summary),
+            )
+            .await
+        }
+        OrderSide::Sell => {
+            let request = SendSellTxRequest {
+                chain_id: order.chain_id,
+                wallet: order.wallet,
+                private_tx: order.private_tx,
+                rebate: order.rebate,
+                sell: order.token_in,
+                receive: order.token_out,
+                sell_amount: order.amount,
+                sell_percentage: order.sell_percentage,
+            };
+            send_swap(
+                bot,
+                providers,
+                key,
+                (request.chain_id, request.wallet),
+                (request.swap_order(), order.swap, None),
+                request.trade_record(key.user_id),
+                (&name, summary),
+            )
+            .await
+        }
+    };
+    let trade_id = match sent {
+        Ok(trade_id) => trade_id,
+        Err(err) => {
+            log::warn!("Unable to execute limit order #{}: {}", order.id, err);
+            None
+        }
+    };
+    let updated = GLOBAL_ORDER_BOOK
+        .update(order.id, move |order| {
+            order.trade_id = trade_id;
+            order.status = match trade_id {
+                Some(_) => LimitOrderStatus::Executed,
+                None => LimitOrderStatus::Failed,
+            };
+        })
+        .await;
+    if let Err(err) = updated {
+        log::warn!("Unable to update limit order #{}: {}", order.id, err);
+    }
+}
+
+/// Expires the order, unless it was cancelled or triggered meanwhile
+async fn expire_order(bot: &Bot, order: LimitOrder) -> anyhow::Result<()> {
+    let order = GLOBAL_ORDER_BOOK
+        .update(order.id, |order| {
+            if order.status == LimitOrderStatus::Open {
+                order.status = LimitOrderStatus::Expired
+            }
+        })
+        .await?;
+    if order.status == LimitOrderStatus::Expired {
+        bot.send_message(
+            order.chat_id,
+            format!(
+                "{} #{} expired without triggering",
+                order_name(&order),
+                order.id
+            ),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Cancels the order whose wallet was deleted, unless it was cancelled or triggered meanwhile
+async fn cancel_orphan(bot: &Bot, order: LimitOrder) -> anyhow::Result<()> {
+    let order = GLOBAL_ORDER_BOOK
+        .update(order.id, |order| {
+            if order.status == LimitOrderStatus::Open {
+                order.status = LimitOrderStatus::Cancelled
+            }
+        })
+        .await?;
+    if order.status == LimitOrderStatus::Cancelled {
+        bot.send_message(
+            order.chat_id,
+            format!(
+                "{} #{} triggered but was cancelled, its wallet {:#x} was deleted",
+                order_name(&order),
+                order.id,
+                order.wallet
+            ),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Checks an open order against its price on the latest block: follows the peak of a trailing
+/// stop and executes the order once the price crosses its trigger
+async fn check_order(
+    bot: &Bot,
+    providers: &Arc<ProviderRegistry>,
+    mut order: LimitOrder,
+    price: f64,
+) -> anyhow::Result<()> {
+    if order.follow(price) {
+        let (peak_price, trigger_price) = (order.peak_price, order.trigger_price);
+        GLOBAL_ORDER_BOOK
+            .update(order.id, move |order| {
+                order.peak_price = peak_price;
+                order.trigger_price = trigger_price;
+            })
+            .await?;
+    }
+    if !order.is_triggered(price) {
+        return Ok(());
+    }
+    // the wallet may have been deleted since the order was placed
+    if GLOBAL_WALLET_STORE
+        .find(order.user_id, order.wallet)
+        .await?
+        .is_none()
+    {
+        return cancel_orphan(bot, order).await;
+    }
+
+    // marked before its swap is sent, so it isn't sent again on the next blocks nor after a
+    // restart, and an order cancelled meanwhile is left alone
+    let order = GLOBAL_ORDER_BOOK
+        .update(order.id, |order| {
+            if order.status == LimitOrderStatus::Open {
+                order.status = LimitOrderStatus::Triggered
+            }
+        })
+        .await?;
+    if order.status == LimitOrderStatus::Triggered {
+        let (bot, providers) = (bot.clone(), providers.clone());
+        tokio::spawn(async move { execute(&bot, &providers, order, price).await });
+    }
+    Ok(())
+}
+
+/// Pair and amount of `token_in` of an order, the orders swapping the same amount of the same
+/// pair share its quote
+type QuoteKey = (Address, Address, u64);
+
+fn quote_key(order: &LimitOrder, amount: f64) -> QuoteKey {
+    (order.token_in, order.token_out, amount.to_bits())
+}
+
+/// Checks the open orders of the chain against the latest block, each quote is fetched once for
+/// all the orders sharing it
+async fn check_orders(
+    bot: &Bot,
+    providers: &Arc<ProviderRegistry>,
+    chain_id: u64,
+) -> anyhow::Result<()> {
+    let orders = GLOBAL_ORDER_BOOK.open_orders(chain_id).await?;
+    let now = chrono::Utc::now().timestamp();
+    let (expired, open): (Vec<_>, Vec<_>) =
+        orders.into_iter().partition(|order| order.is_expired(now));
+    stream::iter(expired)
+        .for_each_concurrent(MAX_CONCURRENT_CHECKS, |order| async move {
+            let id = order.id;
+            if let Err(err) = expire_order(bot, order).await {
+                log::warn!("Unable to expire limit order #{}: {}", id, err);
+            }
+        })
+        .await;
+
+    let open: Vec<(LimitOrder, anyhow::Result<f64>)> = stream::iter(open)
+        .map(|order| async move {
+            let amount = order_amount(providers, &order).await;
+            (order, amount)
+        })
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .collect()
+        .await;
+    let mut quoted = HashMap::new();
+    for (order, amount) in &open {
+        if let Ok(amount) = amount {
+            quoted
+                .entry(quote_key(order, *amount))
+                .or_insert_with(|| (order.clone(), *amount));
+        }
+    }
+    let prices: HashMap<QuoteKey, Result<f64, String>> = stream::iter(quoted)
+        .map(|(key, (order, amount))| async move {
+            let price = quote_price(providers, &order, amount).await;
+            (key, price.map_err(|err| err.to_string()))
+        })
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .collect()
+        .await;
+
+    stream::iter(open)
+        .for_each_concurrent(MAX_CONCURRENT_CHECKS, |(order, amount)| {
+            let prices = &prices;
+            async move {
+                let id = order.id;
+                let checked = async {
+                    let key = quote_key(&order, amount?);
+                    match prices.get(&key) {
+                        Some(Ok(price)) => check_order(bot, providers, order, *price).await,
+                        Some(Err(err)) => Err(anyhow::anyhow!("{}", err)),
+                        None => Ok(()),
+                    }
+                }
+                .await;
+                if let Err(err) = checked {
+                    log::warn!("Unable to check limit order #{}: {}", id, err);
+                }
+            }
+        })
+        .await;
+    Ok(())
+}
+
+async fn watch_new_blocks(
+    bot: &Bot,
+    providers: &Arc<ProviderRegistry>,
+    chain_id: u64,
+) -> anyhow::Result<()> {
+    let provider = providers.query(chain_id)?.provider();
+    let mut blocks = provider.watch_blocks().await?;
+    while blocks.next().await.is_some() {
+        if let Err(err) = check_orders(bot, providers, chain_id).await {
+            log::warn!("Unable to check the limit orders of {}: {}", chain_id, err);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the open limit orders on every new block of their chain
+pub(crate) fn spawn_limit_order_watcher(bot: Bot, providers: Arc<ProviderRegistry>) {
+    for chain in GLOBAL_CHAIN_REGISTRY.chains() {
+        let (bot, providers) = (bot.clone(), providers.clone());
+        let (chain_id, name) = (chain.chain_id, chain.name.clone());
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = watch_new_blocks(&bot, &providers, chain_id).await {
+                    log::warn!("Unable to watch the new blocks of {}: {}", name, err);
+                }
+                sleep(WATCH_RETRY_DELAY).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_expiries() {
+        assert_eq!(parse_expiry("30m"), Ok(Some(1_800)));
+        assert_eq!(parse_expiry(" 12H "), Ok(Some(43_200)));
+        assert_eq!(parse_expiry("7d"), Ok(Some(604_800)));
+        assert_eq!(parse_expiry("None"), Ok(None));
+        assert_eq!(parse_expiry("365d"), Ok(Some(MAX_EXPIRY_SECS)));
+    }
+
+    #[test]
+    fn rejects_invalid_expiries() {
+        for text in ["", "d", "0d", "-1d", "5", "5s", "1.5h", "5ü", "3🕐", "ü"] {
+            assert!(parse_expiry(text).is_err(), "{} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn rejects_expiries_past_the_max() {
+        assert!(parse_expiry("366d").is_err());
+        assert!(parse_expiry("18446744073709551615d").is_err());
+        assert!(parse_expiry("99999999999999999999m").is_err());
+    }
+
+    #[test]
+    fn caps_the_expiry_timestamp() {
+        let trigger = LimitTrigger {
+            expiry_secs: Some(u64::MAX),
+            ..LimitTrigger::default()
+        };
+        assert_eq!(trigger.expires_at(0), Some(MAX_EXPIRY_SECS as i64));
+        assert_eq!(trigger.expires_at(i64::MAX), Some(i64::MAX));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::keyboards::menu_keyboard;
+use crate::requests::on_chain::get_on_chain_info;
+use crate::requests::providers::ProviderRegistry;
+use crate::storages::{DraftKey, MenuKind, GLOBAL_MENU_STORE};
+use hashbrown::HashMap;
+use std::sync::Arc;
+use teloxide::{
+    payloads::EditMessageTextSetters,
+    prelude::Requester,
+    types::{MessageId, ParseMode},
+    ApiError, Bot, RequestError,
+};
+use tokio::time::{interval, sleep, Duration, MissedTickBehavior};
+
+/// Spacing between two edits of a refresh, keeps the bot well under the 30 messages per second
+/// Telegram allows overall
+const MENU_EDIT_SPACING: Duration = Duration::from_millis(50);
+
+/// Re-renders the open main menus whose header changed since they were last rendered, a menu
+/// Telegram refuses to edit is forgotten
+async fn refresh_main_menus(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    rendered: &mut HashMap<DraftKey, (MessageId, String)>,
+) -> anyhow::Result<()> {
+    let menus = GLOBAL_MENU_STORE.entries(MenuKind::Main).await?;
+    rendered.retain(|key, (message_id, _)| {
+        menus
+            .iter()
+            .any(|(menu_key, menu)| menu_key == key && menu.message_id == *message_id)
+    });
+    let header = get_on_chain_info(providers);
+    for (key, menu) in menus {
+        if rendered.get(&key).is_some_and(|(_, text)| *text == header) {
+            continue;
+        }
+        let edit = bot
+            .edit_message_text(menu.chat_id, menu.message_id, header.clone())
+            .parse_mode(ParseMode::MarkdownV2)
+            .reply_markup(menu_keyboard()?)
+            .await;
+        match edit {
+            // the menu was sent with the same header
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {
+                rendered.insert(key, (menu.message_id, header.clone()));
+            }
+            // the remaining menus wait for the next refresh
+            Err(RequestError::RetryAfter(delay)) => {
+                log::warn!("Telegram throttled the main menu refresh for {:?}", delay);
+                sleep(delay).await;
+                return Ok(());
+            }
+            Err(err @ RequestError::Api(_)) => {
+                // most likely deleted, it's not refreshed again
+                log::warn!("Unable to refresh the main menu of {}: {}", key, err);
+                rendered.remove(&key);
+                GLOBAL_MENU_STORE.remove(MenuKind::Main, &key).await?;
+            }
+            Err(err) => log::warn!("Unable to refresh the main menu of {}: {}", key, err),
+        }
+        sleep(MENU_EDIT_SPACING).await;
+    }
+    Ok(())
+}
+
+/// Keeps the gas and block of the open main menus current, every menu is edited at most once per
+/// `every` to respect the Telegram edit rate limits
+pub(crate) fn spawn_live_main_menus(bot: Bot, providers: Arc<ProviderRegistry>, every: Duration) {
+    tokio::spawn(async move {
+        let mut rendered = HashMap::new();
+        let mut ticks = interval(every);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            if let Err(err) = refresh_main_menus(&bot, &providers, &mut rendered).await {
+                log::warn!("Unable to refresh the main menus: {}", err);
+            }
+        }
+    });
+}
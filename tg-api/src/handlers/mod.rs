@@ -1,10 +1,34 @@
+pub(crate) mod admin_handlers;
 pub(crate) mod callback_handlers;
 pub(crate) mod dialogue_handlers;
+pub(crate) mod estimate_handlers;
+pub(crate) mod history_handlers;
+pub(crate) mod limit_order_handlers;
+pub(crate) mod live_menu_handlers;
+pub(crate) mod orders_handlers;
+pub(crate) mod preview_handlers;
+pub(crate) mod rebate_handlers;
+pub(crate) mod risk_handlers;
+pub(crate) mod settings_handlers;
+pub(crate) mod wallet_handlers;
 
 use crate::bot::TgError;
+use crate::consts::{BUY_TOKEN, RECEIVE_TOKEN, SELL_TOKEN};
+use crate::keyboards::callback_data::{decode_keyboard, CallbackData, MenuType};
+use crate::keyboards::{draft_keyboard, DraftDisplay};
+use crate::requests::on_chain;
+use crate::requests::providers::ProviderRegistry;
+use crate::storages::{
+    DraftKey, OrderDraft, OrderSide, GLOBAL_CHAIN_REGISTRY, GLOBAL_ORDER_DRAFT_STORAGE,
+    GLOBAL_WALLET_STORE,
+};
+use ethers::types::{Address, U256};
+use ethers::utils::{format_ether, format_units};
+use teloxide::utils::markdown;
 use teloxide::{
+    payloads::EditMessageTextSetters,
     prelude::Requester,
-    types::{CallbackQuery, ChatId, InlineKeyboardMarkup, Message, MessageId},
+    types::{CallbackQuery, ChatId, InlineKeyboardMarkup, Message, MessageId, ParseMode, UserId},
     Bot,
 };
 use tokio::time::{sleep, Duration};
@@ -15,31 +39,34 @@ pub(crate) enum SubMenuType {
     SendSellTx,
 }
 
-/// Gets the last vec in the larger vec in the InlineKeyboardMarkup. See https://docs.rs/teloxide/latest/teloxide/types/struct.InlineKeyboardMarkup.html
-/// Gets the last button in the last vec, which should either be "Send Buy Tx" or "Send Sell Tx"
+impl SubMenuType {
+    fn from_menu(menu: MenuType) -> Option<Self> {
+        match menu {
+            MenuType::Buy => Some(Self::SendBuyTx),
+            MenuType::Sell => Some(Self::SendSellTx),
+            MenuType::Main
+            | MenuType::Wallets
+            | MenuType::History
+            | MenuType::Settings
+            | MenuType::Orders => None,
+        }
+    }
+}
+
+/// Decodes the [CallbackData] of the clicked button to find which sub menu it belongs to
 pub(crate) fn find_sub_menu_type_from_callback(q: &CallbackQuery) -> anyhow::Result<SubMenuType> {
-    q.message
-        .as_ref()
-        .and_then(|msg| msg.reply_markup())
-        .and_then(|keyboard| keyboard.inline_keyboard.last())
-        .and_then(|last_vec| last_vec.last())
-        .and_then(|last_button| match last_button.text.as_str() {
-            "Send Buy Tx" => Some(SubMenuType::SendBuyTx),
-            "Send Sell Tx" => Some(SubMenuType::SendSellTx),
-            _ => None,
-        })
+    q.data
+        .as_deref()
+        .and_then(|data| CallbackData::decode(data).ok())
+        .and_then(|data| SubMenuType::from_menu(data.menu))
         .ok_or_else(|| anyhow::anyhow!("find_sub_menu_type_from_callback: No valid sub menu found"))
 }
 
+/// Decodes the [CallbackData] of the message's buttons to find which sub menu it is
 pub(crate) fn find_sub_menu_type_from_message(msg: &Message) -> anyhow::Result<SubMenuType> {
     msg.reply_markup()
-        .and_then(|keyboard| keyboard.inline_keyboard.last())
-        .and_then(|last_vec| last_vec.last())
-        .and_then(|last_button| match last_button.text.as_str() {
-            "Send Buy Tx" => Some(SubMenuType::SendBuyTx),
-            "Send Sell Tx" => Some(SubMenuType::SendSellTx),
-            _ => None,
-        })
+        .and_then(|keyboard| decode_keyboard(keyboard).first().map(|data| data.menu))
+        .and_then(SubMenuType::from_menu)
         .ok_or_else(|| anyhow::anyhow!("find_sub_menu_type_from_message: No valid sub menu found"))
 }
 
@@ -49,8 +76,7 @@ pub(crate) fn find_keyboard_from_callback(
     q.message
         .as_ref()
         .and_then(|msg| msg.reply_markup())
-        .and_then(|keyboard| Some(keyboard))
-        .ok_or_else(|| anyhow::anyhow!("find_sub_menu_type_from_callback: No valid sub menu found"))
+        .ok_or_else(|| anyhow::anyhow!("find_keyboard_from_callback: No valid sub menu found"))
 }
 
 pub(crate) fn find_keyboard_from_message(msg: &Message) -> anyhow::Result<&InlineKeyboardMarkup> {
@@ -58,10 +84,164 @@ pub(crate) fn find_keyboard_from_message(msg: &Message) -> anyhow::Result<&Inlin
         .ok_or_else(|| anyhow::anyhow!("find_keyboard_from_message: No valid sub menu found"))
 }
 
-pub(crate) fn matching_sub_menu(_bot: &Bot, q: &CallbackQuery) -> Option<SubMenuType> {
+pub(crate) fn matching_sub_menu(q: &CallbackQuery) -> Option<SubMenuType> {
     find_sub_menu_type_from_callback(q).ok()
 }
 
+/// Checks the clicked button belongs to the menu of the user's current draft, buttons of menus
+/// replaced by a newer buy or sell menu are stale
+pub(crate) async fn is_current_draft(q: &CallbackQuery, data: &CallbackData) -> bool {
+    let key = match DraftKey::from_callback(q) {
+        Some(key) => key,
+        None => return false,
+    };
+    GLOBAL_ORDER_DRAFT_STORAGE
+        .get(&key)
+        .await
+        .map(|draft| draft.session == data.session)
+        .unwrap_or(false)
+}
+
+/// The tokens of the draft along with their label
+fn draft_tokens(draft: &OrderDraft) -> Vec<(&'static str, Address)> {
+    let tokens = match draft.side {
+        OrderSide::Buy => [
+            (BUY_TOKEN, draft.buy_token),
+            (RECEIVE_TOKEN, draft.receive_token),
+        ],
+        OrderSide::Sell => [
+            (SELL_TOKEN, draft.sell_token),
+            (RECEIVE_TOKEN, draft.receive_token),
+        ],
+    };
+    tokens
+        .into_iter()
+        .filter_map(|(label, token)| Some((label, token?)))
+        .collect()
+}
+
+/// Fetches what the draft's menu displays, the user's wallets, the metadata of its tokens and the
+/// buy quote
+async fn draft_display(
+    providers: &ProviderRegistry,
+    user_id: UserId,
+    draft: &OrderDraft,
+) -> DraftDisplay {
+    let mut tokens = Vec::new();
+    for (_, address) in draft_tokens(draft) {
+        match on_chain::token_metadata(providers, draft.chain_id, address).await {
+            Ok(metadata) => tokens.push(metadata),
+            Err(err) => log::warn!("Unable to get the metadata of {:#x}: {}", address, err),
+        }
+    }
+    let wallets = match GLOBAL_WALLET_STORE.wallets(user_id).await {
+        Ok(wallets) => wallets.iter().map(|wallet| wallet.address).collect(),
+        Err(err) => {
+            log::warn!("Unable to get the wallets of {}: {}", user_id, err);
+            Vec::new()
+        }
+    };
+    DraftDisplay {
+        estimate: estimate_handlers::estimate_received(providers, draft).await,
+        tokens,
+        wallets,
+    }
+}
+
+/// Formats the integer part of a token amount with thousands separators
+fn format_supply(supply: U256, decimals: u8) -> String {
+    let supply = format_units(supply, decimals as u32).unwrap_or_default();
+    let whole: Vec<char> = supply
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .collect();
+    let groups: Vec<String> = whole
+        .rchunks(3)
+        .rev()
+        .map(|group| group.iter().collect())
+        .collect();
+    groups.join(",")
+}
+
+/// Info cards of the draft's tokens, in MarkdownV2
+fn token_cards(draft: &OrderDraft, display: &DraftDisplay) -> String {
+    let mut cards = String::new();
+    for (label, address) in draft_tokens(draft) {
+        let token = match display.token(address) {
+            Some(token) => token,
+            None => continue,
+        };
+        cards.push_str(&format!(
+            "\n{}\n{} \\({}\\)\n{}\n*Decimals:* {}",
+            markdown::bold(label),
+            markdown::escape(&token.name),
+            markdown::escape(&token.symbol),
+            markdown::code_inline(&format!("{:#x}", token.address)),
+            token.decimals
+        ));
+        if let Some(supply) = token.total_supply {
+            cards.push_str(&format!(
+                "  ═  *Supply:* {}",
+                markdown::escape(&format_supply(supply, token.decimals))
+            ));
+        }
+        cards.push('\n');
+    }
+    cards
+}
+
+/// Renders the text and keyboard of the draft's menu, along with the info of its tokens and the
+/// buy quote
+pub(crate) async fn draft_menu(
+    providers: &ProviderRegistry,
+    user_id: UserId,
+    draft: &OrderDraft,
+) -> anyhow::Result<(String, InlineKeyboardMarkup)> {
+    let display = draft_display(providers, user_id, draft).await;
+    let menu_msg = format!(
+        "{}{}",
+        on_chain::get_on_chain_info(providers),
+        token_cards(draft, &display)
+    );
+    Ok((menu_msg, draft_keyboard(draft, &display)?))
+}
+
+/// Helper function to render the draft's keyboard into its menu message, along with the info of
+/// its tokens and the buy quote
+pub(crate) async fn edit_draft_menu(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    DraftKey { chat_id, user_id }: DraftKey,
+    message_id: MessageId,
+    draft: &OrderDraft,
+) -> Result<(), TgError> {
+    let (menu_msg, keyboard) = draft_menu(providers, user_id, draft).await?;
+    bot.edit_message_text(chat_id, message_id, menu_msg)
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+/// Formats a wei amount in ETH without the trailing zeros
+pub(crate) fn format_eth(amount: U256) -> String {
+    let amount = format_ether(amount);
+    amount
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Symbol of the chain's native token, ETH for a chain that is no longer configured
+pub(crate) fn native_symbol(chain_id: u64) -> &'static str {
+    GLOBAL_CHAIN_REGISTRY
+        .get(chain_id)
+        .map(|chain| chain.native_symbol.as_str())
+        .unwrap_or("ETH")
+}
+
 /// Helper function to delete number_of_deletes previous messages
 pub(crate) async fn delete_previous_messages(
     bot: &Bot,
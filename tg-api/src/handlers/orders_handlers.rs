@@ -0,0 +1,372 @@
+use crate::bot::TgError;
+use crate::handlers::delete_up_to_messages;
+use crate::handlers::dialogue_handlers::{BuyAddressPromptDialogue, PromptDialogueState};
+use crate::handlers::estimate_handlers::format_amount;
+use crate::handlers::history_handlers::format_date;
+use crate::handlers::limit_order_handlers::{
+    amount_text, current_price, order_name, trigger_market, trigger_text, LimitField,
+};
+use crate::keyboards::callback_data::{
+    decode_keyboard, new_session_id, CallbackAction, CallbackData,
+};
+use crate::keyboards::order_buttons::{order_edit_keyboard, orders_keyboard};
+use crate::requests::on_chain::chain_name;
+use crate::requests::providers::ProviderRegistry;
+use crate::storages::{
+    DraftKey, LimitOrder, LimitOrderStatus, LimitTrigger, MenuKind, TgMessage, GLOBAL_MENU_STORE,
+    GLOBAL_ORDER_BOOK,
+};
+use futures::future::join_all;
+use std::sync::Arc;
+use teloxide::dispatching::dialogue::ErasedStorage;
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    prelude::Requester,
+    types::{CallbackQuery, InlineKeyboardMarkup, Message, UserId},
+    Bot,
+};
+
+/// Number of orders per page of the /orders listing
+const ORDERS_PAGE_SIZE: usize = 5;
+
+/// Formats the time left with its largest unit, like 5h
+fn format_remaining(secs: i64) -> String {
+    match secs {
+        secs if secs >= 86_400 => format!("{}d", secs / 86_400),
+        secs if secs >= 3_600 => format!("{}h", secs / 3_600),
+        secs if secs >= 60 => format!("{}m", secs / 60),
+        secs => format!("{}s", secs.max(0)),
+    }
+}
+
+/// The status of the order along with when it expires
+fn status_text(order: &LimitOrder) -> String {
+    match order.expires_at {
+        Some(expires_at) => format!(
+            "{}, expires in {}",
+            order.status,
+            format_remaining(expires_at - chrono::Utc::now().timestamp())
+        ),
+        None => format!("{}, no expiry", order.status),
+    }
+}
+
+/// The current price and how far it has to move to reach the trigger
+fn distance_text(order: &LimitOrder, price: Option<f64>) -> String {
+    match price.filter(|price| *price > 0.0) {
+        Some(price) => format!(
+            "Price now: {} | Distance: {:+.2}%",
+            format_amount(price),
+            (order.trigger_price / price - 1.0) * 100.0
+        ),
+        None => "Price now: unavailable".to_string(),
+    }
+}
+
+/// Renders a page of the listing along with its keyboard, `page` is clamped to the last page.
+/// The orders of the page are quoted for their distance to the trigger
+async fn orders_page(
+    providers: &ProviderRegistry,
+    user_id: UserId,
+    session: u32,
+    page: u32,
+) -> anyhow::Result<(String, InlineKeyboardMarkup)> {
+    let orders = GLOBAL_ORDER_BOOK.user_open_orders(user_id).await?;
+    let pages = orders.len().div_ceil(ORDERS_PAGE_SIZE).max(1) as u32;
+    let page = page.min(pages - 1);
+    let orders: Vec<LimitOrder> = orders
+        .into_iter()
+        .skip(page as usize * ORDERS_PAGE_SIZE)
+        .take(ORDERS_PAGE_SIZE)
+        .collect();
+
+    if orders.is_empty() {
+        return Ok((
+            "No open orders".to_string(),
+            orders_keyboard(session, &[], 0, 1)?,
+        ));
+    }
+
+    let prices = join_all(orders.iter().map(|order| current_price(providers, order))).await;
+    let mut text = format!("Open Orders ({}/{})\n", page + 1, pages);
+    for (order, price) in orders.iter().zip(prices) {
+        text.push_str(&format!(
+            "\n#{} {} on {} | {}\nPay: {}\nReceive: {:#x}\nTrigger: {}\n{}\n",
+            order.id,
+            order_name(order),
+            chain_name(order.chain_id),
+            status_text(order),
+            amount_text(order),
+            order.token_out,
+            trigger_text(order),
+            distance_text(order, price.ok())
+        ));
+    }
+    Ok((text, orders_keyboard(session, &orders, page, pages)?))
+}
+
+/// Renders the edit view of the order, quoted for its distance to the trigger
+async fn order_detail(providers: &ProviderRegistry, order: &LimitOrder) -> String {
+    let price = current_price(providers, order).await.ok();
+    format!(
+        "{} #{}\nChain: {}\nStatus: {}\nPlaced: {}\nWallet: {:#x}\nPay: {}\nReceive: {:#x}\nTrigger: {}\n{}",
+        order_name(order),
+        order.id,
+        chain_name(order.chain_id),
+        status_text(order),
+        format_date(order.created_at),
+        order.wallet,
+        amount_text(order),
+        order.token_out,
+        trigger_text(order),
+        distance_text(order, price)
+    )
+}
+
+/// Shows the first page of the user's open orders
+pub(crate) async fn orders_command(
+    bot: &Bot,
+    msg: &Message,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    let user_id = match msg.from() {
+        Some(user) => user.id,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+    let (text, keyboard) = orders_page(providers, user_id, new_session_id(), 0).await?;
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+/// Opens the /orders listing from the main menu
+pub(crate) async fn handle_open_orders_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
+    if let Some(Message { chat, .. }) = &q.message {
+        let (text, keyboard) = orders_page(providers, q.from.id, new_session_id(), 0).await?;
+        bot.send_message(chat.id, text)
+            .reply_markup(keyboard)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Handles the page navigation, the edit views and the cancel buttons of the /orders listing
+pub(crate) async fn handle_orders_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    data: &CallbackData,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    let (key, msg) = match (DraftKey::from_callback(q), &q.message) {
+        (Some(key), Some(msg)) => (key, msg),
+        _ => return Err(TgError::NoQueryMessage(Box::new(q.clone()))),
+    };
+    let user_id = q.from.id;
+
+    let (text, keyboard) = match data.action {
+        CallbackAction::PreviousOrdersPage(page)
+        | CallbackAction::NextOrdersPage(page)
+        | CallbackAction::BackToOrders(page) => {
+            bot.answer_callback_query(&q.id).await?;
+            orders_page(providers, user_id, data.session, page).await?
+        }
+        CallbackAction::EditOrder { id, page } => match GLOBAL_ORDER_BOOK.get(user_id, id).await? {
+            Some(order) if order.status == LimitOrderStatus::Open => {
+                bot.answer_callback_query(&q.id).await?;
+                (
+                    order_detail(providers, &order).await,
+                    order_edit_keyboard(data.session, id, page)?,
+                )
+            }
+            _ => {
+                bot.answer_callback_query(&q.id)
+                    .text(format!("Order #{} isn't open anymore", id))
+                    .await?;
+                orders_page(providers, user_id, data.session, page).await?
+            }
+        },
+        CallbackAction::CancelOrder { id, page } => {
+            let text = match GLOBAL_ORDER_BOOK.cancel(user_id, id).await? {
+                Some(order) => format!("{} #{} cancelled", order_name(&order), order.id),
+                None => format!("Order #{} isn't open anymore", id),
+            };
+            bot.answer_callback_query(&q.id).text(text).await?;
+            orders_page(providers, user_id, data.session, page).await?
+        }
+        CallbackAction::EditTrigger { id, .. } | CallbackAction::EditExpiry { id, .. } => {
+            let field = match data.action {
+                CallbackAction::EditTrigger { .. } => LimitField::Trigger,
+                _ => LimitField::Expiry,
+            };
+            let order = match GLOBAL_ORDER_BOOK.get(user_id, id).await? {
+                Some(order) if order.status == LimitOrderStatus::Open => order,
+                _ => {
+                    bot.answer_callback_query(&q.id)
+                        .text(format!("Order #{} isn't open anymore", id))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            bot.answer_callback_query(&q.id).await?;
+            // the order dialogue handler re-renders this message
+            let message = TgMessage {
+                chat_id: msg.chat.id,
+                message_id: msg.id,
+                message: Arc::new(msg.clone()),
+            };
+            GLOBAL_MENU_STORE
+                .insert(MenuKind::Orders, key, message)
+                .await?;
+            storage
+                .update_dialogue(
+                    key.dialogue_id(),
+                    PromptDialogueState::OrderFieldReceived { id, field },
+                )
+                .await?;
+            bot.send_message(
+                msg.chat.id,
+                field.prompt(order.side, &order_trigger(&order)),
+            )
+            .await?;
+            return Ok(());
+        }
+        _ => return Ok(()),
+    };
+
+    bot.edit_message_text(msg.chat.id, msg.id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+/// The trigger of the order as edited from a limit menu
+fn order_trigger(order: &LimitOrder) -> LimitTrigger {
+    LimitTrigger {
+        kind: order.kind,
+        unit: order.unit,
+        value: Some(order.value),
+        expiry_secs: None,
+    }
+}
+
+/// Applies the entered trigger or expiry to the open order, a new trigger is resolved from the
+/// market like at placement. None when the order isn't open anymore
+async fn edit_order(
+    providers: &ProviderRegistry,
+    user_id: UserId,
+    mut order: LimitOrder,
+    field: LimitField,
+    trigger: LimitTrigger,
+) -> anyhow::Result<Option<LimitOrder>> {
+    let id = order.id;
+    match field {
+        LimitField::Trigger => {
+            order.value = trigger.value.unwrap_or(order.value);
+            let market = trigger_market(providers, &order).await?;
+            order.arm(market)?;
+            GLOBAL_ORDER_BOOK
+                .edit(user_id, id, move |edited| {
+                    edited.value = order.value;
+                    edited.entry_price = order.entry_price;
+                    edited.trigger_price = order.trigger_price;
+                    edited.peak_price = order.peak_price;
+                })
+                .await
+        }
+        LimitField::Expiry => {
+            let expires_at = trigger.expires_at(chrono::Utc::now().timestamp());
+            GLOBAL_ORDER_BOOK
+                .edit(user_id, id, move |edited| edited.expires_at = expires_at)
+                .await
+        }
+    }
+}
+
+/// Sets the trigger or the expiry of the open order, its edit view is re-rendered
+pub(crate) async fn order_dialogue_handler(
+    bot: Bot,
+    dialogue: BuyAddressPromptDialogue,
+    (id, field): (u64, LimitField),
+    msg: Message,
+    providers: Arc<ProviderRegistry>,
+) -> Result<(), TgError> {
+    let text = match msg.text() {
+        Some(t) => t,
+        _ => {
+            bot.send_message(msg.chat.id, "Send me plain text.").await?;
+            return Ok(());
+        }
+    };
+    let key = match DraftKey::from_message(&msg) {
+        Some(key) => key,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+
+    let edited = match GLOBAL_ORDER_BOOK.get(key.user_id, id).await? {
+        Some(order) if order.status == LimitOrderStatus::Open => {
+            // the dialogue keeps waiting for a valid value
+            let mut trigger = order_trigger(&order);
+            if let Err(err) = field.apply(&mut trigger, text) {
+                bot.send_message(msg.chat.id, err).await?;
+                return Ok(());
+            }
+            match edit_order(&providers, key.user_id, order, field, trigger).await {
+                Ok(edited) => edited,
+                Err(err) => {
+                    bot.send_message(msg.chat.id, format!("Unable to edit the order: {}", err))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        _ => None,
+    };
+    dialogue.exit().await?;
+
+    let order = match edited {
+        Some(order) => order,
+        None => {
+            bot.send_message(msg.chat.id, format!("Order #{} isn't open anymore", id))
+                .await?;
+            return Ok(());
+        }
+    };
+    let detail = order_detail(&providers, &order).await;
+    match GLOBAL_MENU_STORE.remove(MenuKind::Orders, &key).await? {
+        Some(menu) => {
+            let callbacks = menu
+                .message
+                .reply_markup()
+                .map(decode_keyboard)
+                .unwrap_or_default();
+            let session = callbacks
+                .first()
+                .map(|data| data.session)
+                .unwrap_or_else(new_session_id);
+            let page = callbacks
+                .iter()
+                .find_map(|data| match data.action {
+                    CallbackAction::BackToOrders(page) => Some(page),
+                    _ => None,
+                })
+                .unwrap_or_default();
+            bot.edit_message_text(menu.chat_id, menu.message_id, detail)
+                .reply_markup(order_edit_keyboard(session, id, page)?)
+                .await?;
+            delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, menu.message_id.0).await?;
+        }
+        None => {
+            bot.send_message(msg.chat.id, detail)
+                .reply_markup(order_edit_keyboard(new_session_id(), id, 0)?)
+                .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use crate::handlers::estimate_handlers::format_amount;
+use crate::handlers::risk_handlers::{needs_risk_check, risk_section};
+use crate::handlers::{format_eth, native_symbol};
+use crate::requests::on_chain::token_metadata;
+use crate::requests::providers::ProviderRegistry;
+use crate::requests::server::{SendBuyTxRequest, SendSellTxRequest};
+use crate::requests::simulation::{preview_swap, SwapPreview};
+use crate::requests::swap::{from_units, PreparedSwap, SwapOrder};
+use crate::storages::{OrderDraft, OrderSide, GLOBAL_SWAP_CONFIG};
+use ethers::types::Address;
+use teloxide::types::UserId;
+
+/// Symbol of the token, or its address when its metadata can't be fetched
+async fn token_symbol(providers: &ProviderRegistry, chain_id: u64, address: Address) -> String {
+    match token_metadata(providers, chain_id, address).await {
+        Ok(metadata) => metadata.symbol,
+        Err(_) => format!("{:#x}", address),
+    }
+}
+
+/// Symbol of the token followed by its address
+async fn token_label(providers: &ProviderRegistry, chain_id: u64, address: Address) -> String {
+    match token_metadata(providers, chain_id, address).await {
+        Ok(metadata) => format!("{} ({:#x})", metadata.symbol, address),
+        Err(_) => format!("{:#x}", address),
+    }
+}
+
+fn optional_amount(amount: Option<f64>) -> String {
+    amount.map(format_amount).unwrap_or_else(|| "-".to_string())
+}
+
+fn preview_text(preview: &SwapPreview, chain_id: u64, symbol_out: &str) -> String {
+    let mut text = format!(
+        "Route: {}\nExpected: ≈ {} {}\nMin Received: {} {}",
+        preview.swap.route,
+        optional_amount(preview.amount_out()),
+        symbol_out,
+        optional_amount(preview.min_received()),
+        symbol_out
+    );
+    let gas = match preview.gas_cost() {
+        Some(cost) => {
+            let mut gas = format!("up to {} {}", format_eth(cost), native_symbol(chain_id));
+            let eth = from_units(cost, 18).unwrap_or_default();
+            if let Some(price) = preview.native_usd {
+                gas.push_str(&format!(" (${:.2})", eth * price));
+            }
+            gas
+        }
+        None => "unknown".to_string(),
+    };
+    text.push_str(&format!("\nGas: {}", gas));
+    if preview.approval {
+        text.push_str(", including the approval sent first");
+    }
+    match &preview.revert {
+        Some(reason) => text.push_str(&format!("\nDry Run: ⚠️ would revert: {}", reason)),
+        None => text.push_str("\nDry Run: OK"),
+    }
+    text
+}
+
+/// Dry runs the swap the user's wallet would send for the order and renders its outcome, along
+/// with the swap it dry ran
+async fn preview_section(
+    providers: &ProviderRegistry,
+    user_id: UserId,
+    draft: &OrderDraft,
+    order: &SwapOrder,
+    wallet: Address,
+) -> (String, Option<PreparedSwap>) {
+    let previewed = async {
+        let query = providers.query(draft.chain_id)?;
+        preview_swap(
+            query.provider(),
+            query.chain(),
+            &draft.swap.apply(&GLOBAL_SWAP_CONFIG),
+            order,
+            wallet,
+        )
+        .await
+    }
+    .await;
+    match previewed {
+        Ok(preview) => {
+            let symbol_out = token_symbol(providers, draft.chain_id, order.token_out).await;
+            (
+                preview_text(&preview, draft.chain_id, &symbol_out),
+                Some(preview.swap),
+            )
+        }
+        Err(err) => {
+            log::warn!("Unable to preview the swap of {}: {}", user_id, err);
+            (format!("Unable to simulate the swap: {}", err), None)
+        }
+    }
+}
+
+/// Renders the card shown before the swap of the draft is sent: the dry run of its tx and its
+/// gas cost, and for a buy of a token other than the native one, the risk of the token. Returns
+/// the swap that was dry run along with the card, it's sent as is once confirmed. Fails when the
+/// draft isn't complete
+pub(crate) async fn confirmation_card(
+    providers: &ProviderRegistry,
+    user_id: UserId,
+    draft: &OrderDraft,
+) -> anyhow::Result<(String, Option<PreparedSwap>)> {
+    let chain_id = draft.chain_id;
+    let wallet = draft
+        .wallet
+        .ok_or_else(|| anyhow::anyhow!("No wallet found"))?;
+    let (summary, order, buy) = match draft.side {
+        OrderSide::Buy => {
+            let request = SendBuyTxRequest::new(draft)?;
+            let summary = format!(
+                "Buy: {}\nPay: {} {}",
+                token_label(providers, chain_id, request.buy).await,
+                format_amount(request.buy_amount),
+                token_label(providers, chain_id, request.receive).await,
+            );
+            let order = request.swap_order();
+            (
+                summary,
+                order,
+                needs_risk_check(&request).then_some(request),
+            )
+        }
+        OrderSide::Sell => {
+            let request = SendSellTxRequest::new(draft)?;
+            let amount = match (request.sell_amount, request.sell_percentage) {
+                (Some(amount), _) => format_amount(amount),
+                (_, Some(percentage)) => format!("{}% of the balance of", percentage),
+                _ => unreachable!("validated in SendSellTxRequest::new"),
+            };
+            let summary = format!(
+                "Sell: {} {}\nReceive: {}",
+                amount,
+                token_label(providers, chain_id, request.sell).await,
+                token_label(providers, chain_id, request.receive).await,
+            );
+            (summary, request.swap_order(), None)
+        }
+    };
+
+    let (preview, mut swap) = preview_section(providers, user_id, draft, &order, wallet).await;
+    let mut card = format!("{}\n\n{}", summary, preview);
+    if let Some(request) = buy {
+        let protocol = swap.as_ref().map(|swap| &swap.route.protocol);
+        let (text, risk) = risk_section(providers, &request, protocol).await;
+        card.push_str(&format!("\n\n{}", text));
+        if let (Some(swap), Some(risk)) = (swap.as_mut(), risk) {
+            swap.fee_on_transfer |= risk.is_buy_taxed();
+        }
+    }
+    card.push_str(
+        "\n\nSimulated on the latest block, the sent swap can still behave differently. \
+         Nothing is sent until you confirm",
+    );
+    Ok((card, swap))
+}
@@ -0,0 +1,91 @@
+use crate::bot::TgError;
+use crate::handlers::{format_eth, native_symbol};
+use crate::requests::on_chain::chain_name;
+use crate::storages::{TradeRecord, TradeStatus, GLOBAL_TRADE_JOURNAL};
+use ethers::types::U256;
+use std::collections::BTreeMap;
+use teloxide::{
+    payloads::SendMessageSetters,
+    prelude::Requester,
+    types::{Message, UserId},
+    Bot,
+};
+
+/// Number of trades listed under the totals of /rebates
+const RECENT_REBATE_TRADES: usize = 10;
+
+/// Rebates of the user's trades of a chain
+#[derive(Debug, Default)]
+struct RebateTotals {
+    trades: usize,
+    expected: U256,
+    received: U256,
+}
+
+/// Totals of the user's rebate trades per chain, then the latest ones
+async fn rebates_text(user_id: UserId) -> anyhow::Result<String> {
+    let trades = GLOBAL_TRADE_JOURNAL.rebate_trades(user_id).await?;
+    if trades.is_empty() {
+        return Ok("No rebate trades yet, turn on Rebate in a buy or sell menu".to_string());
+    }
+
+    let mut totals: BTreeMap<u64, RebateTotals> = BTreeMap::new();
+    for trade in trades.iter() {
+        let totals = totals.entry(trade.chain_id).or_default();
+        totals.trades += 1;
+        totals.expected += trade.expected_rebate.unwrap_or_default();
+        totals.received += trade.rebate_received.unwrap_or_default();
+    }
+
+    let mut text = "Rebates\n".to_string();
+    for (chain_id, totals) in totals.iter() {
+        let symbol = native_symbol(*chain_id);
+        text.push_str(&format!(
+            "\n{}: {} {} received over {} trades, ~{} {} expected",
+            chain_name(*chain_id),
+            format_eth(totals.received),
+            symbol,
+            totals.trades,
+            format_eth(totals.expected),
+            symbol
+        ));
+    }
+
+    text.push_str("\n\nLatest:");
+    for trade in trades.iter().take(RECENT_REBATE_TRADES) {
+        text.push_str(&format!("\n{}", trade_rebate(trade)));
+    }
+    Ok(text)
+}
+
+fn trade_rebate(trade: &TradeRecord) -> String {
+    let symbol = native_symbol(trade.chain_id);
+    let format = |rebate: Option<U256>| {
+        rebate
+            .map(|rebate| format!("{} {}", format_eth(rebate), symbol))
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let received = match trade.status {
+        TradeStatus::Pending => "pending".to_string(),
+        _ => format(trade.rebate_received),
+    };
+    format!(
+        "#{} {} | expected ~{} | received {}",
+        trade.id,
+        trade.status,
+        format(trade.expected_rebate),
+        received
+    )
+}
+
+/// Shows the rebates the user received per chain
+pub(crate) async fn rebates_command(bot: &Bot, msg: &Message) -> Result<(), TgError> {
+    let user_id = match msg.from() {
+        Some(user) => user.id,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+    bot.send_message(msg.chat.id, rebates_text(user_id).await?)
+        .disable_web_page_preview(true)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use crate::requests::providers::ProviderRegistry;
+use crate::requests::routing::Protocol;
+use crate::requests::server::SendBuyTxRequest;
+use crate::requests::simulation::{simulate_round_trip, RiskLevel, TokenRisk};
+use crate::requests::swap::native_token;
+use crate::storages::GLOBAL_CHAIN_REGISTRY;
+
+/// Whether the buy needs its token checked, buying the native or wrapped native token doesn't
+pub(crate) fn needs_risk_check(request: &SendBuyTxRequest) -> bool {
+    let wrapped = GLOBAL_CHAIN_REGISTRY
+        .get(request.chain_id)
+        .map(|chain| chain.wrapped_native);
+    request.buy != native_token() && Some(request.buy) != wrapped
+}
+
+fn tax_text(error: &Option<String>, tax: Option<f64>) -> String {
+    match (error, tax) {
+        (Some(reason), _) => format!("Reverts: {}", reason),
+        (None, Some(tax)) => format!("OK, tax {:.2}%", tax),
+        (None, None) => "OK, tax unknown".to_string(),
+    }
+}
+
+fn risk_text(risk: &TokenRisk) -> String {
+    let level = match risk.level() {
+        RiskLevel::Low => "🟢 Low",
+        RiskLevel::Medium => "🟡 Medium",
+        RiskLevel::High => "🔴 High",
+    };
+    let mut text = format!("Risk: {}", level);
+    if risk.is_honeypot() {
+        text.push_str("\nHoneypot: the token can be bought but not sold back");
+    }
+    text.push_str(&format!(
+        "\nBuy: {}",
+        tax_text(&risk.buy_error, risk.buy_tax)
+    ));
+    if risk.buy_error.is_none() {
+        text.push_str(&format!(
+            "\nSell: {}",
+            tax_text(&risk.sell_error, risk.sell_tax)
+        ));
+    }
+    match risk.transferable {
+        Some(true) => text.push_str("\nTransfer: OK"),
+        Some(false) => text.push_str("\nTransfer: blocked"),
+        None => {}
+    }
+    if let Some(max_tx) = risk.max_tx {
+        text.push_str(&format!("\nMax Tx: {:.2}% of the supply", max_tx));
+    }
+    if let Some(max_wallet) = risk.max_wallet {
+        text.push_str(&format!("\nMax Wallet: {:.2}% of the supply", max_wallet));
+    }
+    match risk.blacklisted {
+        Some(true) => text.push_str("\nBlacklist: your wallet is blacklisted"),
+        Some(false) => text.push_str("\nBlacklist: your wallet isn't blacklisted"),
+        None => {}
+    }
+    text
+}
+
+/// Simulates buying the token then selling it back from the user's wallet and renders the risk
+/// part of the card shown before the buy is sent, along with the risk when it could be simulated.
+/// The round trip goes through Uniswap V2 only, which the card says when the buy itself is
+/// routed through `protocol` V3
+pub(crate) async fn risk_section(
+    providers: &ProviderRegistry,
+    request: &SendBuyTxRequest,
+    protocol: Option<&Protocol>,
+) -> (String, Option<TokenRisk>) {
+    let simulated = async {
+        let query = providers.query(request.chain_id)?;
+        simulate_round_trip(
+            query.provider(),
+            query.chain(),
+            request.wallet,
+            request.receive,
+            request.buy,
+            request.buy_amount,
+        )
+        .await
+    }
+    .await;
+    let (mut text, risk) = match simulated {
+        Ok(risk) => (risk_text(&risk), Some(risk)),
+        Err(err) => {
+            log::warn!("Unable to simulate the buy of {:#x}: {}", request.buy, err);
+            (format!("Unable to check the token: {}", err), None)
+        }
+    };
+    if let Some(Protocol::V3 { .. }) = protocol {
+        text.push_str(
+            "\nChecked on Uniswap V2 only, the buy goes through Uniswap V3 where the taxes can differ",
+        );
+    }
+    (text, risk)
+}
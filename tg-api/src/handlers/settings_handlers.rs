@@ -0,0 +1,336 @@
+use crate::bot::TgError;
+use crate::config::GasMode;
+use crate::handlers::delete_up_to_messages;
+use crate::handlers::dialogue_handlers::{BuyAddressPromptDialogue, PromptDialogueState};
+use crate::keyboards::callback_data::{
+    decode_keyboard, gas_mode_label, new_session_id, CallbackAction, CallbackData,
+};
+use crate::keyboards::settings_buttons::settings_keyboard;
+use crate::storages::{
+    DraftKey, MenuKind, TgMessage, UserSettings, GLOBAL_MENU_STORE, GLOBAL_SETTINGS_STORE,
+    GLOBAL_SWAP_CONFIG, GLOBAL_WALLET_STORE, MAX_DEADLINE_SECS, MAX_SLIPPAGE_BPS,
+    MIN_DEADLINE_SECS,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use teloxide::dispatching::dialogue::ErasedStorage;
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    prelude::Requester,
+    types::{CallbackQuery, ChatId, Message, MessageId, UserId},
+    Bot,
+};
+
+/// Entering this instead of a value resets the setting to the bot's default
+const RESET_SETTING: &str = "default";
+
+/// Setting of the settings menu a prompt awaits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum SettingField {
+    Slippage,
+    Deadline,
+    MaxFee,
+    PriorityFee,
+    BuyAmount,
+}
+
+impl SettingField {
+    fn from_action(action: &CallbackAction) -> Option<Self> {
+        match action {
+            CallbackAction::Slippage(_) => Some(Self::Slippage),
+            CallbackAction::Deadline(_) => Some(Self::Deadline),
+            CallbackAction::MaxFee(_) => Some(Self::MaxFee),
+            CallbackAction::PriorityFee(_) => Some(Self::PriorityFee),
+            CallbackAction::DefaultBuyAmount(_) => Some(Self::BuyAmount),
+            _ => None,
+        }
+    }
+
+    fn prompt(self) -> String {
+        let prompt = match self {
+            Self::Slippage => "Enter the slippage in percent, like 0.5",
+            Self::Deadline => "Enter the deadline of the swaps in seconds",
+            Self::MaxFee => "Enter the max fee per gas of the custom gas mode in Gwei",
+            Self::PriorityFee => "Enter the priority fee per gas of the custom gas mode in Gwei",
+            Self::BuyAmount => "Enter the amount new buy menus start with",
+        };
+        format!("{}, or \"{}\" to reset it", prompt, RESET_SETTING)
+    }
+
+    /// Parses and validates the entered value, "default" resets the setting
+    fn apply(self, settings: &mut UserSettings, text: &str) -> Result<(), String> {
+        let text = text.trim();
+        let reset = text.eq_ignore_ascii_case(RESET_SETTING);
+        let positive = || match text.parse::<f64>() {
+            Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+            _ => Err("Please enter a positive number".to_string()),
+        };
+        match self {
+            Self::Slippage => {
+                settings.swap.slippage_bps = match reset {
+                    true => None,
+                    false => Some(parse_slippage(text)?),
+                }
+            }
+            Self::Deadline => {
+                settings.swap.deadline_secs = match reset {
+                    true => None,
+                    false => match text.parse::<u64>() {
+                        Ok(secs) if (MIN_DEADLINE_SECS..=MAX_DEADLINE_SECS).contains(&secs) => {
+                            Some(secs)
+                        }
+                        _ => {
+                            return Err(format!(
+                                "Please enter a number of seconds between {} and {}",
+                                MIN_DEADLINE_SECS, MAX_DEADLINE_SECS
+                            ))
+                        }
+                    },
+                }
+            }
+            Self::MaxFee => {
+                settings.swap.gas.max_fee_gwei = match reset {
+                    true => None,
+                    false => Some(positive()?),
+                }
+            }
+            Self::PriorityFee => {
+                settings.swap.gas.priority_fee_gwei = match reset {
+                    true => None,
+                    false => Some(positive()?),
+                }
+            }
+            Self::BuyAmount => {
+                settings.buy_amount = match reset {
+                    true => None,
+                    false => Some(positive()?),
+                }
+            }
+        }
+        // the custom mode can't be kept without its fees
+        let gas = &mut settings.swap.gas;
+        if gas.mode == GasMode::Custom
+            && (gas.max_fee_gwei.is_none() || gas.priority_fee_gwei.is_none())
+        {
+            gas.mode = GasMode::default();
+        }
+        Ok(())
+    }
+}
+
+/// Parses a slippage entered in percent, like "0.5" or "0.5%", into basis points
+pub(crate) fn parse_slippage(text: &str) -> Result<u64, String> {
+    match text.trim().trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent * 100.0 <= MAX_SLIPPAGE_BPS as f64 => {
+            Ok((percent * 100.0).round().max(1.0) as u64)
+        }
+        _ => Err(format!(
+            "Please enter a slippage between 0 and {}%",
+            MAX_SLIPPAGE_BPS / 100
+        )),
+    }
+}
+
+/// Describes the settings in effect, the unset ones showing the bot's default
+fn settings_text(settings: &UserSettings) -> anyhow::Result<String> {
+    let config = settings.swap.apply(&GLOBAL_SWAP_CONFIG);
+    let default = |set: bool| if set { "" } else { " (default)" };
+    let gas = &settings.swap.gas;
+    let fee = |fee: Option<f64>| match fee {
+        Some(fee) => format!("{} Gwei", fee),
+        None => "not set".to_string(),
+    };
+    Ok(format!(
+        "Settings\nSlippage: {}%{}\nDeadline: {}s{}\nGas: {}\nCustom max fee: {}\nCustom priority fee: {}\nDefault buy amount: {}\n\nNew buy and sell menus start from these settings",
+        config.slippage_bps as f64 / 100.0,
+        default(settings.swap.slippage_bps.is_some()),
+        config.deadline_secs,
+        default(settings.swap.deadline_secs.is_some()),
+        gas_mode_label(gas.mode),
+        fee(gas.max_fee_gwei),
+        fee(gas.priority_fee_gwei),
+        settings
+            .buy_amount
+            .map(|amount| amount.to_string())
+            .unwrap_or_else(|| "not set".to_string()),
+    ))
+}
+
+/// Sends the settings menu of the user and tracks it, so the prompts can re-render it
+async fn send_settings_menu(bot: &Bot, chat_id: ChatId, user_id: UserId) -> Result<(), TgError> {
+    let settings = GLOBAL_SETTINGS_STORE.get(user_id).await?;
+    let default_wallet = GLOBAL_WALLET_STORE.default_wallet(user_id).await?;
+    let message_sent = bot
+        .send_message(chat_id, settings_text(&settings)?)
+        .reply_markup(settings_keyboard(
+            new_session_id(),
+            &settings,
+            default_wallet,
+        )?)
+        .await?;
+
+    let message = TgMessage {
+        chat_id: message_sent.chat.id,
+        message_id: message_sent.id,
+        message: Arc::new(message_sent),
+    };
+    GLOBAL_MENU_STORE
+        .insert(MenuKind::Settings, DraftKey { chat_id, user_id }, message)
+        .await?;
+    Ok(())
+}
+
+/// Helper function to re-render the settings menu of the user
+async fn edit_settings_menu(
+    bot: &Bot,
+    (chat_id, message_id): (ChatId, MessageId),
+    user_id: UserId,
+    session: u32,
+) -> Result<(), TgError> {
+    let settings = GLOBAL_SETTINGS_STORE.get(user_id).await?;
+    let default_wallet = GLOBAL_WALLET_STORE.default_wallet(user_id).await?;
+    bot.edit_message_text(chat_id, message_id, settings_text(&settings)?)
+        .reply_markup(settings_keyboard(session, &settings, default_wallet)?)
+        .await?;
+    Ok(())
+}
+
+pub(crate) async fn settings_command(bot: &Bot, msg: &Message) -> Result<(), TgError> {
+    let user_id = match msg.from() {
+        Some(user) => user.id,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+    send_settings_menu(bot, msg.chat.id, user_id).await
+}
+
+/// Handles the Settings button of the main menu and the buttons of the settings menu
+pub(crate) async fn handle_settings_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    data: &CallbackData,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    let (key, msg) = match (DraftKey::from_callback(q), &q.message) {
+        (Some(key), Some(msg)) => (key, msg),
+        _ => return Err(TgError::NoQueryMessage(Box::new(q.clone()))),
+    };
+    let (chat_id, message_id, user_id) = (msg.chat.id, msg.id, q.from.id);
+
+    if let Some(field) = SettingField::from_action(&data.action) {
+        bot.answer_callback_query(&q.id).await?;
+        // the settings dialogue handler re-renders this message
+        let message = TgMessage {
+            chat_id,
+            message_id,
+            message: Arc::new(msg.clone()),
+        };
+        GLOBAL_MENU_STORE
+            .insert(MenuKind::Settings, key, message)
+            .await?;
+        storage
+            .update_dialogue(
+                key.dialogue_id(),
+                PromptDialogueState::SettingReceived(field),
+            )
+            .await?;
+        bot.send_message(chat_id, field.prompt()).await?;
+        return Ok(());
+    }
+
+    match data.action {
+        CallbackAction::Settings => {
+            bot.answer_callback_query(&q.id).await?;
+            send_settings_menu(bot, chat_id, user_id).await?;
+        }
+        CallbackAction::GasMode { mode, .. } => {
+            let settings = GLOBAL_SETTINGS_STORE.get(user_id).await?;
+            let gas = settings.swap.gas;
+            if mode == GasMode::Custom
+                && (gas.max_fee_gwei.is_none() || gas.priority_fee_gwei.is_none())
+            {
+                bot.answer_callback_query(&q.id)
+                    .text("Set the max fee and priority fee first")
+                    .await?;
+                return Ok(());
+            }
+            bot.answer_callback_query(&q.id).await?;
+            GLOBAL_SETTINGS_STORE
+                .update(user_id, move |settings| settings.swap.gas.mode = mode)
+                .await?;
+            edit_settings_menu(bot, (chat_id, message_id), user_id, data.session).await?;
+        }
+        CallbackAction::Wallet { index, .. } => {
+            // wallets are generated on /start
+            let wallets = GLOBAL_WALLET_STORE.wallets(user_id).await?;
+            let wallet = match wallets.get(index as usize) {
+                Some(wallet) => wallet,
+                None => {
+                    bot.answer_callback_query(&q.id)
+                        .text(format!(
+                            "Wallet {} not found, use /start to create your wallets",
+                            index + 1
+                        ))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            GLOBAL_WALLET_STORE
+                .set_default(user_id, wallet.address)
+                .await?;
+            bot.answer_callback_query(&q.id)
+                .text("Default wallet updated")
+                .await?;
+            edit_settings_menu(bot, (chat_id, message_id), user_id, data.session).await?;
+        }
+        _ => {
+            bot.answer_callback_query(&q.id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Saves the setting entered by the user and re-renders the stored settings menu
+pub(crate) async fn settings_dialogue_handler(
+    bot: Bot,
+    dialogue: BuyAddressPromptDialogue,
+    field: SettingField,
+    msg: Message,
+) -> Result<(), TgError> {
+    let text = match msg.text() {
+        Some(text) => text,
+        None => {
+            bot.send_message(msg.chat.id, "Send me plain text.").await?;
+            return Ok(());
+        }
+    };
+    let key = match DraftKey::from_message(&msg) {
+        Some(key) => key,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+
+    let mut settings = GLOBAL_SETTINGS_STORE.get(key.user_id).await?;
+    if let Err(err) = field.apply(&mut settings, text) {
+        bot.send_message(msg.chat.id, err).await?;
+        return Ok(());
+    }
+    GLOBAL_SETTINGS_STORE
+        .update(key.user_id, move |stored| *stored = settings)
+        .await?;
+    dialogue.exit().await?;
+
+    match GLOBAL_MENU_STORE.get(MenuKind::Settings, &key).await? {
+        Some(menu) => {
+            let session = menu
+                .message
+                .reply_markup()
+                .and_then(|keyboard| decode_keyboard(keyboard).first().map(|data| data.session))
+                .unwrap_or_else(new_session_id);
+            edit_settings_menu(&bot, (menu.chat_id, menu.message_id), key.user_id, session).await?;
+            delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, menu.message_id.0).await?;
+        }
+        None => {
+            bot.send_message(msg.chat.id, "Settings updated").await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,365 @@
+use crate::bot::TgError;
+use crate::handlers::dialogue_handlers::{BuyAddressPromptDialogue, PromptDialogueState};
+use crate::handlers::{delete_up_to_messages, format_eth};
+use crate::keyboards::callback_data::{
+    decode_keyboard, new_session_id, CallbackAction, CallbackData,
+};
+use crate::keyboards::wallet_buttons::{import_wallet_keyboard, wallet_keyboard};
+use crate::requests::providers::ProviderRegistry;
+use crate::storages::{
+    DraftKey, MenuKind, TgMessage, WalletEntry, GLOBAL_CHAIN_REGISTRY, GLOBAL_MENU_STORE,
+    GLOBAL_WALLET_STORE, MAX_WALLETS,
+};
+use ethers::types::Address;
+use futures::future::join_all;
+use std::sync::Arc;
+use teloxide::dispatching::dialogue::ErasedStorage;
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    prelude::Requester,
+    types::{CallbackQuery, ChatId, Message, MessageId, UserId},
+    Bot,
+};
+
+const MAX_WALLET_NAME_LEN: usize = 32;
+
+/// Renders the text of a wallet of the /wallets listing, the balance and nonce are queried
+/// from every enabled chain
+async fn wallet_text(
+    providers: &ProviderRegistry,
+    position: usize,
+    wallet: &WalletEntry,
+    is_default: bool,
+) -> String {
+    let accounts = join_all(
+        GLOBAL_CHAIN_REGISTRY
+            .chains()
+            .iter()
+            .map(|chain| async move {
+                let account = match providers.query(chain.chain_id) {
+                    Ok(query) => query.query_account(wallet.address).await,
+                    Err(err) => Err(err),
+                };
+                (chain, account)
+            }),
+    )
+    .await;
+
+    let mut text = format!(
+        "{}. {}{}\nAddress: {:#x}",
+        position + 1,
+        wallet.name,
+        if is_default { " (default)" } else { "" },
+        wallet.address
+    );
+    for (chain, account) in accounts {
+        match account {
+            Ok((balance, nonce)) => text.push_str(&format!(
+                "\n{}: {} {}  ═  Nonce: {}",
+                chain.name,
+                format_eth(balance),
+                chain.native_symbol,
+                nonce
+            )),
+            Err(err) => {
+                log::warn!(
+                    "Unable to query the account {:#x} on {}: {}",
+                    wallet.address,
+                    chain.name,
+                    err
+                );
+                text.push_str(&format!("\n{}: unavailable", chain.name));
+            }
+        }
+    }
+    text
+}
+
+/// Helper function to re-render the message of a wallet of the /wallets listing
+async fn edit_wallet_message(
+    bot: &Bot,
+    providers: &ProviderRegistry,
+    (chat_id, message_id): (ChatId, MessageId),
+    user_id: UserId,
+    session: u32,
+    address: Address,
+    confirm_delete: bool,
+) -> Result<(), TgError> {
+    let wallets = GLOBAL_WALLET_STORE.wallets(user_id).await?;
+    let default = GLOBAL_WALLET_STORE.default_wallet(user_id).await? as usize;
+    let position = wallets
+        .iter()
+        .position(|wallet| wallet.address == address)
+        .ok_or_else(|| anyhow::anyhow!("Wallet {:#x} not found", address))?;
+
+    let text = wallet_text(providers, position, &wallets[position], position == default).await;
+    let keyboard = wallet_keyboard(session, address, position == default, confirm_delete)?;
+    bot.edit_message_text(chat_id, message_id, text)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+/// Lists the user's wallets, one message with its own keyboard per wallet
+pub(crate) async fn wallets_command(
+    bot: &Bot,
+    msg: &Message,
+    providers: &ProviderRegistry,
+) -> Result<(), TgError> {
+    let user_id = match msg.from() {
+        Some(user) => user.id,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+    let wallets = GLOBAL_WALLET_STORE.wallets(user_id).await?;
+    let default = GLOBAL_WALLET_STORE.default_wallet(user_id).await? as usize;
+    let session = new_session_id();
+
+    if wallets.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "You have no wallets yet, use /start to create them or import one",
+        )
+        .reply_markup(import_wallet_keyboard(session)?)
+        .await?;
+        return Ok(());
+    }
+
+    for (position, wallet) in wallets.iter().enumerate() {
+        let text = wallet_text(providers, position, wallet, position == default).await;
+        bot.send_message(msg.chat.id, text)
+            .reply_markup(wallet_keyboard(
+                session,
+                wallet.address,
+                position == default,
+                false,
+            )?)
+            .await?;
+    }
+
+    if wallets.len() < MAX_WALLETS {
+        bot.send_message(
+            msg.chat.id,
+            format!("You can have up to {} wallets", MAX_WALLETS),
+        )
+        .reply_markup(import_wallet_keyboard(session)?)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Handles the buttons of the /wallets listing
+pub(crate) async fn handle_wallets_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    providers: &ProviderRegistry,
+    data: &CallbackData,
+    storage: Arc<ErasedStorage<PromptDialogueState>>,
+) -> Result<(), TgError> {
+    let (key, msg) = match (DraftKey::from_callback(q), &q.message) {
+        (Some(key), Some(msg)) => (key, msg),
+        _ => return Err(TgError::NoQueryMessage(Box::new(q.clone()))),
+    };
+    let (chat_id, message_id, user_id) = (msg.chat.id, msg.id, q.from.id);
+
+    match data.action {
+        CallbackAction::RenameWallet(address) => {
+            bot.answer_callback_query(&q.id).await?;
+            // the rename dialogue handler re-renders this message
+            let message = TgMessage {
+                chat_id,
+                message_id,
+                message: Arc::new(msg.clone()),
+            };
+            GLOBAL_MENU_STORE
+                .insert(MenuKind::Wallets, key, message)
+                .await?;
+            storage
+                .update_dialogue(
+                    key.dialogue_id(),
+                    PromptDialogueState::WalletRenameReceived(address),
+                )
+                .await?;
+            bot.send_message(chat_id, "Enter the new name of the wallet")
+                .await?;
+        }
+        CallbackAction::SetDefaultWallet { address, .. } => {
+            GLOBAL_WALLET_STORE.set_default(user_id, address).await?;
+            bot.answer_callback_query(&q.id)
+                .text("Default wallet updated")
+                .await?;
+            edit_wallet_message(
+                bot,
+                providers,
+                (chat_id, message_id),
+                user_id,
+                data.session,
+                address,
+                false,
+            )
+            .await?;
+        }
+        CallbackAction::ExportWallet(address) => {
+            // never leak a key into a group
+            if !msg.chat.is_private() {
+                bot.answer_callback_query(&q.id)
+                    .text("Keys can only be exported in a private chat with the bot")
+                    .await?;
+                return Ok(());
+            }
+            bot.answer_callback_query(&q.id).await?;
+            let private_key = GLOBAL_WALLET_STORE.export(user_id, address).await?;
+            bot.send_message(
+                chat_id,
+                format!(
+                    "Private key of {:#x}:\n{}\n\nAnyone with this key controls the wallet, delete this message once the key is saved",
+                    address, private_key
+                ),
+            )
+            .await?;
+        }
+        CallbackAction::DeleteWallet(address) => {
+            bot.answer_callback_query(&q.id)
+                .text("Click Confirm Delete to delete the wallet, its key is lost unless exported")
+                .await?;
+            edit_wallet_message(
+                bot,
+                providers,
+                (chat_id, message_id),
+                user_id,
+                data.session,
+                address,
+                true,
+            )
+            .await?;
+        }
+        CallbackAction::ConfirmDeleteWallet(address) => {
+            let wallet = GLOBAL_WALLET_STORE.delete(user_id, address).await?;
+            bot.answer_callback_query(&q.id).await?;
+            bot.edit_message_text(
+                chat_id,
+                message_id,
+                format!("{} ({:#x}) deleted", wallet.name, wallet.address),
+            )
+            .await?;
+        }
+        CallbackAction::ImportWallet => {
+            if !msg.chat.is_private() {
+                bot.answer_callback_query(&q.id)
+                    .text("Keys can only be imported in a private chat with the bot")
+                    .await?;
+                return Ok(());
+            }
+            bot.answer_callback_query(&q.id).await?;
+            storage
+                .update_dialogue(key.dialogue_id(), PromptDialogueState::WalletImportReceived)
+                .await?;
+            bot.send_message(
+                chat_id,
+                "Send the private key of the wallet to import, your message is deleted right away",
+            )
+            .await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub(crate) async fn wallet_rename_dialogue_handler(
+    bot: Bot,
+    dialogue: BuyAddressPromptDialogue,
+    address: Address,
+    msg: Message,
+    providers: Arc<ProviderRegistry>,
+) -> Result<(), TgError> {
+    let name = match msg.text().map(str::trim) {
+        Some(name) if !name.is_empty() && name.chars().count() <= MAX_WALLET_NAME_LEN => name,
+        _ => {
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Please enter a name of at most {} characters",
+                    MAX_WALLET_NAME_LEN
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let key = match DraftKey::from_message(&msg) {
+        Some(key) => key,
+        None => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+    };
+
+    GLOBAL_WALLET_STORE
+        .rename(key.user_id, address, name.to_string())
+        .await?;
+    dialogue.exit().await?;
+
+    match GLOBAL_MENU_STORE.remove(MenuKind::Wallets, &key).await? {
+        Some(menu) => {
+            let session = menu
+                .message
+                .reply_markup()
+                .and_then(|keyboard| decode_keyboard(keyboard).first().map(|data| data.session))
+                .unwrap_or_else(new_session_id);
+            edit_wallet_message(
+                &bot,
+                &providers,
+                (menu.chat_id, menu.message_id),
+                key.user_id,
+                session,
+                address,
+                false,
+            )
+            .await?;
+            delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, menu.message_id.0).await?;
+        }
+        None => {
+            bot.send_message(msg.chat.id, format!("Wallet renamed to {}", name))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn wallet_import_dialogue_handler(
+    bot: Bot,
+    dialogue: BuyAddressPromptDialogue,
+    msg: Message,
+    providers: Arc<ProviderRegistry>,
+) -> Result<(), TgError> {
+    // the message holds a private key, it must not stay in the chat
+    let _ = bot.delete_message(msg.chat.id, msg.id).await;
+    dialogue.exit().await?;
+
+    let (user_id, private_key) = match (msg.from(), msg.text()) {
+        (Some(user), Some(text)) => (user.id, text),
+        (None, _) => return Err(TgError::UserNotFound(Box::new(msg.clone()))),
+        (_, None) => {
+            bot.send_message(msg.chat.id, "Send me plain text.").await?;
+            return Ok(());
+        }
+    };
+
+    match GLOBAL_WALLET_STORE.import(user_id, private_key).await {
+        Ok(wallet) => {
+            let wallets = GLOBAL_WALLET_STORE.wallets(user_id).await?;
+            let default = GLOBAL_WALLET_STORE.default_wallet(user_id).await? as usize;
+            let position = wallets.len() - 1;
+            let text = wallet_text(&providers, position, &wallet, position == default).await;
+            bot.send_message(msg.chat.id, text)
+                .reply_markup(wallet_keyboard(
+                    new_session_id(),
+                    wallet.address,
+                    position == default,
+                    false,
+                )?)
+                .await?;
+        }
+        Err(err) => {
+            bot.send_message(msg.chat.id, format!("Unable to import the wallet: {}", err))
+                .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,864 @@
+use crate::config::GasMode;
+use crate::consts::{
+    ABORT_SWAP, BACK, BUY, BUY_AMOUNT, BUY_TOKEN, CANCEL_ORDER, CLOSE, CONFIRM_DELETE_WALLET,
+    CONFIRM_SWAP, DEADLINE, DEFAULT_BUY_AMOUNT, DEFAULT_WALLET, DELETE_WALLET, EDIT_EXPIRY,
+    EDIT_ORDER, EDIT_TRIGGER, ENTRY_PERCENT, ESTIMATED_RECEIVED_AMOUNT, EXPIRY, EXPORT_WALLET, GAS,
+    GAS_CUSTOM, GAS_FAST, GAS_NORMAL, GAS_SLOW, IMPORT_WALLET, LIMIT, LIMIT_BUY, LIMIT_SELL,
+    MAIN_MENU, MARKET_CAP, MAX_FEE, NEXT_PAGE, OPEN_ORDERS, PLACE_LIMIT_ORDER, PREVIOUS_PAGE,
+    PRICE, PRIORITY_FEE, PRIVATE_TX, REBATE, RECEIVE_TOKEN, RENAME_WALLET, SELECT_WALLET, SELL,
+    SELL_AMOUNT, SELL_PERCENTAGE, SELL_TOKEN, SEND_BUY_TX, SEND_SELL_TX, SETTINGS,
+    SET_DEFAULT_WALLET, SLIPPAGE, STOP_LOSS, TRAILING_STOP, TRAIL_PERCENT, TRIGGER, WALLET_1,
+    WALLET_2, WALLET_3,
+};
+use crate::keyboards::add_emoji;
+use crate::requests::on_chain::chain_name;
+use crate::storages::{LimitKind, TriggerUnit};
+use ethers::types::Address;
+use std::str::FromStr;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup};
+
+/// Version of the callback data encoding, bump it on any incompatible change so that
+/// buttons of menus sent by an older version are rejected instead of misread
+pub(crate) const CALLBACK_DATA_VERSION: u8 = 1;
+
+/// Telegram rejects callback data longer than 64 bytes
+pub(crate) const MAX_CALLBACK_DATA_LEN: usize = 64;
+
+const SEPARATOR: char = ':';
+
+/// Significant digits kept of the numbers a button carries, the exact value stays in the draft
+const NUMBER_DIGITS: usize = 6;
+
+/// Encodes a number in at most 13 bytes whatever its magnitude, like 1.23457e-1
+fn encode_number(value: f64) -> String {
+    let rounded: f64 = format!("{:.*e}", NUMBER_DIGITS - 1, value)
+        .parse()
+        .unwrap_or(value);
+    format!("{:e}", rounded)
+}
+
+/// The menu a button belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum MenuType {
+    Main,
+    Buy,
+    Sell,
+    Wallets,
+    History,
+    Settings,
+    Orders,
+}
+
+impl MenuType {
+    fn tag(&self) -> &'static str {
+        match self {
+            Self::Main => "m",
+            Self::Buy => "b",
+            Self::Sell => "s",
+            Self::Wallets => "w",
+            Self::History => "h",
+            Self::Settings => "g",
+            Self::Orders => "o",
+        }
+    }
+
+    fn from_tag(tag: &str) -> anyhow::Result<Self> {
+        match tag {
+            "m" => Ok(Self::Main),
+            "b" => Ok(Self::Buy),
+            "s" => Ok(Self::Sell),
+            "w" => Ok(Self::Wallets),
+            "h" => Ok(Self::History),
+            "g" => Ok(Self::Settings),
+            "o" => Ok(Self::Orders),
+            _ => Err(anyhow::anyhow!("Unknown menu tag: {}", tag)),
+        }
+    }
+}
+
+fn gas_mode_tag(mode: GasMode) -> &'static str {
+    match mode {
+        GasMode::Slow => "s",
+        GasMode::Normal => "n",
+        GasMode::Fast => "f",
+        GasMode::Custom => "c",
+    }
+}
+
+fn gas_mode_from_tag(tag: &str) -> anyhow::Result<GasMode> {
+    match tag {
+        "s" => Ok(GasMode::Slow),
+        "n" => Ok(GasMode::Normal),
+        "f" => Ok(GasMode::Fast),
+        "c" => Ok(GasMode::Custom),
+        _ => Err(anyhow::anyhow!("Unknown gas mode: {}", tag)),
+    }
+}
+
+pub(crate) fn gas_mode_label(mode: GasMode) -> &'static str {
+    match mode {
+        GasMode::Slow => GAS_SLOW,
+        GasMode::Normal => GAS_NORMAL,
+        GasMode::Fast => GAS_FAST,
+        GasMode::Custom => GAS_CUSTOM,
+    }
+}
+
+fn limit_kind_tag(kind: LimitKind) -> &'static str {
+    match kind {
+        LimitKind::Limit => "l",
+        LimitKind::StopLoss => "s",
+        LimitKind::TrailingStop => "t",
+    }
+}
+
+fn limit_kind_from_tag(tag: &str) -> anyhow::Result<LimitKind> {
+    match tag {
+        "l" => Ok(LimitKind::Limit),
+        "s" => Ok(LimitKind::StopLoss),
+        "t" => Ok(LimitKind::TrailingStop),
+        _ => Err(anyhow::anyhow!("Unknown limit kind: {}", tag)),
+    }
+}
+
+pub(crate) fn limit_kind_label(kind: LimitKind) -> &'static str {
+    match kind {
+        LimitKind::Limit => LIMIT,
+        LimitKind::StopLoss => STOP_LOSS,
+        LimitKind::TrailingStop => TRAILING_STOP,
+    }
+}
+
+fn trigger_unit_tag(unit: TriggerUnit) -> &'static str {
+    match unit {
+        TriggerUnit::Price => "p",
+        TriggerUnit::EntryPercent => "e",
+        TriggerUnit::MarketCap => "m",
+        TriggerUnit::TrailPercent => "t",
+    }
+}
+
+fn trigger_unit_from_tag(tag: &str) -> anyhow::Result<TriggerUnit> {
+    match tag {
+        "p" => Ok(TriggerUnit::Price),
+        "e" => Ok(TriggerUnit::EntryPercent),
+        "m" => Ok(TriggerUnit::MarketCap),
+        "t" => Ok(TriggerUnit::TrailPercent),
+        _ => Err(anyhow::anyhow!("Unknown trigger unit: {}", tag)),
+    }
+}
+
+pub(crate) fn trigger_unit_label(unit: TriggerUnit) -> &'static str {
+    match unit {
+        TriggerUnit::Price => PRICE,
+        TriggerUnit::EntryPercent => ENTRY_PERCENT,
+        TriggerUnit::MarketCap => MARKET_CAP,
+        TriggerUnit::TrailPercent => TRAIL_PERCENT,
+    }
+}
+
+/// Formats a duration in seconds with its largest whole unit, like 2h
+pub(crate) fn format_duration(secs: u64) -> String {
+    match secs {
+        secs if secs >= 86_400 && secs % 86_400 == 0 => format!("{}d", secs / 86_400),
+        secs if secs >= 3_600 && secs % 3_600 == 0 => format!("{}h", secs / 3_600),
+        secs if secs >= 60 && secs % 60 == 0 => format!("{}m", secs / 60),
+        secs => format!("{}s", secs),
+    }
+}
+
+/// Every action a button can trigger, along with the state the button displays
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CallbackAction {
+    Buy,
+    Sell,
+    LimitBuy,
+    LimitSell,
+    MainMenu,
+    Close,
+    SelectWallet,
+    Chain {
+        chain_id: u64,
+        selected: bool,
+    },
+    PrivateTx(bool),
+    Rebate(bool),
+    /// `index` is zero based
+    Wallet {
+        index: u8,
+        selected: bool,
+    },
+    BuyToken(Option<Address>),
+    ReceiveToken(Option<Address>),
+    SellToken(Option<Address>),
+    BuyAmount(Option<f64>),
+    SellAmount(Option<f64>),
+    SellPercentage(Option<f64>),
+    PresetPercentage {
+        percentage: u8,
+        selected: bool,
+    },
+    EstimatedReceivedAmount,
+    /// One of the tokens matching the name or symbol the user entered
+    SelectToken(Address),
+    SendBuyTx,
+    SendSellTx,
+    /// The wallet actions carry the address rather than the index, which shifts on deletes
+    RenameWallet(Address),
+    SetDefaultWallet {
+        address: Address,
+        selected: bool,
+    },
+    ExportWallet(Address),
+    DeleteWallet(Address),
+    ConfirmDeleteWallet(Address),
+    ImportWallet,
+    /// The history actions carry the zero based page to show
+    PreviousHistoryPage(u32),
+    NextHistoryPage(u32),
+    BackToHistory(u32),
+    TradeDetail {
+        id: u64,
+        page: u32,
+    },
+    Settings,
+    /// In basis points
+    Slippage(Option<u64>),
+    /// In seconds
+    Deadline(Option<u64>),
+    /// One of the gas modes of the settings menu
+    GasMode {
+        mode: GasMode,
+        selected: bool,
+    },
+    /// Switches the gas mode of a buy or sell menu to the next one
+    CycleGasMode(GasMode),
+    /// In Gwei
+    MaxFee(Option<f64>),
+    /// In Gwei
+    PriorityFee(Option<f64>),
+    DefaultBuyAmount(Option<f64>),
+    /// One of the kinds of limit orders of a limit sell menu
+    LimitKind {
+        kind: LimitKind,
+        selected: bool,
+    },
+    /// One of the units the trigger of a limit menu can be entered in
+    TriggerUnit {
+        unit: TriggerUnit,
+        selected: bool,
+    },
+    /// Trigger value of a limit menu, in `unit`
+    Trigger {
+        unit: TriggerUnit,
+        value: Option<f64>,
+    },
+    /// In seconds
+    Expiry(Option<u64>),
+    PlaceLimitOrder,
+    OpenOrders,
+    /// The order actions carry the zero based page of the /orders listing to return to
+    PreviousOrdersPage(u32),
+    NextOrdersPage(u32),
+    BackToOrders(u32),
+    EditOrder {
+        id: u64,
+        page: u32,
+    },
+    CancelOrder {
+        id: u64,
+        page: u32,
+    },
+    EditTrigger {
+        id: u64,
+        page: u32,
+    },
+    EditExpiry {
+        id: u64,
+        page: u32,
+    },
+    /// The confirmation card actions carry the nonce of the card
+    ConfirmSwap(u32),
+    AbortSwap(u32),
+}
+
+impl CallbackAction {
+    fn tag_and_arg(&self) -> (&'static str, Option<String>) {
+        let flag = |on: bool| if on { "1" } else { "0" };
+        match self {
+            Self::Buy => ("b", None),
+            Self::Sell => ("s", None),
+            Self::LimitBuy => ("lb", None),
+            Self::LimitSell => ("ls", None),
+            Self::MainMenu => ("mm", None),
+            Self::Close => ("x", None),
+            Self::SelectWallet => ("sw", None),
+            Self::Chain { chain_id, selected } => {
+                ("c", Some(format!("{},{}", chain_id, flag(*selected))))
+            }
+            Self::PrivateTx(on) => ("pt", Some(flag(*on).to_string())),
+            Self::Rebate(on) => ("rb", Some(flag(*on).to_string())),
+            Self::Wallet { index, selected } => {
+                ("w", Some(format!("{},{}", index, flag(*selected))))
+            }
+            Self::BuyToken(address) => ("bt", address.map(|address| format!("{:x}", address))),
+            Self::ReceiveToken(address) => ("rt", address.map(|address| format!("{:x}", address))),
+            Self::SellToken(address) => ("st", address.map(|address| format!("{:x}", address))),
+            Self::BuyAmount(amount) => ("ba", amount.map(encode_number)),
+            Self::SellAmount(amount) => ("sa", amount.map(encode_number)),
+            Self::SellPercentage(percentage) => ("sp", percentage.map(encode_number)),
+            Self::PresetPercentage {
+                percentage,
+                selected,
+            } => ("pp", Some(format!("{},{}", percentage, flag(*selected)))),
+            Self::EstimatedReceivedAmount => ("er", None),
+            Self::SelectToken(address) => ("tk", Some(format!("{:x}", address))),
+            Self::SendBuyTx => ("sb", None),
+            Self::SendSellTx => ("ss", None),
+            Self::RenameWallet(address) => ("wr", Some(format!("{:x}", address))),
+            Self::SetDefaultWallet { address, selected } => {
+                ("wd", Some(format!("{:x},{}", address, flag(*selected))))
+            }
+            Self::ExportWallet(address) => ("we", Some(format!("{:x}", address))),
+            Self::DeleteWallet(address) => ("wx", Some(format!("{:x}", address))),
+            Self::ConfirmDeleteWallet(address) => ("wy", Some(format!("{:x}", address))),
+            Self::ImportWallet => ("wi", None),
+            Self::PreviousHistoryPage(page) => ("hv", Some(page.to_string())),
+            Self::NextHistoryPage(page) => ("hn", Some(page.to_string())),
+            Self::BackToHistory(page) => ("hb", Some(page.to_string())),
+            Self::TradeDetail { id, page } => ("hd", Some(format!("{},{}", id, page))),
+            Self::Settings => ("cf", None),
+            Self::Slippage(bps) => ("sl", bps.map(|bps| bps.to_string())),
+            Self::Deadline(secs) => ("dl", secs.map(|secs| secs.to_string())),
+            Self::GasMode { mode, selected } => (
+                "gm",
+                Some(format!("{},{}", gas_mode_tag(*mode), flag(*selected))),
+            ),
+            Self::CycleGasMode(mode) => ("gc", Some(gas_mode_tag(*mode).to_string())),
+            Self::MaxFee(fee) => ("mf", fee.map(encode_number)),
+            Self::PriorityFee(fee) => ("pf", fee.map(encode_number)),
+            Self::DefaultBuyAmount(amount) => ("da", amount.map(encode_number)),
+            Self::LimitKind { kind, selected } => (
+                "lk",
+                Some(format!("{},{}", limit_kind_tag(*kind), flag(*selected))),
+            ),
+            Self::TriggerUnit { unit, selected } => (
+                "tu",
+                Some(format!("{},{}", trigger_unit_tag(*unit), flag(*selected))),
+            ),
+            Self::Trigger { unit, value } => (
+                "tv",
+                Some(match value {
+                    Some(value) => format!("{},{}", trigger_unit_tag(*unit), encode_number(*value)),
+                    None => trigger_unit_tag(*unit).to_string(),
+                }),
+            ),
+            Self::Expiry(secs) => ("ex", secs.map(|secs| secs.to_string())),
+            Self::PlaceLimitOrder => ("po", None),
+            Self::OpenOrders => ("oo", None),
+            Self::PreviousOrdersPage(page) => ("ov", Some(page.to_string())),
+            Self::NextOrdersPage(page) => ("on", Some(page.to_string())),
+            Self::BackToOrders(page) => ("ob", Some(page.to_string())),
+            Self::EditOrder { id, page } => ("oe", Some(format!("{},{}", id, page))),
+            Self::CancelOrder { id, page } => ("oc", Some(format!("{},{}", id, page))),
+            Self::EditTrigger { id, page } => ("ot", Some(format!("{},{}", id, page))),
+            Self::EditExpiry { id, page } => ("ox", Some(format!("{},{}", id, page))),
+            Self::ConfirmSwap(nonce) => ("cs", Some(format!("{:x}", nonce))),
+            Self::AbortSwap(nonce) => ("ab", Some(format!("{:x}", nonce))),
+        }
+    }
+
+    fn from_tag(tag: &str, arg: Option<&str>) -> anyhow::Result<Self> {
+        let flag = |value: &str| match value {
+            "1" => Ok(true),
+            "0" => Ok(false),
+            _ => Err(anyhow::anyhow!("Invalid flag: {}", value)),
+        };
+        let required = || arg.ok_or_else(|| anyhow::anyhow!("Missing argument for {}", tag));
+        let pair = || -> anyhow::Result<(u8, bool)> {
+            let (number, selected) = required()?
+                .split_once(',')
+                .ok_or_else(|| anyhow::anyhow!("Invalid argument for {}", tag))?;
+            Ok((number.parse()?, flag(selected)?))
+        };
+        let address = || arg.map(Address::from_str).transpose();
+        let required_address =
+            || -> anyhow::Result<Address> { Ok(Address::from_str(required()?)?) };
+        let number = || arg.map(f64::from_str).transpose();
+        let order = || -> anyhow::Result<(u64, u32)> {
+            let (id, page) = required()?
+                .split_once(',')
+                .ok_or_else(|| anyhow::anyhow!("Invalid argument for {}", tag))?;
+            Ok((id.parse()?, page.parse()?))
+        };
+
+        let action = match tag {
+            "b" => Self::Buy,
+            "s" => Self::Sell,
+            "lb" => Self::LimitBuy,
+            "ls" => Self::LimitSell,
+            "mm" => Self::MainMenu,
+            "x" => Self::Close,
+            "sw" => Self::SelectWallet,
+            "c" => {
+                let (chain_id, selected) = required()?
+                    .split_once(',')
+                    .ok_or_else(|| anyhow::anyhow!("Invalid argument for {}", tag))?;
+                Self::Chain {
+                    chain_id: chain_id.parse()?,
+                    selected: flag(selected)?,
+                }
+            }
+            "pt" => Self::PrivateTx(flag(required()?)?),
+            "rb" => Self::Rebate(flag(required()?)?),
+            "w" => {
+                let (index, selected) = pair()?;
+                Self::Wallet { index, selected }
+            }
+            "bt" => Self::BuyToken(address()?),
+            "rt" => Self::ReceiveToken(address()?),
+            "st" => Self::SellToken(address()?),
+            "ba" => Self::BuyAmount(number()?),
+            "sa" => Self::SellAmount(number()?),
+            "sp" => Self::SellPercentage(number()?),
+            "pp" => {
+                let (percentage, selected) = pair()?;
+                Self::PresetPercentage {
+                    percentage,
+                    selected,
+                }
+            }
+            "er" => Self::EstimatedReceivedAmount,
+            "tk" => Self::SelectToken(required_address()?),
+            "sb" => Self::SendBuyTx,
+            "ss" => Self::SendSellTx,
+            "wr" => Self::RenameWallet(required_address()?),
+            "wd" => {
+                let (address, selected) = required()?
+                    .split_once(',')
+                    .ok_or_else(|| anyhow::anyhow!("Invalid argument for {}", tag))?;
+                Self::SetDefaultWallet {
+                    address: Address::from_str(address)?,
+                    selected: flag(selected)?,
+                }
+            }
+            "we" => Self::ExportWallet(required_address()?),
+            "wx" => Self::DeleteWallet(required_address()?),
+            "wy" => Self::ConfirmDeleteWallet(required_address()?),
+            "wi" => Self::ImportWallet,
+            "hv" => Self::PreviousHistoryPage(required()?.parse()?),
+            "hn" => Self::NextHistoryPage(required()?.parse()?),
+            "hb" => Self::BackToHistory(required()?.parse()?),
+            "hd" => {
+                let (id, page) = required()?
+                    .split_once(',')
+                    .ok_or_else(|| anyhow::anyhow!("Invalid argument for {}", tag))?;
+                Self::TradeDetail {
+                    id: id.parse()?,
+                    page: page.parse()?,
+                }
+            }
+            "cf" => Self::Settings,
+            "sl" => Self::Slippage(arg.map(u64::from_str).transpose()?),
+            "dl" => Self::Deadline(arg.map(u64::from_str).transpose()?),
+            "gm" => {
+                let (mode, selected) = required()?
+                    .split_once(',')
+                    .ok_or_else(|| anyhow::anyhow!("Invalid argument for {}", tag))?;
+                Self::GasMode {
+                    mode: gas_mode_from_tag(mode)?,
+                    selected: flag(selected)?,
+                }
+            }
+            "gc" => Self::CycleGasMode(gas_mode_from_tag(required()?)?),
+            "mf" => Self::MaxFee(number()?),
+            "pf" => Self::PriorityFee(number()?),
+            "da" => Self::DefaultBuyAmount(number()?),
+            "lk" => {
+                let (kind, selected) = required()?
+                    .split_once(',')
+                    .ok_or_else(|| anyhow::anyhow!("Invalid argument for {}", tag))?;
+                Self::LimitKind {
+                    kind: limit_kind_from_tag(kind)?,
+                    selected: flag(selected)?,
+                }
+            }
+            "tu" => {
+                let (unit, selected) = required()?
+                    .split_once(',')
+                    .ok_or_else(|| anyhow::anyhow!("Invalid argument for {}", tag))?;
+                Self::TriggerUnit {
+                    unit: trigger_unit_from_tag(unit)?,
+                    selected: flag(selected)?,
+                }
+            }
+            "tv" => {
+                let (unit, value) = match required()?.split_once(',') {
+                    Some((unit, value)) => (unit, Some(value.parse()?)),
+                    None => (required()?, None),
+                };
+                Self::Trigger {
+                    unit: trigger_unit_from_tag(unit)?,
+                    value,
+                }
+            }
+            "ex" => Self::Expiry(arg.map(u64::from_str).transpose()?),
+            "po" => Self::PlaceLimitOrder,
+            "oo" => Self::OpenOrders,
+            "ov" => Self::PreviousOrdersPage(required()?.parse()?),
+            "on" => Self::NextOrdersPage(required()?.parse()?),
+            "ob" => Self::BackToOrders(required()?.parse()?),
+            "oe" => {
+                let (id, page) = order()?;
+                Self::EditOrder { id, page }
+            }
+            "oc" => {
+                let (id, page) = order()?;
+                Self::CancelOrder { id, page }
+            }
+            "ot" => {
+                let (id, page) = order()?;
+                Self::EditTrigger { id, page }
+            }
+            "ox" => {
+                let (id, page) = order()?;
+                Self::EditExpiry { id, page }
+            }
+            "cs" => Self::ConfirmSwap(u32::from_str_radix(required()?, 16)?),
+            "ab" => Self::AbortSwap(u32::from_str_radix(required()?, 16)?),
+            _ => return Err(anyhow::anyhow!("Unknown action tag: {}", tag)),
+        };
+        Ok(action)
+    }
+
+    /// Text displayed on the button
+    pub(crate) fn label(&self) -> String {
+        let toggled = |on: bool, text: &str| {
+            if on {
+                add_emoji(text)
+            } else {
+                text.to_string()
+            }
+        };
+        let labelled = |label: &str, value: Option<String>| match value {
+            Some(value) => format!("{}: {}", label, value),
+            None => label.to_string(),
+        };
+        match self {
+            Self::Buy => BUY.to_string(),
+            Self::Sell => SELL.to_string(),
+            Self::LimitBuy => LIMIT_BUY.to_string(),
+            Self::LimitSell => LIMIT_SELL.to_string(),
+            Self::MainMenu => add_emoji(MAIN_MENU),
+            Self::Close => add_emoji(CLOSE),
+            Self::SelectWallet => SELECT_WALLET.to_string(),
+            Self::Chain { chain_id, selected } => toggled(*selected, &chain_name(*chain_id)),
+            Self::PrivateTx(on) => toggled(*on, PRIVATE_TX),
+            Self::Rebate(on) => toggled(*on, REBATE),
+            Self::Wallet { index, selected } => {
+                let wallet = match index {
+                    0 => WALLET_1,
+                    1 => WALLET_2,
+                    _ => WALLET_3,
+                };
+                toggled(*selected, wallet)
+            }
+            Self::BuyToken(address) => {
+                labelled(BUY_TOKEN, address.map(|address| format!("{:#x}", address)))
+            }
+            Self::ReceiveToken(address) => labelled(
+                RECEIVE_TOKEN,
+                address.map(|address| format!("{:#x}", address)),
+            ),
+            Self::SellToken(address) => {
+                labelled(SELL_TOKEN, address.map(|address| format!("{:#x}", address)))
+            }
+            Self::BuyAmount(amount) => labelled(BUY_AMOUNT, amount.map(|a| a.to_string())),
+            Self::SellAmount(amount) => labelled(SELL_AMOUNT, amount.map(|a| a.to_string())),
+            Self::SellPercentage(percentage) => {
+                labelled(SELL_PERCENTAGE, percentage.map(|p| p.to_string()))
+            }
+            Self::PresetPercentage {
+                percentage,
+                selected,
+            } => toggled(*selected, &format!("{}%", percentage)),
+            Self::EstimatedReceivedAmount => ESTIMATED_RECEIVED_AMOUNT.to_string(),
+            Self::SelectToken(address) => format!("{:#x}", address),
+            Self::SendBuyTx => SEND_BUY_TX.to_string(),
+            Self::SendSellTx => SEND_SELL_TX.to_string(),
+            Self::RenameWallet(_) => RENAME_WALLET.to_string(),
+            Self::SetDefaultWallet { selected, .. } => match selected {
+                true => add_emoji(DEFAULT_WALLET),
+                false => SET_DEFAULT_WALLET.to_string(),
+            },
+            Self::ExportWallet(_) => EXPORT_WALLET.to_string(),
+            Self::DeleteWallet(_) => DELETE_WALLET.to_string(),
+            Self::ConfirmDeleteWallet(_) => add_emoji(CONFIRM_DELETE_WALLET),
+            Self::ImportWallet => IMPORT_WALLET.to_string(),
+            Self::PreviousHistoryPage(_) => add_emoji(PREVIOUS_PAGE),
+            Self::NextHistoryPage(_) => add_emoji(NEXT_PAGE),
+            Self::BackToHistory(_) => add_emoji(BACK),
+            Self::TradeDetail { id, .. } => format!("#{}", id),
+            Self::Settings => add_emoji(SETTINGS),
+            Self::Slippage(bps) => {
+                labelled(SLIPPAGE, bps.map(|bps| format!("{}%", bps as f64 / 100.0)))
+            }
+            Self::Deadline(secs) => labelled(DEADLINE, secs.map(|secs| format!("{}s", secs))),
+            Self::GasMode { mode, selected } => toggled(*selected, gas_mode_label(*mode)),
+            Self::CycleGasMode(mode) => labelled(GAS, Some(gas_mode_label(*mode).to_string())),
+            Self::MaxFee(fee) => labelled(MAX_FEE, fee.map(|fee| format!("{} Gwei", fee))),
+            Self::PriorityFee(fee) => {
+                labelled(PRIORITY_FEE, fee.map(|fee| format!("{} Gwei", fee)))
+            }
+            Self::DefaultBuyAmount(amount) => {
+                labelled(DEFAULT_BUY_AMOUNT, amount.map(|a| a.to_string()))
+            }
+            Self::LimitKind { kind, selected } => toggled(*selected, limit_kind_label(*kind)),
+            Self::TriggerUnit { unit, selected } => toggled(*selected, trigger_unit_label(*unit)),
+            Self::Trigger { unit, value } => labelled(
+                &format!("{} {}", TRIGGER, trigger_unit_label(*unit)),
+                value.map(|v| v.to_string()),
+            ),
+            Self::Expiry(secs) => labelled(
+                EXPIRY,
+                Some(
+                    secs.map(format_duration)
+                        .unwrap_or_else(|| "None".to_string()),
+                ),
+            ),
+            Self::PlaceLimitOrder => PLACE_LIMIT_ORDER.to_string(),
+            Self::OpenOrders => OPEN_ORDERS.to_string(),
+            Self::PreviousOrdersPage(_) => add_emoji(PREVIOUS_PAGE),
+            Self::NextOrdersPage(_) => add_emoji(NEXT_PAGE),
+            Self::BackToOrders(_) => add_emoji(BACK),
+            Self::EditOrder { id, .. } => format!("{} #{}", EDIT_ORDER, id),
+            Self::CancelOrder { id, .. } => format!("{} #{}", CANCEL_ORDER, id),
+            Self::EditTrigger { .. } => EDIT_TRIGGER.to_string(),
+            Self::EditExpiry { .. } => EDIT_EXPIRY.to_string(),
+            Self::ConfirmSwap(_) => add_emoji(CONFIRM_SWAP),
+            Self::AbortSwap(_) => add_emoji(ABORT_SWAP),
+        }
+    }
+}
+
+/// Payload of every inline button, encoded as `version:menu:session:action[:argument]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CallbackData {
+    pub(crate) menu: MenuType,
+    /// Identifies the menu instance the button was rendered for
+    pub(crate) session: u32,
+    pub(crate) action: CallbackAction,
+}
+
+impl CallbackData {
+    pub(crate) fn new(menu: MenuType, session: u32, action: CallbackAction) -> Self {
+        Self {
+            menu,
+            session,
+            action,
+        }
+    }
+
+    pub(crate) fn encode(&self) -> anyhow::Result<String> {
+        let (tag, arg) = self.action.tag_and_arg();
+        let mut data = format!(
+            "{}{sep}{}{sep}{:x}{sep}{}",
+            CALLBACK_DATA_VERSION,
+            self.menu.tag(),
+            self.session,
+            tag,
+            sep = SEPARATOR
+        );
+        if let Some(arg) = arg {
+            data.push(SEPARATOR);
+            data.push_str(&arg);
+        }
+        if data.len() > MAX_CALLBACK_DATA_LEN {
+            return Err(anyhow::anyhow!(
+                "Callback data exceeds {} bytes: {}",
+                MAX_CALLBACK_DATA_LEN,
+                data
+            ));
+        }
+        Ok(data)
+    }
+
+    pub(crate) fn decode(data: &str) -> anyhow::Result<Self> {
+        let mut parts = data.splitn(5, SEPARATOR);
+        let mut next = |name: &str| {
+            parts
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Missing {} in callback data: {}", name, data))
+        };
+
+        let version: u8 = next("version")?.parse()?;
+        if version != CALLBACK_DATA_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported callback data version: {}",
+                version
+            ));
+        }
+        let menu = MenuType::from_tag(next("menu")?)?;
+        let session = u32::from_str_radix(next("session")?, 16)?;
+        let tag = next("action")?;
+        let action = CallbackAction::from_tag(tag, parts.next())?;
+
+        Ok(Self {
+            menu,
+            session,
+            action,
+        })
+    }
+
+    /// Renders the button displaying this action
+    pub(crate) fn button(&self) -> anyhow::Result<InlineKeyboardButton> {
+        Ok(InlineKeyboardButton::callback(
+            self.action.label(),
+            self.encode()?,
+        ))
+    }
+
+    /// Renders the button with a text that doesn't fit in the callback data, like a quote
+    pub(crate) fn button_with_label(&self, label: String) -> anyhow::Result<InlineKeyboardButton> {
+        Ok(InlineKeyboardButton::callback(label, self.encode()?))
+    }
+}
+
+/// Generates the session id of a newly opened menu
+pub(crate) fn new_session_id() -> u32 {
+    ethers::core::rand::random()
+}
+
+/// Decodes the callback data of a button, None for buttons without (valid) callback data
+pub(crate) fn decode_button(button: &InlineKeyboardButton) -> Option<CallbackData> {
+    match &button.kind {
+        InlineKeyboardButtonKind::CallbackData(data) => CallbackData::decode(data).ok(),
+        _ => None,
+    }
+}
+
+/// Decodes every button of the keyboard, skipping the ones without valid callback data
+pub(crate) fn decode_keyboard(keyboard: &InlineKeyboardMarkup) -> Vec<CallbackData> {
+    keyboard
+        .inline_keyboard
+        .iter()
+        .flatten()
+        .filter_map(decode_button)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(action: CallbackAction) {
+        let data = CallbackData::new(MenuType::Buy, u32::MAX, action);
+        let encoded = data.encode().unwrap();
+        assert!(encoded.len() <= MAX_CALLBACK_DATA_LEN, "{}", encoded);
+        assert_eq!(CallbackData::decode(&encoded).unwrap(), data, "{}", encoded);
+    }
+
+    #[test]
+    fn round_trips_the_actions() {
+        let address = Address::repeat_byte(0xab);
+        let actions = vec![
+            CallbackAction::Buy,
+            CallbackAction::Close,
+            CallbackAction::Chain {
+                chain_id: u64::MAX,
+                selected: true,
+            },
+            CallbackAction::PrivateTx(false),
+            CallbackAction::Wallet {
+                index: 2,
+                selected: true,
+            },
+            CallbackAction::BuyToken(Some(address)),
+            CallbackAction::ReceiveToken(None),
+            CallbackAction::BuyAmount(Some(0.5)),
+            CallbackAction::SellAmount(Some(1_500.25)),
+            CallbackAction::SellPercentage(Some(33.3)),
+            CallbackAction::SelectToken(address),
+            CallbackAction::SetDefaultWallet {
+                address,
+                selected: false,
+            },
+            CallbackAction::TradeDetail {
+                id: u64::MAX,
+                page: u32::MAX,
+            },
+            CallbackAction::Slippage(Some(50)),
+            CallbackAction::GasMode {
+                mode: GasMode::Custom,
+                selected: true,
+            },
+            CallbackAction::MaxFee(Some(30.0)),
+            CallbackAction::DefaultBuyAmount(None),
+            CallbackAction::LimitKind {
+                kind: LimitKind::TrailingStop,
+                selected: false,
+            },
+            CallbackAction::Trigger {
+                unit: TriggerUnit::MarketCap,
+                value: Some(-2.5e-308),
+            },
+            CallbackAction::Trigger {
+                unit: TriggerUnit::Price,
+                value: None,
+            },
+            CallbackAction::Expiry(Some(86_400)),
+            CallbackAction::CancelOrder {
+                id: u64::MAX,
+                page: u32::MAX,
+            },
+            CallbackAction::ConfirmSwap(u32::MAX),
+            CallbackAction::AbortSwap(0),
+        ];
+        for action in actions {
+            round_trip(action);
+        }
+    }
+
+    #[test]
+    fn bounds_the_numbers() {
+        for value in [1e300, -1e-300, 0.1234567890123, 123_456_789_012.0, f64::MAX] {
+            let data = CallbackData::new(
+                MenuType::Sell,
+                u32::MAX,
+                CallbackAction::Trigger {
+                    unit: TriggerUnit::EntryPercent,
+                    value: Some(value),
+                },
+            );
+            let encoded = data.encode().unwrap();
+            assert!(encoded.len() <= MAX_CALLBACK_DATA_LEN, "{}", encoded);
+        }
+        let data = CallbackData::new(
+            MenuType::Buy,
+            1,
+            CallbackAction::BuyAmount(Some(0.1234567890123)),
+        );
+        let decoded = CallbackData::decode(&data.encode().unwrap()).unwrap();
+        assert_eq!(decoded.action, CallbackAction::BuyAmount(Some(0.123457)));
+    }
+
+    #[test]
+    fn rejects_legacy_and_unknown_versions() {
+        // buttons sent before the callback data was versioned carry their label
+        assert!(CallbackData::decode("Buy").is_err());
+        assert!(CallbackData::decode("Main Menu").is_err());
+        assert!(CallbackData::decode("0:b:1:b").is_err());
+        assert!(CallbackData::decode(&format!("{}:b:1:b", CALLBACK_DATA_VERSION + 1)).is_err());
+        assert!(CallbackData::decode(&format!("{}:b:1:b", CALLBACK_DATA_VERSION)).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_menus_and_actions() {
+        let version = CALLBACK_DATA_VERSION;
+        assert!(CallbackData::decode(&format!("{}:z:1:b", version)).is_err());
+        assert!(CallbackData::decode(&format!("{}:b:1:zz", version)).is_err());
+        assert!(CallbackData::decode(&format!("{}:b:xyz:b", version)).is_err());
+        assert!(CallbackData::decode(&format!("{}:b:1", version)).is_err());
+        assert!(CallbackData::decode(&format!("{}:b:1:pt:2", version)).is_err());
+    }
+}
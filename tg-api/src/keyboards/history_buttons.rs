@@ -0,0 +1,56 @@
+use crate::keyboards::callback_data::{CallbackAction, CallbackData, MenuType};
+use crate::storages::TradeRecord;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Number of trade buttons per row
+const TRADES_PER_ROW: usize = 5;
+
+/// Create the keyboard of a page of the /history listing, one button per trade to open its
+/// detail view, then the page navigation
+pub(crate) fn history_keyboard(
+    session: u32,
+    trades: &[TradeRecord],
+    page: u32,
+    pages: u32,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let button = |action: CallbackAction| -> anyhow::Result<InlineKeyboardButton> {
+        CallbackData::new(MenuType::History, session, action).button()
+    };
+
+    let mut keyboard = InlineKeyboardMarkup::default();
+    for row in trades.chunks(TRADES_PER_ROW) {
+        keyboard = keyboard.append_row(
+            row.iter()
+                .map(|trade| button(CallbackAction::TradeDetail { id: trade.id, page }))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        );
+    }
+
+    let mut navigation = vec![];
+    if page > 0 {
+        navigation.push(button(CallbackAction::PreviousHistoryPage(page - 1))?);
+    }
+    if page + 1 < pages {
+        navigation.push(button(CallbackAction::NextHistoryPage(page + 1))?);
+    }
+    if !navigation.is_empty() {
+        keyboard = keyboard.append_row(navigation);
+    }
+
+    Ok(keyboard.append_row(vec![button(CallbackAction::Close)?]))
+}
+
+/// Create the keyboard of the detail view of a trade, Back returns to the page it was opened from
+pub(crate) fn trade_detail_keyboard(
+    session: u32,
+    page: u32,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let button = |action: CallbackAction| -> anyhow::Result<InlineKeyboardButton> {
+        CallbackData::new(MenuType::History, session, action).button()
+    };
+
+    Ok(InlineKeyboardMarkup::default().append_row(vec![
+        button(CallbackAction::BackToHistory(page))?,
+        button(CallbackAction::Close)?,
+    ]))
+}
@@ -1,5 +1,18 @@
 pub(crate) mod buy_buttons;
+pub(crate) mod callback_data;
+pub(crate) mod history_buttons;
+pub(crate) mod order_buttons;
+pub(crate) mod sell_buttons;
+pub(crate) mod settings_buttons;
+pub(crate) mod token_buttons;
+pub(crate) mod wallet_buttons;
 
+use crate::keyboards::buy_buttons::buy_keyboard;
+use crate::keyboards::callback_data::{new_session_id, CallbackAction, CallbackData, MenuType};
+use crate::keyboards::sell_buttons::sell_keyboard;
+use crate::requests::on_chain::short_address;
+use crate::storages::{LimitKind, LimitTrigger, OrderDraft, OrderSide, TokenMetadata, TriggerUnit};
+use ethers::types::Address;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 
 /// Default layout for the keyboard
@@ -18,32 +31,189 @@ fn create_keyboard(actions: Vec<&str>) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new(keyboard)
 }
 
+/// Default layout for the keyboard with typed [CallbackData]
+fn create_action_keyboard(
+    menu: MenuType,
+    session: u32,
+    actions: Vec<CallbackAction>,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
+
+    for action in actions.chunks(3) {
+        let row = action
+            .iter()
+            .map(|action| CallbackData::new(menu, session, action.clone()).button())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        keyboard.push(row);
+    }
+
+    Ok(InlineKeyboardMarkup::new(keyboard))
+}
+
 pub(crate) fn add_emoji(text: &str) -> String {
     let button = match text {
         "Main Menu" => format!("🏠 {}", text),
         "Close" => format!("❌ {}", text),
+        "Confirm" => format!("✅ {}", text),
+        "Abort" => format!("🛑 {}", text),
         "Private Tx" => format!("✅ {}", text),
         "Rebate" => format!("✅ {}", text),
         "Wallet 1" => format!("✅ {}", text),
         "Wallet 2" => format!("✅{}", text),
         "Wallet 3" => format!("✅ {}", text),
+        "25%" | "50%" | "75%" | "100%" => format!("✅ {}", text),
+        "Default" => format!("⭐ {}", text),
+        "Confirm Delete" => format!("⚠️ {}", text),
+        "Prev" => format!("⬅️ {}", text),
+        "Next" => format!("{} ➡️", text),
+        "Back" => format!("↩️ {}", text),
+        "Settings" => format!("⚙️ {}", text),
+        "Slow" | "Normal" | "Fast" | "Custom" => format!("✅ {}", text),
+        "Limit" | "Stop Loss" | "Trailing Stop" => format!("✅ {}", text),
+        "Price" | "% From Entry" | "Market Cap" | "Trail %" => format!("✅ {}", text),
         _ => text.to_string(),
     };
     button
 }
 
-pub(crate) fn menu_keyboard() -> InlineKeyboardMarkup {
-    create_keyboard(vec!["Buy", "Sell", "Limit Buy", "Limit Sell"])
+pub(crate) fn menu_keyboard() -> anyhow::Result<InlineKeyboardMarkup> {
+    create_action_keyboard(
+        MenuType::Main,
+        new_session_id(),
+        vec![
+            CallbackAction::Buy,
+            CallbackAction::Sell,
+            CallbackAction::LimitBuy,
+            CallbackAction::LimitSell,
+            CallbackAction::OpenOrders,
+            CallbackAction::Settings,
+        ],
+    )
+}
+
+/// What the draft keyboards display besides the draft itself, fetched by the handlers
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DraftDisplay {
+    /// Quote rendered into the estimated received amount row of the buy menu
+    pub(crate) estimate: Option<String>,
+    /// Metadata of the draft's tokens
+    pub(crate) tokens: Vec<TokenMetadata>,
+    /// Addresses of the user's wallets, in the order of the wallet buttons
+    pub(crate) wallets: Vec<Address>,
+}
+
+impl DraftDisplay {
+    /// Whether the wallet button at `index` is the draft's wallet
+    fn wallet_selected(&self, draft: &OrderDraft, index: u8) -> bool {
+        draft.wallet.is_some() && self.wallets.get(index as usize) == draft.wallet.as_ref()
+    }
+
+    pub(crate) fn token(&self, address: Address) -> Option<&TokenMetadata> {
+        self.tokens.iter().find(|token| token.address == address)
+    }
+
+    /// Renders a token button as "label: SYMBOL (0xabc…def)" once the token's metadata is known
+    fn token_button(
+        &self,
+        data: CallbackData,
+        label: &str,
+        address: Option<Address>,
+    ) -> anyhow::Result<InlineKeyboardButton> {
+        match address.and_then(|address| self.token(address)) {
+            Some(token) => data.button_with_label(format!(
+                "{}: {} ({})",
+                label,
+                token.symbol,
+                short_address(&token.address)
+            )),
+            None => data.button(),
+        }
+    }
 }
 
-pub(crate) fn sell_keyboard() -> InlineKeyboardMarkup {
-    create_keyboard(vec!["BTC", "ETH", "LTC", "BCH", "Main Menu", "Close"])
+/// Rows setting the trigger of a limit draft: the kind of a sell order, then the unit and value
+/// of the trigger and the expiry
+fn limit_rows(
+    menu: MenuType,
+    session: u32,
+    side: OrderSide,
+    limit: &LimitTrigger,
+) -> anyhow::Result<Vec<Vec<InlineKeyboardButton>>> {
+    let button = |action: CallbackAction| CallbackData::new(menu, session, action).button();
+    let mut rows = Vec::new();
+    if side == OrderSide::Sell {
+        rows.push(
+            [
+                LimitKind::Limit,
+                LimitKind::StopLoss,
+                LimitKind::TrailingStop,
+            ]
+            .into_iter()
+            .map(|kind| {
+                button(CallbackAction::LimitKind {
+                    kind,
+                    selected: kind == limit.kind,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        );
+    }
+    // trailing stops only trail by a percentage
+    if limit.kind != LimitKind::TrailingStop {
+        rows.push(
+            [
+                TriggerUnit::Price,
+                TriggerUnit::EntryPercent,
+                TriggerUnit::MarketCap,
+            ]
+            .into_iter()
+            .map(|unit| {
+                button(CallbackAction::TriggerUnit {
+                    unit,
+                    selected: unit == limit.unit,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        );
+    }
+    rows.push(vec![
+        button(CallbackAction::Trigger {
+            unit: limit.unit,
+            value: limit.value,
+        })?,
+        button(CallbackAction::Expiry(limit.expiry_secs))?,
+    ]);
+    Ok(rows)
 }
 
-pub(crate) fn limit_buy_keyboard() -> InlineKeyboardMarkup {
-    create_keyboard(vec!["BTC", "ETH", "LTC", "BCH", "Main Menu", "Close"])
+/// Renders the buy or sell keyboard of the draft
+pub(crate) fn draft_keyboard(
+    draft: &OrderDraft,
+    display: &DraftDisplay,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    match draft.side {
+        OrderSide::Buy => buy_keyboard(draft, display),
+        OrderSide::Sell => sell_keyboard(draft, display),
+    }
 }
 
-pub(crate) fn limit_sell_keyboard() -> InlineKeyboardMarkup {
-    create_keyboard(vec!["BTC", "ETH", "LTC", "BCH", "Main Menu", "Close"])
+/// Create the keyboard of the card shown before a swap of the draft is sent, its buttons are
+/// under the draft's menu and session so they go stale with it
+pub(crate) fn confirm_swap_keyboard(
+    draft: &OrderDraft,
+    nonce: u32,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let menu = match draft.side {
+        OrderSide::Buy => MenuType::Buy,
+        OrderSide::Sell => MenuType::Sell,
+    };
+    create_action_keyboard(
+        menu,
+        draft.session,
+        vec![
+            CallbackAction::ConfirmSwap(nonce),
+            CallbackAction::AbortSwap(nonce),
+        ],
+    )
 }
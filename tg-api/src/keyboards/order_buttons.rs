@@ -0,0 +1,62 @@
+use crate::keyboards::callback_data::{CallbackAction, CallbackData, MenuType};
+use crate::storages::LimitOrder;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Create the keyboard of a page of the /orders listing, the edit and cancel buttons of each
+/// order, then the page navigation
+pub(crate) fn orders_keyboard(
+    session: u32,
+    orders: &[LimitOrder],
+    page: u32,
+    pages: u32,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let button = |action: CallbackAction| -> anyhow::Result<InlineKeyboardButton> {
+        CallbackData::new(MenuType::Orders, session, action).button()
+    };
+
+    let mut keyboard = InlineKeyboardMarkup::default();
+    for order in orders.iter() {
+        keyboard = keyboard.append_row(vec![
+            button(CallbackAction::EditOrder { id: order.id, page })?,
+            button(CallbackAction::CancelOrder { id: order.id, page })?,
+        ]);
+    }
+
+    let mut navigation = vec![];
+    if page > 0 {
+        navigation.push(button(CallbackAction::PreviousOrdersPage(page - 1))?);
+    }
+    if page + 1 < pages {
+        navigation.push(button(CallbackAction::NextOrdersPage(page + 1))?);
+    }
+    if !navigation.is_empty() {
+        keyboard = keyboard.append_row(navigation);
+    }
+
+    Ok(keyboard.append_row(vec![
+        button(CallbackAction::MainMenu)?,
+        button(CallbackAction::Close)?,
+    ]))
+}
+
+/// Create the keyboard of the edit view of an order, Back returns to the page it was opened from
+pub(crate) fn order_edit_keyboard(
+    session: u32,
+    id: u64,
+    page: u32,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let button = |action: CallbackAction| -> anyhow::Result<InlineKeyboardButton> {
+        CallbackData::new(MenuType::Orders, session, action).button()
+    };
+
+    Ok(InlineKeyboardMarkup::default()
+        .append_row(vec![
+            button(CallbackAction::EditTrigger { id, page })?,
+            button(CallbackAction::EditExpiry { id, page })?,
+        ])
+        .append_row(vec![button(CallbackAction::CancelOrder { id, page })?])
+        .append_row(vec![
+            button(CallbackAction::BackToOrders(page))?,
+            button(CallbackAction::Close)?,
+        ]))
+}
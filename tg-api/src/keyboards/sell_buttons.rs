@@ -0,0 +1,154 @@
+use crate::consts::{RECEIVE_TOKEN, SELL_TOKEN};
+use crate::keyboards::callback_data::{CallbackAction, CallbackData, MenuType};
+use crate::keyboards::{limit_rows, DraftDisplay};
+use crate::storages::MAX_WALLETS;
+use crate::storages::{OrderDraft, OrderSide, GLOBAL_CHAIN_REGISTRY};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Preset percentages of the token balance offered in the sell menu
+pub(crate) const SELL_PERCENTAGE_PRESETS: [u8; 4] = [25, 50, 75, 100];
+
+pub(crate) fn is_preset(percentage: f64) -> bool {
+    SELL_PERCENTAGE_PRESETS
+        .iter()
+        .any(|&preset| preset as f64 == percentage)
+}
+
+/// Create the Sell keyboard layout
+/// Note: the layout can change freely, the state is read from the [OrderDraft] and the handlers
+/// only rely on the [CallbackData] of the buttons
+fn create_sell_keyboard(
+    draft: &OrderDraft,
+    display: &DraftDisplay,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let session = draft.session;
+
+    let button = |action: CallbackAction| -> anyhow::Result<InlineKeyboardButton> {
+        CallbackData::new(MenuType::Sell, session, action).button()
+    };
+
+    let mut keyboard = InlineKeyboardMarkup::default();
+
+    // 1st row
+    keyboard = keyboard.append_row(vec![
+        button(CallbackAction::MainMenu)?,
+        button(CallbackAction::Close)?,
+    ]);
+
+    // 2nd row
+    keyboard = keyboard.append_row(vec![
+        button(CallbackAction::PrivateTx(draft.private_tx))?,
+        button(CallbackAction::Rebate(draft.rebate))?,
+    ]);
+
+    // 3rd row
+    keyboard = keyboard.append_row(
+        GLOBAL_CHAIN_REGISTRY
+            .chains()
+            .iter()
+            .map(|chain| {
+                button(CallbackAction::Chain {
+                    chain_id: chain.chain_id,
+                    selected: chain.chain_id == draft.chain_id,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    );
+
+    // 4th row
+    keyboard = keyboard.append_row(vec![
+        button(CallbackAction::Slippage(draft.swap.slippage_bps))?,
+        button(CallbackAction::CycleGasMode(draft.swap.gas.mode))?,
+    ]);
+
+    // 5th row
+    keyboard = keyboard.append_row(vec![button(CallbackAction::SelectWallet)?]);
+
+    // 6th row
+    keyboard = keyboard.append_row(
+        (0..MAX_WALLETS as u8)
+            .map(|index| {
+                button(CallbackAction::Wallet {
+                    index,
+                    selected: display.wallet_selected(draft, index),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    );
+
+    // 7th row
+    keyboard = keyboard.append_row(vec![
+        display.token_button(
+            CallbackData::new(
+                MenuType::Sell,
+                session,
+                CallbackAction::SellToken(draft.sell_token),
+            ),
+            SELL_TOKEN,
+            draft.sell_token,
+        )?,
+        display.token_button(
+            CallbackData::new(
+                MenuType::Sell,
+                session,
+                CallbackAction::ReceiveToken(draft.receive_token),
+            ),
+            RECEIVE_TOKEN,
+            draft.receive_token,
+        )?,
+    ]);
+
+    // 8th row
+    // Either an exact amount or a custom percentage of the balance
+    keyboard = keyboard.append_row(vec![
+        button(CallbackAction::SellAmount(draft.amount))?,
+        // presets are highlighted below instead
+        button(CallbackAction::SellPercentage(
+            draft
+                .sell_percentage
+                .filter(|&percentage| !is_preset(percentage)),
+        ))?,
+    ]);
+
+    // 9th row
+    // Preset percentages of the balance
+    keyboard = keyboard.append_row(
+        SELL_PERCENTAGE_PRESETS
+            .into_iter()
+            .map(|percentage| {
+                button(CallbackAction::PresetPercentage {
+                    percentage,
+                    selected: draft.sell_percentage == Some(percentage as f64),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    );
+
+    // limit orders set their kind, trigger and expiry
+    if let Some(limit) = &draft.limit {
+        for row in limit_rows(MenuType::Sell, session, OrderSide::Sell, limit)? {
+            keyboard = keyboard.append_row(row);
+        }
+    }
+
+    // 10th row
+    keyboard = keyboard.append_row(vec![button(CallbackAction::EstimatedReceivedAmount)?]);
+
+    // 11th row
+    keyboard = keyboard.append_row(vec![match draft.limit {
+        Some(_) => button(CallbackAction::PlaceLimitOrder)?,
+        None => button(CallbackAction::SendSellTx)?,
+    }]);
+
+    Ok(keyboard)
+}
+
+pub(crate) fn sell_keyboard(
+    draft: &OrderDraft,
+    display: &DraftDisplay,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    match create_sell_keyboard(draft, display) {
+        Ok(keyboard) => Ok(keyboard),
+        _ => Err(anyhow::anyhow!("Error creating keyboard")),
+    }
+}
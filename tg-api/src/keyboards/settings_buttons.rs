@@ -0,0 +1,58 @@
+use crate::config::GasMode;
+use crate::keyboards::callback_data::{CallbackAction, CallbackData, MenuType};
+use crate::storages::{UserSettings, MAX_WALLETS};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Create the Settings keyboard layout, `default_wallet` is the zero based index of the user's
+/// default wallet
+pub(crate) fn settings_keyboard(
+    session: u32,
+    settings: &UserSettings,
+    default_wallet: u8,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let button = |action: CallbackAction| -> anyhow::Result<InlineKeyboardButton> {
+        CallbackData::new(MenuType::Settings, session, action).button()
+    };
+    let swap = &settings.swap;
+
+    let keyboard = InlineKeyboardMarkup::default()
+        .append_row(vec![
+            button(CallbackAction::MainMenu)?,
+            button(CallbackAction::Close)?,
+        ])
+        .append_row(vec![
+            button(CallbackAction::Slippage(swap.slippage_bps))?,
+            button(CallbackAction::Deadline(swap.deadline_secs))?,
+        ])
+        .append_row(
+            GasMode::ALL
+                .iter()
+                .map(|mode| {
+                    button(CallbackAction::GasMode {
+                        mode: *mode,
+                        selected: *mode == swap.gas.mode,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        )
+        .append_row(vec![
+            button(CallbackAction::MaxFee(swap.gas.max_fee_gwei))?,
+            button(CallbackAction::PriorityFee(swap.gas.priority_fee_gwei))?,
+        ])
+        .append_row(vec![button(CallbackAction::SelectWallet)?])
+        .append_row(
+            (0..MAX_WALLETS as u8)
+                .map(|index| {
+                    button(CallbackAction::Wallet {
+                        index,
+                        selected: index == default_wallet,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        )
+        .append_row(vec![button(CallbackAction::DefaultBuyAmount(
+            settings.buy_amount,
+        ))?]);
+
+    Ok(keyboard)
+}
@@ -0,0 +1,29 @@
+use crate::keyboards::callback_data::{CallbackAction, CallbackData, MenuType};
+use crate::requests::on_chain::short_address;
+use crate::storages::TokenEntry;
+use teloxide::types::InlineKeyboardMarkup;
+
+/// One button per token matching the name or symbol the user entered, the address tells apart
+/// tokens sharing a symbol
+pub(crate) fn token_choice_keyboard(
+    menu: MenuType,
+    session: u32,
+    tokens: &[TokenEntry],
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let mut keyboard = InlineKeyboardMarkup::default();
+    for token in tokens {
+        let label = format!(
+            "{} · {} ({})",
+            token.symbol,
+            token.name,
+            short_address(&token.address)
+        );
+        keyboard = keyboard.append_row(vec![CallbackData::new(
+            menu,
+            session,
+            CallbackAction::SelectToken(token.address),
+        )
+        .button_with_label(label)?]);
+    }
+    Ok(keyboard)
+}
@@ -0,0 +1,43 @@
+use crate::keyboards::callback_data::{CallbackAction, CallbackData, MenuType};
+use ethers::types::Address;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Create the keyboard of a single wallet of the /wallets listing, `confirm_delete` swaps the
+/// Delete button for the confirmation button
+pub(crate) fn wallet_keyboard(
+    session: u32,
+    address: Address,
+    is_default: bool,
+    confirm_delete: bool,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let button = |action: CallbackAction| -> anyhow::Result<InlineKeyboardButton> {
+        CallbackData::new(MenuType::Wallets, session, action).button()
+    };
+
+    let delete = match confirm_delete {
+        true => CallbackAction::ConfirmDeleteWallet(address),
+        false => CallbackAction::DeleteWallet(address),
+    };
+
+    let keyboard = InlineKeyboardMarkup::default()
+        .append_row(vec![
+            button(CallbackAction::RenameWallet(address))?,
+            button(CallbackAction::SetDefaultWallet {
+                address,
+                selected: is_default,
+            })?,
+        ])
+        .append_row(vec![
+            button(CallbackAction::ExportWallet(address))?,
+            button(delete)?,
+        ]);
+
+    Ok(keyboard)
+}
+
+/// Create the keyboard sent after the wallets of the /wallets listing
+pub(crate) fn import_wallet_keyboard(session: u32) -> anyhow::Result<InlineKeyboardMarkup> {
+    let button =
+        CallbackData::new(MenuType::Wallets, session, CallbackAction::ImportWallet).button()?;
+    Ok(InlineKeyboardMarkup::default().append_row(vec![button]))
+}
@@ -1,4 +1,5 @@
 pub mod bot;
+mod config;
 mod consts;
 #[allow(dead_code)]
 mod handlers;
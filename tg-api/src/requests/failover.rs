@@ -0,0 +1,515 @@
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, HttpClientError, JsonRpcClient, JsonRpcError, Provider, ProviderError, RpcError,
+};
+use ethers::types::{U256, U64};
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use tokio::time::{timeout, Duration, Instant};
+use url::Url;
+
+/// Provider of a chain, reading from the healthiest of its endpoints
+pub(crate) type RpcProvider = Provider<FailoverClient>;
+
+/// Weight of the latest request in the latency and error rate averages
+const EWMA_WEIGHT: f64 = 0.2;
+/// Requests taking longer count as failed and are retried on the next endpoint
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Methods a retry could apply twice, they are only sent to the healthiest endpoint
+const NON_IDEMPOTENT_METHODS: [&str; 2] = ["eth_sendRawTransaction", "eth_sendTransaction"];
+/// Methods installing a filter, the filter only exists on the endpoint that installed it
+const FILTER_INSTALL_METHODS: [&str; 3] = [
+    "eth_newFilter",
+    "eth_newBlockFilter",
+    "eth_newPendingTransactionFilter",
+];
+const FILTER_METHODS: [&str; 3] = [
+    "eth_getFilterChanges",
+    "eth_getFilterLogs",
+    "eth_uninstallFilter",
+];
+/// Nodes uninstall the filters that weren't polled for this long, 5 minutes on geth
+const FILTER_TTL: Duration = Duration::from_secs(300);
+
+/// How an endpoint has been doing lately
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EndpointHealth {
+    /// Average response time, in milliseconds
+    pub(crate) latency_ms: f64,
+    /// Average share of failed requests, from 0 to 1
+    pub(crate) error_rate: f64,
+    /// Block number the endpoint reported on the last probe
+    pub(crate) head: Option<U64>,
+    /// Blocks behind the most advanced endpoint of the chain
+    pub(crate) lag: u64,
+    pub(crate) requests: u64,
+    pub(crate) failures: u64,
+    pub(crate) last_error: Option<String>,
+}
+
+impl EndpointHealth {
+    /// Higher is healthier, errors weigh the most, then the head lag, then the latency
+    pub(crate) fn score(&self) -> f64 {
+        100.0 * (1.0 - self.error_rate) - 5.0 * self.lag as f64 - self.latency_ms / 100.0
+    }
+
+    fn record(&mut self, latency: Duration, error: Option<String>) {
+        let failed = if error.is_some() { 1.0 } else { 0.0 };
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        if self.requests == 0 {
+            self.latency_ms = latency_ms;
+            self.error_rate = failed;
+        } else {
+            self.latency_ms += EWMA_WEIGHT * (latency_ms - self.latency_ms);
+            self.error_rate += EWMA_WEIGHT * (failed - self.error_rate);
+        }
+        self.requests += 1;
+        if error.is_some() {
+            self.failures += 1;
+            self.last_error = error;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    /// Displayed instead of the url, which may hold an API key
+    host: String,
+    client: Http,
+    health: RwLock<EndpointHealth>,
+}
+
+impl Endpoint {
+    fn new(url: &str) -> anyhow::Result<Self> {
+        let parsed = Url::parse(url).map_err(|err| anyhow::anyhow!("Invalid RPC URL: {}", err))?;
+        Ok(Self {
+            url: url.to_string(),
+            host: parsed.host_str().unwrap_or("unknown host").to_string(),
+            client: Http::new(parsed),
+            health: RwLock::new(EndpointHealth::default()),
+        })
+    }
+
+    /// Sends the request with a timeout, recording how the endpoint did
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, FailoverError>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let started = Instant::now();
+        let result = match timeout(REQUEST_TIMEOUT, self.client.request(method, params)).await {
+            Ok(Ok(response)) => Ok(response),
+            // the node did answer, any other one would answer the same
+            Ok(Err(HttpClientError::JsonRpcError(err))) => {
+                self.health.write().record(started.elapsed(), None);
+                return Err(FailoverError::JsonRpc(err));
+            }
+            Ok(Err(err)) => Err(FailoverError::Endpoint(
+                err.to_string().replace(&self.url, &self.host),
+            )),
+            Err(_) => Err(FailoverError::Endpoint(format!(
+                "{} timed out after {:?}",
+                self.host, REQUEST_TIMEOUT
+            ))),
+        };
+        let error = result.as_ref().err().map(ToString::to_string);
+        self.health.write().record(started.elapsed(), error);
+        result
+    }
+}
+
+/// Endpoint a filter was installed on
+#[derive(Debug, Clone, Copy)]
+struct InstalledFilter {
+    endpoint: usize,
+    /// Last time the filter was installed or polled
+    used_at: Instant,
+}
+
+/// Id of the filter the params of a filter method are for, its first param
+fn filter_id<T: Serialize>(params: &T) -> Option<U256> {
+    match serde_json::to_value(params).ok()? {
+        serde_json::Value::Array(params) => serde_json::from_value(params.into_iter().next()?).ok(),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum FailoverError {
+    /// Error response of the node
+    JsonRpc(JsonRpcError),
+    /// The endpoint couldn't be reached or its response couldn't be read
+    Endpoint(String),
+}
+
+impl fmt::Display for FailoverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::JsonRpc(err) => write!(f, "{}", err),
+            Self::Endpoint(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for FailoverError {}
+
+impl RpcError for FailoverError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            Self::JsonRpc(err) => Some(err),
+            Self::Endpoint(_) => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        None
+    }
+}
+
+impl From<FailoverError> for ProviderError {
+    fn from(err: FailoverError) -> Self {
+        match err {
+            FailoverError::JsonRpc(_) => Self::JsonRpcClientError(Box::new(err)),
+            FailoverError::Endpoint(err) => Self::CustomError(err),
+        }
+    }
+}
+
+/// Spreads the requests of a chain over its endpoints: reads go to the healthiest one and
+/// are retried on the others when it fails
+#[derive(Debug, Clone)]
+pub(crate) struct FailoverClient {
+    endpoints: Arc<Vec<Endpoint>>,
+    /// Endpoint each installed filter lives on, by filter id
+    filters: Arc<RwLock<HashMap<U256, InstalledFilter>>>,
+}
+
+impl FailoverClient {
+    pub(crate) fn new(urls: &[String]) -> anyhow::Result<Self> {
+        if urls.is_empty() {
+            return Err(anyhow::anyhow!("No RPC URL given"));
+        }
+        let endpoints = urls
+            .iter()
+            .map(|url| Endpoint::new(url))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            endpoints: Arc::new(endpoints),
+            filters: Arc::new(RwLock::new(HashMap::new())),
+        })
+    }
+
+    /// Indexes of the endpoints, healthiest first
+    fn ranked(&self) -> Vec<usize> {
+        let scores: Vec<f64> = self
+            .endpoints
+            .iter()
+            .map(|endpoint| endpoint.health.read().score())
+            .collect();
+        let mut ranked: Vec<usize> = (0..self.endpoints.len()).collect();
+        // sort_by is stable, endpoints of equal health stay in the configured order
+        ranked.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+        ranked
+    }
+
+    /// Asks every endpoint for its latest block, to measure how far behind each one is
+    pub(crate) async fn probe(&self) {
+        let heads = futures::future::join_all(self.endpoints.iter().map(|endpoint| async move {
+            endpoint.request::<_, U64>("eth_blockNumber", ()).await.ok()
+        }))
+        .await;
+        for (endpoint, head) in self.endpoints.iter().zip(heads) {
+            if head.is_some() {
+                endpoint.health.write().head = head;
+            }
+        }
+        let best = self
+            .endpoints
+            .iter()
+            .filter_map(|endpoint| endpoint.health.read().head)
+            .max();
+        if let Some(best) = best {
+            for endpoint in self.endpoints.iter() {
+                let mut health = endpoint.health.write();
+                health.lag = health.head.map_or(0, |head| (best - head).as_u64());
+            }
+        }
+    }
+
+    /// Installs the filter on the healthiest endpoint that answers, and records the endpoint
+    /// so the filter's changes are read from it
+    async fn install_filter<T, R>(&self, method: &str, params: T) -> Result<R, FailoverError>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let mut last_error = None;
+        for index in self.ranked() {
+            match self.endpoints[index]
+                .request::<_, serde_json::Value>(method, &params)
+                .await
+            {
+                Ok(response) => {
+                    if let Ok(id) = serde_json::from_value::<U256>(response.clone()) {
+                        self.prune_filters(Instant::now());
+                        let filter = InstalledFilter {
+                            endpoint: index,
+                            used_at: Instant::now(),
+                        };
+                        self.filters.write().insert(id, filter);
+                    }
+                    return serde_json::from_value(response)
+                        .map_err(|err| FailoverError::Endpoint(err.to_string()));
+                }
+                Err(err @ FailoverError::JsonRpc(_)) => return Err(err),
+                Err(err) => {
+                    log::warn!("{} failed: {}", method, err);
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| FailoverError::Endpoint("No RPC endpoint".to_string())))
+    }
+
+    /// Sends the filter method to the endpoint that installed the filter. The filter is forgotten
+    /// once uninstalled, when its endpoint fails or once the endpoint dropped it for being idle,
+    /// the caller then gets an error from the healthiest endpoint and installs a new one
+    async fn filter_request<T, R>(&self, method: &str, params: T) -> Result<R, FailoverError>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        self.prune_filters(Instant::now());
+        let id = filter_id(&params);
+        let installed = id.and_then(|id| {
+            let mut filters = self.filters.write();
+            let filter = filters.get_mut(&id)?;
+            filter.used_at = Instant::now();
+            Some(filter.endpoint)
+        });
+        let index = installed.unwrap_or_else(|| self.ranked()[0]);
+        let result = self.endpoints[index].request(method, &params).await;
+        if let Some(id) = id {
+            if method == "eth_uninstallFilter" || matches!(result, Err(FailoverError::Endpoint(_)))
+            {
+                self.filters.write().remove(&id);
+            }
+        }
+        if let Err(err @ FailoverError::Endpoint(_)) = &result {
+            log::warn!("{} failed: {}", method, err);
+        }
+        result
+    }
+
+    /// Forgets the filters not polled for [FILTER_TTL] at `now`
+    fn prune_filters(&self, now: Instant) {
+        self.filters
+            .write()
+            .retain(|_, filter| now.duration_since(filter.used_at) < FILTER_TTL);
+    }
+
+    /// Host and health of every endpoint, in the configured order
+    pub(crate) fn health(&self) -> Vec<(String, EndpointHealth)> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| (endpoint.host.clone(), endpoint.health.read().clone()))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for FailoverClient {
+    type Error = FailoverError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        if FILTER_INSTALL_METHODS.contains(&method) {
+            return self.install_filter(method, params).await;
+        }
+        if FILTER_METHODS.contains(&method) {
+            // the filter is only known to the endpoint that installed it
+            return self.filter_request(method, params).await;
+        }
+        let candidates = if NON_IDEMPOTENT_METHODS.contains(&method) {
+            self.ranked().into_iter().take(1).collect()
+        } else {
+            self.ranked()
+        };
+
+        let mut last_error = None;
+        for index in candidates {
+            match self.endpoints[index].request(method, &params).await {
+                Ok(response) => return Ok(response),
+                Err(err @ FailoverError::JsonRpc(_)) => return Err(err),
+                Err(err) => {
+                    log::warn!("{} failed: {}", method, err);
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| FailoverError::Endpoint("No RPC endpoint".to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use parking_lot::Mutex;
+    use serde_json::{json, Value};
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// A local node recording the methods it receives, it answers with a server error while
+    /// `down` is set
+    struct MockNode {
+        url: String,
+        methods: Arc<Mutex<Vec<String>>>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl MockNode {
+        fn received(&self) -> Vec<String> {
+            self.methods.lock().clone()
+        }
+    }
+
+    fn result(method: &str) -> Value {
+        match method {
+            "eth_blockNumber" => json!(U64::from(100)),
+            "eth_newBlockFilter" => json!(U256::one()),
+            "eth_getFilterChanges" => json!([]),
+            "eth_uninstallFilter" => json!(true),
+            _ => Value::Null,
+        }
+    }
+
+    /// Starts a node on a free local port
+    fn mock_node(down: bool) -> MockNode {
+        let methods = Arc::new(Mutex::new(Vec::new()));
+        let down = Arc::new(AtomicBool::new(down));
+        let (recorded, failing) = (methods.clone(), down.clone());
+        let service = make_service_fn(move |_| {
+            let (recorded, failing) = (recorded.clone(), failing.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let (recorded, failing) = (recorded.clone(), failing.clone());
+                    async move {
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let request: Value = serde_json::from_slice(&body).unwrap();
+                        let method = request["method"].as_str().unwrap_or_default().to_string();
+                        recorded.lock().push(method.clone());
+                        if failing.load(Ordering::SeqCst) {
+                            let mut response = Response::new(Body::from("down"));
+                            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                            return Ok::<_, Infallible>(response);
+                        }
+                        let response = json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "result": result(&method),
+                        });
+                        Ok::<_, Infallible>(Response::new(Body::from(response.to_string())))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        MockNode { url, methods, down }
+    }
+
+    fn client(nodes: &[&MockNode]) -> FailoverClient {
+        let urls: Vec<String> = nodes.iter().map(|node| node.url.clone()).collect();
+        FailoverClient::new(&urls).unwrap()
+    }
+
+    #[test]
+    fn averages_the_latency_and_error_rate() {
+        let mut health = EndpointHealth::default();
+        health.record(Duration::from_millis(100), None);
+        assert_eq!((health.latency_ms, health.error_rate), (100.0, 0.0));
+        assert_eq!(health.score(), 99.0);
+
+        health.record(Duration::from_millis(200), Some("down".to_string()));
+        assert!((health.latency_ms - 120.0).abs() < 1e-6);
+        assert!((health.error_rate - 0.2).abs() < 1e-6);
+        assert_eq!((health.requests, health.failures), (2, 1));
+        assert_eq!(health.last_error.as_deref(), Some("down"));
+        assert!((health.score() - 78.8).abs() < 1e-6);
+
+        // a block behind costs as much as 5% more errors
+        health.lag = 1;
+        assert!((health.score() - 73.8).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_endpoint_and_ranks_it_first() {
+        let (first, second) = (mock_node(true), mock_node(false));
+        let client = client(&[&first, &second]);
+        assert_eq!(client.ranked(), vec![0, 1]);
+
+        let head: U64 = client.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(head, U64::from(100));
+        assert_eq!(first.received(), vec!["eth_blockNumber"]);
+        assert_eq!(second.received(), vec!["eth_blockNumber"]);
+        assert_eq!(client.ranked(), vec![1, 0]);
+
+        // the failed endpoint is only tried once the healthy one fails too
+        let _: U64 = client.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(first.received().len(), 1);
+        assert_eq!(second.received().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reads_a_filter_from_the_endpoint_that_installed_it() {
+        let (first, second) = (mock_node(false), mock_node(false));
+        let client = client(&[&first, &second]);
+        let id: U256 = client.request("eth_newBlockFilter", ()).await.unwrap();
+
+        // the endpoint that installed the filter falls behind
+        client.endpoints[0].health.write().lag = 10;
+        assert_eq!(client.ranked(), vec![1, 0]);
+        let _: Vec<Value> = client.request("eth_getFilterChanges", [id]).await.unwrap();
+        let _: bool = client.request("eth_uninstallFilter", [id]).await.unwrap();
+        assert_eq!(
+            first.received(),
+            vec![
+                "eth_newBlockFilter",
+                "eth_getFilterChanges",
+                "eth_uninstallFilter"
+            ]
+        );
+        assert!(second.received().is_empty());
+        assert!(client.filters.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn forgets_the_filters_of_failed_or_idle_endpoints() {
+        let (first, second) = (mock_node(false), mock_node(false));
+        let client = client(&[&first, &second]);
+        let id: U256 = client.request("eth_newBlockFilter", ()).await.unwrap();
+
+        first.down.store(true, Ordering::SeqCst);
+        let polled: Result<Vec<Value>, _> = client.request("eth_getFilterChanges", [id]).await;
+        assert!(matches!(polled, Err(FailoverError::Endpoint(_))));
+        assert!(client.filters.read().is_empty());
+        // the next poll goes to the healthiest endpoint, which doesn't know the filter
+        let _: Vec<Value> = client.request("eth_getFilterChanges", [id]).await.unwrap();
+        assert_eq!(second.received(), vec!["eth_getFilterChanges"]);
+
+        let _: U256 = client.request("eth_newBlockFilter", ()).await.unwrap();
+        assert_eq!(client.filters.read().len(), 1);
+        client.prune_filters(Instant::now() + FILTER_TTL);
+        assert!(client.filters.read().is_empty());
+    }
+}
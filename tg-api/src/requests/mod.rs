@@ -1,2 +1,8 @@
+pub(crate) mod failover;
 pub(crate) mod on_chain;
+pub(crate) mod providers;
+pub(crate) mod relay;
+pub(crate) mod routing;
 pub(crate) mod server;
+pub(crate) mod simulation;
+pub(crate) mod swap;
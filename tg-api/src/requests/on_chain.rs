@@ -1,38 +1,42 @@
-use dotenv::dotenv;
+use crate::config::ChainConfig;
+use crate::requests::failover::{FailoverClient, RpcProvider};
+use crate::requests::providers::ProviderRegistry;
+use crate::requests::swap::{native_token, Erc20};
+use crate::storages::{
+    CachedMetadata, TokenMetadata, GLOBAL_CHAIN_REGISTRY, GLOBAL_TOKEN_METADATA,
+    GLOBAL_WALLET_STORE, MAX_WALLETS,
+};
 use ethers::{
-    prelude::*,
-    providers::{Http, Middleware, Provider},
-    types::{U256, U64},
+    providers::Middleware,
+    types::{Address, H256, U256, U64},
 };
-use std::convert::TryFrom;
+use std::sync::Arc;
+use teloxide::types::UserId;
+use teloxide::utils::markdown;
 
 /// Type to query on chain info
 #[derive(Debug, Clone)]
 pub(crate) struct OnChainInfoQuery {
-    provider: Provider<Http>,
+    chain: ChainConfig,
+    provider: RpcProvider,
 }
 
 impl OnChainInfoQuery {
-    pub(crate) fn new(_chain_id: u64) -> anyhow::Result<Self> {
-        dotenv().ok();
-        //let provider = match chain_id {
-        //    137 => {
-        //        let rpc_url = std::env::var("POLYGON_RPC_URL")?;
-        //        let provider = Provider::<Http>::try_from(rpc_url)?;
-        //        log::info!("Connected to Polygon mainnet");
-        //        provider
-        //    }
-        //    1 => {
-        //        let rpc_url = std::env::var("ETH_RPC_URL")?;
-        //        let provider = Provider::<Http>::try_from(rpc_url)?;
-        //        log::info!("Connected to Ethereum mainnet");
-        //        provider
-        //    }
-        //    _ => return Err(anyhow!("Unsupported chain id: {}", chain_id)),
-        let rpc_url = std::env::var("ETH_RPC_URL")?;
-        let provider = Provider::<Http>::try_from(rpc_url)?;
-
-        Ok(Self { provider })
+    /// Connects to the RPC URLs of the chain, the queries are rather cloned from the
+    /// [ProviderRegistry] so they share its connections
+    pub(crate) fn connect(chain: ChainConfig) -> anyhow::Result<Self> {
+        let provider = RpcProvider::new(FailoverClient::new(&chain.rpc_urls)?);
+
+        Ok(Self { chain, provider })
+    }
+
+    pub(crate) fn chain(&self) -> &ChainConfig {
+        &self.chain
+    }
+
+    /// The provider of the chain, to build signing clients on
+    pub(crate) fn provider(&self) -> RpcProvider {
+        self.provider.clone()
     }
 
     /// Gets the block number and gas fee
@@ -43,35 +47,163 @@ impl OnChainInfoQuery {
 
         Ok((block_number, gas_price))
     }
+
+    /// Gets the ETH balance and nonce of the account
+    pub(crate) async fn query_account(&self, address: Address) -> anyhow::Result<(U256, U256)> {
+        let balance = self.provider.get_balance(address, None).await?;
+
+        let nonce = self.provider.get_transaction_count(address, None).await?;
+
+        Ok((balance, nonce))
+    }
+
+    /// Gets the name, symbol, decimals and total supply of the ERC-20 token, addresses without
+    /// contract code are rejected
+    pub(crate) async fn query_token_metadata(
+        &self,
+        address: Address,
+    ) -> anyhow::Result<TokenMetadata> {
+        let chain_id = self.chain.chain_id;
+        if address == native_token() {
+            return Ok(TokenMetadata {
+                chain_id,
+                address,
+                name: self.chain.native_name.clone(),
+                symbol: self.chain.native_symbol.clone(),
+                decimals: 18,
+                total_supply: None,
+            });
+        }
+        if self.provider.get_code(address, None).await?.is_empty() {
+            return Err(anyhow::anyhow!("No contract found at {:#x}", address));
+        }
+
+        let token = Erc20::new(address, Arc::new(self.provider.clone()));
+        let (name, symbol, decimals, total_supply) = (
+            token.name(),
+            token.symbol(),
+            token.decimals(),
+            token.total_supply(),
+        );
+        let (name, symbol, decimals, total_supply) = futures::join!(
+            name.call(),
+            symbol.call(),
+            decimals.call(),
+            total_supply.call()
+        );
+        let (decimals, total_supply) = match (decimals, total_supply) {
+            (Ok(decimals), Ok(total_supply)) => (decimals, total_supply),
+            _ => return Err(anyhow::anyhow!("{:#x} is not an ERC-20 token", address)),
+        };
+        // name and symbol are optional in ERC-20, some tokens return them as bytes32
+        Ok(TokenMetadata {
+            chain_id,
+            address,
+            name: name.unwrap_or_else(|_| "Unknown".to_string()),
+            symbol: symbol.unwrap_or_else(|_| "???".to_string()),
+            decimals,
+            total_supply: Some(total_supply),
+        })
+    }
+
+    /// Gets the total supply of the ERC-20 token
+    pub(crate) async fn query_total_supply(&self, address: Address) -> anyhow::Result<U256> {
+        let token = Erc20::new(address, Arc::new(self.provider.clone()));
+        Ok(token.total_supply().call().await?)
+    }
 }
 
-/// Helper function to query the block number and gas fee from supported networks
-pub(crate) async fn get_on_chain_info() -> anyhow::Result<String> {
-    let eth_provider = OnChainInfoQuery::new(1).unwrap();
-    let (eth_block_number, eth_gas_price) = eth_provider.query_info().await.unwrap();
-    let eth_gas_price = eth_gas_price / 1_000_000_000u64;
-    let message = format!(
-        "*Ethereum*\n*Gas:* {} Gwei  ═  *Block:* {}\n",
-        eth_gas_price, eth_block_number
-    );
-    Ok(message)
+/// Gets the metadata of the token, cached once queried. Only the total supply is queried again
+/// once expired
+pub(crate) async fn token_metadata(
+    providers: &ProviderRegistry,
+    chain_id: u64,
+    address: Address,
+) -> anyhow::Result<TokenMetadata> {
+    match GLOBAL_TOKEN_METADATA.get(chain_id, address) {
+        Some(CachedMetadata::Fresh(metadata)) => Ok(metadata),
+        Some(CachedMetadata::StaleSupply(mut metadata)) => {
+            let supply = providers
+                .query(chain_id)?
+                .query_total_supply(address)
+                .await?;
+            GLOBAL_TOKEN_METADATA.update_supply(chain_id, address, supply);
+            metadata.total_supply = Some(supply);
+            Ok(metadata)
+        }
+        None => {
+            let metadata = providers
+                .query(chain_id)?
+                .query_token_metadata(address)
+                .await?;
+            GLOBAL_TOKEN_METADATA.insert(metadata.clone());
+            Ok(metadata)
+        }
+    }
+}
+
+/// Shortens the address to its first and last hex digits, like 0xa0b8…eb48
+pub(crate) fn short_address(address: &Address) -> String {
+    let address = format!("{:#x}", address);
+    format!("{}…{}", &address[..6], &address[address.len() - 4..])
 }
 
-pub(crate) async fn get_on_chain_info_start() -> anyhow::Result<String> {
-    let wallet1 = LocalWallet::new(&mut rand::thread_rng());
-    let address1 = wallet1.address();
-
-    let wallet2 = LocalWallet::new(&mut rand::thread_rng());
-    let address2 = wallet2.address();
-
-    let wallet3 = LocalWallet::new(&mut rand::thread_rng());
-    let address3 = wallet3.address();
-    let eth_provider = OnChainInfoQuery::new(1).unwrap();
-    let (eth_block_number, eth_gas_price) = eth_provider.query_info().await.unwrap();
-    let eth_gas_price = eth_gas_price / 1_000_000_000u64;
-    let message = format!(
-        "*Ethereum*\n*Gas:* {} Gwei  ═  *Block:* {}\n\n *Wallet 1* {}\n *Wallet2* {}\n*Wallet 3* {}",
-        eth_gas_price, eth_block_number,  address1, address2, address3
-    );
+/// Link to the tx on the block explorer of the chain
+pub(crate) fn explorer_tx_url(chain_id: u64, tx_hash: H256) -> Option<String> {
+    let chain = GLOBAL_CHAIN_REGISTRY.get(chain_id)?;
+    Some(format!(
+        "{}/tx/{:#x}",
+        chain.explorer_url.trim_end_matches('/'),
+        tx_hash
+    ))
+}
+
+/// Name of the chain, or its id for chains that aren't enabled
+pub(crate) fn chain_name(chain_id: u64) -> String {
+    match GLOBAL_CHAIN_REGISTRY.get(chain_id) {
+        Some(chain) => chain.name.clone(),
+        None => format!("Chain {}", chain_id),
+    }
+}
+
+/// The gas fee and block number of every enabled chain, in MarkdownV2
+fn chains_header(providers: &ProviderRegistry) -> String {
+    let mut message = String::new();
+    for (chain, status) in providers.statuses() {
+        match status {
+            Some(status) => message.push_str(&format!(
+                "*{}*\n*Gas:* {} Gwei  ═  *Block:* {}\n",
+                markdown::escape(&chain.name),
+                status.gas_price / 1_000_000_000u64,
+                status.block_number
+            )),
+            None => message.push_str(&format!(
+                "*{}*\nUnavailable\n",
+                markdown::escape(&chain.name)
+            )),
+        }
+    }
+    message
+}
+
+/// Helper function to render the block number and gas fee of the supported networks, as last
+/// refreshed in the background
+pub(crate) fn get_on_chain_info(providers: &ProviderRegistry) -> String {
+    chains_header(providers)
+}
+
+/// Helper function to render the on chain info along with the user's wallets, generating
+/// the missing ones
+pub(crate) async fn get_on_chain_info_start(
+    providers: &ProviderRegistry,
+    user_id: UserId,
+) -> anyhow::Result<String> {
+    let wallets = GLOBAL_WALLET_STORE
+        .ensure_wallets(user_id, MAX_WALLETS)
+        .await?;
+    let mut message = chains_header(providers);
+    for (index, wallet) in wallets.iter().enumerate() {
+        message.push_str(&format!("\n*Wallet {}* {:#x}", index + 1, wallet.address));
+    }
     Ok(message)
 }
@@ -0,0 +1,198 @@
+use crate::config::{ChainConfig, ChainRegistry, RelayConfig};
+use crate::requests::failover::EndpointHealth;
+use crate::requests::on_chain::OnChainInfoQuery;
+use crate::requests::relay::RelayClient;
+use ethers::providers::{Middleware, Provider, Ws};
+use ethers::types::{U256, U64};
+use futures::future::join_all;
+use futures::StreamExt;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use tokio::time::{interval, sleep, Duration, MissedTickBehavior};
+
+/// How often the block number and gas price of every chain are refreshed
+const STATUS_REFRESH_INTERVAL: Duration = Duration::from_secs(6);
+/// How long to wait before subscribing to the new heads again once the WebSocket dropped
+const SUBSCRIBE_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Latest block number and gas price of a chain
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChainStatus {
+    pub(crate) block_number: U64,
+    pub(crate) gas_price: U256,
+}
+
+#[derive(Debug)]
+struct ChainProvider {
+    query: OnChainInfoQuery,
+    /// None when the chain has no relay, its private txs go public
+    relay: Option<RelayClient>,
+    /// None until the first refresh succeeds
+    status: RwLock<Option<ChainStatus>>,
+}
+
+/// One long lived provider per enabled chain shared by every handler, so the connections are
+/// pooled, along with the status of the chains refreshed in the background so menus render
+/// without waiting on the RPC
+#[derive(Debug)]
+pub(crate) struct ProviderRegistry {
+    chains: Vec<ChainProvider>,
+}
+
+impl ProviderRegistry {
+    pub(crate) fn new(registry: &ChainRegistry, relay: &RelayConfig) -> anyhow::Result<Self> {
+        let chains = registry
+            .chains()
+            .iter()
+            .map(|chain| {
+                Ok(ChainProvider {
+                    query: OnChainInfoQuery::connect(chain.clone())?,
+                    relay: chain
+                        .relay_url
+                        .as_deref()
+                        .map(|url| RelayClient::new(url, relay))
+                        .transpose()?,
+                    status: RwLock::new(None),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { chains })
+    }
+
+    fn chain(&self, chain_id: u64) -> anyhow::Result<&ChainProvider> {
+        self.chains
+            .iter()
+            .find(|chain| chain.query.chain().chain_id == chain_id)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported chain id: {}", chain_id))
+    }
+
+    /// Queries the chain through its shared provider
+    pub(crate) fn query(&self, chain_id: u64) -> anyhow::Result<OnChainInfoQuery> {
+        Ok(self.chain(chain_id)?.query.clone())
+    }
+
+    /// The relay the private txs of the chain are sent through, if it has one
+    pub(crate) fn relay(&self, chain_id: u64) -> anyhow::Result<Option<RelayClient>> {
+        Ok(self.chain(chain_id)?.relay.clone())
+    }
+
+    /// The last known status of every enabled chain, in the order they are displayed
+    pub(crate) fn statuses(&self) -> Vec<(&ChainConfig, Option<ChainStatus>)> {
+        self.chains
+            .iter()
+            .map(|chain| (chain.query.chain(), *chain.status.read()))
+            .collect()
+    }
+
+    /// Health of the RPC endpoints of every enabled chain
+    pub(crate) fn endpoint_health(&self) -> Vec<(&ChainConfig, Vec<(String, EndpointHealth)>)> {
+        self.chains
+            .iter()
+            .map(|chain| {
+                (
+                    chain.query.chain(),
+                    chain.query.provider().as_ref().health(),
+                )
+            })
+            .collect()
+    }
+
+    /// Probes the endpoints of every chain, then refreshes its status from the healthiest one,
+    /// a chain whose endpoints all fail keeps its last known status
+    async fn refresh_statuses(&self) {
+        join_all(self.chains.iter().map(|chain| async move {
+            chain.query.provider().as_ref().probe().await;
+            match chain.query.query_info().await {
+                Ok((block_number, gas_price)) => {
+                    *chain.status.write() = Some(ChainStatus {
+                        block_number,
+                        gas_price,
+                    })
+                }
+                Err(err) => log::warn!(
+                    "Unable to refresh the status of {}: {}",
+                    chain.query.chain().name,
+                    err
+                ),
+            }
+        }))
+        .await;
+    }
+
+    /// Updates the status of the chain on every new head sent by its WebSocket, until the
+    /// subscription ends
+    async fn follow_new_heads(&self, chain: &ChainProvider, ws_url: &str) -> anyhow::Result<()> {
+        let ws = Provider::<Ws>::connect(ws_url).await?;
+        let mut heads = ws.subscribe_blocks().await?;
+        while let Some(head) = heads.next().await {
+            let block_number = match head.number {
+                Some(block_number) => block_number,
+                None => continue,
+            };
+            let gas_price = match ws.get_gas_price().await {
+                Ok(gas_price) => gas_price,
+                Err(err) => {
+                    log::warn!(
+                        "Unable to get the gas price of {}: {}",
+                        chain.query.chain().name,
+                        err
+                    );
+                    match *chain.status.read() {
+                        Some(status) => status.gas_price,
+                        None => continue,
+                    }
+                }
+            };
+            let mut status = chain.status.write();
+            // the HTTP refresh may already be ahead
+            if !status.is_some_and(|status| status.block_number > block_number) {
+                *status = Some(ChainStatus {
+                    block_number,
+                    gas_price,
+                });
+            }
+        }
+        Err(anyhow::anyhow!("The new heads subscription ended"))
+    }
+
+    /// Subscribes to the new heads of the chains with a WebSocket URL, so their status follows
+    /// every block instead of the periodic refresh
+    pub(crate) fn spawn_new_heads_subscriptions(self: Arc<Self>) {
+        for index in 0..self.chains.len() {
+            let ws_url = match &self.chains[index].query.chain().ws_url {
+                Some(ws_url) => ws_url.clone(),
+                None => continue,
+            };
+            let providers = self.clone();
+            tokio::spawn(async move {
+                let chain = &providers.chains[index];
+                loop {
+                    if let Err(err) = providers.follow_new_heads(chain, &ws_url).await {
+                        log::warn!(
+                            "Unable to follow the new heads of {}: {}",
+                            chain.query.chain().name,
+                            err
+                        );
+                    }
+                    sleep(SUBSCRIBE_RETRY_DELAY).await;
+                }
+            });
+        }
+    }
+
+    /// Refreshes the status of every chain periodically, the first refresh is awaited so the
+    /// first menus aren't rendered without it
+    pub(crate) async fn spawn_status_refresh(self: Arc<Self>) {
+        self.refresh_statuses().await;
+        tokio::spawn(async move {
+            let mut ticks = interval(STATUS_REFRESH_INTERVAL);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // the first tick completes immediately
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                self.refresh_statuses().await;
+            }
+        });
+    }
+}
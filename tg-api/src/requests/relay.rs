@@ -0,0 +1,435 @@
+use crate::config::{RelayConfig, RelayMethod};
+use ethers::{
+    providers::Middleware,
+    signers::{LocalWallet, Signer},
+    types::{Address, Bytes, H256, U256, U64},
+    utils::keccak256,
+};
+use futures::future::join_all;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+
+/// How long a relay has to answer a request
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A signed tx handed to a relay, it only reaches the public mempool on fallback
+#[derive(Debug, Clone)]
+pub(crate) struct RelayedTx {
+    pub(crate) raw: Bytes,
+    /// Last block the relay may include the tx in
+    pub(crate) max_block: U64,
+    /// Host of the relay, for display
+    pub(crate) relay: String,
+    /// Whether the tx is broadcast publicly when the relay doesn't include it in time
+    pub(crate) public_fallback: bool,
+    /// Whether the tx was shared with the searchers for rebates
+    pub(crate) rebate: bool,
+}
+
+/// How a private tx was submitted
+#[derive(Debug, Clone)]
+pub(crate) enum PrivateSubmission {
+    Relayed(RelayedTx),
+    /// The relay refused the tx, it was broadcast publicly instead
+    FellBack {
+        relay: String,
+        reason: String,
+    },
+}
+
+/// Client of a Flashbots compatible relay, every request is signed with the relay key in the
+/// `X-Flashbots-Signature` header
+#[derive(Debug, Clone)]
+pub(crate) struct RelayClient {
+    url: Url,
+    http: reqwest::Client,
+    signer: LocalWallet,
+    config: RelayConfig,
+}
+
+impl RelayClient {
+    pub(crate) fn new(url: &str, config: &RelayConfig) -> anyhow::Result<Self> {
+        let signer = match &config.signing_key {
+            Some(key) => LocalWallet::from_str(key)?,
+            None => LocalWallet::new(&mut ethers::core::rand::thread_rng()),
+        };
+        Ok(Self {
+            url: Url::parse(url)?,
+            http: reqwest::Client::builder().timeout(RELAY_TIMEOUT).build()?,
+            signer,
+            config: config.clone(),
+        })
+    }
+
+    /// Host of the relay, for display
+    pub(crate) fn host(&self) -> String {
+        self.url.host_str().unwrap_or_default().to_string()
+    }
+
+    async fn request<R: DeserializeOwned>(&self, method: &str, params: Value) -> anyhow::Result<R> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+        // the relay expects the signature of the hex encoded body hash
+        let digest = format!("{:#x}", H256::from(keccak256(body.as_bytes())));
+        let signature = self.signer.sign_message(digest).await?;
+        let response: Value = self
+            .http
+            .post(self.url.clone())
+            .header("Content-Type", "application/json")
+            .header(
+                "X-Flashbots-Signature",
+                format!("{:#x}:0x{}", self.signer.address(), signature),
+            )
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(err) = response.get("error") {
+            return Err(anyhow::anyhow!(
+                "{} rejected by {}: {}",
+                method,
+                self.host(),
+                err
+            ));
+        }
+        Ok(serde_json::from_value(
+            response.get("result").cloned().unwrap_or(Value::Null),
+        )?)
+    }
+
+    /// Hands the tx to the relay, which retries it until `max_block`
+    pub(crate) async fn send_private_transaction(
+        &self,
+        raw: &Bytes,
+        max_block: U64,
+    ) -> anyhow::Result<()> {
+        let _: Value = self
+            .request(
+                "eth_sendPrivateTransaction",
+                json!([{ "tx": raw, "maxBlockNumber": max_block }]),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Sends a bundle of the tx alone, to be included in `block` only
+    pub(crate) async fn send_bundle(&self, raw: &Bytes, block: U64) -> anyhow::Result<()> {
+        let _: Value = self
+            .request(
+                "eth_sendBundle",
+                json!([{ "txs": [raw], "blockNumber": block }]),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Sends the tx as a MEV-Share bundle, the searchers see the hints of the rebate policy and
+    /// the relay pays `refund_to` its share of their backruns
+    pub(crate) async fn send_mev_share_bundle(
+        &self,
+        raw: &Bytes,
+        (block, max_block): (U64, U64),
+        refund_to: Address,
+    ) -> anyhow::Result<()> {
+        let _: Value = self
+            .request(
+                "mev_sendBundle",
+                json!([{
+                    "version": "v0.1",
+                    "inclusion": { "block": block, "maxBlock": max_block },
+                    "body": [{ "tx": raw, "canRevert": false }],
+                    "validity": { "refundConfig": [{ "address": refund_to, "percent": 100 }] },
+                    "privacy": { "hints": self.config.rebate.hints },
+                }]),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Estimates the rebate of a swap moving `native_amount` of the native token with the given
+    /// price impact: a backrun restoring the price earns about half the impact on the amount,
+    /// of which the relay refunds its share
+    pub(crate) fn expected_rebate(&self, native_amount: U256, price_impact: f64) -> U256 {
+        let impact_bps = (price_impact.clamp(0.0, 100.0) * 100.0).round() as u64;
+        native_amount * U256::from(impact_bps) / U256::from(2 * 10_000u64)
+            * U256::from(self.config.rebate.refund_percent)
+            / U256::from(100u64)
+    }
+
+    /// Submits the signed tx through the relay, a MEV-Share bundle refunding `refund_to` when it
+    /// is set. It's broadcast publicly instead when the relay refuses it and the public fallback
+    /// is enabled
+    pub(crate) async fn submit<M: Middleware + 'static>(
+        &self,
+        provider: &M,
+        raw: Bytes,
+        refund_to: Option<Address>,
+    ) -> anyhow::Result<PrivateSubmission> {
+        let block = provider.get_block_number().await?;
+        let max_block = block + self.config.max_blocks;
+        let relayed = match (refund_to, self.config.method) {
+            (Some(refund_to), _) => {
+                self.send_mev_share_bundle(&raw, (block + 1, max_block), refund_to)
+                    .await
+            }
+            (None, RelayMethod::PrivateTransaction) => {
+                self.send_private_transaction(&raw, max_block).await
+            }
+            (None, RelayMethod::Bundle) => {
+                // bundles target a single block, so one is sent for each block of the window
+                let results = join_all(
+                    (1..=self.config.max_blocks)
+                        .map(|offset| self.send_bundle(&raw, block + offset)),
+                )
+                .await;
+                let accepted = results.iter().any(Result::is_ok);
+                match results.into_iter().find_map(Result::err) {
+                    Some(err) if !accepted => Err(err),
+                    _ => Ok(()),
+                }
+            }
+        };
+        match relayed {
+            Ok(()) => Ok(PrivateSubmission::Relayed(RelayedTx {
+                raw,
+                max_block,
+                relay: self.host(),
+                public_fallback: self.config.public_fallback,
+                rebate: refund_to.is_some(),
+            })),
+            Err(err) if self.config.public_fallback => {
+                log::warn!("Unable to relay the tx, broadcasting it publicly: {}", err);
+                provider.send_raw_transaction(raw).await?;
+                Ok(PrivateSubmission::FellBack {
+                    relay: self.host(),
+                    reason: err.to_string(),
+                })
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RebateConfig;
+    use ethers::providers::{MockProvider, Provider};
+    use ethers::types::Signature;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use parking_lot::Mutex;
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    const SIGNING_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    /// Signature header and body of a request the relay received
+    type Received = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+    /// Starts a relay on a free local port recording every request, it refuses them all when
+    /// `reject` is set. Returns its URL
+    fn mock_relay(reject: bool) -> (String, Received) {
+        let received = Received::default();
+        let recorded = received.clone();
+        let service = make_service_fn(move |_| {
+            let recorded = recorded.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let recorded = recorded.clone();
+                    async move {
+                        let header = request
+                            .headers()
+                            .get("X-Flashbots-Signature")
+                            .and_then(|header| header.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string();
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        recorded.lock().push((header, body.to_vec()));
+                        let response = match reject {
+                            true => json!({
+                                "jsonrpc": "2.0",
+                                "id": 1,
+                                "error": { "code": -32000, "message": "rejected" },
+                            }),
+                            false => json!({ "jsonrpc": "2.0", "id": 1, "result": H256::zero() }),
+                        };
+                        Ok::<_, Infallible>(Response::new(Body::from(response.to_string())))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, received)
+    }
+
+    fn relay_config(method: RelayMethod, public_fallback: bool) -> RelayConfig {
+        RelayConfig {
+            method,
+            signing_key: Some(SIGNING_KEY.to_string()),
+            max_blocks: 3,
+            public_fallback,
+            rebate: RebateConfig {
+                hints: vec!["hash".to_string(), "logs".to_string()],
+                refund_percent: 90,
+            },
+        }
+    }
+
+    /// The requests the relay received, checking each is signed by the relay key
+    fn signed_requests(received: &Received) -> Vec<Value> {
+        let signer = LocalWallet::from_str(SIGNING_KEY).unwrap().address();
+        received
+            .lock()
+            .iter()
+            .map(|(header, body)| {
+                let (address, signature) = header.split_once(':').unwrap();
+                assert_eq!(Address::from_str(address).unwrap(), signer);
+                let digest = format!("{:#x}", H256::from(keccak256(body)));
+                let signature = Signature::from_str(signature).unwrap();
+                assert_eq!(signature.recover(digest).unwrap(), signer);
+                serde_json::from_slice(body).unwrap()
+            })
+            .collect()
+    }
+
+    fn raw_tx() -> Bytes {
+        Bytes::from(vec![0x02, 0xf8, 0x01, 0x02])
+    }
+
+    /// A node at block 100 accepting the public broadcast, if any
+    fn node() -> (Provider<MockProvider>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        // the responses are popped last in first out
+        mock.push(H256::from(keccak256(raw_tx()))).unwrap();
+        mock.push(U64::from(100)).unwrap();
+        (provider, mock)
+    }
+
+    #[tokio::test]
+    async fn signs_the_private_transaction_with_the_relay_key() {
+        let (url, received) = mock_relay(false);
+        let config = relay_config(RelayMethod::PrivateTransaction, false);
+        let relay = RelayClient::new(&url, &config).unwrap();
+        let (provider, _) = node();
+        let submission = relay.submit(&provider, raw_tx(), None).await.unwrap();
+
+        let requests = signed_requests(&received);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["method"], "eth_sendPrivateTransaction");
+        assert_eq!(
+            requests[0]["params"],
+            json!([{ "tx": raw_tx(), "maxBlockNumber": U64::from(103) }])
+        );
+        match submission {
+            PrivateSubmission::Relayed(relayed) => {
+                assert_eq!(relayed.max_block, U64::from(103));
+                assert_eq!(relayed.relay, "127.0.0.1");
+                assert!(!relayed.rebate);
+            }
+            submission => panic!("Unexpected submission {:?}", submission),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_a_bundle_for_every_block_of_the_window() {
+        let (url, received) = mock_relay(false);
+        let relay = RelayClient::new(&url, &relay_config(RelayMethod::Bundle, false)).unwrap();
+        let (provider, _) = node();
+        relay.submit(&provider, raw_tx(), None).await.unwrap();
+
+        let mut blocks: Vec<Value> = signed_requests(&received)
+            .into_iter()
+            .map(|request| {
+                assert_eq!(request["method"], "eth_sendBundle");
+                assert_eq!(request["params"][0]["txs"], json!([raw_tx()]));
+                request["params"][0]["blockNumber"].clone()
+            })
+            .collect();
+        blocks.sort_by_key(|block| serde_json::from_value::<U64>(block.clone()).unwrap());
+        assert_eq!(
+            blocks,
+            vec![
+                json!(U64::from(101)),
+                json!(U64::from(102)),
+                json!(U64::from(103))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_a_mev_share_bundle_refunding_the_wallet() {
+        let (url, received) = mock_relay(false);
+        let relay = RelayClient::new(&url, &relay_config(RelayMethod::Bundle, false)).unwrap();
+        let (provider, _) = node();
+        let wallet = Address::repeat_byte(0x77);
+        let submission = relay
+            .submit(&provider, raw_tx(), Some(wallet))
+            .await
+            .unwrap();
+
+        let requests = signed_requests(&received);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["method"], "mev_sendBundle");
+        assert_eq!(
+            requests[0]["params"],
+            json!([{
+                "version": "v0.1",
+                "inclusion": { "block": U64::from(101), "maxBlock": U64::from(103) },
+                "body": [{ "tx": raw_tx(), "canRevert": false }],
+                "validity": { "refundConfig": [{ "address": wallet, "percent": 100 }] },
+                "privacy": { "hints": ["hash", "logs"] },
+            }])
+        );
+        assert!(matches!(
+            submission,
+            PrivateSubmission::Relayed(RelayedTx { rebate: true, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn broadcasts_publicly_when_the_relay_refuses() {
+        let (url, received) = mock_relay(true);
+        let config = relay_config(RelayMethod::PrivateTransaction, true);
+        let relay = RelayClient::new(&url, &config).unwrap();
+        let (provider, mock) = node();
+        let submission = relay.submit(&provider, raw_tx(), None).await.unwrap();
+
+        assert_eq!(signed_requests(&received).len(), 1);
+        mock.assert_request("eth_blockNumber", ()).unwrap();
+        mock.assert_request("eth_sendRawTransaction", [raw_tx()])
+            .unwrap();
+        match submission {
+            PrivateSubmission::FellBack { relay, reason } => {
+                assert_eq!(relay, "127.0.0.1");
+                assert!(reason.contains("rejected"), "{}", reason);
+            }
+            submission => panic!("Unexpected submission {:?}", submission),
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_relay_refuses_without_fallback() {
+        let (url, _) = mock_relay(true);
+        let config = relay_config(RelayMethod::PrivateTransaction, false);
+        let relay = RelayClient::new(&url, &config).unwrap();
+        let (provider, mock) = node();
+        assert!(relay.submit(&provider, raw_tx(), None).await.is_err());
+        mock.assert_request("eth_blockNumber", ()).unwrap();
+        assert!(mock
+            .assert_request("eth_sendRawTransaction", [raw_tx()])
+            .is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::config::ChainConfig;
+use crate::requests::on_chain::short_address;
+use crate::requests::swap::{native_token, UniswapV2Router};
+use ethers::{
+    contract::abigen,
+    providers::Middleware,
+    types::{Address, Bytes, U256},
+};
+use futures::future::{join_all, BoxFuture, FutureExt};
+use std::fmt;
+use std::sync::Arc;
+
+abigen!(
+    UniswapV3Quoter,
+    r#"[
+        function quoteExactInput(bytes path, uint256 amountIn) external returns (uint256 amountOut)
+    ]"#
+);
+
+abigen!(
+    UniswapV3Router,
+    r#"[
+        struct ExactInputParams { bytes path; address recipient; uint256 deadline; uint256 amountIn; uint256 amountOutMinimum; }
+        function exactInput(ExactInputParams params) external payable returns (uint256 amountOut)
+        function unwrapWETH9(uint256 amountMinimum, address recipient) external payable
+        function multicall(bytes[] data) external payable returns (bytes[] results)
+    ]"#
+);
+
+/// Fee tiers of the Uniswap V3 pools quoted, in hundredths of a basis point
+pub(crate) const V3_FEE_TIERS: [u32; 3] = [500, 3000, 10000];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    V2,
+    /// Fee tier of each hop
+    V3 {
+        fees: Vec<u32>,
+    },
+}
+
+/// A quoted route, every hop goes through pools of the same protocol so the swap is sent to a
+/// single router
+#[derive(Debug, Clone)]
+pub(crate) struct Route {
+    pub(crate) protocol: Protocol,
+    /// Tokens swapped through, WETH stands in for the native token
+    pub(crate) path: Vec<Address>,
+    pub(crate) amount_out: U256,
+}
+
+impl Route {
+    /// Router the swap along this route is sent to
+    pub(crate) fn router(&self, chain: &ChainConfig) -> Address {
+        match self.protocol {
+            Protocol::V2 => chain.router,
+            Protocol::V3 { .. } => chain.v3_router,
+        }
+    }
+
+    /// Path encoded the way the V3 contracts expect it, each token followed by the fee tier of
+    /// the next pool
+    pub(crate) fn v3_path(&self) -> Bytes {
+        let fees = match &self.protocol {
+            Protocol::V3 { fees } => fees.as_slice(),
+            Protocol::V2 => &[],
+        };
+        encode_v3_path(&self.path, fees)
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.protocol {
+            Protocol::V2 => write!(f, "Uniswap V2: ")?,
+            Protocol::V3 { .. } => write!(f, "Uniswap V3: ")?,
+        }
+        for (hop, token) in self.path.iter().enumerate() {
+            if hop > 0 {
+                match &self.protocol {
+                    Protocol::V2 => write!(f, " → ")?,
+                    Protocol::V3 { fees } => {
+                        write!(f, " →({}%) ", fees[hop - 1] as f64 / 10_000.0)?
+                    }
+                }
+            }
+            write!(f, "{}", short_address(token))?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_v3_path(path: &[Address], fees: &[u32]) -> Bytes {
+    let mut encoded = Vec::with_capacity(path.len() * 23);
+    for (hop, token) in path.iter().enumerate() {
+        encoded.extend_from_slice(token.as_bytes());
+        if let Some(fee) = fees.get(hop) {
+            encoded.extend_from_slice(&fee.to_be_bytes()[1..]);
+        }
+    }
+    encoded.into()
+}
+
+async fn quote_v2<M: Middleware + 'static>(
+    router: UniswapV2Router<M>,
+    path: Vec<Address>,
+    amount_in: U256,
+) -> Option<Route> {
+    let amounts = router
+        .get_amounts_out(amount_in, path.clone())
+        .call()
+        .await
+        .ok()?;
+    let amount_out = *amounts.last()?;
+    (!amount_out.is_zero()).then_some(Route {
+        protocol: Protocol::V2,
+        path,
+        amount_out,
+    })
+}
+
+async fn quote_v3<M: Middleware + 'static>(
+    quoter: UniswapV3Quoter<M>,
+    path: Vec<Address>,
+    fees: Vec<u32>,
+    amount_in: U256,
+) -> Option<Route> {
+    // the quoter reverts when one of the pools doesn't exist
+    let amount_out = quoter
+        .quote_exact_input(encode_v3_path(&path, &fees), amount_in)
+        .call()
+        .await
+        .ok()?;
+    (!amount_out.is_zero()).then_some(Route {
+        protocol: Protocol::V3 { fees },
+        path,
+        amount_out,
+    })
+}
+
+/// Quotes `amount_in` along the route again, None when one of its pools can't be quoted
+pub(crate) async fn quote_route<M: Middleware + 'static>(
+    client: Arc<M>,
+    chain: &ChainConfig,
+    route: &Route,
+    amount_in: U256,
+) -> Option<U256> {
+    let quote = match &route.protocol {
+        Protocol::V2 => {
+            let router = UniswapV2Router::new(chain.router, client);
+            quote_v2(router, route.path.clone(), amount_in).await
+        }
+        Protocol::V3 { fees } => {
+            let quoter = UniswapV3Quoter::new(chain.quoter, client);
+            quote_v3(quoter, route.path.clone(), fees.clone(), amount_in).await
+        }
+    };
+    quote.map(|route| route.amount_out)
+}
+
+/// Quotes the direct and the two hop routes through the wrapped native token and the chain's
+/// routing tokens, across the V2 pairs and the V3 fee tiers, and returns the one receiving the
+/// most
+pub(crate) async fn best_route<M: Middleware + 'static>(
+    client: Arc<M>,
+    chain: &ChainConfig,
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+) -> anyhow::Result<Route> {
+    let v2_router = UniswapV2Router::new(chain.router, client.clone());
+    let quoter = UniswapV3Quoter::new(chain.quoter, client);
+    let weth = chain.wrapped_native;
+    let native = native_token();
+    let token_in = if token_in == native { weth } else { token_in };
+    let token_out = if token_out == native { weth } else { token_out };
+    if token_in == token_out {
+        return Err(anyhow::anyhow!("Cannot swap a token for itself"));
+    }
+
+    let mut paths = vec![vec![token_in, token_out]];
+    let mut hubs = vec![weth];
+    hubs.extend(chain.routing_tokens.iter().copied());
+    for hub in hubs {
+        if hub != token_in && hub != token_out && !paths.iter().any(|path| path[1] == hub) {
+            paths.push(vec![token_in, hub, token_out]);
+        }
+    }
+
+    let mut quotes: Vec<BoxFuture<'_, Option<Route>>> = Vec::new();
+    for path in paths {
+        quotes.push(quote_v2(v2_router.clone(), path.clone(), amount_in).boxed());
+        let fee_combinations: Vec<Vec<u32>> = if path.len() == 2 {
+            V3_FEE_TIERS.iter().map(|&fee| vec![fee]).collect()
+        } else {
+            V3_FEE_TIERS
+                .iter()
+                .flat_map(|&first| V3_FEE_TIERS.iter().map(move |&second| vec![first, second]))
+                .collect()
+        };
+        for fees in fee_combinations {
+            quotes.push(quote_v3(quoter.clone(), path.clone(), fees, amount_in).boxed());
+        }
+    }
+
+    join_all(quotes)
+        .await
+        .into_iter()
+        .flatten()
+        .max_by_key(|route| route.amount_out)
+        .ok_or_else(|| anyhow::anyhow!("No route found from {:#x} to {:#x}", token_in, token_out))
+}
@@ -1,92 +1,179 @@
-use teloxide::types::InlineKeyboardMarkup;
+use crate::requests::swap::{SwapAmount, SwapOrder};
+use crate::storages::{OrderDraft, OrderSide, TradeRecord, TradeStatus};
+use ethers::types::Address;
+use teloxide::types::UserId;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub(crate) struct SendBuyTxRequest {
-    pub(crate) wallet: String,
+    pub(crate) chain_id: u64,
+    /// Address of the selected wallet
+    pub(crate) wallet: Address,
     pub(crate) private_tx: bool,
     pub(crate) rebate: bool,
-    pub(crate) buy: String,
-    pub(crate) receive: String,
+    pub(crate) buy: Address,
+    pub(crate) receive: Address,
     pub(crate) buy_amount: f64,
 }
 
 #[allow(dead_code)]
 impl SendBuyTxRequest {
-    ///  function called in handle_send_tx() to extract the [InlineKeyboardButton](cteloxide::types::InlineKeyboardButton) texts
-    /// Note: any change to the buy button layout from the [keyboard.rs](crate::keyboards) will affect this function
-    pub(crate) fn new(keyboard: &InlineKeyboardMarkup) -> anyhow::Result<Self> {
-        // find the wallet that has emoji
-        let selected_wallet = match keyboard.inline_keyboard.get(3).and_then(|row| {
-            row.iter()
-                .find(|&button| {
-                    !(button.text == "Wallet 1"
-                        || button.text == "Wallet 2"
-                        || button.text == "Wallet 3")
-                })
-                .map(|button| button.text.clone())
-        }) {
-            Some(wallet) => wallet,
-            None => return Err(anyhow::anyhow!("No wallet found")),
-        };
+    /// function called in handle_send_tx() to validate the user's [OrderDraft]
+    pub(crate) fn new(draft: &OrderDraft) -> anyhow::Result<Self> {
+        if draft.side != OrderSide::Buy {
+            return Err(anyhow::anyhow!("Not a buy order"));
+        }
 
-        // find it user want private transaction or not
-        // if has emoji, then user wants private tx, otherwise no
-        let private_tx = match keyboard.inline_keyboard[1][0].text.as_str() {
-            "Private Tx" => false,
-            _ => true,
-        };
+        Ok(Self {
+            chain_id: draft.chain_id,
+            wallet: draft
+                .wallet
+                .ok_or_else(|| anyhow::anyhow!("No wallet found"))?,
+            private_tx: draft.private_tx,
+            rebate: draft.rebate,
+            buy: draft
+                .buy_token
+                .ok_or_else(|| anyhow::anyhow!("No buy token address found"))?,
+            receive: draft
+                .receive_token
+                .ok_or_else(|| anyhow::anyhow!("No receive token address found"))?,
+            buy_amount: draft
+                .amount
+                .ok_or_else(|| anyhow::anyhow!("No amount found"))?,
+        })
+    }
 
-        // find if user wants rebate or not
-        // if has emoji, then user wants rebate, otherwise no
-        let rebate = match keyboard.inline_keyboard[1][1].text.as_str() {
-            "Rebate" => false,
-            _ => true,
-        };
+    /// The swap paying `buy_amount` of the receive token for the buy token
+    pub(crate) fn swap_order(&self) -> SwapOrder {
+        SwapOrder {
+            token_in: self.receive,
+            token_out: self.buy,
+            amount: SwapAmount::Exact(self.buy_amount),
+            private: self.private_tx,
+            rebate: self.rebate,
+            // set once the simulation of the buy measures a tax, see TokenRisk::is_buy_taxed
+            fee_on_transfer: false,
+        }
+    }
 
-        let buy_token_address = match keyboard.inline_keyboard[4][0]
-            .text
-            .as_str()
-            .split(": ")
-            .collect::<Vec<&str>>()
-            .get(1)
-            .cloned()
-        {
-            Some(token_address) => token_address,
-            None => return Err(anyhow::anyhow!("No token address found")),
-        };
+    /// The journal entry of this order
+    pub(crate) fn trade_record(&self, user_id: UserId) -> TradeRecord {
+        TradeRecord {
+            id: 0,
+            user_id,
+            chain_id: self.chain_id,
+            side: OrderSide::Buy,
+            wallet: self.wallet,
+            private_tx: self.private_tx,
+            rebate: self.rebate,
+            // the buy amount is paid in the receive token
+            token_in: self.receive,
+            token_out: self.buy,
+            amount: Some(self.buy_amount),
+            sell_percentage: None,
+            tx_hash: None,
+            status: TradeStatus::Pending,
+            gas_paid: None,
+            filled_in: None,
+            filled_out: None,
+            expected_rebate: None,
+            rebate_received: None,
+            created_at: chrono::Utc::now().timestamp(),
+        }
+    }
+}
 
-        let receive_token_addesss = match keyboard.inline_keyboard[4][1]
-            .text
-            .as_str()
-            .split(": ")
-            .collect::<Vec<&str>>()
-            .get(1)
-            .cloned()
-        {
-            Some(token_address) => token_address,
-            None => return Err(anyhow::anyhow!("No token address found")),
-        };
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub(crate) struct SendSellTxRequest {
+    pub(crate) chain_id: u64,
+    /// Address of the selected wallet
+    pub(crate) wallet: Address,
+    pub(crate) private_tx: bool,
+    pub(crate) rebate: bool,
+    pub(crate) sell: Address,
+    pub(crate) receive: Address,
+    /// Exact amount of the sell token, mutually exclusive with `sell_percentage`
+    pub(crate) sell_amount: Option<f64>,
+    /// Percentage of the wallet's sell token balance, mutually exclusive with `sell_amount`
+    pub(crate) sell_percentage: Option<f64>,
+}
 
-        let buy_amount: f64 = match keyboard.inline_keyboard[5][0]
-            .text
-            .as_str()
-            .split(": ")
-            .collect::<Vec<&str>>()
-            .get(1)
-            .cloned()
-        {
-            Some(amount) => amount.parse().expect("Unable to parse amount"),
-            None => return Err(anyhow::anyhow!("No amount found")),
-        };
+#[allow(dead_code)]
+impl SendSellTxRequest {
+    /// function called in handle_send_tx() to validate the user's [OrderDraft]
+    pub(crate) fn new(draft: &OrderDraft) -> anyhow::Result<Self> {
+        if draft.side != OrderSide::Sell {
+            return Err(anyhow::anyhow!("Not a sell order"));
+        }
+
+        match (draft.amount, draft.sell_percentage) {
+            (None, None) => return Err(anyhow::anyhow!("No sell amount or percentage found")),
+            (Some(_), Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "Only one of sell amount or percentage can be set"
+                ))
+            }
+            _ => {}
+        }
 
         Ok(Self {
-            wallet: selected_wallet,
-            private_tx,
-            rebate,
-            buy: buy_token_address.to_string(),
-            receive: receive_token_addesss.to_string(),
-            buy_amount,
+            chain_id: draft.chain_id,
+            wallet: draft
+                .wallet
+                .ok_or_else(|| anyhow::anyhow!("No wallet found"))?,
+            private_tx: draft.private_tx,
+            rebate: draft.rebate,
+            sell: draft
+                .sell_token
+                .ok_or_else(|| anyhow::anyhow!("No sell token address found"))?,
+            receive: draft
+                .receive_token
+                .ok_or_else(|| anyhow::anyhow!("No receive token address found"))?,
+            sell_amount: draft.amount,
+            sell_percentage: draft.sell_percentage,
         })
     }
+
+    /// The swap selling the sell token for the receive token
+    pub(crate) fn swap_order(&self) -> SwapOrder {
+        let amount = match (self.sell_amount, self.sell_percentage) {
+            (Some(amount), _) => SwapAmount::Exact(amount),
+            (_, Some(percentage)) => SwapAmount::BalancePercentage(percentage),
+            _ => unreachable!("validated in SendSellTxRequest::new"),
+        };
+        SwapOrder {
+            token_in: self.sell,
+            token_out: self.receive,
+            amount,
+            private: self.private_tx,
+            rebate: self.rebate,
+            fee_on_transfer: true,
+        }
+    }
+
+    /// The journal entry of this order
+    pub(crate) fn trade_record(&self, user_id: UserId) -> TradeRecord {
+        TradeRecord {
+            id: 0,
+            user_id,
+            chain_id: self.chain_id,
+            side: OrderSide::Sell,
+            wallet: self.wallet,
+            private_tx: self.private_tx,
+            rebate: self.rebate,
+            token_in: self.sell,
+            token_out: self.receive,
+            amount: self.sell_amount,
+            sell_percentage: self.sell_percentage,
+            tx_hash: None,
+            status: TradeStatus::Pending,
+            gas_paid: None,
+            filled_in: None,
+            filled_out: None,
+            expected_rebate: None,
+            rebate_received: None,
+            created_at: chrono::Utc::now().timestamp(),
+        }
+    }
 }
@@ -0,0 +1,818 @@
+use crate::config::{ChainConfig, SwapConfig};
+use crate::requests::failover::RpcProvider;
+use crate::requests::routing::{Protocol, Route};
+use crate::requests::swap::{
+    self, balance, decimals, from_units, native_token, needs_approval, prepare_swap, quote_swap,
+    swap_tx, to_units, Erc20, PreparedSwap, SwapOrder, UniswapV2Router,
+};
+use ethers::{
+    abi::{self, ParamType, Token},
+    contract::abigen,
+    providers::{spoof, Middleware, RawCall, RpcError},
+    types::{Address, Bytes, TransactionRequest, U256},
+    utils::keccak256,
+};
+use std::sync::Arc;
+
+abigen!(
+    Multicall3,
+    r#"[
+        struct Call3Value { address target; bool allowFailure; uint256 value; bytes callData; }
+        struct CallResult { bool success; bytes returnData; }
+        function aggregate3Value(Call3Value[] calls) external payable returns (CallResult[] returnData)
+    ]"#
+);
+
+/// Getters tokens commonly expose their largest amount per tx with
+const MAX_TX_GETTERS: [&str; 4] = [
+    "_maxTxAmount()",
+    "maxTxAmount()",
+    "maxTransactionAmount()",
+    "_maxTransactionAmount()",
+];
+
+/// Getters tokens commonly expose their largest balance per wallet with
+const MAX_WALLET_GETTERS: [&str; 5] = [
+    "_maxWalletSize()",
+    "maxWallet()",
+    "maxWalletSize()",
+    "_maxWalletToken()",
+    "maxWalletAmount()",
+];
+
+/// Getters tokens commonly expose their blacklist with
+const BLACKLIST_GETTERS: [&str; 5] = [
+    "isBlacklisted(address)",
+    "_isBlacklisted(address)",
+    "blacklist(address)",
+    "isBot(address)",
+    "bots(address)",
+];
+
+/// Below this a measured tax is rounding
+const MIN_TAX_PERCENT: f64 = 0.01;
+
+/// A tax above this is flagged as high
+const HIGH_TAX_PERCENT: f64 = 10.0;
+
+/// A sell tax above this makes the token a honeypot, selling returns next to nothing
+const HONEYPOT_TAX_PERCENT: f64 = 90.0;
+
+/// How risky the token looks from its simulated round trip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RiskLevel {
+    Low,
+    Medium,
+    High,
+}
+
+/// Outcome of buying the token then selling it back in a simulation, nothing is sent
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TokenRisk {
+    /// Revert reason of the buy, None when it went through
+    pub(crate) buy_error: Option<String>,
+    /// Revert reason of the sell, None when it went through or wasn't simulated
+    pub(crate) sell_error: Option<String>,
+    /// Percentage of the quoted amount the buy didn't deliver
+    pub(crate) buy_tax: Option<f64>,
+    /// Percentage of the quoted amount the sell didn't deliver
+    pub(crate) sell_tax: Option<f64>,
+    /// Whether the bought tokens can be transferred to another address
+    pub(crate) transferable: Option<bool>,
+    /// Largest amount per tx, in percent of the supply, when the token exposes it
+    pub(crate) max_tx: Option<f64>,
+    /// Largest balance per wallet, in percent of the supply, when the token exposes it
+    pub(crate) max_wallet: Option<f64>,
+    /// Whether the token reports the wallet as blacklisted, when it exposes a blacklist
+    pub(crate) blacklisted: Option<bool>,
+}
+
+impl TokenRisk {
+    /// Whether the token can be bought but not sold back
+    pub(crate) fn is_honeypot(&self) -> bool {
+        self.buy_error.is_none()
+            && (self.sell_error.is_some()
+                || self.sell_tax.is_some_and(|tax| tax >= HONEYPOT_TAX_PERCENT))
+    }
+
+    /// Whether buying the token delivered less than quoted, the buy then has to go through the
+    /// router's variants supporting the fee on transfer
+    pub(crate) fn is_buy_taxed(&self) -> bool {
+        self.buy_tax.is_some_and(|tax| tax >= MIN_TAX_PERCENT)
+    }
+
+    pub(crate) fn level(&self) -> RiskLevel {
+        let taxes = [self.buy_tax, self.sell_tax];
+        if self.is_honeypot()
+            || self.buy_error.is_some()
+            || self.blacklisted == Some(true)
+            || self.transferable == Some(false)
+            || taxes.iter().flatten().any(|tax| *tax > HIGH_TAX_PERCENT)
+        {
+            RiskLevel::High
+        } else if taxes.iter().flatten().any(|tax| *tax >= MIN_TAX_PERCENT)
+            || self.max_tx.is_some()
+            || self.max_wallet.is_some()
+        {
+            RiskLevel::Medium
+        } else {
+            RiskLevel::Low
+        }
+    }
+}
+
+/// Readable reason of a revert, from its `Error(string)` or `Panic(uint256)` data
+pub(crate) fn revert_reason(data: &[u8]) -> String {
+    let decoded = match data.get(..4) {
+        Some([0x08, 0xc3, 0x79, 0xa0]) => abi::decode(&[ParamType::String], &data[4..])
+            .ok()
+            .and_then(|tokens| tokens.into_iter().next()?.into_string()),
+        Some([0x4e, 0x48, 0x7b, 0x71]) => abi::decode(&[ParamType::Uint(256)], &data[4..])
+            .ok()
+            .and_then(|tokens| tokens.into_iter().next()?.into_uint())
+            .map(|code| format!("panic {:#x}", code)),
+        Some(selector) => Some(format!("custom error 0x{}", hex(selector))),
+        None => None,
+    };
+    decoded.unwrap_or_else(|| "reverted without a reason".to_string())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn decode_uint(data: &[u8]) -> Option<U256> {
+    (data.len() >= 32).then(|| U256::from_big_endian(&data[..32]))
+}
+
+/// Last amount of a `getAmountsOut` result
+fn decode_amount_out(data: &[u8]) -> Option<U256> {
+    let tokens = abi::decode(&[ParamType::Array(Box::new(ParamType::Uint(256)))], data).ok()?;
+    tokens.into_iter().next()?.into_array()?.pop()?.into_uint()
+}
+
+/// Lost share of the quoted amount, in percent
+fn tax(quoted: U256, delivered: U256) -> Option<f64> {
+    if quoted.is_zero() {
+        return None;
+    }
+    let lost = quoted.saturating_sub(delivered);
+    let percent = lost.saturating_mul(U256::from(1_000_000u64)) / quoted;
+    Some(percent.as_u64() as f64 / 10_000.0)
+}
+
+/// A sub call of the simulation, its failure is read from its result rather than reverting the
+/// whole batch
+fn sub_call(target: Address, value: U256, data: Option<Bytes>) -> anyhow::Result<Call3Value> {
+    Ok(Call3Value {
+        target,
+        allow_failure: true,
+        value,
+        call_data: data.ok_or_else(|| anyhow::anyhow!("Unable to encode the simulated call"))?,
+    })
+}
+
+/// Calls the first of the `getters` the token implements, with `args` encoded after the selector
+async fn first_getter<M: Middleware + 'static>(
+    client: &Arc<M>,
+    token: Address,
+    getters: &[&str],
+    args: &[Token],
+) -> Option<U256> {
+    for getter in getters {
+        let mut data = selector(getter).to_vec();
+        data.extend(abi::encode(args));
+        let tx = TransactionRequest::new().to(token).data(data).into();
+        if let Ok(output) = client.call(&tx, None).await {
+            if output.len() == 32 {
+                return decode_uint(&output);
+            }
+        }
+    }
+    None
+}
+
+/// Share of the supply in percent, None when the limit doesn't limit anything
+fn supply_percent(amount: U256, supply: U256) -> Option<f64> {
+    if supply.is_zero() || amount >= supply {
+        return None;
+    }
+    let percent = amount.saturating_mul(U256::from(10_000u64)) / supply;
+    Some(percent.as_u64() as f64 / 100.0)
+}
+
+/// Buys `amount` whole tokens of `token_in` worth of `token` from the `wallet`, then sells what
+/// was received back, with `eth_call` against the latest block. The code of the chain's
+/// Multicall3 is put at the wallet's address for the call, so the wallet's balances, allowances
+/// and blacklisting apply. The round trip goes through the Uniswap V2 router, where the taxed
+/// tokens trade, with the fee on transfer swaps
+pub(crate) async fn simulate_round_trip<M: Middleware + 'static>(
+    provider: M,
+    chain: &ChainConfig,
+    wallet: Address,
+    token_in: Address,
+    token: Address,
+    amount: f64,
+) -> anyhow::Result<TokenRisk> {
+    let client = Arc::new(provider);
+    let native = native_token();
+    let pay = if token_in == native {
+        chain.wrapped_native
+    } else {
+        token_in
+    };
+    let amount_in = to_units(amount, decimals(&client, token_in).await?)?;
+    if balance(&client, token_in, wallet).await? < amount_in {
+        return Err(anyhow::anyhow!("Insufficient balance to simulate the buy"));
+    }
+
+    let router = UniswapV2Router::new(chain.router, client.clone());
+    let mut paths = vec![vec![pay, token]];
+    if pay != chain.wrapped_native && token != chain.wrapped_native {
+        paths.push(vec![pay, chain.wrapped_native, token]);
+    }
+    let mut quoted = None;
+    for path in paths {
+        if let Ok(amounts) = router.get_amounts_out(amount_in, path.clone()).call().await {
+            match amounts.last() {
+                Some(amount_out) if !amount_out.is_zero() => {
+                    quoted = Some((path, *amount_out));
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+    let (path, quoted_buy) =
+        quoted.ok_or_else(|| anyhow::anyhow!("No Uniswap V2 pair to simulate the buy through"))?;
+    let sell_path: Vec<Address> = path.iter().rev().copied().collect();
+
+    let code = client.get_code(chain.multicall, None).await?;
+    if code.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No Multicall3 contract at {:#x} to simulate with",
+            chain.multicall
+        ));
+    }
+    let state = spoof::code(wallet, code);
+    let multicall = Multicall3::new(wallet, client.clone());
+    let bought = Erc20::new(token, client.clone());
+    let paid = Erc20::new(pay, client.clone());
+    // far enough to never expire, the simulation runs in the latest block
+    let deadline = U256::MAX;
+    let zero = U256::zero();
+
+    // the buy, between two balance reads, is the start of both batches
+    let mut buy = vec![sub_call(token, zero, bought.balance_of(wallet).calldata())?];
+    if token_in == native {
+        buy.push(sub_call(
+            chain.router,
+            amount_in,
+            router
+                .swap_exact_eth_for_tokens_supporting_fee_on_transfer_tokens(
+                    zero,
+                    path.clone(),
+                    wallet,
+                    deadline,
+                )
+                .calldata(),
+        )?);
+    } else {
+        buy.push(sub_call(
+            pay,
+            zero,
+            paid.approve(chain.router, amount_in).calldata(),
+        )?);
+        buy.push(sub_call(
+            chain.router,
+            zero,
+            router
+                .swap_exact_tokens_for_tokens_supporting_fee_on_transfer_tokens(
+                    amount_in,
+                    zero,
+                    path.clone(),
+                    wallet,
+                    deadline,
+                )
+                .calldata(),
+        )?);
+    }
+    buy.push(sub_call(token, zero, bought.balance_of(wallet).calldata())?);
+    let swap_index = buy.len() - 2;
+    let value = if token_in == native { amount_in } else { zero };
+
+    // the first batch buys and transfers a part of the bought tokens to a fresh address
+    let probe = Address::from_slice(&keccak256(b"koi-bot transfer probe")[12..]);
+    let mut calls = buy.clone();
+    calls.push(sub_call(
+        token,
+        zero,
+        bought.transfer(probe, quoted_buy / 100).calldata(),
+    )?);
+    let call = multicall.aggregate_3_value(calls).value(value).from(wallet);
+    let results = call.call_raw().state(&state).await?;
+
+    let mut risk = TokenRisk::default();
+    if !results[swap_index].0 {
+        risk.buy_error = Some(revert_reason(&results[swap_index].1));
+        return Ok(risk);
+    }
+    let balance_of = |index: usize| decode_uint(&results[index].1).unwrap_or_default();
+    let received = balance_of(swap_index + 1).saturating_sub(balance_of(0));
+    risk.buy_tax = tax(quoted_buy, received);
+    risk.transferable = Some(results[swap_index + 2].0);
+
+    // the second batch buys again and sells what was received
+    if !received.is_zero() {
+        let mut calls = buy;
+        let quote_index = calls.len();
+        calls.push(sub_call(
+            chain.router,
+            zero,
+            router
+                .get_amounts_out(received, sell_path.clone())
+                .calldata(),
+        )?);
+        calls.push(sub_call(pay, zero, paid.balance_of(wallet).calldata())?);
+        calls.push(sub_call(
+            token,
+            zero,
+            bought.approve(chain.router, received).calldata(),
+        )?);
+        calls.push(sub_call(
+            chain.router,
+            zero,
+            router
+                .swap_exact_tokens_for_tokens_supporting_fee_on_transfer_tokens(
+                    received, zero, sell_path, wallet, deadline,
+                )
+                .calldata(),
+        )?);
+        calls.push(sub_call(pay, zero, paid.balance_of(wallet).calldata())?);
+        let call = multicall.aggregate_3_value(calls).value(value).from(wallet);
+        let results = call.call_raw().state(&state).await?;
+
+        let sell_index = quote_index + 3;
+        if results[sell_index].0 {
+            let balance_of = |index: usize| decode_uint(&results[index].1).unwrap_or_default();
+            let sold = balance_of(sell_index + 1).saturating_sub(balance_of(quote_index + 1));
+            risk.sell_tax = decode_amount_out(&results[quote_index].1)
+                .and_then(|quoted_sell| tax(quoted_sell, sold));
+        } else {
+            risk.sell_error = Some(revert_reason(&results[sell_index].1));
+        }
+    } else {
+        risk.sell_error = Some("nothing was received to sell".to_string());
+    }
+
+    let supply = bought.total_supply().call().await.unwrap_or_default();
+    risk.max_tx = first_getter(&client, token, &MAX_TX_GETTERS, &[])
+        .await
+        .and_then(|max| supply_percent(max, supply));
+    risk.max_wallet = first_getter(&client, token, &MAX_WALLET_GETTERS, &[])
+        .await
+        .and_then(|max| supply_percent(max, supply));
+    risk.blacklisted = first_getter(
+        &client,
+        token,
+        &BLACKLIST_GETTERS,
+        &[Token::Address(wallet)],
+    )
+    .await
+    .map(|listed| !listed.is_zero());
+    Ok(risk)
+}
+
+/// Outcome of the exact swap tx run with `eth_call` against the latest block, and its gas
+/// estimate, nothing is sent
+#[derive(Debug, Clone)]
+pub(crate) struct SwapPreview {
+    pub(crate) swap: PreparedSwap,
+    /// Whether the router is approved before the swap, the approval is part of the gas
+    pub(crate) approval: bool,
+    /// Amount of `token_out` the dry run received, the route's quote when it reverted
+    pub(crate) amount_out: U256,
+    /// Revert reason of the dry run, None when it went through
+    pub(crate) revert: Option<String>,
+    /// Gas the swap and its approval use, None when the node couldn't estimate it
+    pub(crate) gas: Option<U256>,
+    /// Most the swap pays per gas, in wei
+    pub(crate) gas_price: U256,
+    /// Price of the native token in the chain's USD token, when it has one
+    pub(crate) native_usd: Option<f64>,
+}
+
+impl SwapPreview {
+    pub(crate) fn amount_out(&self) -> Option<f64> {
+        from_units(self.amount_out, self.swap.decimals_out)
+    }
+
+    pub(crate) fn min_received(&self) -> Option<f64> {
+        from_units(self.swap.amount_out_min, self.swap.decimals_out)
+    }
+
+    /// Most the gas can cost, in wei of the native token
+    pub(crate) fn gas_cost(&self) -> Option<U256> {
+        self.gas.map(|gas| gas.saturating_mul(self.gas_price))
+    }
+}
+
+/// Reason of a failed call, from the revert data the node returned with its error
+fn call_error<E: RpcError>(err: &E) -> String {
+    match err.as_error_response() {
+        Some(response) => match response.as_revert_data() {
+            Some(data) if !data.is_empty() => revert_reason(&data),
+            _ => response.message.clone(),
+        },
+        None => err.to_string(),
+    }
+}
+
+/// Amount of `token_out` received, from the return data of the swap tx
+fn decode_swap_output(route: &Route, to_native: bool, data: &[u8]) -> Option<U256> {
+    match route.protocol {
+        Protocol::V2 => decode_amount_out(data),
+        // the router's multicall returns the result of exactInput first
+        Protocol::V3 { .. } if to_native => {
+            let tokens = abi::decode(&[ParamType::Array(Box::new(ParamType::Bytes))], data).ok()?;
+            let first = tokens
+                .into_iter()
+                .next()?
+                .into_array()?
+                .into_iter()
+                .next()?;
+            decode_uint(&first.into_bytes()?)
+        }
+        Protocol::V3 { .. } => decode_uint(data),
+    }
+}
+
+/// Builds the swap tx the `wallet` would send for the order and runs it with `eth_call`, then
+/// estimates its gas. When the router has to be approved first, the approval and the swap run
+/// in one batch from the wallet, given the code of the chain's Multicall3 for the call, as the
+/// swap alone would revert
+pub(crate) async fn preview_swap(
+    provider: RpcProvider,
+    chain: &ChainConfig,
+    config: &SwapConfig,
+    order: &SwapOrder,
+    wallet: Address,
+) -> anyhow::Result<SwapPreview> {
+    let client = Arc::new(provider);
+    let swap = prepare_swap(&client, wallet, chain, config, order).await?;
+    let approval = needs_approval(&client, chain, order, &swap).await?;
+    let tx = swap_tx(&client, chain, config, order, &swap).await?;
+    let gas_price = match swap.fees {
+        Some((max_fee, _)) => max_fee,
+        None => client.get_gas_price().await?,
+    };
+
+    let (output, gas) = if approval {
+        let code = client.get_code(chain.multicall, None).await?;
+        if code.is_empty() {
+            return Err(anyhow::anyhow!(
+                "No Multicall3 contract at {:#x} to simulate with",
+                chain.multicall
+            ));
+        }
+        let state = spoof::code(wallet, code);
+        let router = swap.route.router(chain);
+        let value = tx.value().copied().unwrap_or_default();
+        let calls = vec![
+            sub_call(
+                order.token_in,
+                U256::zero(),
+                swap::approval(&client, chain, order, &swap).calldata(),
+            )?,
+            sub_call(router, value, tx.data().cloned())?,
+        ];
+        let call = Multicall3::new(wallet, client.clone())
+            .aggregate_3_value(calls)
+            .value(value)
+            .from(wallet);
+        let results = call.call_raw().state(&state).await?;
+        let (success, data) = results
+            .get(1)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("The simulated swap returned no result"))?;
+        let output = match success {
+            true => Ok(data),
+            false => Err(revert_reason(&data)),
+        };
+        // the batch is estimated with the same override, nodes without overrides on
+        // eth_estimateGas leave the gas unknown
+        let gas = match output {
+            Ok(_) => client
+                .provider()
+                .request::<_, U256>("eth_estimateGas", (&call.tx, "latest", &state))
+                .await
+                .ok(),
+            Err(_) => None,
+        };
+        (output, gas)
+    } else {
+        match client.call(&tx, None).await {
+            Ok(data) => (Ok(data), client.estimate_gas(&tx, None).await.ok()),
+            Err(err) => (Err(call_error(&err)), None),
+        }
+    };
+
+    let to_native = order.token_out == native_token();
+    let (amount_out, revert) = match output {
+        Ok(data) => (
+            decode_swap_output(&swap.route, to_native, &data).unwrap_or(swap.route.amount_out),
+            None,
+        ),
+        Err(reason) => (swap.route.amount_out, Some(reason)),
+    };
+    let native_usd = match chain.usd_token {
+        Some(usd_token) => quote_swap(
+            client.clone(),
+            chain,
+            config,
+            native_token(),
+            usd_token,
+            1.0,
+        )
+        .await
+        .map(|quote| quote.amount_out)
+        .ok(),
+        None => None,
+    };
+
+    Ok(SwapPreview {
+        swap,
+        approval,
+        amount_out,
+        revert,
+        gas,
+        gas_price,
+        native_usd,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::requests::swap::{Erc20Calls, UniswapV2RouterCalls};
+    use async_trait::async_trait;
+    use ethers::abi::{AbiDecode, AbiEncode};
+    use ethers::providers::{JsonRpcClient, JsonRpcError, MockError, Provider};
+    use ethers::types::U64;
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::{json, Value};
+    use std::fmt::Debug;
+
+    const WALLET: Address = Address::repeat_byte(0x77);
+    const TOKEN: Address = Address::repeat_byte(0xa1);
+    const WETH: Address = Address::repeat_byte(0xee);
+    const ROUTER: Address = Address::repeat_byte(0x02);
+    const MULTICALL: Address = Address::repeat_byte(0xca);
+    /// Tokens per WETH of the pair
+    const RATE: u64 = 1_000;
+
+    /// A chain with a V2 pair of [TOKEN] and [WETH] quoted at [RATE]. The Multicall3 batches
+    /// sent to [WALLET] with the Multicall3 code put at its address run against balances that
+    /// start empty for every call, like an `eth_call` does
+    #[derive(Debug, Default)]
+    struct MockChain {
+        /// Taxes taken from the tokens bought and sold, in basis points
+        buy_tax_bps: u64,
+        sell_tax_bps: u64,
+        /// Revert reason of the sells, the token can't be sold back when set
+        sell_revert: Option<String>,
+        /// Returned by `_maxTxAmount()`, the token has no such getter when None
+        max_tx: Option<U256>,
+    }
+
+    fn revert() -> MockError {
+        MockError::JsonRpcError(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        })
+    }
+
+    fn supply() -> U256 {
+        U256::exp10(27)
+    }
+
+    fn error(reason: &str) -> Bytes {
+        let mut data = vec![0x08, 0xc3, 0x79, 0xa0];
+        data.extend(abi::encode(&[Token::String(reason.to_string())]));
+        data.into()
+    }
+
+    fn quote(amount_in: U256, path: &[Address]) -> Vec<U256> {
+        let amount_out = if path == [WETH, TOKEN] {
+            amount_in * RATE
+        } else if path == [TOKEN, WETH] {
+            amount_in / RATE
+        } else {
+            U256::zero()
+        };
+        vec![amount_in, amount_out]
+    }
+
+    fn after_tax(amount: U256, tax_bps: u64) -> U256 {
+        amount * (10_000 - tax_bps) / 10_000
+    }
+
+    impl MockChain {
+        /// Runs the sub calls of the batch, returns the success and output of each
+        fn batch(&self, calls: Vec<Call3Value>) -> Vec<(bool, Bytes)> {
+            let (mut tokens, mut weth) = (U256::zero(), U256::zero());
+            calls
+                .into_iter()
+                .map(|call| {
+                    let (value, data) = (call.value, call.call_data.as_ref());
+                    if call.target == ROUTER {
+                        return match UniswapV2RouterCalls::decode(data).unwrap() {
+                            UniswapV2RouterCalls::GetAmountsOut(call) => {
+                                (true, quote(call.amount_in, &call.path).encode().into())
+                            }
+                            UniswapV2RouterCalls::SwapExactETHForTokensSupportingFeeOnTransferTokens(
+                                call,
+                            ) => {
+                                assert_eq!(call.to, WALLET);
+                                let bought = quote(value, &call.path)[1];
+                                tokens += after_tax(bought, self.buy_tax_bps);
+                                (true, Bytes::new())
+                            }
+                            UniswapV2RouterCalls::SwapExactTokensForTokensSupportingFeeOnTransferTokens(
+                                call,
+                            ) => match &self.sell_revert {
+                                Some(reason) => (false, error(reason)),
+                                None => {
+                                    tokens -= call.amount_in;
+                                    let sold = quote(call.amount_in, &call.path)[1];
+                                    weth += after_tax(sold, self.sell_tax_bps);
+                                    (true, Bytes::new())
+                                }
+                            },
+                            call => panic!("Unexpected router call {:?}", call),
+                        };
+                    }
+                    let balance = if call.target == TOKEN { tokens } else { weth };
+                    match Erc20Calls::decode(data).unwrap() {
+                        Erc20Calls::BalanceOf(call) => {
+                            assert_eq!(call.owner, WALLET);
+                            (true, balance.encode().into())
+                        }
+                        Erc20Calls::Approve(_) => (true, true.encode().into()),
+                        Erc20Calls::Transfer(call) => {
+                            tokens -= call.amount;
+                            (true, true.encode().into())
+                        }
+                        call => panic!("Unexpected token call {:?}", call),
+                    }
+                })
+                .collect()
+        }
+
+        fn call(&self, tx: &Value, state: &Value) -> Result<Bytes, MockError> {
+            let to: Address = serde_json::from_value(tx["to"].clone())?;
+            let data: Bytes = serde_json::from_value(match &tx["data"] {
+                Value::Null => tx["input"].clone(),
+                data => data.clone(),
+            })?;
+            if to == WALLET {
+                // the batch only runs with the Multicall3 code put at the wallet
+                assert!(!state[format!("{:#x}", WALLET)]["code"].is_null());
+                let calls = Aggregate3ValueCall::decode(&data)
+                    .map_err(|_| revert())?
+                    .calls;
+                let results = self
+                    .batch(calls)
+                    .into_iter()
+                    .map(|(success, data)| {
+                        Token::Tuple(vec![Token::Bool(success), Token::Bytes(data.to_vec())])
+                    })
+                    .collect();
+                return Ok(abi::encode(&[Token::Array(results)]).into());
+            }
+            if to == ROUTER {
+                return match UniswapV2RouterCalls::decode(&data).map_err(|_| revert())? {
+                    UniswapV2RouterCalls::GetAmountsOut(call) => {
+                        Ok(quote(call.amount_in, &call.path).encode().into())
+                    }
+                    _ => Err(revert()),
+                };
+            }
+            if to == TOKEN && data.starts_with(&selector("_maxTxAmount()")) {
+                return self
+                    .max_tx
+                    .map(|max| max.encode().into())
+                    .ok_or_else(revert);
+            }
+            match Erc20Calls::decode(&data).map_err(|_| revert())? {
+                Erc20Calls::TotalSupply(_) if to == TOKEN => Ok(supply().encode().into()),
+                _ => Err(revert()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for MockChain {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, MockError>
+        where
+            T: Debug + Serialize + Send + Sync,
+            R: DeserializeOwned + Send,
+        {
+            let params = serde_json::to_value(params)?;
+            let response = match method {
+                "eth_chainId" => json!(U64::one()),
+                "eth_getBalance" => json!(U256::exp10(19)),
+                "eth_getCode" if params[0] == json!(MULTICALL) => json!(Bytes::from(vec![0x60])),
+                "eth_call" => json!(self.call(&params[0], &params[2])?),
+                _ => return Err(revert()),
+            };
+            Ok(serde_json::from_value(response)?)
+        }
+    }
+
+    fn chain_config() -> ChainConfig {
+        serde_json::from_value(json!({
+            "chainId": 1,
+            "name": "Mock",
+            "nativeSymbol": "ETH",
+            "nativeName": "Ether",
+            "wrappedNative": WETH,
+            "router": ROUTER,
+            "v3Router": Address::repeat_byte(0x03),
+            "quoter": Address::repeat_byte(0x04),
+            "multicall": MULTICALL,
+            "explorerUrl": "https://etherscan.io",
+        }))
+        .unwrap()
+    }
+
+    /// Buys [TOKEN] with 1 ETH and sells it back
+    async fn round_trip(chain: MockChain) -> TokenRisk {
+        simulate_round_trip(
+            Provider::new(chain),
+            &chain_config(),
+            WALLET,
+            native_token(),
+            TOKEN,
+            1.0,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn passes_a_clean_token() {
+        let risk = round_trip(MockChain::default()).await;
+        assert_eq!(risk.buy_tax, Some(0.0));
+        assert_eq!(risk.sell_tax, Some(0.0));
+        assert_eq!(risk.transferable, Some(true));
+        assert_eq!(
+            (risk.max_tx, risk.max_wallet, risk.blacklisted),
+            (None, None, None)
+        );
+        assert!(!risk.is_honeypot());
+        assert!(!risk.is_buy_taxed());
+        assert_eq!(risk.level(), RiskLevel::Low);
+    }
+
+    #[tokio::test]
+    async fn measures_the_taxes_and_limits_of_a_taxed_token() {
+        let risk = round_trip(MockChain {
+            buy_tax_bps: 500,
+            sell_tax_bps: 300,
+            max_tx: Some(supply() / 100),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(risk.buy_tax, Some(5.0));
+        assert_eq!(risk.sell_tax, Some(3.0));
+        assert_eq!(risk.max_tx, Some(1.0));
+        assert!(risk.is_buy_taxed());
+        assert!(!risk.is_honeypot());
+        assert_eq!(risk.level(), RiskLevel::Medium);
+    }
+
+    #[tokio::test]
+    async fn flags_a_token_that_cant_be_sold_back() {
+        let risk = round_trip(MockChain {
+            sell_revert: Some("TransferHelper: TRANSFER_FROM_FAILED".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(risk.buy_tax, Some(0.0));
+        assert_eq!(
+            risk.sell_error.as_deref(),
+            Some("TransferHelper: TRANSFER_FROM_FAILED")
+        );
+        assert!(risk.is_honeypot());
+        assert_eq!(risk.level(), RiskLevel::High);
+    }
+}
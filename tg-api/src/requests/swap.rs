@@ -0,0 +1,1227 @@
+use crate::config::{ChainConfig, GasMode, GasSettings, SwapConfig};
+use crate::requests::failover::RpcProvider;
+use crate::requests::relay::{PrivateSubmission, RelayClient};
+use crate::requests::routing::{
+    best_route, quote_route, ExactInputParams, Protocol, Route, UniswapV3Router,
+};
+use ethers::{
+    contract::{abigen, ContractCall, EthEvent},
+    middleware::SignerMiddleware,
+    providers::{Middleware, PendingTransaction},
+    signers::{LocalWallet, Signer},
+    types::{
+        transaction::eip2718::TypedTransaction, Address, BlockNumber, TransactionReceipt, H256,
+        U256,
+    },
+    utils::{format_units, keccak256, parse_units},
+};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+abigen!(
+    UniswapV2Router,
+    r#"[
+        function getAmountsOut(uint256 amountIn, address[] calldata path) external view returns (uint256[] memory amounts)
+        function swapExactETHForTokens(uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external payable returns (uint256[] memory amounts)
+        function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts)
+        function swapExactTokensForETH(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts)
+        function swapExactETHForTokensSupportingFeeOnTransferTokens(uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external payable
+        function swapExactTokensForTokensSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external
+        function swapExactTokensForETHSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external
+    ]"#
+);
+
+abigen!(
+    Erc20,
+    r#"[
+        function symbol() external view returns (string)
+        function name() external view returns (string)
+        function decimals() external view returns (uint8)
+        function totalSupply() external view returns (uint256)
+        function balanceOf(address owner) external view returns (uint256)
+        function allowance(address owner, address spender) external view returns (uint256)
+        function approve(address spender, uint256 amount) external returns (bool)
+        function transfer(address to, uint256 amount) external returns (bool)
+        event Transfer(address indexed from, address indexed to, uint256 value)
+        event Withdrawal(address indexed src, uint256 wad)
+    ]"#
+);
+
+/// Address the users enter to pay or receive the chain's native token instead of an ERC-20
+pub(crate) const NATIVE_TOKEN: &str = "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE";
+
+const NATIVE_DECIMALS: u8 = 18;
+
+/// How often the receipt of a broadcast swap is polled for
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub(crate) fn native_token() -> Address {
+    Address::from_str(NATIVE_TOKEN).expect("valid native token address")
+}
+
+/// How much of `token_in` is swapped
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum SwapAmount {
+    /// Amount in whole tokens, converted with the token's decimals
+    Exact(f64),
+    /// Percentage of the wallet's balance
+    BalancePercentage(f64),
+}
+
+/// A swap to send through the configured router
+#[derive(Debug, Clone)]
+pub(crate) struct SwapOrder {
+    /// Token paid, [NATIVE_TOKEN] for the native token
+    pub(crate) token_in: Address,
+    /// Token received, [NATIVE_TOKEN] for the native token
+    pub(crate) token_out: Address,
+    pub(crate) amount: SwapAmount,
+    /// Whether the swap tx is sent through the chain's relay instead of the public mempool
+    pub(crate) private: bool,
+    /// Whether the swap tx is shared with the searchers through the chain's relay for rebates
+    pub(crate) rebate: bool,
+    /// Whether a token of the swap may take a fee on transfer, set for every sell
+    pub(crate) fee_on_transfer: bool,
+}
+
+/// A swap that was broadcast, its tx may not be mined yet
+#[derive(Debug, Clone)]
+pub(crate) struct SubmittedSwap {
+    pub(crate) tx_hash: H256,
+    /// Chain id reported by the node, a local dev node reports its own
+    pub(crate) chain_id: u64,
+    pub(crate) wallet: Address,
+    pub(crate) token_in: Address,
+    pub(crate) token_out: Address,
+    pub(crate) decimals_in: u8,
+    pub(crate) decimals_out: u8,
+    pub(crate) amount_in: U256,
+    pub(crate) amount_out_min: U256,
+    pub(crate) route: Route,
+    /// How the swap tx was sent privately, None when it went to the public mempool directly
+    pub(crate) private: Option<PrivateSubmission>,
+    /// Estimated rebate of the backruns, in wei of the native token, when sent for rebates
+    pub(crate) expected_rebate: Option<U256>,
+}
+
+impl SubmittedSwap {
+    /// Least amount of `token_out` the swap can receive without reverting, in whole tokens
+    pub(crate) fn min_received(&self) -> Option<f64> {
+        from_units(self.amount_out_min, self.decimals_out)
+    }
+}
+
+/// Outcome of a mined swap
+#[derive(Debug, Clone)]
+pub(crate) struct SwapReceipt {
+    pub(crate) success: bool,
+    /// Gas used times the effective gas price, in wei
+    pub(crate) gas_paid: Option<U256>,
+    /// Amount of `token_in` actually paid, in whole tokens
+    pub(crate) filled_in: Option<f64>,
+    /// Amount of `token_out` actually received, in whole tokens
+    pub(crate) filled_out: Option<f64>,
+    /// Refunds of the backruns paid to the wallet in the swap's block, in wei, when the swap
+    /// was sent for rebates
+    pub(crate) rebate: Option<U256>,
+}
+
+/// Expected outcome of a swap, quoted along the best route
+#[derive(Debug, Clone)]
+pub(crate) struct SwapQuote {
+    /// Amount of `token_out` expected, in whole tokens
+    pub(crate) amount_out: f64,
+    /// Least amount of `token_out` received after slippage, in whole tokens
+    pub(crate) min_received: f64,
+    /// How much worse the rate is than for a tiny amount along the same route, in percent
+    pub(crate) price_impact: f64,
+}
+
+pub(crate) async fn decimals<M: Middleware + 'static>(
+    client: &Arc<M>,
+    token: Address,
+) -> anyhow::Result<u8> {
+    if token == native_token() {
+        return Ok(NATIVE_DECIMALS);
+    }
+    Ok(Erc20::new(token, client.clone()).decimals().call().await?)
+}
+
+pub(crate) async fn balance<M: Middleware + 'static>(
+    client: &Arc<M>,
+    token: Address,
+    owner: Address,
+) -> anyhow::Result<U256> {
+    if token == native_token() {
+        return Ok(client.get_balance(owner, None).await?);
+    }
+    Ok(Erc20::new(token, client.clone())
+        .balance_of(owner)
+        .call()
+        .await?)
+}
+
+/// Balance of `owner` in whole tokens of `token`, [NATIVE_TOKEN] for the native token
+pub(crate) async fn token_balance<M: Middleware + 'static>(
+    client: Arc<M>,
+    token: Address,
+    owner: Address,
+) -> anyhow::Result<f64> {
+    let decimals = decimals(&client, token).await?;
+    Ok(from_units(balance(&client, token, owner).await?, decimals).unwrap_or_default())
+}
+
+/// Converts a whole token amount into the token's base units
+pub(crate) fn to_units(amount: f64, decimals: u8) -> anyhow::Result<U256> {
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err(anyhow::anyhow!("Invalid amount: {}", amount));
+    }
+    let amount = format!("{:.*}", decimals as usize, amount);
+    Ok(parse_units(amount, decimals as u32)?.into())
+}
+
+/// Converts base units of a token into a whole token amount
+pub(crate) fn from_units(amount: U256, decimals: u8) -> Option<f64> {
+    format_units(amount, decimals as u32).ok()?.parse().ok()
+}
+
+/// EIP-1559 max fee and priority fee per gas of the gas settings, None leaves the fees to the
+/// node on chains without EIP-1559
+async fn gas_fees<M: Middleware + 'static>(
+    client: &Arc<M>,
+    gas: &GasSettings,
+) -> anyhow::Result<Option<(U256, U256)>> {
+    if gas.mode == GasMode::Custom {
+        return match (gas.max_fee_gwei, gas.priority_fee_gwei) {
+            (Some(max_fee), Some(priority_fee)) => {
+                let (max_fee, priority_fee) = (to_units(max_fee, 9)?, to_units(priority_fee, 9)?);
+                if priority_fee > max_fee {
+                    return Err(anyhow::anyhow!("The priority fee can't exceed the max fee"));
+                }
+                Ok(Some((max_fee, priority_fee)))
+            }
+            _ => Err(anyhow::anyhow!(
+                "Set the max fee and priority fee of the custom gas mode in /settings"
+            )),
+        };
+    }
+    match client.estimate_eip1559_fees(None).await {
+        Ok((max_fee, priority_fee)) => {
+            let percent = U256::from(gas.mode.fee_percent());
+            Ok(Some((
+                max_fee * percent / U256::from(100u64),
+                priority_fee * percent / U256::from(100u64),
+            )))
+        }
+        Err(err) => {
+            log::warn!("Unable to estimate the EIP-1559 fees: {}", err);
+            Ok(None)
+        }
+    }
+}
+
+/// Sets the fees of an EIP-1559 tx, legacy txs are left to the node
+fn with_fees(mut tx: TypedTransaction, fees: Option<(U256, U256)>) -> TypedTransaction {
+    if let (TypedTransaction::Eip1559(inner), Some((max_fee, priority_fee))) = (&mut tx, fees) {
+        inner.max_fee_per_gas = Some(max_fee);
+        inner.max_priority_fee_per_gas = Some(priority_fee);
+    }
+    tx
+}
+
+/// How much worse the rate of the route is than for a tiny amount, in percent
+async fn price_impact<M: Middleware + 'static>(
+    client: Arc<M>,
+    chain: &ChainConfig,
+    route: &Route,
+    amount_in: U256,
+) -> anyhow::Result<f64> {
+    // the rate of a thousandth of the amount is close enough to the spot rate of the route
+    let reference_in = (amount_in / 1000).max(U256::one());
+    Ok(
+        match quote_route(client, chain, route, reference_in).await {
+            Some(reference_out) if !reference_out.is_zero() => {
+                let rate = route.amount_out.to_string().parse::<f64>()?
+                    / amount_in.to_string().parse::<f64>()?;
+                let spot_rate = reference_out.to_string().parse::<f64>()?
+                    / reference_in.to_string().parse::<f64>()?;
+                ((1.0 - rate / spot_rate) * 100.0).max(0.0)
+            }
+            _ => 0.0,
+        },
+    )
+}
+
+/// Best route for swapping `amount` whole tokens of `token_in`, along with the amount in base
+/// units and the decimals of `token_out`
+async fn route_for_amount<M: Middleware + 'static>(
+    client: &Arc<M>,
+    chain: &ChainConfig,
+    token_in: Address,
+    token_out: Address,
+    amount: f64,
+) -> anyhow::Result<(Route, U256, u8)> {
+    let decimals_in = decimals(client, token_in).await?;
+    let decimals_out = decimals(client, token_out).await?;
+    let amount_in = to_units(amount, decimals_in)?;
+    let route = best_route(client.clone(), chain, token_in, token_out, amount_in).await?;
+    Ok((route, amount_in, decimals_out))
+}
+
+/// Amount of `token_out` expected for `amount` whole tokens of `token_in`, in whole tokens.
+/// Unlike [quote_swap] the price impact isn't quoted, which saves a route quote per call
+pub(crate) async fn quote_amount_out<M: Middleware + 'static>(
+    client: Arc<M>,
+    chain: &ChainConfig,
+    token_in: Address,
+    token_out: Address,
+    amount: f64,
+) -> anyhow::Result<f64> {
+    let (route, _, decimals_out) =
+        route_for_amount(&client, chain, token_in, token_out, amount).await?;
+    Ok(from_units(route.amount_out, decimals_out).unwrap_or_default())
+}
+
+/// Quotes swapping `amount` whole tokens of `token_in` for `token_out`, nothing is signed
+pub(crate) async fn quote_swap<M: Middleware + 'static>(
+    client: Arc<M>,
+    chain: &ChainConfig,
+    config: &SwapConfig,
+    token_in: Address,
+    token_out: Address,
+    amount: f64,
+) -> anyhow::Result<SwapQuote> {
+    let (route, amount_in, decimals_out) =
+        route_for_amount(&client, chain, token_in, token_out, amount).await?;
+    let price_impact = price_impact(client, chain, &route, amount_in).await?;
+    let amount_out_min =
+        route.amount_out * U256::from(10_000 - config.slippage_bps) / U256::from(10_000u64);
+
+    Ok(SwapQuote {
+        amount_out: from_units(route.amount_out, decimals_out).unwrap_or_default(),
+        min_received: from_units(amount_out_min, decimals_out).unwrap_or_default(),
+        price_impact,
+    })
+}
+
+/// Amounts and route of a swap resolved from the wallet's balance and the pools, nothing is
+/// signed yet
+#[derive(Debug, Clone)]
+pub(crate) struct PreparedSwap {
+    pub(crate) wallet: Address,
+    pub(crate) decimals_in: u8,
+    pub(crate) decimals_out: u8,
+    pub(crate) amount_in: U256,
+    pub(crate) amount_out_min: U256,
+    pub(crate) route: Route,
+    /// EIP-1559 max fee and priority fee per gas, None leaves them to the node
+    pub(crate) fees: Option<(U256, U256)>,
+    /// Whether the swap goes through the variants of the V2 router supporting the tokens
+    /// taking a fee on transfer, which check the amount received instead of the quoted one
+    pub(crate) fee_on_transfer: bool,
+}
+
+/// Resolves the amount the `wallet` swaps and the best route for it
+pub(crate) async fn prepare_swap<M: Middleware + 'static>(
+    client: &Arc<M>,
+    wallet: Address,
+    chain: &ChainConfig,
+    config: &SwapConfig,
+    order: &SwapOrder,
+) -> anyhow::Result<PreparedSwap> {
+    let decimals_in = decimals(client, order.token_in).await?;
+    let decimals_out = decimals(client, order.token_out).await?;
+    let balance_in = balance(client, order.token_in, wallet).await?;
+    let amount_in = match order.amount {
+        SwapAmount::Exact(amount) => to_units(amount, decimals_in)?,
+        SwapAmount::BalancePercentage(percentage) => {
+            if !(percentage > 0.0 && percentage <= 100.0) {
+                return Err(anyhow::anyhow!("Invalid percentage: {}", percentage));
+            }
+            balance_in * U256::from((percentage * 100.0).round() as u64) / U256::from(10_000u64)
+        }
+    };
+    if amount_in.is_zero() {
+        return Err(anyhow::anyhow!("Nothing to swap, the balance is empty"));
+    }
+    if amount_in > balance_in {
+        return Err(anyhow::anyhow!(
+            "Insufficient balance: {} available",
+            from_units(balance_in, decimals_in).unwrap_or_default()
+        ));
+    }
+
+    let route = best_route(
+        client.clone(),
+        chain,
+        order.token_in,
+        order.token_out,
+        amount_in,
+    )
+    .await?;
+    let amount_out_min =
+        route.amount_out * U256::from(10_000 - config.slippage_bps) / U256::from(10_000u64);
+    let fees = gas_fees(client, &config.gas).await?;
+
+    Ok(PreparedSwap {
+        wallet,
+        decimals_in,
+        decimals_out,
+        amount_in,
+        amount_out_min,
+        route,
+        fees,
+        fee_on_transfer: order.fee_on_transfer,
+    })
+}
+
+/// Quotes the route of a swap prepared earlier again, returns why it can't be sent as it was
+/// prepared anymore: the wallet no longer holds the amount or the route no longer fills the
+/// minimum received
+pub(crate) async fn recheck_swap<M: Middleware + 'static>(
+    client: &Arc<M>,
+    chain: &ChainConfig,
+    order: &SwapOrder,
+    swap: &PreparedSwap,
+) -> anyhow::Result<Option<String>> {
+    let balance_in = balance(client, order.token_in, swap.wallet).await?;
+    if balance_in < swap.amount_in {
+        return Ok(Some(format!(
+            "the wallet only holds {} of the {} to pay",
+            from_units(balance_in, swap.decimals_in).unwrap_or_default(),
+            from_units(swap.amount_in, swap.decimals_in).unwrap_or_default()
+        )));
+    }
+    Ok(
+        match quote_route(client.clone(), chain, &swap.route, swap.amount_in).await {
+            Some(amount_out) if amount_out >= swap.amount_out_min => None,
+            Some(amount_out) => Some(format!(
+                "the route now returns {}, below the minimum of {}",
+                from_units(amount_out, swap.decimals_out).unwrap_or_default(),
+                from_units(swap.amount_out_min, swap.decimals_out).unwrap_or_default()
+            )),
+            None => Some(format!("the route through {} no longer quotes", swap.route)),
+        },
+    )
+}
+
+/// Whether the router of the swap has to be approved before it can spend the paid token
+pub(crate) async fn needs_approval<M: Middleware + 'static>(
+    client: &Arc<M>,
+    chain: &ChainConfig,
+    order: &SwapOrder,
+    swap: &PreparedSwap,
+) -> anyhow::Result<bool> {
+    if order.token_in == native_token() {
+        return Ok(false);
+    }
+    let token = Erc20::new(order.token_in, client.clone());
+    Ok(token
+        .allowance(swap.wallet, swap.route.router(chain))
+        .call()
+        .await?
+        < swap.amount_in)
+}
+
+/// Approval of the router of the swap for exactly the amount swapped, so the wallet never
+/// leaves a standing allowance behind
+pub(crate) fn approval<M: Middleware + 'static>(
+    client: &Arc<M>,
+    chain: &ChainConfig,
+    order: &SwapOrder,
+    swap: &PreparedSwap,
+) -> ContractCall<M, bool> {
+    Erc20::new(order.token_in, client.clone()).approve(swap.route.router(chain), swap.amount_in)
+}
+
+/// Builds the swap tx of the prepared swap, its deadline counted from the latest block
+pub(crate) async fn swap_tx<M: Middleware + 'static>(
+    client: &Arc<M>,
+    chain: &ChainConfig,
+    config: &SwapConfig,
+    order: &SwapOrder,
+    swap: &PreparedSwap,
+) -> anyhow::Result<TypedTransaction> {
+    let native = native_token();
+    let (wallet, amount_in, amount_out_min) = (swap.wallet, swap.amount_in, swap.amount_out_min);
+    let router = swap.route.router(chain);
+
+    // the deadline follows the chain's clock, which a dev node may have moved
+    let timestamp = client
+        .get_block(BlockNumber::Latest)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No latest block found"))?
+        .timestamp;
+    let deadline = timestamp + U256::from(config.deadline_secs);
+
+    let tx = match swap.route.protocol {
+        Protocol::V2 => {
+            let router = UniswapV2Router::new(router, client.clone());
+            let path = swap.route.path.clone();
+            match (order.token_in == native, order.token_out == native) {
+                (true, _) if swap.fee_on_transfer => {
+                    router
+                        .swap_exact_eth_for_tokens_supporting_fee_on_transfer_tokens(
+                            amount_out_min,
+                            path,
+                            wallet,
+                            deadline,
+                        )
+                        .value(amount_in)
+                        .tx
+                }
+                (true, _) => {
+                    router
+                        .swap_exact_eth_for_tokens(amount_out_min, path, wallet, deadline)
+                        .value(amount_in)
+                        .tx
+                }
+                (_, true) if swap.fee_on_transfer => {
+                    router
+                        .swap_exact_tokens_for_eth_supporting_fee_on_transfer_tokens(
+                            amount_in,
+                            amount_out_min,
+                            path,
+                            wallet,
+                            deadline,
+                        )
+                        .tx
+                }
+                (_, true) => {
+                    router
+                        .swap_exact_tokens_for_eth(
+                            amount_in,
+                            amount_out_min,
+                            path,
+                            wallet,
+                            deadline,
+                        )
+                        .tx
+                }
+                _ if swap.fee_on_transfer => {
+                    router
+                        .swap_exact_tokens_for_tokens_supporting_fee_on_transfer_tokens(
+                            amount_in,
+                            amount_out_min,
+                            path,
+                            wallet,
+                            deadline,
+                        )
+                        .tx
+                }
+                _ => {
+                    router
+                        .swap_exact_tokens_for_tokens(
+                            amount_in,
+                            amount_out_min,
+                            path,
+                            wallet,
+                            deadline,
+                        )
+                        .tx
+                }
+            }
+        }
+        Protocol::V3 { .. } => {
+            let router = UniswapV3Router::new(router, client.clone());
+            let mut params = ExactInputParams {
+                path: swap.route.v3_path(),
+                recipient: wallet,
+                deadline,
+                amount_in,
+                amount_out_minimum: amount_out_min,
+            };
+            if order.token_out == native {
+                // the router keeps the WETH and unwraps it to the wallet in the same tx
+                params.recipient = router.address();
+                let calls = vec![
+                    router
+                        .exact_input(params)
+                        .calldata()
+                        .ok_or_else(|| anyhow::anyhow!("Unable to encode the V3 swap"))?,
+                    router
+                        .unwrap_weth9(amount_out_min, wallet)
+                        .calldata()
+                        .ok_or_else(|| anyhow::anyhow!("Unable to encode the V3 swap"))?,
+                ];
+                router.multicall(calls).tx
+            } else if order.token_in == native {
+                // the router wraps the value sent when the path starts with WETH
+                router.exact_input(params).value(amount_in).tx
+            } else {
+                router.exact_input(params).tx
+            }
+        }
+    };
+    let mut tx = with_fees(tx, swap.fees);
+    tx.set_from(wallet);
+    Ok(tx)
+}
+
+/// Signs and broadcasts the swap from the `signer`'s wallet, along the route of the `prepared`
+/// swap as it was shown to the user, or along the best route when None. The route's router is
+/// approved for the amount first when its allowance doesn't cover it. A private or rebate swap
+/// is sent through the `relay`, its approval still goes through the public mempool
+pub(crate) async fn submit_swap(
+    provider: RpcProvider,
+    signer: LocalWallet,
+    chain: &ChainConfig,
+    config: &SwapConfig,
+    order: &SwapOrder,
+    prepared: Option<PreparedSwap>,
+    relay: Option<&RelayClient>,
+) -> anyhow::Result<SubmittedSwap> {
+    // the chain id is read from the node, so a local dev node signs with its own
+    let chain_id = provider.get_chainid().await?.as_u64();
+    let wallet = signer.address();
+    let client = Arc::new(SignerMiddleware::new(
+        provider,
+        signer.with_chain_id(chain_id),
+    ));
+    let native = native_token();
+
+    let swap = match prepared {
+        Some(swap) if swap.wallet != wallet => {
+            return Err(anyhow::anyhow!(
+                "The swap was prepared for {:#x}, not {:#x}",
+                swap.wallet,
+                wallet
+            ))
+        }
+        Some(swap) => swap,
+        None => prepare_swap(&client, wallet, chain, config, order).await?,
+    };
+    if needs_approval(&client, chain, order, &swap).await? {
+        let mut approve = approval(&client, chain, order, &swap);
+        approve.tx = with_fees(approve.tx, swap.fees);
+        approve
+            .send()
+            .await?
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Approval of {:#x} dropped", order.token_in))?;
+    }
+
+    let mut tx = swap_tx(&client, chain, config, order, &swap).await?;
+    let PreparedSwap {
+        decimals_in,
+        decimals_out,
+        amount_in,
+        amount_out_min,
+        route,
+        ..
+    } = swap;
+    let (tx_hash, private, expected_rebate) = match relay {
+        Some(relay) if order.private || order.rebate => {
+            // only the native leg of the swap is valued, the rebates are paid in the native token
+            let native_amount = if order.token_in == native {
+                Some(amount_in)
+            } else if order.token_out == native {
+                Some(route.amount_out)
+            } else {
+                None
+            };
+            let expected_rebate = match native_amount {
+                Some(native_amount) if order.rebate => {
+                    let impact = price_impact(client.clone(), chain, &route, amount_in).await?;
+                    Some(relay.expected_rebate(native_amount, impact))
+                }
+                _ => None,
+            };
+            client.fill_transaction(&mut tx, None).await?;
+            let signature = client.signer().sign_transaction(&tx).await?;
+            let raw = tx.rlp_signed(&signature);
+            let tx_hash = H256::from(keccak256(&raw));
+            let refund_to = order.rebate.then_some(wallet);
+            let private = relay.submit(client.inner(), raw, refund_to).await?;
+            (tx_hash, Some(private), expected_rebate)
+        }
+        _ => (
+            client.send_transaction(tx, None).await?.tx_hash(),
+            None,
+            None,
+        ),
+    };
+
+    Ok(SubmittedSwap {
+        tx_hash,
+        chain_id,
+        wallet,
+        token_in: order.token_in,
+        token_out: order.token_out,
+        decimals_in,
+        decimals_out,
+        amount_in,
+        amount_out_min,
+        route,
+        private,
+        expected_rebate,
+    })
+}
+
+/// Waits for the relay to include the tx before its max block, the tx is broadcast publicly
+/// afterwards when the public fallback is enabled. None once it's left to the public mempool
+async fn wait_for_relay(
+    provider: &RpcProvider,
+    swap: &SubmittedSwap,
+) -> anyhow::Result<Option<TransactionReceipt>> {
+    let relayed = match &swap.private {
+        Some(PrivateSubmission::Relayed(relayed)) => relayed,
+        _ => return Ok(None),
+    };
+    let mut ticks = tokio::time::interval(RECEIPT_POLL_INTERVAL);
+    loop {
+        ticks.tick().await;
+        if let Some(receipt) = provider.get_transaction_receipt(swap.tx_hash).await? {
+            return Ok(Some(receipt));
+        }
+        if provider.get_block_number().await? > relayed.max_block {
+            break;
+        }
+    }
+    if !relayed.public_fallback {
+        return Err(anyhow::anyhow!(
+            "Not included by {} before block {}",
+            relayed.relay,
+            relayed.max_block
+        ));
+    }
+    log::warn!(
+        "Tx {:#x} not included by {}, broadcasting it publicly",
+        swap.tx_hash,
+        relayed.relay
+    );
+    match provider.send_raw_transaction(relayed.raw.clone()).await {
+        Ok(_) => Ok(None),
+        // the tx may have been included since the last poll
+        Err(err) => match provider.get_transaction_receipt(swap.tx_hash).await? {
+            Some(receipt) => Ok(Some(receipt)),
+            None => Err(err.into()),
+        },
+    }
+}
+
+/// Waits for the swap to be mined and reads what was paid and received from its logs
+pub(crate) async fn wait_for_swap(
+    provider: &RpcProvider,
+    swap: &SubmittedSwap,
+) -> anyhow::Result<SwapReceipt> {
+    let receipt = match wait_for_relay(provider, swap).await? {
+        Some(receipt) => receipt,
+        None => PendingTransaction::new(swap.tx_hash, provider)
+            .interval(RECEIPT_POLL_INTERVAL)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Tx {:#x} dropped", swap.tx_hash))?,
+    };
+
+    let success = receipt.status.is_some_and(|status| status.as_u64() == 1);
+    let gas_paid = receipt
+        .gas_used
+        .zip(receipt.effective_gas_price)
+        .map(|(gas_used, gas_price)| gas_used * gas_price);
+    if !success {
+        return Ok(SwapReceipt {
+            success,
+            gas_paid,
+            filled_in: None,
+            filled_out: None,
+            rebate: None,
+        });
+    }
+
+    let filled_in = if swap.token_in == native_token() {
+        Some(swap.amount_in)
+    } else {
+        transferred(&receipt, swap.token_in, |from, _| from == swap.wallet)
+    };
+    let filled_out = if swap.token_out == native_token() {
+        unwrapped(&receipt)
+    } else {
+        transferred(&receipt, swap.token_out, |_, to| to == swap.wallet)
+    };
+    let rebate = match &swap.private {
+        Some(PrivateSubmission::Relayed(relayed)) if relayed.rebate => {
+            match refunded(provider, &receipt, swap.wallet).await {
+                Ok(rebate) => Some(rebate),
+                Err(err) => {
+                    log::warn!("Unable to read the rebate of {:#x}: {}", swap.tx_hash, err);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    Ok(SwapReceipt {
+        success,
+        gas_paid,
+        filled_in: filled_in.and_then(|amount| from_units(amount, swap.decimals_in)),
+        filled_out: filled_out.and_then(|amount| from_units(amount, swap.decimals_out)),
+        rebate,
+    })
+}
+
+/// The relay pays the refunds of the backruns to the wallet later in the block of the swap, from
+/// the fee recipient of the builder of the block. Transfers from anyone else aren't refunds
+async fn refunded<M: Middleware + 'static>(
+    provider: &M,
+    receipt: &TransactionReceipt,
+    wallet: Address,
+) -> anyhow::Result<U256> {
+    let block = receipt
+        .block_number
+        .ok_or_else(|| anyhow::anyhow!("The receipt has no block"))?;
+    let block = provider
+        .get_block_with_txs(block)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Block {} not found", block))?;
+    let builder = block
+        .author
+        .ok_or_else(|| anyhow::anyhow!("Block {:?} has no fee recipient", block.number))?;
+    Ok(block
+        .transactions
+        .iter()
+        .filter(|tx| {
+            tx.transaction_index
+                .is_some_and(|index| index > receipt.transaction_index)
+        })
+        .filter(|tx| tx.from == builder && tx.to == Some(wallet))
+        .fold(U256::zero(), |sum, tx| sum + tx.value))
+}
+
+/// Sum of the `token` transfers of the receipt matching `filter(from, to)`
+fn transferred(
+    receipt: &TransactionReceipt,
+    token: Address,
+    filter: impl Fn(Address, Address) -> bool,
+) -> Option<U256> {
+    let amounts: Vec<U256> = receipt
+        .logs
+        .iter()
+        .filter(|log| log.address == token)
+        .filter_map(|log| ethers::contract::parse_log::<TransferFilter>(log.clone()).ok())
+        .filter(|transfer| filter(transfer.from, transfer.to))
+        .map(|transfer| transfer.value)
+        .collect();
+    (!amounts.is_empty()).then(|| {
+        amounts
+            .into_iter()
+            .fold(U256::zero(), |sum, value| sum + value)
+    })
+}
+
+/// The router unwraps WETH before paying out the native token, the withdrawal holds the amount
+fn unwrapped(receipt: &TransactionReceipt) -> Option<U256> {
+    receipt
+        .logs
+        .iter()
+        .filter(|log| log.topics.first() == Some(&WithdrawalFilter::signature()))
+        .filter_map(|log| ethers::contract::parse_log::<WithdrawalFilter>(log.clone()).ok())
+        .map(|withdrawal| withdrawal.wad)
+        .last()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use ethers::abi::{AbiDecode, AbiEncode};
+    use ethers::providers::{JsonRpcClient, JsonRpcError, MockError, Provider};
+    use ethers::types::{Block, Bytes, Transaction, U64};
+    use hashbrown::HashMap;
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::{json, Value};
+    use std::fmt::Debug;
+
+    const WALLET: Address = Address::repeat_byte(0x77);
+    const TOKEN_A: Address = Address::repeat_byte(0xa1);
+    const TOKEN_B: Address = Address::repeat_byte(0xb2);
+    const WETH: Address = Address::repeat_byte(0xee);
+    const ROUTER: Address = Address::repeat_byte(0x02);
+    const TIMESTAMP: u64 = 1_700_000_000;
+
+    /// A chain answering the calls of the swaps: ERC-20 tokens held by [WALLET] and V2 pairs
+    /// quoted at a fixed rate, every other call reverts
+    #[derive(Debug, Default)]
+    struct MockChain {
+        /// Decimals, balance of the wallet and allowance of the router, by token
+        tokens: HashMap<Address, (u8, U256, U256)>,
+        native_balance: U256,
+        /// Base units of the second token received per base unit of the first, as a ratio
+        pairs: HashMap<(Address, Address), (U256, U256)>,
+        /// Returned with the transactions of a block
+        block_txs: Vec<Transaction>,
+        /// Fee recipient of the block
+        block_author: Option<Address>,
+    }
+
+    fn revert() -> MockError {
+        MockError::JsonRpcError(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        })
+    }
+
+    impl MockChain {
+        fn call(&self, to: Address, data: &[u8]) -> Result<Bytes, MockError> {
+            if let Some((decimals, balance, allowance)) = self.tokens.get(&to) {
+                return match Erc20Calls::decode(data).map_err(|_| revert())? {
+                    Erc20Calls::Decimals(_) => Ok(decimals.encode().into()),
+                    Erc20Calls::BalanceOf(call) if call.owner == WALLET => {
+                        Ok(balance.encode().into())
+                    }
+                    Erc20Calls::Allowance(call) if call.spender == ROUTER => {
+                        Ok(allowance.encode().into())
+                    }
+                    _ => Err(revert()),
+                };
+            }
+            match UniswapV2RouterCalls::decode(data) {
+                Ok(UniswapV2RouterCalls::GetAmountsOut(call)) if to == ROUTER => {
+                    let mut amounts = vec![call.amount_in];
+                    for hop in call.path.windows(2) {
+                        let (num, den) = self.pairs.get(&(hop[0], hop[1])).ok_or_else(revert)?;
+                        amounts.push(amounts[amounts.len() - 1] * num / den);
+                    }
+                    Ok(amounts.encode().into())
+                }
+                _ => Err(revert()),
+            }
+        }
+
+        fn respond(&self, method: &str, params: &Value) -> Result<Value, MockError> {
+            Ok(match method {
+                "eth_chainId" => json!(U64::one()),
+                "eth_getBalance" => json!(self.native_balance),
+                "eth_getBlockByNumber" if params[1] == json!(true) => json!(Block {
+                    number: Some(U64::from(100)),
+                    author: self.block_author,
+                    transactions: self.block_txs.clone(),
+                    ..Default::default()
+                }),
+                "eth_getBlockByNumber" => json!(Block::<H256> {
+                    number: Some(U64::from(100)),
+                    timestamp: U256::from(TIMESTAMP),
+                    ..Default::default()
+                }),
+                "eth_call" => {
+                    let tx = &params[0];
+                    let to = serde_json::from_value(tx["to"].clone())?;
+                    let data: Bytes = serde_json::from_value(match &tx["data"] {
+                        Value::Null => tx["input"].clone(),
+                        data => data.clone(),
+                    })?;
+                    json!(self.call(to, &data)?)
+                }
+                _ => return Err(revert()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for MockChain {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, MockError>
+        where
+            T: Debug + Serialize + Send + Sync,
+            R: DeserializeOwned + Send,
+        {
+            let params = serde_json::to_value(params)?;
+            Ok(serde_json::from_value(self.respond(method, &params)?)?)
+        }
+    }
+
+    fn units(amount: u64, decimals: u8) -> U256 {
+        U256::from(amount) * U256::exp10(decimals as usize)
+    }
+
+    /// [TOKEN_A] has 18 decimals and [TOKEN_B] 6, one A is worth two B
+    fn mock_chain(allowance: U256) -> MockChain {
+        let mut mock = MockChain::default();
+        mock.tokens.insert(TOKEN_A, (18, units(10, 18), allowance));
+        mock.tokens.insert(TOKEN_B, (6, U256::zero(), U256::zero()));
+        mock.native_balance = units(5, 18);
+        mock.pairs
+            .insert((TOKEN_A, TOKEN_B), (U256::from(2), units(1, 12)));
+        mock.pairs
+            .insert((WETH, TOKEN_B), (U256::from(3_000), units(1, 12)));
+        mock
+    }
+
+    fn chain_with_allowance(allowance: U256) -> Arc<Provider<MockChain>> {
+        Arc::new(Provider::new(mock_chain(allowance)))
+    }
+
+    fn chain_config() -> ChainConfig {
+        serde_json::from_value(json!({
+            "chainId": 1,
+            "name": "Mock",
+            "nativeSymbol": "ETH",
+            "nativeName": "Ether",
+            "wrappedNative": WETH,
+            "router": ROUTER,
+            "v3Router": Address::repeat_byte(0x03),
+            "quoter": Address::repeat_byte(0x04),
+            "explorerUrl": "https://etherscan.io",
+        }))
+        .unwrap()
+    }
+
+    fn swap_config() -> SwapConfig {
+        SwapConfig {
+            slippage_bps: 50,
+            deadline_secs: 120,
+            gas: GasSettings::default(),
+        }
+    }
+
+    fn order(token_in: Address, amount: SwapAmount) -> SwapOrder {
+        SwapOrder {
+            token_in,
+            token_out: TOKEN_B,
+            amount,
+            private: false,
+            rebate: false,
+            fee_on_transfer: false,
+        }
+    }
+
+    async fn prepare(client: &Arc<Provider<MockChain>>, order: &SwapOrder) -> PreparedSwap {
+        prepare_swap(client, WALLET, &chain_config(), &swap_config(), order)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn prepares_the_swap_with_the_slippage_applied() {
+        let client = chain_with_allowance(U256::zero());
+        let swap = prepare(&client, &order(TOKEN_A, SwapAmount::Exact(1.5))).await;
+        assert_eq!(swap.amount_in, units(15, 17));
+        assert_eq!(swap.route.protocol, Protocol::V2);
+        assert_eq!(swap.route.path, vec![TOKEN_A, TOKEN_B]);
+        assert_eq!(swap.route.amount_out, U256::from(3_000_000));
+        // 0.5% below the quote
+        assert_eq!(swap.amount_out_min, U256::from(2_985_000));
+        assert_eq!((swap.decimals_in, swap.decimals_out), (18, 6));
+    }
+
+    #[tokio::test]
+    async fn prepares_a_share_of_the_balance() {
+        let client = chain_with_allowance(U256::zero());
+        let swap = prepare(
+            &client,
+            &order(TOKEN_A, SwapAmount::BalancePercentage(25.0)),
+        )
+        .await;
+        assert_eq!(swap.amount_in, units(25, 17));
+    }
+
+    #[tokio::test]
+    async fn rejects_swaps_above_the_balance() {
+        let client = chain_with_allowance(U256::zero());
+        let prepared = prepare_swap(
+            &client,
+            WALLET,
+            &chain_config(),
+            &swap_config(),
+            &order(TOKEN_A, SwapAmount::Exact(11.0)),
+        )
+        .await;
+        assert!(prepared
+            .unwrap_err()
+            .to_string()
+            .starts_with("Insufficient balance"));
+    }
+
+    #[tokio::test]
+    async fn approves_exactly_the_amount_when_the_allowance_is_short() {
+        let chain = chain_config();
+        let order = order(TOKEN_A, SwapAmount::Exact(1.5));
+        let client = chain_with_allowance(units(1, 18));
+        let swap = prepare(&client, &order).await;
+        assert!(needs_approval(&client, &chain, &order, &swap)
+            .await
+            .unwrap());
+
+        let approve = approval(&client, &chain, &order, &swap);
+        assert_eq!(approve.tx.to_addr(), Some(&TOKEN_A));
+        match Erc20Calls::decode(approve.calldata().unwrap()).unwrap() {
+            Erc20Calls::Approve(call) => {
+                assert_eq!(call.spender, ROUTER);
+                assert_eq!(call.amount, units(15, 17));
+            }
+            call => panic!("Unexpected call {:?}", call),
+        }
+
+        let client = chain_with_allowance(units(15, 17));
+        assert!(!needs_approval(&client, &chain, &order, &swap)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn never_approves_the_native_token() {
+        let chain = chain_config();
+        let client = chain_with_allowance(U256::zero());
+        let order = order(native_token(), SwapAmount::Exact(1.0));
+        let swap = prepare(&client, &order).await;
+        assert_eq!(swap.route.path, vec![WETH, TOKEN_B]);
+        assert!(!needs_approval(&client, &chain, &order, &swap)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn builds_the_swap_tx_of_the_route() {
+        let chain = chain_config();
+        let client = chain_with_allowance(U256::zero());
+        let order = order(TOKEN_A, SwapAmount::Exact(1.5));
+        let swap = prepare(&client, &order).await;
+        let tx = swap_tx(&client, &chain, &swap_config(), &order, &swap)
+            .await
+            .unwrap();
+        assert_eq!(tx.from(), Some(&WALLET));
+        assert_eq!(tx.to_addr(), Some(&ROUTER));
+        match UniswapV2RouterCalls::decode(tx.data().unwrap()).unwrap() {
+            UniswapV2RouterCalls::SwapExactTokensForTokens(call) => {
+                assert_eq!(call.amount_in, swap.amount_in);
+                assert_eq!(call.amount_out_min, swap.amount_out_min);
+                assert_eq!(call.path, vec![TOKEN_A, TOKEN_B]);
+                assert_eq!(call.to, WALLET);
+                assert_eq!(call.deadline, U256::from(TIMESTAMP + 120));
+            }
+            call => panic!("Unexpected call {:?}", call),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_the_native_token_as_value() {
+        let chain = chain_config();
+        let client = chain_with_allowance(U256::zero());
+        let order = order(native_token(), SwapAmount::Exact(1.0));
+        let swap = prepare(&client, &order).await;
+        let tx = swap_tx(&client, &chain, &swap_config(), &order, &swap)
+            .await
+            .unwrap();
+        assert_eq!(tx.value(), Some(&units(1, 18)));
+        match UniswapV2RouterCalls::decode(tx.data().unwrap()).unwrap() {
+            UniswapV2RouterCalls::SwapExactETHForTokens(call) => {
+                assert_eq!(call.amount_out_min, swap.amount_out_min);
+                assert_eq!(call.path, vec![WETH, TOKEN_B]);
+            }
+            call => panic!("Unexpected call {:?}", call),
+        }
+    }
+
+    #[tokio::test]
+    async fn supports_the_fee_on_transfer_of_sells_and_taxed_buys() {
+        let chain = chain_config();
+        let client = chain_with_allowance(U256::zero());
+        let mut sell = order(TOKEN_A, SwapAmount::Exact(1.5));
+        sell.fee_on_transfer = true;
+        let swap = prepare(&client, &sell).await;
+        let tx = swap_tx(&client, &chain, &swap_config(), &sell, &swap)
+            .await
+            .unwrap();
+        match UniswapV2RouterCalls::decode(tx.data().unwrap()).unwrap() {
+            UniswapV2RouterCalls::SwapExactTokensForTokensSupportingFeeOnTransferTokens(call) => {
+                assert_eq!(call.amount_in, swap.amount_in);
+                assert_eq!(call.amount_out_min, swap.amount_out_min);
+                assert_eq!(call.path, vec![TOKEN_A, TOKEN_B]);
+            }
+            call => panic!("Unexpected call {:?}", call),
+        }
+
+        // the simulation of the buy measured a tax
+        let buy = order(native_token(), SwapAmount::Exact(1.0));
+        let mut swap = prepare(&client, &buy).await;
+        swap.fee_on_transfer = true;
+        let tx = swap_tx(&client, &chain, &swap_config(), &buy, &swap)
+            .await
+            .unwrap();
+        assert_eq!(tx.value(), Some(&units(1, 18)));
+        match UniswapV2RouterCalls::decode(tx.data().unwrap()).unwrap() {
+            UniswapV2RouterCalls::SwapExactETHForTokensSupportingFeeOnTransferTokens(call) => {
+                assert_eq!(call.amount_out_min, swap.amount_out_min);
+                assert_eq!(call.path, vec![WETH, TOKEN_B]);
+            }
+            call => panic!("Unexpected call {:?}", call),
+        }
+    }
+
+    #[tokio::test]
+    async fn rechecks_the_prepared_swap_against_the_latest_quote() {
+        let chain = chain_config();
+        let order = order(TOKEN_A, SwapAmount::Exact(1.5));
+        let client = chain_with_allowance(U256::zero());
+        let swap = prepare(&client, &order).await;
+        assert_eq!(
+            recheck_swap(&client, &chain, &order, &swap).await.unwrap(),
+            None
+        );
+
+        // within the slippage the prepared swap still fills
+        let mut mock = mock_chain(U256::zero());
+        mock.pairs
+            .insert((TOKEN_A, TOKEN_B), (U256::from(1_995), units(1, 15)));
+        let client = Arc::new(Provider::new(mock));
+        assert_eq!(
+            recheck_swap(&client, &chain, &order, &swap).await.unwrap(),
+            None
+        );
+
+        let mut mock = mock_chain(U256::zero());
+        mock.pairs
+            .insert((TOKEN_A, TOKEN_B), (U256::from(19), units(1, 13)));
+        let client = Arc::new(Provider::new(mock));
+        let moved = recheck_swap(&client, &chain, &order, &swap).await.unwrap();
+        assert!(moved.unwrap().contains("below the minimum of 2.985"));
+
+        let mut mock = mock_chain(U256::zero());
+        mock.tokens
+            .insert(TOKEN_A, (18, units(1, 18), U256::zero()));
+        let client = Arc::new(Provider::new(mock));
+        let moved = recheck_swap(&client, &chain, &order, &swap).await.unwrap();
+        assert!(moved.unwrap().contains("only holds 1 of the 1.5"));
+    }
+
+    #[tokio::test]
+    async fn reads_the_refunds_paid_after_the_swap() {
+        let tx = |index: u64, from: Address, to: Address, value: u64| Transaction {
+            transaction_index: Some(U64::from(index)),
+            from,
+            to: Some(to),
+            value: U256::from(value),
+            ..Default::default()
+        };
+        let builder = Address::repeat_byte(0xbb);
+        let mock = MockChain {
+            block_txs: vec![
+                // paid before the swap
+                tx(1, builder, WALLET, 5),
+                // the swap itself
+                tx(2, WALLET, ROUTER, 1_000),
+                tx(3, builder, WALLET, 7),
+                // sent by the wallet to itself
+                tx(4, WALLET, WALLET, 100),
+                tx(5, builder, Address::repeat_byte(0x99), 9),
+                tx(6, builder, WALLET, 11),
+                // an unrelated transfer to the wallet in the same block
+                tx(7, Address::repeat_byte(0xcc), WALLET, 13),
+            ],
+            block_author: Some(builder),
+            ..Default::default()
+        };
+        let provider = Provider::new(mock);
+        let receipt = TransactionReceipt {
+            block_number: Some(U64::from(100)),
+            transaction_index: U64::from(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            refunded(&provider, &receipt, WALLET).await.unwrap(),
+            U256::from(18)
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use crate::requests::swap::PreparedSwap;
+use crate::storages::DraftKey;
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+
+/// The confirmation card shown last to a user, the swap of the draft it was shown for is sent
+/// once its Confirm button is pressed
+#[derive(Debug, Clone)]
+pub(crate) struct PendingSwap {
+    /// Carried by the buttons of the card
+    nonce: u32,
+    /// [crate::storages::OrderDraft::fingerprint] of the draft when the card was shown
+    pub(crate) fingerprint: u32,
+    /// Route, amounts and fees the card was shown for, sent as is. None when the swap couldn't
+    /// be previewed
+    pub(crate) swap: Option<PreparedSwap>,
+}
+
+/// Outcome of taking the pending swap of a Confirm press
+#[derive(Debug, Clone)]
+pub(crate) enum TakenSwap {
+    /// The card was pending, it can't be confirmed again
+    Confirmed(Box<PendingSwap>),
+    /// A newer card was shown since
+    Replaced,
+    /// The card was confirmed or aborted already
+    Missing,
+}
+
+/// Confirmation cards waiting for Confirm, one per user. Kept in memory only, so the cards
+/// shown before a restart can't be confirmed anymore
+#[derive(Debug, Default)]
+pub(crate) struct PendingSwaps {
+    pending: RwLock<HashMap<DraftKey, PendingSwap>>,
+}
+
+impl PendingSwaps {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the card shown for the draft and its previewed swap, replacing the user's
+    /// previous card. Returns the nonce its buttons carry
+    pub(crate) fn issue(&self, key: DraftKey, fingerprint: u32, swap: Option<PreparedSwap>) -> u32 {
+        let nonce = ethers::core::rand::random();
+        self.pending.write().insert(
+            key,
+            PendingSwap {
+                nonce,
+                fingerprint,
+                swap,
+            },
+        );
+        nonce
+    }
+
+    /// Removes the card of the nonce, so a second press of its Confirm button finds nothing
+    pub(crate) fn take(&self, key: &DraftKey, nonce: u32) -> TakenSwap {
+        let mut pending = self.pending.write();
+        match pending.get(key) {
+            Some(swap) if swap.nonce != nonce => TakenSwap::Replaced,
+            _ => pending.remove(key).map_or(TakenSwap::Missing, |swap| {
+                TakenSwap::Confirmed(Box::new(swap))
+            }),
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::storages::sqlite::{blocking, SqliteDatabase};
+use crate::storages::OrderSide;
+use ethers::types::{Address, H256, U256};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use teloxide::types::UserId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum TradeStatus {
+    /// Recorded, the tx is not mined yet
+    Pending,
+    Confirmed,
+    Failed,
+}
+
+impl fmt::Display for TradeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pending => write!(f, "Pending"),
+            Self::Confirmed => write!(f, "Confirmed"),
+            Self::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+/// An order submitted by a user, along with the outcome of its tx
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct TradeRecord {
+    /// Assigned by the [TradeJournal]
+    pub(crate) id: u64,
+    pub(crate) user_id: UserId,
+    pub(crate) chain_id: u64,
+    pub(crate) side: OrderSide,
+    pub(crate) wallet: Address,
+    pub(crate) private_tx: bool,
+    pub(crate) rebate: bool,
+    /// Token paid
+    pub(crate) token_in: Address,
+    /// Token received
+    pub(crate) token_out: Address,
+    /// Amount of `token_in` requested by the user
+    pub(crate) amount: Option<f64>,
+    /// Percentage of the `token_in` balance requested by the user, sell orders only
+    pub(crate) sell_percentage: Option<f64>,
+    pub(crate) tx_hash: Option<H256>,
+    pub(crate) status: TradeStatus,
+    /// Gas used times the effective gas price, in wei
+    pub(crate) gas_paid: Option<U256>,
+    /// Amount of `token_in` actually paid
+    pub(crate) filled_in: Option<f64>,
+    /// Amount of `token_out` actually received
+    pub(crate) filled_out: Option<f64>,
+    /// Estimated refund of the backruns when sent for rebates, in wei of the native token
+    #[serde(default)]
+    pub(crate) expected_rebate: Option<U256>,
+    /// Refunds of the backruns actually received, in wei of the native token
+    #[serde(default)]
+    pub(crate) rebate_received: Option<U256>,
+    /// Unix timestamp
+    pub(crate) created_at: i64,
+}
+
+#[derive(Debug)]
+enum JournalBackend {
+    Memory(RwLock<Vec<TradeRecord>>),
+    Sqlite(Arc<SqliteDatabase>),
+}
+
+/// Append only journal of the trades of every user, `/history` is rendered from it
+#[derive(Debug)]
+pub(crate) struct TradeJournal {
+    backend: JournalBackend,
+}
+
+impl TradeJournal {
+    pub(crate) fn in_memory() -> Self {
+        Self {
+            backend: JournalBackend::Memory(RwLock::new(Vec::new())),
+        }
+    }
+
+    pub(crate) fn sqlite(database: Arc<SqliteDatabase>) -> Self {
+        Self {
+            backend: JournalBackend::Sqlite(database),
+        }
+    }
+
+    /// Records a new trade, the `id` of `trade` is ignored and the assigned one returned
+    pub(crate) async fn record(&self, trade: TradeRecord) -> anyhow::Result<u64> {
+        match &self.backend {
+            JournalBackend::Memory(trades) => {
+                let mut trades = trades.write();
+                let id = trades.len() as u64 + 1;
+                trades.push(TradeRecord { id, ..trade });
+                Ok(id)
+            }
+            JournalBackend::Sqlite(database) => {
+                blocking(database, move |database| database.insert_trade(&trade)).await
+            }
+        }
+    }
+
+    /// Applies `f` to the stored trade and returns the updated trade
+    pub(crate) async fn update(
+        &self,
+        id: u64,
+        f: impl FnOnce(&mut TradeRecord) + Send + 'static,
+    ) -> anyhow::Result<TradeRecord> {
+        match &self.backend {
+            JournalBackend::Memory(trades) => {
+                let mut trades = trades.write();
+                let trade = trades
+                    .iter_mut()
+                    .find(|trade| trade.id == id)
+                    .ok_or_else(|| anyhow::anyhow!("Trade {} not found", id))?;
+                f(trade);
+                Ok(trade.clone())
+            }
+            JournalBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    let mut trade = database
+                        .load_trade(id)?
+                        .ok_or_else(|| anyhow::anyhow!("Trade {} not found", id))?;
+                    f(&mut trade);
+                    database.save_trade(&trade)?;
+                    Ok(trade)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Gets a trade of the user, other users' trades are never returned
+    pub(crate) async fn get(
+        &self,
+        user_id: UserId,
+        id: u64,
+    ) -> anyhow::Result<Option<TradeRecord>> {
+        let trade = match &self.backend {
+            JournalBackend::Memory(trades) => {
+                trades.read().iter().find(|trade| trade.id == id).cloned()
+            }
+            JournalBackend::Sqlite(database) => {
+                blocking(database, move |database| database.load_trade(id)).await?
+            }
+        };
+        Ok(trade.filter(|trade| trade.user_id == user_id))
+    }
+
+    pub(crate) async fn count(&self, user_id: UserId) -> anyhow::Result<usize> {
+        match &self.backend {
+            JournalBackend::Memory(trades) => Ok(trades
+                .read()
+                .iter()
+                .filter(|trade| trade.user_id == user_id)
+                .count()),
+            JournalBackend::Sqlite(database) => {
+                blocking(database, move |database| database.count_trades(user_id)).await
+            }
+        }
+    }
+
+    /// Trades of the user, newest first
+    pub(crate) async fn page(
+        &self,
+        user_id: UserId,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<TradeRecord>> {
+        match &self.backend {
+            JournalBackend::Memory(trades) => Ok(trades
+                .read()
+                .iter()
+                .rev()
+                .filter(|trade| trade.user_id == user_id)
+                .skip(offset)
+                .take(limit)
+                .cloned()
+                .collect()),
+            JournalBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.load_trades(user_id, offset, limit)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Trades of the user sent with the Rebate toggle on, newest first
+    pub(crate) async fn rebate_trades(&self, user_id: UserId) -> anyhow::Result<Vec<TradeRecord>> {
+        match &self.backend {
+            JournalBackend::Memory(trades) => Ok(trades
+                .read()
+                .iter()
+                .rev()
+                .filter(|trade| trade.user_id == user_id && trade.rebate)
+                .cloned()
+                .collect()),
+            JournalBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.load_rebate_trades(user_id)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Confirmed buys of the token by the user, oldest first
+    pub(crate) async fn token_buys(
+        &self,
+        user_id: UserId,
+        token: Address,
+    ) -> anyhow::Result<Vec<TradeRecord>> {
+        match &self.backend {
+            JournalBackend::Memory(trades) => Ok(trades
+                .read()
+                .iter()
+                .filter(|trade| {
+                    trade.user_id == user_id
+                        && trade.token_out == token
+                        && trade.side == OrderSide::Buy
+                        && trade.status == TradeStatus::Confirmed
+                })
+                .cloned()
+                .collect()),
+            JournalBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.load_token_buys(user_id, token)
+                })
+                .await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(user_id: u64) -> TradeRecord {
+        TradeRecord {
+            id: 0,
+            user_id: UserId(user_id),
+            chain_id: 1,
+            side: OrderSide::Buy,
+            wallet: Address::repeat_byte(1),
+            private_tx: false,
+            rebate: false,
+            token_in: Address::repeat_byte(2),
+            token_out: Address::repeat_byte(3),
+            amount: Some(1.0),
+            sell_percentage: None,
+            tx_hash: None,
+            status: TradeStatus::Pending,
+            gas_paid: None,
+            filled_in: None,
+            filled_out: None,
+            expected_rebate: None,
+            rebate_received: None,
+            created_at: 0,
+        }
+    }
+
+    fn journals() -> [TradeJournal; 2] {
+        let database = SqliteDatabase::open(":memory:").unwrap();
+        [
+            TradeJournal::in_memory(),
+            TradeJournal::sqlite(Arc::new(database)),
+        ]
+    }
+
+    async fn page_ids(journal: &TradeJournal, offset: usize, limit: usize) -> Vec<u64> {
+        let trades = journal.page(UserId(1), offset, limit).await.unwrap();
+        assert!(trades.iter().all(|trade| trade.user_id == UserId(1)));
+        trades.iter().map(|trade| trade.id).collect()
+    }
+
+    #[tokio::test]
+    async fn pages_the_trades_of_the_user_newest_first() {
+        for journal in journals() {
+            // trades 2, 4 and 6 belong to another user
+            let mut ids = Vec::new();
+            for user_id in [1, 2, 1, 2, 1, 2, 1, 1] {
+                let id = journal.record(trade(user_id)).await.unwrap();
+                if user_id == 1 {
+                    ids.push(id);
+                }
+            }
+            ids.reverse();
+            assert_eq!(journal.count(UserId(1)).await.unwrap(), 5);
+
+            assert_eq!(page_ids(&journal, 0, 2).await, ids[0..2]);
+            assert_eq!(page_ids(&journal, 2, 2).await, ids[2..4]);
+            // the last page is partial
+            assert_eq!(page_ids(&journal, 4, 2).await, ids[4..]);
+            assert!(page_ids(&journal, 6, 2).await.is_empty());
+            assert_eq!(page_ids(&journal, 0, 5).await, ids);
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_an_empty_journal() {
+        for journal in journals() {
+            journal.record(trade(2)).await.unwrap();
+            assert_eq!(journal.count(UserId(1)).await.unwrap(), 0);
+            assert!(page_ids(&journal, 0, 5).await.is_empty());
+        }
+    }
+}
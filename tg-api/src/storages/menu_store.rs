@@ -0,0 +1,290 @@
+use crate::storages::sqlite::{blocking, SqliteDatabase};
+use crate::storages::TgMessage;
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Every menu the bot keeps track of, one stored message per kind and user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum MenuKind {
+    Main,
+    Buy,
+    Sell,
+    LimitBuy,
+    LimitSell,
+    Settings,
+    Wallets,
+    Orders,
+}
+
+impl MenuKind {
+    /// Name of the kind in the database
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Main => "main",
+            Self::Buy => "buy",
+            Self::Sell => "sell",
+            Self::LimitBuy => "limit_buy",
+            Self::LimitSell => "limit_sell",
+            Self::Settings => "settings",
+            Self::Wallets => "wallets",
+            Self::Orders => "orders",
+        }
+    }
+}
+
+impl fmt::Display for MenuKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MenuEntry {
+    message: TgMessage,
+    stored_at: SystemTime,
+}
+
+#[derive(Debug)]
+enum MenuBackend<K> {
+    Memory(RwLock<HashMap<(MenuKind, K), MenuEntry>>),
+    /// Keys are stored with their `Display` representation
+    Sqlite(Arc<SqliteDatabase>),
+}
+
+/// Locates the menu messages of every user, entries older than `ttl` are treated as missing
+/// and evicted
+#[derive(Debug)]
+pub(crate) struct MenuStore<K> {
+    backend: MenuBackend<K>,
+    ttl: Duration,
+}
+
+impl<K> MenuStore<K>
+where
+    K: Clone + Eq + Hash + fmt::Display + Send + Sync + 'static,
+{
+    pub(crate) fn in_memory(ttl: Duration) -> Self {
+        Self {
+            backend: MenuBackend::Memory(RwLock::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    pub(crate) fn sqlite(database: Arc<SqliteDatabase>, ttl: Duration) -> Self {
+        Self {
+            backend: MenuBackend::Sqlite(database),
+            ttl,
+        }
+    }
+
+    fn is_expired(&self, stored_at: SystemTime) -> bool {
+        stored_at.elapsed().is_ok_and(|elapsed| elapsed > self.ttl)
+    }
+
+    pub(crate) async fn insert(
+        &self,
+        kind: MenuKind,
+        key: K,
+        message: TgMessage,
+    ) -> anyhow::Result<()> {
+        match &self.backend {
+            MenuBackend::Memory(storage) => {
+                let entry = MenuEntry {
+                    message,
+                    stored_at: SystemTime::now(),
+                };
+                storage.write().insert((kind, key), entry);
+                Ok(())
+            }
+            MenuBackend::Sqlite(database) => {
+                let key = key.to_string();
+                let stored_at = unix_timestamp(SystemTime::now());
+                blocking(database, move |database| {
+                    database.save_menu(kind.as_str(), &key, &message, stored_at)
+                })
+                .await
+            }
+        }
+    }
+
+    pub(crate) async fn get(&self, kind: MenuKind, key: &K) -> anyhow::Result<Option<TgMessage>> {
+        let entry = match &self.backend {
+            MenuBackend::Memory(storage) => storage.read().get(&(kind, key.clone())).cloned(),
+            MenuBackend::Sqlite(database) => {
+                let key = key.to_string();
+                blocking(database, move |database| {
+                    database.load_menu(kind.as_str(), &key)
+                })
+                .await?
+                .map(|(message, stored_at)| MenuEntry {
+                    message,
+                    stored_at: UNIX_EPOCH + Duration::from_secs(stored_at),
+                })
+            }
+        };
+
+        match entry {
+            Some(entry) if self.is_expired(entry.stored_at) => {
+                self.remove(kind, key).await?;
+                Ok(None)
+            }
+            entry => Ok(entry.map(|entry| entry.message)),
+        }
+    }
+
+    pub(crate) async fn remove(
+        &self,
+        kind: MenuKind,
+        key: &K,
+    ) -> anyhow::Result<Option<TgMessage>> {
+        match &self.backend {
+            MenuBackend::Memory(storage) => Ok(storage
+                .write()
+                .remove(&(kind, key.clone()))
+                .map(|entry| entry.message)),
+            MenuBackend::Sqlite(database) => {
+                let key = key.to_string();
+                blocking(database, move |database| {
+                    database.delete_menu(kind.as_str(), &key)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Removes every stored menu of the given kind
+    pub(crate) async fn clear(&self, kind: MenuKind) -> anyhow::Result<()> {
+        match &self.backend {
+            MenuBackend::Memory(storage) => {
+                storage.write().retain(|(menu, _), _| *menu != kind);
+                Ok(())
+            }
+            MenuBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.delete_menus(kind.as_str())
+                })
+                .await
+            }
+        }
+    }
+
+    /// Removes the entries older than the TTL, returns the number of evicted entries
+    pub(crate) async fn evict_expired(&self) -> anyhow::Result<usize> {
+        match &self.backend {
+            MenuBackend::Memory(storage) => {
+                let mut storage = storage.write();
+                let before = storage.len();
+                storage.retain(|_, entry| !self.is_expired(entry.stored_at));
+                Ok(before - storage.len())
+            }
+            MenuBackend::Sqlite(database) => {
+                let expired_before =
+                    unix_timestamp(SystemTime::now()).saturating_sub(self.ttl.as_secs());
+                blocking(database, move |database| {
+                    database.delete_menus_before(expired_before)
+                })
+                .await
+            }
+        }
+    }
+}
+
+impl<K> MenuStore<K>
+where
+    K: Clone + Eq + Hash + fmt::Display + FromStr + Send + Sync + 'static,
+{
+    /// Every unexpired menu of the given kind, persisted keys that no longer parse are skipped
+    pub(crate) async fn entries(&self, kind: MenuKind) -> anyhow::Result<Vec<(K, TgMessage)>> {
+        match &self.backend {
+            MenuBackend::Memory(storage) => Ok(storage
+                .read()
+                .iter()
+                .filter(|((menu, _), entry)| *menu == kind && !self.is_expired(entry.stored_at))
+                .map(|((_, key), entry)| (key.clone(), entry.message.clone()))
+                .collect()),
+            MenuBackend::Sqlite(database) => {
+                let rows =
+                    blocking(database, move |database| database.load_menus(kind.as_str())).await?;
+                Ok(rows
+                    .into_iter()
+                    .filter(|(_, _, stored_at)| {
+                        !self.is_expired(UNIX_EPOCH + Duration::from_secs(*stored_at))
+                    })
+                    .filter_map(|(key, message, _)| Some((key.parse().ok()?, message)))
+                    .collect())
+            }
+        }
+    }
+}
+
+pub(crate) fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use teloxide::types::Message;
+
+    fn message() -> TgMessage {
+        let message: Message = serde_json::from_value(json!({
+            "message_id": 10,
+            "date": 0,
+            "chat": { "id": 1, "type": "private", "first_name": "Alice" },
+            "text": "menu",
+        }))
+        .unwrap();
+        TgMessage {
+            chat_id: message.chat.id,
+            message_id: message.id,
+            message: Arc::new(message),
+        }
+    }
+
+    fn database() -> Arc<SqliteDatabase> {
+        Arc::new(SqliteDatabase::open(":memory:").unwrap())
+    }
+
+    #[tokio::test]
+    async fn keeps_the_menus_for_the_ttl() {
+        let hour = Duration::from_secs(3_600);
+        for store in [
+            MenuStore::in_memory(hour),
+            MenuStore::sqlite(database(), hour),
+        ] {
+            store.insert(MenuKind::Buy, 1, message()).await.unwrap();
+            let menu = store.get(MenuKind::Buy, &1).await.unwrap().unwrap();
+            assert_eq!(menu.message_id, message().message_id);
+            assert!(store.get(MenuKind::Sell, &1).await.unwrap().is_none());
+            assert_eq!(store.entries(MenuKind::Buy).await.unwrap().len(), 1);
+            assert_eq!(store.evict_expired().await.unwrap(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn treats_the_expired_menus_as_missing() {
+        for store in [
+            MenuStore::in_memory(Duration::ZERO),
+            MenuStore::sqlite(database(), Duration::ZERO),
+        ] {
+            store.insert(MenuKind::Buy, 1, message()).await.unwrap();
+            std::thread::sleep(Duration::from_millis(5));
+            assert!(store.entries(MenuKind::Buy).await.unwrap().is_empty());
+            assert!(store.get(MenuKind::Buy, &1).await.unwrap().is_none());
+        }
+
+        let store = MenuStore::in_memory(Duration::ZERO);
+        store.insert(MenuKind::Main, 1, message()).await.unwrap();
+        store.insert(MenuKind::Main, 2, message()).await.unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(store.evict_expired().await.unwrap(), 2);
+    }
+}
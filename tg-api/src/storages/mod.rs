@@ -0,0 +1,597 @@
+mod confirmations;
+mod journal;
+mod menu_store;
+mod orders;
+mod settings;
+mod sqlite;
+mod token_metadata;
+mod tokens;
+mod wallets;
+
+pub(crate) use confirmations::{PendingSwaps, TakenSwap};
+pub(crate) use journal::{TradeJournal, TradeRecord, TradeStatus};
+use menu_store::unix_timestamp;
+pub(crate) use menu_store::{MenuKind, MenuStore};
+pub(crate) use orders::{
+    LimitKind, LimitOrder, LimitOrderStatus, LimitTrigger, OrderBook, TriggerMarket, TriggerUnit,
+    MAX_EXPIRY_SECS,
+};
+pub(crate) use settings::{
+    SettingsStore, SwapSettings, UserSettings, MAX_DEADLINE_SECS, MAX_SLIPPAGE_BPS,
+    MIN_DEADLINE_SECS,
+};
+pub(crate) use token_metadata::{
+    CachedMetadata, TokenMetadata, TokenMetadataCache, MAX_CACHED_TOKENS, SUPPLY_TTL,
+};
+pub(crate) use tokens::{TokenEntry, TokenRegistry};
+pub(crate) use wallets::{WalletEntry, WalletStore, MAX_WALLETS};
+
+use crate::config::{menu_ttl_from_env, ChainRegistry, StorageBackend, SwapConfig, WalletConfig};
+use ethers::types::Address;
+use ethers::utils::keccak256;
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlite::{blocking, SqliteDatabase, SqliteDialogueStorage};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use teloxide::dispatching::dialogue::{ErasedStorage, InMemStorage, Storage};
+use teloxide::types::{CallbackQuery, ChatId, Message, MessageId, UserId};
+use tokio::sync::Mutex;
+
+const MENU_EVICTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    /// Opened when `STORAGE_BACKEND=sqlite`, shared by every persistent storage
+    static ref SQLITE_DATABASE: Option<Arc<SqliteDatabase>> = match StorageBackend::from_env()
+        .expect("Invalid storage backend")
+    {
+        StorageBackend::Memory => None,
+        StorageBackend::Sqlite(path) => Some(Arc::new(
+            SqliteDatabase::open(&path).expect("Unable to open the sqlite database"),
+        )),
+    };
+}
+
+lazy_static! {
+    /// Used to look up the enabled chains, their RPC URLs and contracts
+    pub(crate) static ref GLOBAL_CHAIN_REGISTRY: ChainRegistry =
+        ChainRegistry::from_env().expect("Invalid chain config");
+}
+
+lazy_static! {
+    /// Used as the slippage and deadline of the swaps whose user didn't set their own
+    pub(crate) static ref GLOBAL_SWAP_CONFIG: SwapConfig =
+        SwapConfig::from_env().expect("Invalid swap config");
+}
+
+lazy_static! {
+    /// Used to locate the menus of every user
+    pub(crate) static ref GLOBAL_MENU_STORE: MenuStore<DraftKey> = {
+        let ttl = menu_ttl_from_env().expect("Invalid menu TTL");
+        match SQLITE_DATABASE.as_ref() {
+            Some(database) => MenuStore::sqlite(database.clone(), ttl),
+            None => MenuStore::in_memory(ttl),
+        }
+    };
+}
+
+lazy_static! {
+    /// Used to hold the order each user is editing in the buy or sell menu
+    pub(crate) static ref GLOBAL_ORDER_DRAFT_STORAGE: OrderDraftStorage = OrderDraftStorage::new(
+        SQLITE_DATABASE.clone(),
+        menu_ttl_from_env().expect("Invalid menu TTL"),
+    );
+}
+
+lazy_static! {
+    /// Used to send the swap of a confirmation card once only
+    pub(crate) static ref GLOBAL_PENDING_SWAPS: PendingSwaps = PendingSwaps::new();
+}
+
+lazy_static! {
+    /// Used to generate, persist and decrypt the wallets of every user
+    pub(crate) static ref GLOBAL_WALLET_STORE: WalletStore = WalletStore::new(
+        WalletConfig::from_env().expect("Invalid wallet config"),
+    )
+    .expect("Unable to open the keystore directory");
+}
+
+lazy_static! {
+    /// Used to record every submitted order, `/history` is rendered from it
+    pub(crate) static ref GLOBAL_TRADE_JOURNAL: TradeJournal = match SQLITE_DATABASE.as_ref() {
+        Some(database) => TradeJournal::sqlite(database.clone()),
+        None => TradeJournal::in_memory(),
+    };
+}
+
+lazy_static! {
+    /// Used to keep the limit orders of every user, the open ones are watched on every new block
+    pub(crate) static ref GLOBAL_ORDER_BOOK: OrderBook = match SQLITE_DATABASE.as_ref() {
+        Some(database) => OrderBook::sqlite(database.clone()),
+        None => OrderBook::in_memory(),
+    };
+}
+
+lazy_static! {
+    /// Used to resolve the tokens users enter by symbol or name
+    pub(crate) static ref GLOBAL_TOKEN_REGISTRY: TokenRegistry = match SQLITE_DATABASE.as_ref() {
+        Some(database) => TokenRegistry::sqlite(database.clone()),
+        None => TokenRegistry::in_memory(),
+    };
+}
+
+lazy_static! {
+    /// Used to hold the slippage, deadline, gas and default buy amount of every user
+    pub(crate) static ref GLOBAL_SETTINGS_STORE: SettingsStore = match SQLITE_DATABASE.as_ref() {
+        Some(database) => SettingsStore::sqlite(database.clone()),
+        None => SettingsStore::in_memory(),
+    };
+}
+
+lazy_static! {
+    /// Used to avoid querying the metadata of a token more than once
+    pub(crate) static ref GLOBAL_TOKEN_METADATA: TokenMetadataCache =
+        TokenMetadataCache::new(SUPPLY_TTL, MAX_CACHED_TOKENS);
+}
+
+/// Opens the configured backend, called once at startup so that a bad configuration
+/// fails right away instead of on the first update
+pub(crate) fn init_storages() {
+    lazy_static::initialize(&GLOBAL_CHAIN_REGISTRY);
+    lazy_static::initialize(&GLOBAL_SWAP_CONFIG);
+    lazy_static::initialize(&SQLITE_DATABASE);
+    lazy_static::initialize(&GLOBAL_MENU_STORE);
+    lazy_static::initialize(&GLOBAL_ORDER_DRAFT_STORAGE);
+    lazy_static::initialize(&GLOBAL_PENDING_SWAPS);
+    lazy_static::initialize(&GLOBAL_WALLET_STORE);
+    lazy_static::initialize(&GLOBAL_TRADE_JOURNAL);
+    lazy_static::initialize(&GLOBAL_ORDER_BOOK);
+    lazy_static::initialize(&GLOBAL_TOKEN_REGISTRY);
+    lazy_static::initialize(&GLOBAL_TOKEN_METADATA);
+    lazy_static::initialize(&GLOBAL_SETTINGS_STORE);
+}
+
+/// Periodically evicts the expired menus and drafts, so users who never come back don't pile up
+pub(crate) fn spawn_menu_eviction() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(MENU_EVICTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            match GLOBAL_MENU_STORE.evict_expired().await {
+                Ok(0) => {}
+                Ok(evicted) => log::info!("Evicted {} expired menus", evicted),
+                Err(err) => log::error!("Unable to evict the expired menus: {}", err),
+            }
+            match GLOBAL_ORDER_DRAFT_STORAGE.evict_expired().await {
+                Ok(0) => {}
+                Ok(evicted) => log::info!("Evicted {} expired order drafts", evicted),
+                Err(err) => log::error!("Unable to evict the expired order drafts: {}", err),
+            }
+        }
+    });
+}
+
+/// Dialogue storage of the configured backend
+pub(crate) fn dialogue_storage<D>() -> Arc<ErasedStorage<D>>
+where
+    D: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    match SQLITE_DATABASE.as_ref() {
+        Some(database) => SqliteDialogueStorage::new(database.clone()).erase(),
+        None => InMemStorage::new().erase(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TgMessage {
+    pub(crate) chat_id: ChatId,
+    pub(crate) message_id: MessageId,
+    pub(crate) message: Arc<Message>,
+}
+
+/// Identifies a user in a chat, menus and drafts of different users never collide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct DraftKey {
+    pub(crate) chat_id: ChatId,
+    pub(crate) user_id: UserId,
+}
+
+impl DraftKey {
+    pub(crate) fn from_callback(q: &CallbackQuery) -> Option<Self> {
+        q.message.as_ref().map(|msg| Self {
+            chat_id: msg.chat.id,
+            user_id: q.from.id,
+        })
+    }
+
+    pub(crate) fn from_message(msg: &Message) -> Option<Self> {
+        msg.from().map(|user| Self {
+            chat_id: msg.chat.id,
+            user_id: user.id,
+        })
+    }
+
+    /// Id the prompt dialogue of the user in the chat is stored under. Teloxide keys dialogues
+    /// by chat only, so the prompts of two users of a group would overwrite each other
+    pub(crate) fn dialogue_id(&self) -> ChatId {
+        let hash = keccak256(self.to_string());
+        ChatId(i64::from_be_bytes(
+            hash[..8].try_into().expect("keccak256 is 32 bytes"),
+        ))
+    }
+}
+
+/// Used as the key of the persisted menus
+impl fmt::Display for DraftKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.chat_id, self.user_id)
+    }
+}
+
+impl FromStr for DraftKey {
+    type Err = anyhow::Error;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let (chat_id, user_id) = key
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Invalid menu key: {}", key))?;
+        Ok(Self {
+            chat_id: ChatId(chat_id.parse()?),
+            user_id: UserId(user_id.parse()?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum OrderSide {
+    Buy,
+    Sell,
+}
+
+/// The order a user is editing, the buy and sell keyboards are rendered from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct OrderDraft {
+    pub(crate) side: OrderSide,
+    /// Session id of the menu rendered from this draft, callbacks of other sessions are stale
+    pub(crate) session: u32,
+    /// Chain the order is sent on, drafts saved before chains were selectable are on mainnet
+    #[serde(default = "mainnet")]
+    pub(crate) chain_id: u64,
+    /// Address of the selected wallet, None until the user has wallets. Kept by address so
+    /// deleting another wallet doesn't move the selection
+    pub(crate) wallet: Option<Address>,
+    pub(crate) private_tx: bool,
+    pub(crate) rebate: bool,
+    /// Token to buy, buy menu only
+    pub(crate) buy_token: Option<Address>,
+    /// Token to sell, sell menu only
+    pub(crate) sell_token: Option<Address>,
+    pub(crate) receive_token: Option<Address>,
+    /// Buy amount in the buy menu, exact sell amount in the sell menu
+    pub(crate) amount: Option<f64>,
+    /// Percentage of the balance to sell, mutually exclusive with `amount`, sell menu only
+    pub(crate) sell_percentage: Option<f64>,
+    /// Slippage, deadline and gas of the swap, taken from the user's settings
+    #[serde(default)]
+    pub(crate) swap: SwapSettings,
+    /// Trigger of a limit order, None for an order swapped right away
+    #[serde(default)]
+    pub(crate) limit: Option<LimitTrigger>,
+}
+
+fn mainnet() -> u64 {
+    1
+}
+
+impl OrderDraft {
+    pub(crate) fn new(side: OrderSide, session: u32) -> Self {
+        Self {
+            side,
+            session,
+            chain_id: GLOBAL_CHAIN_REGISTRY.default_chain().chain_id,
+            wallet: None,
+            private_tx: true,
+            rebate: false,
+            buy_token: None,
+            sell_token: None,
+            receive_token: None,
+            amount: None,
+            sell_percentage: None,
+            swap: SwapSettings::default(),
+            limit: None,
+        }
+    }
+
+    /// Starts the draft from the user's settings
+    pub(crate) fn with_settings(mut self, settings: &UserSettings) -> Self {
+        self.swap = settings.swap;
+        if self.side == OrderSide::Buy {
+            self.amount = settings.buy_amount;
+        }
+        self
+    }
+
+    /// Moves the draft to another chain, the tokens of the previous chain are cleared
+    pub(crate) fn set_chain(&mut self, chain_id: u64) {
+        if self.chain_id != chain_id {
+            self.chain_id = chain_id;
+            self.buy_token = None;
+            self.sell_token = None;
+            self.receive_token = None;
+        }
+    }
+
+    /// Hash of the draft's fields, kept with a confirmation card so a draft edited after the
+    /// card was shown isn't sent
+    pub(crate) fn fingerprint(&self) -> u32 {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(self)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+        hasher.finish() as u32
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DraftEntry {
+    draft: OrderDraft,
+    updated_at: SystemTime,
+}
+
+/// Drafts are cached in memory, and written through to the database when one is configured.
+/// Drafts not edited for `ttl` are treated as missing and evicted, like the menus they're
+/// edited from
+#[derive(Debug)]
+pub(crate) struct OrderDraftStorage {
+    storage: Arc<RwLock<HashMap<DraftKey, DraftEntry>>>,
+    database: Option<Arc<SqliteDatabase>>,
+    ttl: Duration,
+    /// Held while a draft is written to memory and the database, so the database is never left
+    /// with an older draft than the memory
+    lock: Mutex<()>,
+}
+
+impl OrderDraftStorage {
+    pub(crate) fn new(database: Option<Arc<SqliteDatabase>>, ttl: Duration) -> Self {
+        OrderDraftStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+            database,
+            ttl,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn is_expired(&self, updated_at: SystemTime) -> bool {
+        updated_at.elapsed().is_ok_and(|elapsed| elapsed > self.ttl)
+    }
+
+    async fn persist(&self, key: DraftKey, draft: OrderDraft, updated_at: SystemTime) {
+        if let Some(database) = &self.database {
+            let updated_at = unix_timestamp(updated_at);
+            let saved = blocking(database, move |database| {
+                database.save_draft(&key, &draft, updated_at)
+            })
+            .await;
+            if let Err(err) = saved {
+                log::error!("Unable to persist the order draft of {}: {}", key, err);
+            }
+        }
+    }
+
+    pub(crate) async fn insert(&self, key: DraftKey, draft: OrderDraft) {
+        let _lock = self.lock.lock().await;
+        let updated_at = SystemTime::now();
+        self.persist(key, draft.clone(), updated_at).await;
+        self.storage
+            .write()
+            .insert(key, DraftEntry { draft, updated_at });
+    }
+
+    /// The unexpired draft cached in memory
+    fn cached(&self, key: &DraftKey) -> Option<OrderDraft> {
+        let storage = self.storage.read();
+        let entry = storage.get(key)?;
+        (!self.is_expired(entry.updated_at)).then(|| entry.draft.clone())
+    }
+
+    /// Falls back to the database for drafts created before a restart, must be called with the
+    /// lock held
+    async fn load(&self, key: &DraftKey) -> Option<OrderDraft> {
+        if let Some(draft) = self.cached(key) {
+            return Some(draft);
+        }
+        self.storage.write().remove(key);
+        let database = self.database.as_ref()?;
+        let key = *key;
+        let (draft, updated_at) = blocking(database, move |database| database.load_draft(&key))
+            .await
+            .unwrap_or_else(|err| {
+                log::error!("Unable to load the order draft of {}: {}", key, err);
+                None
+            })?;
+        let updated_at = UNIX_EPOCH + Duration::from_secs(updated_at);
+        if self.is_expired(updated_at) {
+            return None;
+        }
+        let entry = DraftEntry {
+            draft: draft.clone(),
+            updated_at,
+        };
+        self.storage.write().insert(key, entry);
+        Some(draft)
+    }
+
+    pub(crate) async fn get(&self, key: &DraftKey) -> Option<OrderDraft> {
+        if let Some(draft) = self.cached(key) {
+            return Some(draft);
+        }
+        let _lock = self.lock.lock().await;
+        self.load(key).await
+    }
+
+    /// Applies `f` to the stored draft and returns the updated draft
+    pub(crate) async fn update(
+        &self,
+        key: &DraftKey,
+        f: impl FnOnce(&mut OrderDraft) + Send,
+    ) -> Option<OrderDraft> {
+        let _lock = self.lock.lock().await;
+        // loads the draft from the database if needed
+        self.load(key).await?;
+        let updated_at = SystemTime::now();
+        let draft = {
+            let mut storage = self.storage.write();
+            storage.get_mut(key).map(|entry| {
+                f(&mut entry.draft);
+                entry.updated_at = updated_at;
+                entry.draft.clone()
+            })
+        }?;
+        self.persist(*key, draft.clone(), updated_at).await;
+        Some(draft)
+    }
+
+    /// Unexpired drafts cached in memory, the ones not touched since a restart are left in the
+    /// database
+    pub(crate) fn entries(&self) -> Vec<(DraftKey, OrderDraft)> {
+        self.storage
+            .read()
+            .iter()
+            .filter(|(_, entry)| !self.is_expired(entry.updated_at))
+            .map(|(key, entry)| (*key, entry.draft.clone()))
+            .collect()
+    }
+
+    pub(crate) async fn remove(&self, key: &DraftKey) -> Option<OrderDraft> {
+        let _lock = self.lock.lock().await;
+        if let Some(database) = &self.database {
+            let key = *key;
+            let deleted = blocking(database, move |database| database.delete_draft(&key)).await;
+            if let Err(err) = deleted {
+                log::error!("Unable to delete the order draft of {}: {}", key, err);
+            }
+        }
+        let mut storage = self.storage.write();
+        storage.remove(key).map(|entry| entry.draft)
+    }
+
+    /// Removes the drafts older than the TTL, returns the number of evicted drafts
+    pub(crate) async fn evict_expired(&self) -> anyhow::Result<usize> {
+        let _lock = self.lock.lock().await;
+        let evicted = {
+            let mut storage = self.storage.write();
+            let before = storage.len();
+            storage.retain(|_, entry| !self.is_expired(entry.updated_at));
+            before - storage.len()
+        };
+        match &self.database {
+            // drafts not loaded since a restart are only in the database
+            Some(database) => {
+                let expired_before =
+                    unix_timestamp(SystemTime::now()).saturating_sub(self.ttl.as_secs());
+                blocking(database, move |database| {
+                    database.delete_drafts_before(expired_before)
+                })
+                .await
+            }
+            None => Ok(evicted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: DraftKey = DraftKey {
+        chat_id: ChatId(-100),
+        user_id: UserId(1),
+    };
+
+    fn database() -> Option<Arc<SqliteDatabase>> {
+        Some(Arc::new(SqliteDatabase::open(":memory:").unwrap()))
+    }
+
+    fn draft() -> OrderDraft {
+        OrderDraft {
+            side: OrderSide::Buy,
+            session: 7,
+            chain_id: 1,
+            wallet: None,
+            private_tx: true,
+            rebate: false,
+            buy_token: Some(Address::repeat_byte(0xa1)),
+            sell_token: None,
+            receive_token: None,
+            amount: Some(0.5),
+            sell_percentage: None,
+            swap: SwapSettings::default(),
+            limit: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reloads_the_persisted_drafts_after_a_restart() {
+        let database = database();
+        let hour = Duration::from_secs(3_600);
+        let drafts = OrderDraftStorage::new(database.clone(), hour);
+        drafts.insert(KEY, draft()).await;
+
+        // a new storage on the same database is a restart
+        let restarted = OrderDraftStorage::new(database.clone(), hour);
+        assert!(restarted.entries().is_empty());
+        let loaded = restarted.get(&KEY).await.unwrap();
+        assert_eq!(loaded.fingerprint(), draft().fingerprint());
+        let updated = restarted
+            .update(&KEY, |draft| draft.amount = Some(2.0))
+            .await
+            .unwrap();
+
+        let restarted = OrderDraftStorage::new(database.clone(), hour);
+        let loaded = restarted.get(&KEY).await.unwrap();
+        assert_eq!(loaded.amount, Some(2.0));
+        assert_eq!(loaded.fingerprint(), updated.fingerprint());
+
+        restarted.remove(&KEY).await.unwrap();
+        assert!(OrderDraftStorage::new(database, hour)
+            .get(&KEY)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn forgets_the_drafts_older_than_the_ttl() {
+        let drafts = OrderDraftStorage::new(None, Duration::ZERO);
+        drafts.insert(KEY, draft()).await;
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(drafts.entries().is_empty());
+        assert_eq!(drafts.evict_expired().await.unwrap(), 1);
+        assert!(drafts.get(&KEY).await.is_none());
+        assert!(drafts.update(&KEY, |_| {}).await.is_none());
+
+        // the persisted ones too
+        let database = database();
+        OrderDraftStorage::new(database.clone(), Duration::from_secs(3_600))
+            .insert(KEY, draft())
+            .await;
+        std::thread::sleep(Duration::from_millis(5));
+        let expired = OrderDraftStorage::new(database, Duration::ZERO);
+        assert!(expired.get(&KEY).await.is_none());
+    }
+
+    #[test]
+    fn derives_a_dialogue_id_per_user_of_a_chat() {
+        let other = DraftKey {
+            user_id: UserId(2),
+            ..KEY
+        };
+        assert_eq!(KEY.dialogue_id(), KEY.dialogue_id());
+        assert_ne!(KEY.dialogue_id(), other.dialogue_id());
+    }
+}
@@ -0,0 +1,745 @@
+use crate::storages::sqlite::{blocking, SqliteDatabase};
+use crate::storages::{DraftKey, OrderDraft, OrderSide, SwapSettings};
+use ethers::types::Address;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use teloxide::types::{ChatId, UserId};
+
+/// What an order does once its trigger is crossed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum LimitKind {
+    /// Buys at or below the trigger, or sells at or above it
+    #[default]
+    Limit,
+    /// Sells at or below the trigger
+    StopLoss,
+    /// Sells once the price falls the trail percentage below its highest since placed
+    TrailingStop,
+}
+
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Limit => write!(f, "Limit"),
+            Self::StopLoss => write!(f, "Stop Loss"),
+            Self::TrailingStop => write!(f, "Trailing Stop"),
+        }
+    }
+}
+
+/// What the trigger value of an order is expressed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum TriggerUnit {
+    /// Price of the position token in the other token of the order
+    #[default]
+    Price,
+    /// Percentage from the entry price: the average price the position was bought at, or the
+    /// current price for a buy
+    EntryPercent,
+    /// Market cap of the position token in the other token of the order
+    MarketCap,
+    /// Percentage below the highest price since the order was placed, trailing stops only
+    TrailPercent,
+}
+
+/// Longest an order can stay open before it expires
+pub(crate) const MAX_EXPIRY_SECS: u64 = 365 * 86_400;
+
+/// Trigger of a limit order being edited in the buy or sell menu
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct LimitTrigger {
+    pub(crate) kind: LimitKind,
+    pub(crate) unit: TriggerUnit,
+    /// In `unit`, None until entered
+    pub(crate) value: Option<f64>,
+    /// How long the order stays open, None keeps it open until it triggers
+    pub(crate) expiry_secs: Option<u64>,
+}
+
+impl LimitTrigger {
+    /// Trailing stops trail by a percentage, the other kinds can't
+    pub(crate) fn set_kind(&mut self, kind: LimitKind) {
+        self.kind = kind;
+        match kind {
+            LimitKind::TrailingStop => self.set_unit(TriggerUnit::TrailPercent),
+            _ if self.unit == TriggerUnit::TrailPercent => self.set_unit(TriggerUnit::Price),
+            _ => {}
+        }
+    }
+
+    /// The value is cleared, it doesn't mean the same in another unit
+    pub(crate) fn set_unit(&mut self, unit: TriggerUnit) {
+        if self.unit != unit {
+            self.unit = unit;
+            self.value = None;
+        }
+    }
+
+    /// Timestamp the order expires at when placed or edited at `now`, the expiry is capped at
+    /// [MAX_EXPIRY_SECS]
+    pub(crate) fn expires_at(&self, now: i64) -> Option<i64> {
+        self.expiry_secs
+            .map(|secs| now.saturating_add(secs.min(MAX_EXPIRY_SECS) as i64))
+    }
+}
+
+/// Market data the trigger of a new order is resolved from
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TriggerMarket {
+    /// Current price of the position token
+    pub(crate) price: f64,
+    /// Average price the position was bought at, when known
+    pub(crate) entry_price: Option<f64>,
+    /// Total supply of the position token in whole tokens, market cap triggers only
+    pub(crate) supply: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum LimitOrderStatus {
+    /// Watched on every new block
+    Open,
+    /// Crossed its trigger, its swap is being sent
+    Triggered,
+    /// Its swap was broadcast, the outcome is in the trade journal
+    Executed,
+    /// Its swap couldn't be sent
+    Failed,
+    Expired,
+    Cancelled,
+}
+
+impl LimitOrderStatus {
+    /// Name of the status in the database
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Triggered => "triggered",
+            Self::Executed => "executed",
+            Self::Failed => "failed",
+            Self::Expired => "expired",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for LimitOrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open => write!(f, "Open"),
+            Self::Triggered => write!(f, "Triggered"),
+            Self::Executed => write!(f, "Executed"),
+            Self::Failed => write!(f, "Failed"),
+            Self::Expired => write!(f, "Expired"),
+            Self::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+/// An order swapped by the watcher once the quote of the new blocks crosses its trigger. Prices
+/// are of the position token, the token bought or sold, in the other token of the order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct LimitOrder {
+    /// Assigned by the [OrderBook]
+    pub(crate) id: u64,
+    pub(crate) user_id: UserId,
+    /// Chat the user is notified in
+    pub(crate) chat_id: ChatId,
+    pub(crate) side: OrderSide,
+    pub(crate) kind: LimitKind,
+    pub(crate) chain_id: u64,
+    /// Address of the wallet the swap is sent from, the order doesn't run once the user deletes
+    /// it
+    pub(crate) wallet: Address,
+    pub(crate) private_tx: bool,
+    pub(crate) rebate: bool,
+    /// Token paid
+    pub(crate) token_in: Address,
+    /// Token received
+    pub(crate) token_out: Address,
+    /// Amount of `token_in` paid, mutually exclusive with `sell_percentage`
+    pub(crate) amount: Option<f64>,
+    /// Percentage of the `token_in` balance sold, sell orders only
+    pub(crate) sell_percentage: Option<f64>,
+    pub(crate) unit: TriggerUnit,
+    /// Trigger value as entered, in `unit`
+    pub(crate) value: f64,
+    /// Price the percentage triggers are relative to
+    pub(crate) entry_price: Option<f64>,
+    /// Price crossed by the trigger, a trailing stop raises it along with `peak_price`
+    pub(crate) trigger_price: f64,
+    /// Highest price since a trailing stop was placed
+    pub(crate) peak_price: Option<f64>,
+    pub(crate) swap: SwapSettings,
+    pub(crate) status: LimitOrderStatus,
+    /// Journal entry of the swap once triggered
+    pub(crate) trade_id: Option<u64>,
+    /// Unix timestamp
+    pub(crate) created_at: i64,
+    /// Unix timestamp after which the order expires, None keeps it open until it triggers
+    pub(crate) expires_at: Option<i64>,
+}
+
+impl LimitOrder {
+    /// Validates the limit draft of the user, its trigger is resolved by [LimitOrder::arm]
+    pub(crate) fn from_draft(key: DraftKey, draft: &OrderDraft) -> anyhow::Result<Self> {
+        let trigger = draft
+            .limit
+            .ok_or_else(|| anyhow::anyhow!("Not a limit order"))?;
+        let value = trigger
+            .value
+            .filter(|value| *value > 0.0 && value.is_finite())
+            .ok_or_else(|| anyhow::anyhow!("No trigger found"))?;
+        let (token_in, token_out) = match draft.side {
+            // the buy amount is paid in the receive token
+            OrderSide::Buy => {
+                if trigger.kind != LimitKind::Limit {
+                    return Err(anyhow::anyhow!(
+                        "Only sell orders can be a {}",
+                        trigger.kind
+                    ));
+                }
+                (draft.receive_token, draft.buy_token)
+            }
+            OrderSide::Sell => (draft.sell_token, draft.receive_token),
+        };
+        let (amount, sell_percentage) = match (draft.side, draft.amount, draft.sell_percentage) {
+            (_, Some(amount), None) => (Some(amount), None),
+            (OrderSide::Sell, None, Some(percentage)) => (None, Some(percentage)),
+            (OrderSide::Sell, Some(_), Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "Only one of sell amount or percentage can be set"
+                ))
+            }
+            _ => return Err(anyhow::anyhow!("No amount found")),
+        };
+        let created_at = chrono::Utc::now().timestamp();
+        Ok(Self {
+            id: 0,
+            user_id: key.user_id,
+            chat_id: key.chat_id,
+            side: draft.side,
+            kind: trigger.kind,
+            chain_id: draft.chain_id,
+            wallet: draft
+                .wallet
+                .ok_or_else(|| anyhow::anyhow!("No wallet found"))?,
+            private_tx: draft.private_tx,
+            rebate: draft.rebate,
+            token_in: token_in.ok_or_else(|| anyhow::anyhow!("No token to pay found"))?,
+            token_out: token_out.ok_or_else(|| anyhow::anyhow!("No token to receive found"))?,
+            amount,
+            sell_percentage,
+            unit: trigger.unit,
+            value,
+            entry_price: None,
+            trigger_price: 0.0,
+            peak_price: None,
+            swap: draft.swap,
+            status: LimitOrderStatus::Open,
+            trade_id: None,
+            created_at,
+            expires_at: trigger.expires_at(created_at),
+        })
+    }
+
+    /// Resolves the trigger price from the market at placement, or once its value is edited
+    pub(crate) fn arm(&mut self, market: TriggerMarket) -> anyhow::Result<()> {
+        let below = |base: f64| {
+            if self.value >= 100.0 {
+                return Err(anyhow::anyhow!("The percentage must be below 100"));
+            }
+            Ok(base * (1.0 - self.value / 100.0))
+        };
+        self.trigger_price = match (self.side, self.kind, self.unit) {
+            // an edited trailing stop keeps the peak it reached
+            (_, LimitKind::TrailingStop, TriggerUnit::TrailPercent) => {
+                let peak = self.peak_price.unwrap_or(market.price).max(market.price);
+                self.peak_price = Some(peak);
+                below(peak)?
+            }
+            (_, LimitKind::TrailingStop, _) | (_, _, TriggerUnit::TrailPercent) => {
+                return Err(anyhow::anyhow!("Only trailing stops trail by a percentage"))
+            }
+            (_, _, TriggerUnit::Price) => self.value,
+            (_, _, TriggerUnit::MarketCap) => {
+                let supply = market
+                    .supply
+                    .filter(|supply| *supply > 0.0)
+                    .ok_or_else(|| anyhow::anyhow!("The total supply of the token is unknown"))?;
+                self.value / supply
+            }
+            // a buy is relative to the current price
+            (OrderSide::Buy, _, TriggerUnit::EntryPercent) => {
+                self.entry_price = Some(market.price);
+                below(market.price)?
+            }
+            (OrderSide::Sell, kind, TriggerUnit::EntryPercent) => {
+                let entry = market.entry_price.unwrap_or(market.price);
+                self.entry_price = Some(entry);
+                match kind {
+                    LimitKind::Limit => entry * (1.0 + self.value / 100.0),
+                    _ => below(entry)?,
+                }
+            }
+        };
+        Ok(())
+    }
+
+    pub(crate) fn key(&self) -> DraftKey {
+        DraftKey {
+            chat_id: self.chat_id,
+            user_id: self.user_id,
+        }
+    }
+
+    /// The token bought or sold, prices are of this token
+    pub(crate) fn position_token(&self) -> Address {
+        match self.side {
+            OrderSide::Buy => self.token_out,
+            OrderSide::Sell => self.token_in,
+        }
+    }
+
+    pub(crate) fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Whether the order triggers when the price rises to the trigger, rather than falls to it
+    pub(crate) fn triggers_above(&self) -> bool {
+        self.side == OrderSide::Sell && self.kind == LimitKind::Limit
+    }
+
+    /// Whether the quoted price crosses the trigger
+    pub(crate) fn is_triggered(&self, price: f64) -> bool {
+        match self.triggers_above() {
+            true => price >= self.trigger_price,
+            false => price <= self.trigger_price,
+        }
+    }
+
+    /// Raises the peak and the trigger of a trailing stop along with the price, returns whether
+    /// they moved
+    pub(crate) fn follow(&mut self, price: f64) -> bool {
+        match self.peak_price {
+            Some(peak) if self.kind == LimitKind::TrailingStop && price > peak => {
+                self.peak_price = Some(price);
+                self.trigger_price = price * (1.0 - self.value / 100.0);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum OrderBookBackend {
+    Memory(RwLock<Vec<LimitOrder>>),
+    Sqlite(Arc<SqliteDatabase>),
+}
+
+/// Limit orders of every user, the open ones are watched in the background
+#[derive(Debug)]
+pub(crate) struct OrderBook {
+    backend: OrderBookBackend,
+}
+
+impl OrderBook {
+    pub(crate) fn in_memory() -> Self {
+        Self {
+            backend: OrderBookBackend::Memory(RwLock::new(Vec::new())),
+        }
+    }
+
+    pub(crate) fn sqlite(database: Arc<SqliteDatabase>) -> Self {
+        Self {
+            backend: OrderBookBackend::Sqlite(database),
+        }
+    }
+
+    /// Places a new order, the `id` of `order` is ignored and the assigned one returned
+    pub(crate) async fn place(&self, order: LimitOrder) -> anyhow::Result<u64> {
+        match &self.backend {
+            OrderBookBackend::Memory(orders) => {
+                let mut orders = orders.write();
+                let id = orders.len() as u64 + 1;
+                orders.push(LimitOrder { id, ..order });
+                Ok(id)
+            }
+            OrderBookBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.insert_limit_order(&order)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Applies `f` to the stored order and returns the updated order
+    pub(crate) async fn update(
+        &self,
+        id: u64,
+        f: impl FnOnce(&mut LimitOrder) + Send + 'static,
+    ) -> anyhow::Result<LimitOrder> {
+        match &self.backend {
+            OrderBookBackend::Memory(orders) => {
+                let mut orders = orders.write();
+                let order = orders
+                    .iter_mut()
+                    .find(|order| order.id == id)
+                    .ok_or_else(|| anyhow::anyhow!("Limit order {} not found", id))?;
+                f(order);
+                Ok(order.clone())
+            }
+            OrderBookBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    let mut order = database
+                        .load_limit_order(id)?
+                        .ok_or_else(|| anyhow::anyhow!("Limit order {} not found", id))?;
+                    f(&mut order);
+                    database.save_limit_order(&order)?;
+                    Ok(order)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Open orders of every user on the chain, oldest first
+    pub(crate) async fn open_orders(&self, chain_id: u64) -> anyhow::Result<Vec<LimitOrder>> {
+        match &self.backend {
+            OrderBookBackend::Memory(orders) => Ok(orders
+                .read()
+                .iter()
+                .filter(|order| {
+                    order.chain_id == chain_id && order.status == LimitOrderStatus::Open
+                })
+                .cloned()
+                .collect()),
+            OrderBookBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.load_open_limit_orders(chain_id)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Open orders of the user on every chain, oldest first
+    pub(crate) async fn user_open_orders(
+        &self,
+        user_id: UserId,
+    ) -> anyhow::Result<Vec<LimitOrder>> {
+        match &self.backend {
+            OrderBookBackend::Memory(orders) => Ok(orders
+                .read()
+                .iter()
+                .filter(|order| order.user_id == user_id && order.status == LimitOrderStatus::Open)
+                .cloned()
+                .collect()),
+            OrderBookBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.load_user_open_limit_orders(user_id)
+                })
+                .await
+            }
+        }
+    }
+
+    /// The order of the user, None when it belongs to another user
+    pub(crate) async fn get(&self, user_id: UserId, id: u64) -> anyhow::Result<Option<LimitOrder>> {
+        let order = match &self.backend {
+            OrderBookBackend::Memory(orders) => {
+                orders.read().iter().find(|order| order.id == id).cloned()
+            }
+            OrderBookBackend::Sqlite(database) => {
+                blocking(database, move |database| database.load_limit_order(id)).await?
+            }
+        };
+        Ok(order.filter(|order| order.user_id == user_id))
+    }
+
+    /// Applies `f` to the open order of the user, None when it isn't open anymore
+    pub(crate) async fn edit(
+        &self,
+        user_id: UserId,
+        id: u64,
+        f: impl FnOnce(&mut LimitOrder) + Send + 'static,
+    ) -> anyhow::Result<Option<LimitOrder>> {
+        let edited = self
+            .update(id, move |order| {
+                if order.user_id == user_id && order.status == LimitOrderStatus::Open {
+                    f(order)
+                }
+            })
+            .await?;
+        Ok(Some(edited)
+            .filter(|order| order.user_id == user_id && order.status == LimitOrderStatus::Open))
+    }
+
+    /// Cancels the open order of the user, None when it isn't open anymore
+    pub(crate) async fn cancel(
+        &self,
+        user_id: UserId,
+        id: u64,
+    ) -> anyhow::Result<Option<LimitOrder>> {
+        let cancelled = self
+            .update(id, move |order| {
+                if order.user_id == user_id && order.status == LimitOrderStatus::Open {
+                    order.status = LimitOrderStatus::Cancelled;
+                }
+            })
+            .await?;
+        Ok(Some(cancelled).filter(|order| {
+            order.user_id == user_id && order.status == LimitOrderStatus::Cancelled
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(side: OrderSide, kind: LimitKind, unit: TriggerUnit, value: f64) -> LimitOrder {
+        LimitOrder {
+            id: 1,
+            user_id: UserId(1),
+            chat_id: ChatId(1),
+            side,
+            kind,
+            chain_id: 1,
+            wallet: Address::repeat_byte(1),
+            private_tx: false,
+            rebate: false,
+            token_in: Address::repeat_byte(2),
+            token_out: Address::repeat_byte(3),
+            amount: Some(1.0),
+            sell_percentage: None,
+            unit,
+            value,
+            entry_price: None,
+            trigger_price: 0.0,
+            peak_price: None,
+            swap: SwapSettings::default(),
+            status: LimitOrderStatus::Open,
+            trade_id: None,
+            created_at: 0,
+            expires_at: None,
+        }
+    }
+
+    fn market(price: f64) -> TriggerMarket {
+        TriggerMarket {
+            price,
+            entry_price: None,
+            supply: None,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} isn't {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn arms_price_and_market_cap_triggers() {
+        let mut price = order(OrderSide::Buy, LimitKind::Limit, TriggerUnit::Price, 1.5);
+        price.arm(market(2.0)).unwrap();
+        assert_close(price.trigger_price, 1.5);
+
+        let mut cap = order(
+            OrderSide::Sell,
+            LimitKind::StopLoss,
+            TriggerUnit::MarketCap,
+            1_000_000.0,
+        );
+        assert!(cap.arm(market(2.0)).is_err());
+        cap.arm(TriggerMarket {
+            supply: Some(500_000.0),
+            ..market(3.0)
+        })
+        .unwrap();
+        assert_close(cap.trigger_price, 2.0);
+    }
+
+    #[test]
+    fn arms_entry_percentage_triggers() {
+        // a buy is relative to the current price
+        let mut buy = order(
+            OrderSide::Buy,
+            LimitKind::Limit,
+            TriggerUnit::EntryPercent,
+            10.0,
+        );
+        buy.arm(market(2.0)).unwrap();
+        assert_eq!(buy.entry_price, Some(2.0));
+        assert_close(buy.trigger_price, 1.8);
+
+        let with_entry = TriggerMarket {
+            entry_price: Some(1.0),
+            ..market(2.0)
+        };
+        let mut take_profit = order(
+            OrderSide::Sell,
+            LimitKind::Limit,
+            TriggerUnit::EntryPercent,
+            50.0,
+        );
+        take_profit.arm(with_entry).unwrap();
+        assert_close(take_profit.trigger_price, 1.5);
+
+        let mut stop_loss = order(
+            OrderSide::Sell,
+            LimitKind::StopLoss,
+            TriggerUnit::EntryPercent,
+            20.0,
+        );
+        stop_loss.arm(with_entry).unwrap();
+        assert_eq!(stop_loss.entry_price, Some(1.0));
+        assert_close(stop_loss.trigger_price, 0.8);
+
+        // without buys in the journal the entry is the current price
+        stop_loss.arm(market(2.0)).unwrap();
+        assert_eq!(stop_loss.entry_price, Some(2.0));
+        assert_close(stop_loss.trigger_price, 1.6);
+
+        let mut total_loss = order(
+            OrderSide::Sell,
+            LimitKind::StopLoss,
+            TriggerUnit::EntryPercent,
+            100.0,
+        );
+        assert!(total_loss.arm(with_entry).is_err());
+    }
+
+    #[test]
+    fn arms_trailing_stops_from_their_peak() {
+        let mut trailing = order(
+            OrderSide::Sell,
+            LimitKind::TrailingStop,
+            TriggerUnit::TrailPercent,
+            10.0,
+        );
+        trailing.arm(market(2.0)).unwrap();
+        assert_eq!(trailing.peak_price, Some(2.0));
+        assert_close(trailing.trigger_price, 1.8);
+
+        // an edited trailing stop keeps the peak it reached
+        trailing.value = 20.0;
+        trailing.arm(market(1.9)).unwrap();
+        assert_eq!(trailing.peak_price, Some(2.0));
+        assert_close(trailing.trigger_price, 1.6);
+
+        let mut by_price = order(
+            OrderSide::Sell,
+            LimitKind::TrailingStop,
+            TriggerUnit::Price,
+            1.0,
+        );
+        assert!(by_price.arm(market(2.0)).is_err());
+        let mut not_trailing = order(
+            OrderSide::Sell,
+            LimitKind::StopLoss,
+            TriggerUnit::TrailPercent,
+            10.0,
+        );
+        assert!(not_trailing.arm(market(2.0)).is_err());
+    }
+
+    #[test]
+    fn follows_the_peak_of_trailing_stops() {
+        let mut trailing = order(
+            OrderSide::Sell,
+            LimitKind::TrailingStop,
+            TriggerUnit::TrailPercent,
+            10.0,
+        );
+        trailing.arm(market(2.0)).unwrap();
+
+        assert!(trailing.follow(2.5));
+        assert_eq!(trailing.peak_price, Some(2.5));
+        assert_close(trailing.trigger_price, 2.25);
+
+        // a lower or equal price leaves the peak and trigger alone
+        for price in [2.5, 2.4, 1.0] {
+            assert!(!trailing.follow(price));
+            assert_eq!(trailing.peak_price, Some(2.5));
+            assert_close(trailing.trigger_price, 2.25);
+        }
+
+        let mut stop_loss = order(
+            OrderSide::Sell,
+            LimitKind::StopLoss,
+            TriggerUnit::Price,
+            1.0,
+        );
+        stop_loss.arm(market(2.0)).unwrap();
+        assert!(!stop_loss.follow(3.0));
+        assert_eq!(stop_loss.trigger_price, 1.0);
+    }
+
+    #[test]
+    fn triggers_at_the_boundary_prices() {
+        let below = |price: f64| price - 1e-9;
+        let above = |price: f64| price + 1e-9;
+
+        // a limit sell triggers once the price rises to its trigger
+        let mut limit_sell = order(OrderSide::Sell, LimitKind::Limit, TriggerUnit::Price, 2.0);
+        limit_sell.arm(market(1.0)).unwrap();
+        assert!(limit_sell.triggers_above());
+        assert!(!limit_sell.is_triggered(below(2.0)));
+        assert!(limit_sell.is_triggered(2.0));
+        assert!(limit_sell.is_triggered(above(2.0)));
+
+        // the other kinds trigger once the price falls to their trigger
+        let mut limit_buy = order(OrderSide::Buy, LimitKind::Limit, TriggerUnit::Price, 1.0);
+        let mut stop_loss = order(
+            OrderSide::Sell,
+            LimitKind::StopLoss,
+            TriggerUnit::Price,
+            1.0,
+        );
+        let mut trailing = order(
+            OrderSide::Sell,
+            LimitKind::TrailingStop,
+            TriggerUnit::TrailPercent,
+            50.0,
+        );
+        for order in [&mut limit_buy, &mut stop_loss, &mut trailing] {
+            order.arm(market(2.0)).unwrap();
+            assert!(!order.triggers_above());
+            assert_close(order.trigger_price, 1.0);
+            assert!(order.is_triggered(below(order.trigger_price)));
+            assert!(order.is_triggered(order.trigger_price));
+            assert!(!order.is_triggered(above(order.trigger_price)));
+        }
+
+        // a trailing stop triggers relative to its latest peak
+        trailing.follow(4.0);
+        assert!(trailing.is_triggered(2.0));
+        assert!(!trailing.is_triggered(above(2.0)));
+    }
+
+    #[test]
+    fn expires_at_its_expiry() {
+        let mut order = order(OrderSide::Buy, LimitKind::Limit, TriggerUnit::Price, 1.0);
+        assert!(!order.is_expired(i64::MAX));
+
+        order.expires_at = Some(100);
+        assert!(!order.is_expired(99));
+        assert!(order.is_expired(100));
+        assert!(order.is_expired(101));
+
+        let trigger = LimitTrigger {
+            expiry_secs: Some(3_600),
+            ..LimitTrigger::default()
+        };
+        assert_eq!(trigger.expires_at(1_000), Some(4_600));
+        assert_eq!(LimitTrigger::default().expires_at(1_000), None);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::{GasMode, GasSettings, SwapConfig};
+use crate::storages::sqlite::{blocking, SqliteDatabase};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use teloxide::types::UserId;
+
+/// Highest slippage a user can set, in basis points
+pub(crate) const MAX_SLIPPAGE_BPS: u64 = 5_000;
+/// Range of the deadline a user can set, in seconds
+pub(crate) const MIN_DEADLINE_SECS: u64 = 30;
+pub(crate) const MAX_DEADLINE_SECS: u64 = 3_600;
+
+/// Swap parameters of an order, the unset ones fall back to the bot's [SwapConfig]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SwapSettings {
+    pub(crate) slippage_bps: Option<u64>,
+    pub(crate) deadline_secs: Option<u64>,
+    pub(crate) gas: GasSettings,
+}
+
+impl SwapSettings {
+    /// The bot's config overridden with these settings
+    pub(crate) fn apply(&self, config: &SwapConfig) -> SwapConfig {
+        SwapConfig {
+            slippage_bps: self.slippage_bps.unwrap_or(config.slippage_bps),
+            deadline_secs: self.deadline_secs.unwrap_or(config.deadline_secs),
+            gas: self.gas,
+        }
+    }
+
+    /// Moves to the next gas mode, the custom mode is skipped until its fees are set
+    pub(crate) fn cycle_gas_mode(&mut self) {
+        let has_custom_fees =
+            self.gas.max_fee_gwei.is_some() && self.gas.priority_fee_gwei.is_some();
+        let position = GasMode::ALL
+            .iter()
+            .position(|mode| *mode == self.gas.mode)
+            .unwrap_or_default();
+        self.gas.mode = GasMode::ALL
+            .iter()
+            .cycle()
+            .skip(position + 1)
+            .find(|mode| **mode != GasMode::Custom || has_custom_fees)
+            .copied()
+            .unwrap_or_default();
+    }
+}
+
+/// Defaults of a user, new buy and sell menus start from them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct UserSettings {
+    pub(crate) swap: SwapSettings,
+    /// Amount new buy menus start with
+    pub(crate) buy_amount: Option<f64>,
+}
+
+#[derive(Debug)]
+enum SettingsBackend {
+    Memory(RwLock<HashMap<UserId, UserSettings>>),
+    Sqlite(Arc<SqliteDatabase>),
+}
+
+/// Keeps the settings of every user, users who never changed them get the defaults
+#[derive(Debug)]
+pub(crate) struct SettingsStore {
+    backend: SettingsBackend,
+}
+
+impl SettingsStore {
+    pub(crate) fn in_memory() -> Self {
+        Self {
+            backend: SettingsBackend::Memory(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) fn sqlite(database: Arc<SqliteDatabase>) -> Self {
+        Self {
+            backend: SettingsBackend::Sqlite(database),
+        }
+    }
+
+    pub(crate) async fn get(&self, user_id: UserId) -> anyhow::Result<UserSettings> {
+        match &self.backend {
+            SettingsBackend::Memory(settings) => {
+                Ok(settings.read().get(&user_id).copied().unwrap_or_default())
+            }
+            SettingsBackend::Sqlite(database) => {
+                Ok(
+                    blocking(database, move |database| database.load_settings(user_id))
+                        .await?
+                        .unwrap_or_default(),
+                )
+            }
+        }
+    }
+
+    /// Applies `f` to the user's settings and returns the updated settings
+    pub(crate) async fn update(
+        &self,
+        user_id: UserId,
+        f: impl FnOnce(&mut UserSettings) + Send + 'static,
+    ) -> anyhow::Result<UserSettings> {
+        match &self.backend {
+            SettingsBackend::Memory(settings) => {
+                let mut settings = settings.write();
+                let entry = settings.entry(user_id).or_default();
+                f(entry);
+                Ok(*entry)
+            }
+            SettingsBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.update_settings(user_id, f)
+                })
+                .await
+            }
+        }
+    }
+}
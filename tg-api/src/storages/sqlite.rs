@@ -0,0 +1,763 @@
+use crate::storages::{
+    DraftKey, LimitOrder, LimitOrderStatus, OrderDraft, TgMessage, TokenEntry, TradeRecord,
+    UserSettings,
+};
+use ethers::types::Address;
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use teloxide::dispatching::dialogue::Storage;
+use teloxide::types::{ChatId, Message, UserId};
+
+/// A single SQLite connection shared by every persistent storage
+pub(crate) struct SqliteDatabase {
+    connection: Mutex<Connection>,
+}
+
+impl fmt::Debug for SqliteDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqliteDatabase").finish_non_exhaustive()
+    }
+}
+
+impl SqliteDatabase {
+    /// Opens (or creates) the database at `path` and creates the missing tables
+    pub(crate) fn open(path: &str) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS menu_messages (
+                 menu TEXT NOT NULL,
+                 key TEXT NOT NULL,
+                 message TEXT NOT NULL,
+                 stored_at INTEGER NOT NULL,
+                 PRIMARY KEY (menu, key)
+             );
+             CREATE TABLE IF NOT EXISTS user_dialogues (
+                 dialogue_id INTEGER PRIMARY KEY,
+                 dialogue TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS trades (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 user_id INTEGER NOT NULL,
+                 record TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS trades_user_id ON trades (user_id, id);
+             CREATE TABLE IF NOT EXISTS order_drafts (
+                 chat_id INTEGER NOT NULL,
+                 user_id INTEGER NOT NULL,
+                 draft TEXT NOT NULL,
+                 updated_at INTEGER NOT NULL,
+                 PRIMARY KEY (chat_id, user_id)
+             );
+             CREATE TABLE IF NOT EXISTS user_tokens (
+                 user_id INTEGER NOT NULL,
+                 chain_id INTEGER NOT NULL,
+                 address TEXT NOT NULL,
+                 token TEXT NOT NULL,
+                 PRIMARY KEY (user_id, chain_id, address)
+             );
+             CREATE TABLE IF NOT EXISTS user_settings (
+                 user_id INTEGER PRIMARY KEY,
+                 settings TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS limit_orders (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 user_id INTEGER NOT NULL,
+                 chain_id INTEGER NOT NULL,
+                 status TEXT NOT NULL,
+                 record TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS limit_orders_status ON limit_orders (chain_id, status);
+             CREATE INDEX IF NOT EXISTS limit_orders_user_id ON limit_orders (user_id, id);",
+        )?;
+        log::info!("Opened sqlite database at {}", path);
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    pub(crate) fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&Connection) -> rusqlite::Result<T>,
+    ) -> rusqlite::Result<T> {
+        let connection = self.connection.lock();
+        f(&connection)
+    }
+
+    pub(crate) fn save_menu(
+        &self,
+        menu: &str,
+        key: &str,
+        message: &TgMessage,
+        stored_at: u64,
+    ) -> anyhow::Result<()> {
+        let message = serde_json::to_string(&*message.message)?;
+        self.with_connection(|connection| {
+            connection.execute(
+                "INSERT OR REPLACE INTO menu_messages (menu, key, message, stored_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![menu, key, message, stored_at as i64],
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Returns the stored message along with the unix timestamp it was stored at
+    pub(crate) fn load_menu(
+        &self,
+        menu: &str,
+        key: &str,
+    ) -> anyhow::Result<Option<(TgMessage, u64)>> {
+        let row: Option<(String, i64)> = self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT message, stored_at FROM menu_messages WHERE menu = ?1 AND key = ?2",
+                    params![menu, key],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+        })?;
+        row.map(|(message, stored_at)| {
+            let message: Message = serde_json::from_str(&message)?;
+            let message = TgMessage {
+                chat_id: message.chat.id,
+                message_id: message.id,
+                message: Arc::new(message),
+            };
+            Ok((message, stored_at.max(0) as u64))
+        })
+        .transpose()
+    }
+
+    /// Returns every stored message of the menu along with its key and the unix timestamp it was
+    /// stored at
+    pub(crate) fn load_menus(&self, menu: &str) -> anyhow::Result<Vec<(String, TgMessage, u64)>> {
+        let rows: Vec<(String, String, i64)> = self.with_connection(|connection| {
+            let mut statement = connection
+                .prepare("SELECT key, message, stored_at FROM menu_messages WHERE menu = ?1")?;
+            let rows = statement
+                .query_map(params![menu], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<rusqlite::Result<_>>();
+            rows
+        })?;
+        rows.into_iter()
+            .map(|(key, message, stored_at)| {
+                let message: Message = serde_json::from_str(&message)?;
+                let message = TgMessage {
+                    chat_id: message.chat.id,
+                    message_id: message.id,
+                    message: Arc::new(message),
+                };
+                Ok((key, message, stored_at.max(0) as u64))
+            })
+            .collect()
+    }
+
+    pub(crate) fn delete_menu(&self, menu: &str, key: &str) -> anyhow::Result<Option<TgMessage>> {
+        let message = self.load_menu(menu, key)?.map(|(message, _)| message);
+        self.with_connection(|connection| {
+            connection.execute(
+                "DELETE FROM menu_messages WHERE menu = ?1 AND key = ?2",
+                params![menu, key],
+            )
+        })?;
+        Ok(message)
+    }
+
+    pub(crate) fn delete_menus(&self, menu: &str) -> anyhow::Result<()> {
+        self.with_connection(|connection| {
+            connection.execute("DELETE FROM menu_messages WHERE menu = ?1", params![menu])
+        })?;
+        Ok(())
+    }
+
+    /// Deletes the menus stored before the given unix timestamp, returns the number of rows deleted
+    pub(crate) fn delete_menus_before(&self, stored_at: u64) -> anyhow::Result<usize> {
+        Ok(self.with_connection(|connection| {
+            connection.execute(
+                "DELETE FROM menu_messages WHERE stored_at < ?1",
+                params![stored_at as i64],
+            )
+        })?)
+    }
+
+    /// Inserts the trade and returns its id, the stored record carries the id
+    pub(crate) fn insert_trade(&self, trade: &TradeRecord) -> anyhow::Result<u64> {
+        let mut connection = self.connection.lock();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO trades (user_id, record) VALUES (?1, '')",
+            params![trade.user_id.0],
+        )?;
+        let id = transaction.last_insert_rowid() as u64;
+        let record = serde_json::to_string(&TradeRecord {
+            id,
+            ..trade.clone()
+        })?;
+        transaction.execute(
+            "UPDATE trades SET record = ?1 WHERE id = ?2",
+            params![record, id as i64],
+        )?;
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    pub(crate) fn save_trade(&self, trade: &TradeRecord) -> anyhow::Result<()> {
+        let record = serde_json::to_string(trade)?;
+        self.with_connection(|connection| {
+            connection.execute(
+                "UPDATE trades SET record = ?1 WHERE id = ?2",
+                params![record, trade.id as i64],
+            )
+        })?;
+        Ok(())
+    }
+
+    pub(crate) fn load_trade(&self, id: u64) -> anyhow::Result<Option<TradeRecord>> {
+        let record: Option<String> = self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT record FROM trades WHERE id = ?1",
+                    params![id as i64],
+                    |row| row.get(0),
+                )
+                .optional()
+        })?;
+        Ok(record
+            .map(|record| serde_json::from_str(&record))
+            .transpose()?)
+    }
+
+    pub(crate) fn count_trades(&self, user_id: UserId) -> anyhow::Result<usize> {
+        let count: i64 = self.with_connection(|connection| {
+            connection.query_row(
+                "SELECT COUNT(*) FROM trades WHERE user_id = ?1",
+                params![user_id.0],
+                |row| row.get(0),
+            )
+        })?;
+        Ok(count as usize)
+    }
+
+    /// Trades of the user, newest first
+    pub(crate) fn load_trades(
+        &self,
+        user_id: UserId,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<TradeRecord>> {
+        let records: Vec<String> = self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT record FROM trades WHERE user_id = ?1 ORDER BY id DESC LIMIT ?2 OFFSET ?3",
+            )?;
+            let records = statement
+                .query_map(params![user_id.0, limit as i64, offset as i64], |row| {
+                    row.get(0)
+                })?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(records)
+        })?;
+        records
+            .iter()
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
+    }
+
+    /// Trades of the user with the rebate toggle on, newest first
+    pub(crate) fn load_rebate_trades(&self, user_id: UserId) -> anyhow::Result<Vec<TradeRecord>> {
+        let records: Vec<String> = self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT record FROM trades WHERE user_id = ?1 AND json_extract(record, '$.rebate') = 1 ORDER BY id DESC",
+            )?;
+            let records = statement
+                .query_map(params![user_id.0], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(records)
+        })?;
+        records
+            .iter()
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
+    }
+
+    /// Inserts the limit order and returns its id, the stored record carries the id
+    pub(crate) fn insert_limit_order(&self, order: &LimitOrder) -> anyhow::Result<u64> {
+        let mut connection = self.connection.lock();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO limit_orders (user_id, chain_id, status, record) VALUES (?1, ?2, ?3, '')",
+            params![
+                order.user_id.0,
+                order.chain_id as i64,
+                order.status.as_str()
+            ],
+        )?;
+        let id = transaction.last_insert_rowid() as u64;
+        let record = serde_json::to_string(&LimitOrder {
+            id,
+            ..order.clone()
+        })?;
+        transaction.execute(
+            "UPDATE limit_orders SET record = ?1 WHERE id = ?2",
+            params![record, id as i64],
+        )?;
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    pub(crate) fn save_limit_order(&self, order: &LimitOrder) -> anyhow::Result<()> {
+        let record = serde_json::to_string(order)?;
+        self.with_connection(|connection| {
+            connection.execute(
+                "UPDATE limit_orders SET status = ?1, record = ?2 WHERE id = ?3",
+                params![order.status.as_str(), record, order.id as i64],
+            )
+        })?;
+        Ok(())
+    }
+
+    pub(crate) fn load_limit_order(&self, id: u64) -> anyhow::Result<Option<LimitOrder>> {
+        let record: Option<String> = self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT record FROM limit_orders WHERE id = ?1",
+                    params![id as i64],
+                    |row| row.get(0),
+                )
+                .optional()
+        })?;
+        record
+            .map(|record| Ok(serde_json::from_str(&record)?))
+            .transpose()
+    }
+
+    /// Open limit orders of every user on the chain, oldest first
+    pub(crate) fn load_open_limit_orders(&self, chain_id: u64) -> anyhow::Result<Vec<LimitOrder>> {
+        let records: Vec<String> = self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT record FROM limit_orders WHERE chain_id = ?1 AND status = ?2 ORDER BY id",
+            )?;
+            let records = statement
+                .query_map(
+                    params![chain_id as i64, LimitOrderStatus::Open.as_str()],
+                    |row| row.get(0),
+                )?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(records)
+        })?;
+        records
+            .iter()
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
+    }
+
+    /// Open limit orders of the user on every chain, oldest first
+    pub(crate) fn load_user_open_limit_orders(
+        &self,
+        user_id: UserId,
+    ) -> anyhow::Result<Vec<LimitOrder>> {
+        let records: Vec<String> = self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT record FROM limit_orders WHERE user_id = ?1 AND status = ?2 ORDER BY id",
+            )?;
+            let records = statement
+                .query_map(params![user_id.0, LimitOrderStatus::Open.as_str()], |row| {
+                    row.get(0)
+                })?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(records)
+        })?;
+        records
+            .iter()
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
+    }
+
+    /// Confirmed buys of the token by the user, oldest first
+    pub(crate) fn load_token_buys(
+        &self,
+        user_id: UserId,
+        token: Address,
+    ) -> anyhow::Result<Vec<TradeRecord>> {
+        let records: Vec<String> = self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT record FROM trades WHERE user_id = ?1 AND json_extract(record, '$.token_out') = ?2 AND json_extract(record, '$.side') = 'Buy' AND json_extract(record, '$.status') = 'Confirmed' ORDER BY id",
+            )?;
+            let records = statement
+                .query_map(params![user_id.0, format!("{:#x}", token)], |row| {
+                    row.get(0)
+                })?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(records)
+        })?;
+        records
+            .iter()
+            .map(|record| Ok(serde_json::from_str(record)?))
+            .collect()
+    }
+
+    pub(crate) fn save_draft(
+        &self,
+        key: &DraftKey,
+        draft: &OrderDraft,
+        updated_at: u64,
+    ) -> anyhow::Result<()> {
+        let draft = serde_json::to_string(draft)?;
+        self.with_connection(|connection| {
+            connection.execute(
+                "INSERT OR REPLACE INTO order_drafts (chat_id, user_id, draft, updated_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![key.chat_id.0, key.user_id.0, draft, updated_at as i64],
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Returns the stored draft along with the unix timestamp it was last updated at
+    pub(crate) fn load_draft(&self, key: &DraftKey) -> anyhow::Result<Option<(OrderDraft, u64)>> {
+        let row: Option<(String, i64)> = self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT draft, updated_at FROM order_drafts WHERE chat_id = ?1 AND user_id = ?2",
+                    params![key.chat_id.0, key.user_id.0],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+        })?;
+        row.map(|(draft, updated_at)| Ok((serde_json::from_str(&draft)?, updated_at.max(0) as u64)))
+            .transpose()
+    }
+
+    /// Deletes the drafts last updated before the unix timestamp, returns how many were deleted
+    pub(crate) fn delete_drafts_before(&self, updated_at: u64) -> anyhow::Result<usize> {
+        Ok(self.with_connection(|connection| {
+            connection.execute(
+                "DELETE FROM order_drafts WHERE updated_at < ?1",
+                params![updated_at as i64],
+            )
+        })?)
+    }
+
+    pub(crate) fn delete_draft(&self, key: &DraftKey) -> anyhow::Result<()> {
+        self.with_connection(|connection| {
+            connection.execute(
+                "DELETE FROM order_drafts WHERE chat_id = ?1 AND user_id = ?2",
+                params![key.chat_id.0, key.user_id.0],
+            )
+        })?;
+        Ok(())
+    }
+
+    pub(crate) fn save_user_token(
+        &self,
+        user_id: UserId,
+        token: &TokenEntry,
+    ) -> anyhow::Result<()> {
+        let entry = serde_json::to_string(token)?;
+        self.with_connection(|connection| {
+            connection.execute(
+                "INSERT OR REPLACE INTO user_tokens (user_id, chain_id, address, token)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    user_id.0,
+                    token.chain_id as i64,
+                    format!("{:#x}", token.address),
+                    entry
+                ],
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Tokens the user added on the chain, oldest first
+    pub(crate) fn load_user_tokens(
+        &self,
+        user_id: UserId,
+        chain_id: u64,
+    ) -> anyhow::Result<Vec<TokenEntry>> {
+        let tokens: Vec<String> = self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT token FROM user_tokens WHERE user_id = ?1 AND chain_id = ?2 ORDER BY rowid",
+            )?;
+            let tokens = statement
+                .query_map(params![user_id.0, chain_id as i64], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(tokens)
+        })?;
+        tokens
+            .iter()
+            .map(|token| Ok(serde_json::from_str(token)?))
+            .collect()
+    }
+
+    /// Applies `f` to the user's settings in a single transaction, so concurrent updates of the
+    /// same user don't overwrite each other
+    pub(crate) fn update_settings(
+        &self,
+        user_id: UserId,
+        f: impl FnOnce(&mut UserSettings),
+    ) -> anyhow::Result<UserSettings> {
+        let mut connection = self.connection.lock();
+        let transaction = connection.transaction()?;
+        let stored: Option<String> = transaction
+            .query_row(
+                "SELECT settings FROM user_settings WHERE user_id = ?1",
+                params![user_id.0],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let mut settings = match stored {
+            Some(settings) => serde_json::from_str(&settings)?,
+            None => UserSettings::default(),
+        };
+        f(&mut settings);
+        transaction.execute(
+            "INSERT OR REPLACE INTO user_settings (user_id, settings) VALUES (?1, ?2)",
+            params![user_id.0, serde_json::to_string(&settings)?],
+        )?;
+        transaction.commit()?;
+        Ok(settings)
+    }
+
+    pub(crate) fn load_settings(&self, user_id: UserId) -> anyhow::Result<Option<UserSettings>> {
+        let settings: Option<String> = self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT settings FROM user_settings WHERE user_id = ?1",
+                    params![user_id.0],
+                    |row| row.get(0),
+                )
+                .optional()
+        })?;
+        Ok(settings
+            .map(|settings| serde_json::from_str(&settings))
+            .transpose()?)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum SqliteStorageError {
+    Sqlite(rusqlite::Error),
+    Serde(serde_json::Error),
+    Task(tokio::task::JoinError),
+    DialogueNotFound,
+}
+
+impl fmt::Display for SqliteStorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sqlite(err) => write!(f, "Sqlite error: {}", err),
+            Self::Serde(err) => write!(f, "Dialogue serialization error: {}", err),
+            Self::Task(err) => write!(f, "Sqlite task error: {}", err),
+            Self::DialogueNotFound => write!(f, "Row not found"),
+        }
+    }
+}
+
+impl std::error::Error for SqliteStorageError {}
+
+impl From<rusqlite::Error> for SqliteStorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Sqlite(err)
+    }
+}
+
+impl From<serde_json::Error> for SqliteStorageError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Serde(err)
+    }
+}
+
+impl From<tokio::task::JoinError> for SqliteStorageError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Self::Task(err)
+    }
+}
+
+/// SQLite backed teloxide dialogue [Storage], dialogues are stored as JSON under the
+/// [DraftKey::dialogue_id] of their user
+pub(crate) struct SqliteDialogueStorage<D> {
+    database: Arc<SqliteDatabase>,
+    _dialogue: PhantomData<fn() -> D>,
+}
+
+impl<D> SqliteDialogueStorage<D> {
+    pub(crate) fn new(database: Arc<SqliteDatabase>) -> Arc<Self> {
+        Arc::new(Self {
+            database,
+            _dialogue: PhantomData,
+        })
+    }
+
+    /// Runs the query on the blocking thread pool, see [blocking]
+    async fn query<T, F>(&self, f: F) -> Result<T, SqliteStorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let database = self.database.clone();
+        Ok(tokio::task::spawn_blocking(move || database.with_connection(f)).await??)
+    }
+}
+
+impl<D> Storage<D> for SqliteDialogueStorage<D>
+where
+    D: Serialize + DeserializeOwned + Send + 'static,
+{
+    type Error = SqliteStorageError;
+
+    fn remove_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<(), Self::Error>>
+    where
+        D: Send + 'static,
+    {
+        Box::pin(async move {
+            let deleted = self
+                .query(move |connection| {
+                    connection.execute(
+                        "DELETE FROM user_dialogues WHERE dialogue_id = ?1",
+                        params![chat_id.0],
+                    )
+                })
+                .await?;
+            match deleted {
+                0 => Err(SqliteStorageError::DialogueNotFound),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+        dialogue: D,
+    ) -> BoxFuture<'static, Result<(), Self::Error>>
+    where
+        D: Send + 'static,
+    {
+        Box::pin(async move {
+            let dialogue = serde_json::to_string(&dialogue)?;
+            self.query(move |connection| {
+                connection.execute(
+                    "INSERT OR REPLACE INTO user_dialogues (dialogue_id, dialogue) VALUES (?1, ?2)",
+                    params![chat_id.0, dialogue],
+                )
+            })
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn get_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<Option<D>, Self::Error>> {
+        Box::pin(async move {
+            let dialogue: Option<String> = self
+                .query(move |connection| {
+                    connection
+                        .query_row(
+                            "SELECT dialogue FROM user_dialogues WHERE dialogue_id = ?1",
+                            params![chat_id.0],
+                            |row| row.get(0),
+                        )
+                        .optional()
+                })
+                .await?;
+            Ok(dialogue
+                .map(|dialogue| serde_json::from_str(&dialogue))
+                .transpose()?)
+        })
+    }
+}
+
+/// Runs a database call on the blocking thread pool, so the dispatcher is never stalled by
+/// the disk
+pub(crate) async fn blocking<T, F>(database: &Arc<SqliteDatabase>, f: F) -> anyhow::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&SqliteDatabase) -> anyhow::Result<T> + Send + 'static,
+{
+    let database = database.clone();
+    tokio::task::spawn_blocking(move || f(&database)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> Arc<SqliteDatabase> {
+        Arc::new(SqliteDatabase::open(":memory:").unwrap())
+    }
+
+    #[tokio::test]
+    async fn keeps_the_dialogues_of_two_users_of_a_chat_apart() {
+        let storage = SqliteDialogueStorage::<String>::new(database());
+        let (alice, bob) = (
+            DraftKey {
+                chat_id: ChatId(-100),
+                user_id: UserId(1),
+            },
+            DraftKey {
+                chat_id: ChatId(-100),
+                user_id: UserId(2),
+            },
+        );
+
+        storage
+            .clone()
+            .update_dialogue(alice.dialogue_id(), "amount".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .clone()
+                .get_dialogue(bob.dialogue_id())
+                .await
+                .unwrap(),
+            None
+        );
+
+        storage
+            .clone()
+            .update_dialogue(bob.dialogue_id(), "token".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .clone()
+                .get_dialogue(alice.dialogue_id())
+                .await
+                .unwrap(),
+            Some("amount".to_string())
+        );
+
+        storage
+            .clone()
+            .remove_dialogue(alice.dialogue_id())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .clone()
+                .get_dialogue(alice.dialogue_id())
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            storage.get_dialogue(bob.dialogue_id()).await.unwrap(),
+            Some("token".to_string())
+        );
+    }
+}
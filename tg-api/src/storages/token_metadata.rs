@@ -0,0 +1,158 @@
+use ethers::types::{Address, U256};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long the cached total supply of a token is used before it's queried again, mints and
+/// burns change it and the market cap triggers of the limit orders are computed from it
+pub(crate) const SUPPLY_TTL: Duration = Duration::from_secs(60);
+
+/// Number of tokens kept in the cache
+pub(crate) const MAX_CACHED_TOKENS: usize = 10_000;
+
+/// What an ERC-20 contract reports about itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TokenMetadata {
+    pub(crate) chain_id: u64,
+    pub(crate) address: Address,
+    pub(crate) name: String,
+    pub(crate) symbol: String,
+    pub(crate) decimals: u8,
+    /// None for the native token
+    pub(crate) total_supply: Option<U256>,
+}
+
+/// Cached metadata of a token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CachedMetadata {
+    Fresh(TokenMetadata),
+    /// The total supply is older than the TTL, the other fields are still valid
+    StaleSupply(TokenMetadata),
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    metadata: TokenMetadata,
+    supply_at: Instant,
+}
+
+/// Metadata of the tokens fetched so far. Name, symbol and decimals never change, the total
+/// supply is only used for [SUPPLY_TTL]. Once full, the token whose supply was fetched the
+/// longest ago is dropped
+#[derive(Debug)]
+pub(crate) struct TokenMetadataCache {
+    tokens: RwLock<HashMap<(u64, Address), CachedToken>>,
+    supply_ttl: Duration,
+    capacity: usize,
+}
+
+impl TokenMetadataCache {
+    pub(crate) fn new(supply_ttl: Duration, capacity: usize) -> Self {
+        Self {
+            tokens: RwLock::new(HashMap::new()),
+            supply_ttl,
+            capacity,
+        }
+    }
+
+    pub(crate) fn get(&self, chain_id: u64, address: Address) -> Option<CachedMetadata> {
+        let tokens = self.tokens.read();
+        let token = tokens.get(&(chain_id, address))?;
+        let metadata = token.metadata.clone();
+        if metadata.total_supply.is_some() && token.supply_at.elapsed() >= self.supply_ttl {
+            return Some(CachedMetadata::StaleSupply(metadata));
+        }
+        Some(CachedMetadata::Fresh(metadata))
+    }
+
+    pub(crate) fn insert(&self, metadata: TokenMetadata) {
+        let mut tokens = self.tokens.write();
+        let key = (metadata.chain_id, metadata.address);
+        if !tokens.contains_key(&key) && tokens.len() >= self.capacity {
+            let oldest = tokens
+                .iter()
+                .min_by_key(|(_, token)| token.supply_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                tokens.remove(&oldest);
+            }
+        }
+        tokens.insert(
+            key,
+            CachedToken {
+                metadata,
+                supply_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Replaces the cached total supply of the token with the one just queried
+    pub(crate) fn update_supply(&self, chain_id: u64, address: Address, supply: U256) {
+        if let Some(token) = self.tokens.write().get_mut(&(chain_id, address)) {
+            token.metadata.total_supply = Some(supply);
+            token.supply_at = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(byte: u8, total_supply: Option<U256>) -> TokenMetadata {
+        TokenMetadata {
+            chain_id: 1,
+            address: Address::repeat_byte(byte),
+            name: "Token".to_string(),
+            symbol: "TKN".to_string(),
+            decimals: 18,
+            total_supply,
+        }
+    }
+
+    #[test]
+    fn refreshes_only_the_supply_once_stale() {
+        let cache = TokenMetadataCache::new(Duration::ZERO, 10);
+        let metadata = token(1, Some(U256::from(100)));
+        cache.insert(metadata.clone());
+        assert_eq!(
+            cache.get(1, metadata.address),
+            Some(CachedMetadata::StaleSupply(metadata.clone()))
+        );
+
+        let cache = TokenMetadataCache::new(SUPPLY_TTL, 10);
+        cache.insert(metadata.clone());
+        cache.update_supply(1, metadata.address, U256::from(90));
+        assert_eq!(
+            cache.get(1, metadata.address),
+            Some(CachedMetadata::Fresh(token(1, Some(U256::from(90)))))
+        );
+    }
+
+    #[test]
+    fn never_expires_the_native_token() {
+        let cache = TokenMetadataCache::new(Duration::ZERO, 10);
+        let native = token(0xee, None);
+        cache.insert(native.clone());
+        assert_eq!(
+            cache.get(1, native.address),
+            Some(CachedMetadata::Fresh(native))
+        );
+    }
+
+    #[test]
+    fn drops_the_oldest_token_once_full() {
+        let cache = TokenMetadataCache::new(SUPPLY_TTL, 2);
+        for byte in 1..=3 {
+            cache.insert(token(byte, Some(U256::one())));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(cache.get(1, Address::repeat_byte(1)), None);
+        assert!(cache.get(1, Address::repeat_byte(2)).is_some());
+        assert!(cache.get(1, Address::repeat_byte(3)).is_some());
+
+        // replacing a cached token doesn't evict another one
+        cache.insert(token(3, Some(U256::from(2))));
+        assert!(cache.get(1, Address::repeat_byte(2)).is_some());
+    }
+}
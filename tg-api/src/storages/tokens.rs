@@ -0,0 +1,304 @@
+use crate::storages::sqlite::{blocking, SqliteDatabase};
+use crate::storages::TokenMetadata;
+use ethers::types::Address;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use teloxide::types::UserId;
+
+/// Token lists bundled with the bot, in the Uniswap token list format
+const BUNDLED_TOKEN_LISTS: [&str; 2] = [
+    include_str!("../../tokens/1.json"),
+    include_str!("../../tokens/137.json"),
+];
+
+/// Most tokens offered when a name or symbol matches several
+const MAX_TOKEN_MATCHES: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TokenEntry {
+    pub(crate) chain_id: u64,
+    pub(crate) address: Address,
+    pub(crate) symbol: String,
+    pub(crate) name: String,
+    pub(crate) decimals: u8,
+}
+
+impl From<&TokenMetadata> for TokenEntry {
+    fn from(metadata: &TokenMetadata) -> Self {
+        Self {
+            chain_id: metadata.chain_id,
+            address: metadata.address,
+            symbol: metadata.symbol.clone(),
+            name: metadata.name.clone(),
+            decimals: metadata.decimals,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenList {
+    tokens: Vec<TokenEntry>,
+}
+
+#[derive(Debug)]
+enum UserTokensBackend {
+    Memory(RwLock<HashMap<UserId, Vec<TokenEntry>>>),
+    Sqlite(Arc<SqliteDatabase>),
+}
+
+/// The bundled tokens of every chain, along with the tokens each user added by entering their
+/// address
+#[derive(Debug)]
+pub(crate) struct TokenRegistry {
+    bundled: Vec<TokenEntry>,
+    user_tokens: UserTokensBackend,
+}
+
+impl TokenRegistry {
+    fn bundled() -> Vec<TokenEntry> {
+        BUNDLED_TOKEN_LISTS
+            .iter()
+            .flat_map(|list| {
+                serde_json::from_str::<TokenList>(list)
+                    .expect("valid bundled token list")
+                    .tokens
+            })
+            .collect()
+    }
+
+    pub(crate) fn in_memory() -> Self {
+        Self {
+            bundled: Self::bundled(),
+            user_tokens: UserTokensBackend::Memory(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) fn sqlite(database: Arc<SqliteDatabase>) -> Self {
+        Self {
+            bundled: Self::bundled(),
+            user_tokens: UserTokensBackend::Sqlite(database),
+        }
+    }
+
+    async fn user_tokens(&self, user_id: UserId, chain_id: u64) -> anyhow::Result<Vec<TokenEntry>> {
+        match &self.user_tokens {
+            UserTokensBackend::Memory(tokens) => Ok(tokens
+                .read()
+                .get(&user_id)
+                .map(|tokens| {
+                    tokens
+                        .iter()
+                        .filter(|token| token.chain_id == chain_id)
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()),
+            UserTokensBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.load_user_tokens(user_id, chain_id)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Tokens the user can pick on the chain, the bundled ones first
+    async fn tokens(&self, user_id: UserId, chain_id: u64) -> anyhow::Result<Vec<TokenEntry>> {
+        let mut tokens: Vec<TokenEntry> = self
+            .bundled
+            .iter()
+            .filter(|token| token.chain_id == chain_id)
+            .cloned()
+            .collect();
+        for token in self.user_tokens(user_id, chain_id).await? {
+            if !tokens.iter().any(|known| known.address == token.address) {
+                tokens.push(token);
+            }
+        }
+        Ok(tokens)
+    }
+
+    pub(crate) async fn get(
+        &self,
+        user_id: UserId,
+        chain_id: u64,
+        address: Address,
+    ) -> anyhow::Result<Option<TokenEntry>> {
+        Ok(self
+            .tokens(user_id, chain_id)
+            .await?
+            .into_iter()
+            .find(|token| token.address == address))
+    }
+
+    /// Adds a token for the user, tokens already known are left as they are
+    pub(crate) async fn add(&self, user_id: UserId, token: TokenEntry) -> anyhow::Result<()> {
+        if self
+            .get(user_id, token.chain_id, token.address)
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+        match &self.user_tokens {
+            UserTokensBackend::Memory(tokens) => {
+                tokens.write().entry(user_id).or_default().push(token);
+                Ok(())
+            }
+            UserTokensBackend::Sqlite(database) => {
+                blocking(database, move |database| {
+                    database.save_user_token(user_id, &token)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Tokens whose symbol or name match the query, best matches first
+    pub(crate) async fn search(
+        &self,
+        user_id: UserId,
+        chain_id: u64,
+        query: &str,
+    ) -> anyhow::Result<Vec<TokenEntry>> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut matches: Vec<(u32, TokenEntry)> = self
+            .tokens(user_id, chain_id)
+            .await?
+            .into_iter()
+            .filter_map(|token| Some((match_score(&query, &token)?, token)))
+            .collect();
+        // sort_by is stable, the bundled tokens stay ahead of the user's on equal scores
+        matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        // exact matches shadow the looser ones
+        if matches
+            .first()
+            .is_some_and(|(score, _)| *score >= EXACT_NAME_SCORE)
+        {
+            matches.retain(|(score, _)| *score >= EXACT_NAME_SCORE);
+        }
+        Ok(matches
+            .into_iter()
+            .take(MAX_TOKEN_MATCHES)
+            .map(|(_, token)| token)
+            .collect())
+    }
+}
+
+const EXACT_SYMBOL_SCORE: u32 = 100;
+const EXACT_NAME_SCORE: u32 = 90;
+
+/// How well the lowercase query matches the token, None when it doesn't at all
+fn match_score(query: &str, token: &TokenEntry) -> Option<u32> {
+    let symbol = token.symbol.to_lowercase();
+    let name = token.name.to_lowercase();
+    let score = if symbol == query {
+        EXACT_SYMBOL_SCORE
+    } else if name == query {
+        EXACT_NAME_SCORE
+    } else if symbol.starts_with(query) {
+        70
+    } else if name.starts_with(query) {
+        60
+    } else if symbol.contains(query) {
+        50
+    } else if name.contains(query) {
+        40
+    } else if query.chars().count() >= 3 && edit_distance(query, &symbol) == 1 {
+        // a typo in the symbol
+        30
+    } else {
+        return None;
+    };
+    Some(score)
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(symbol: &str, name: &str) -> TokenEntry {
+        TokenEntry {
+            chain_id: 1,
+            address: Address::zero(),
+            symbol: symbol.to_string(),
+            name: name.to_string(),
+            decimals: 18,
+        }
+    }
+
+    async fn search(query: &str) -> Vec<String> {
+        TokenRegistry::in_memory()
+            .search(UserId(1), 1, query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|token| token.symbol)
+            .collect()
+    }
+
+    #[test]
+    fn scores_exact_matches_first() {
+        let usdc = token("USDC", "USD Coin");
+        assert_eq!(match_score("usdc", &usdc), Some(EXACT_SYMBOL_SCORE));
+        assert_eq!(match_score("usd coin", &usdc), Some(EXACT_NAME_SCORE));
+        assert_eq!(match_score("us", &usdc), Some(70));
+        assert_eq!(match_score("usd c", &usdc), Some(60));
+        assert_eq!(match_score("sdc", &usdc), Some(50));
+        assert_eq!(match_score("coin", &usdc), Some(40));
+    }
+
+    #[test]
+    fn tolerates_a_single_typo_in_the_symbol() {
+        let pepe = token("PEPE", "Pepe");
+        assert_eq!(match_score("pepx", &pepe), Some(30));
+        assert_eq!(match_score("pxpx", &pepe), None);
+        // too short for a typo to mean anything
+        assert_eq!(match_score("ox", &token("OP", "Optimism")), None);
+    }
+
+    #[test]
+    fn measures_the_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("weth", "weth"), 0);
+        assert_eq!(edit_distance("wteh", "weth"), 2);
+    }
+
+    #[tokio::test]
+    async fn searches_the_bundled_tokens_ignoring_case() {
+        // the exact match shadows the prefix matches of USDT and the others
+        assert_eq!(search(" UsDc ").await, vec!["USDC"]);
+        assert_eq!(search("Pepx").await, vec!["PEPE"]);
+        assert_eq!(search("US").await[..2], ["USDC", "USDT"]);
+    }
+
+    #[tokio::test]
+    async fn finds_nothing_for_unknown_or_empty_queries() {
+        assert!(search("zzzz").await.is_empty());
+        assert!(search("   ").await.is_empty());
+        assert_eq!(match_score("zzzz", &token("USDC", "USD Coin")), None);
+    }
+}
@@ -0,0 +1,423 @@
+use crate::config::WalletConfig;
+use ethers::core::rand::thread_rng;
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::Address;
+use ethers::utils::{hex, keccak256};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use teloxide::types::UserId;
+use tokio::sync::Mutex;
+
+/// Maximum number of wallets of a user, one per wallet button of the buy and sell menus
+pub(crate) const MAX_WALLETS: usize = 3;
+
+const INDEX_FILE: &str = "index.json";
+
+/// A wallet of a user, the key itself only lives encrypted in the keystore file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct WalletEntry {
+    pub(crate) name: String,
+    pub(crate) address: Address,
+    /// Name of the keystore file in the user's directory
+    pub(crate) keystore: String,
+}
+
+/// Content of the user's `index.json`, the order of `wallets` is the order of the wallet buttons
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct WalletIndex {
+    /// Zero based index of the wallet selected when a menu is opened
+    default: u8,
+    wallets: Vec<WalletEntry>,
+}
+
+/// Keeps the wallets of every user as ethers keystore JSON files under
+/// `<dir>/<user id>/`, encrypted with a passphrase derived from the master passphrase
+#[derive(Debug)]
+pub(crate) struct WalletStore {
+    dir: PathBuf,
+    master_passphrase: String,
+    /// Serializes the updates of the index files
+    lock: Mutex<()>,
+}
+
+impl WalletStore {
+    pub(crate) fn new(config: WalletConfig) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&config.keystore_dir)?;
+        Ok(Self {
+            dir: config.keystore_dir.into(),
+            master_passphrase: config.master_passphrase,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn user_dir(&self, user_id: UserId) -> PathBuf {
+        self.dir.join(user_id.to_string())
+    }
+
+    /// Every user's keystores are encrypted with a different passphrase, so a leaked
+    /// passphrase only exposes the wallets of a single user
+    fn passphrase(&self, user_id: UserId) -> String {
+        hex::encode(keccak256(format!("{}:{}", self.master_passphrase, user_id)))
+    }
+
+    async fn read_index(&self, user_id: UserId) -> anyhow::Result<WalletIndex> {
+        let path = self.user_dir(user_id).join(INDEX_FILE);
+        match tokio::fs::read(&path).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(WalletIndex::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes to a temporary file first so a crash never leaves a truncated index
+    async fn write_index(&self, user_id: UserId, index: &WalletIndex) -> anyhow::Result<()> {
+        let dir = self.user_dir(user_id);
+        tokio::fs::create_dir_all(&dir).await?;
+        let tmp = dir.join(format!("{}.tmp", INDEX_FILE));
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(index)?).await?;
+        tokio::fs::rename(&tmp, dir.join(INDEX_FILE)).await?;
+        Ok(())
+    }
+
+    pub(crate) async fn wallets(&self, user_id: UserId) -> anyhow::Result<Vec<WalletEntry>> {
+        Ok(self.read_index(user_id).await?.wallets)
+    }
+
+    /// The user's wallet of the address, None once it was deleted
+    pub(crate) async fn find(
+        &self,
+        user_id: UserId,
+        address: Address,
+    ) -> anyhow::Result<Option<WalletEntry>> {
+        Ok(self
+            .wallets(user_id)
+            .await?
+            .into_iter()
+            .find(|wallet| wallet.address == address))
+    }
+
+    /// Zero based index of the user's default wallet
+    pub(crate) async fn default_wallet(&self, user_id: UserId) -> anyhow::Result<u8> {
+        Ok(self.read_index(user_id).await?.default)
+    }
+
+    /// Generates wallets until the user has `count` of them, returns all the user's wallets
+    pub(crate) async fn ensure_wallets(
+        &self,
+        user_id: UserId,
+        count: usize,
+    ) -> anyhow::Result<Vec<WalletEntry>> {
+        let _lock = self.lock.lock().await;
+        let mut index = self.read_index(user_id).await?;
+        let count = count.min(MAX_WALLETS);
+        if index.wallets.len() >= count {
+            return Ok(index.wallets);
+        }
+
+        while index.wallets.len() < count {
+            let name = format!("Wallet {}", index.wallets.len() + 1);
+            let entry = self.generate_keystore(user_id, name).await?;
+            log::info!("Generated wallet {:#x} for user {}", entry.address, user_id);
+            index.wallets.push(entry);
+        }
+        self.write_index(user_id, &index).await?;
+        Ok(index.wallets)
+    }
+
+    async fn generate_keystore(
+        &self,
+        user_id: UserId,
+        name: String,
+    ) -> anyhow::Result<WalletEntry> {
+        let dir = self.user_dir(user_id);
+        tokio::fs::create_dir_all(&dir).await?;
+        let passphrase = self.passphrase(user_id);
+
+        // scrypt is deliberately slow, keep it off the async workers
+        let (wallet, keystore) = tokio::task::spawn_blocking(move || {
+            LocalWallet::new_keystore(&dir, &mut thread_rng(), passphrase, None)
+        })
+        .await??;
+
+        Ok(WalletEntry {
+            name,
+            address: wallet.address(),
+            keystore,
+        })
+    }
+
+    /// Runs `f` on the user's index under the lock and writes the index back
+    async fn update_index<T>(
+        &self,
+        user_id: UserId,
+        f: impl FnOnce(&mut WalletIndex) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let _lock = self.lock.lock().await;
+        let mut index = self.read_index(user_id).await?;
+        let result = f(&mut index)?;
+        self.write_index(user_id, &index).await?;
+        Ok(result)
+    }
+
+    pub(crate) async fn rename(
+        &self,
+        user_id: UserId,
+        address: Address,
+        name: String,
+    ) -> anyhow::Result<()> {
+        self.update_index(user_id, |index| {
+            let position = index.position(address)?;
+            index.wallets[position].name = name;
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn set_default(
+        &self,
+        user_id: UserId,
+        address: Address,
+    ) -> anyhow::Result<()> {
+        self.update_index(user_id, |index| {
+            index.default = index.position(address)? as u8;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Removes the wallet from the index and deletes its keystore file
+    pub(crate) async fn delete(
+        &self,
+        user_id: UserId,
+        address: Address,
+    ) -> anyhow::Result<WalletEntry> {
+        let entry = self
+            .update_index(user_id, |index| {
+                let position = index.position(address)?;
+                let entry = index.wallets.remove(position);
+                // the default follows its wallet, or falls back to the first one
+                index.default = match (index.default as usize).cmp(&position) {
+                    std::cmp::Ordering::Less => index.default,
+                    std::cmp::Ordering::Equal => 0,
+                    std::cmp::Ordering::Greater => index.default - 1,
+                };
+                Ok(entry)
+            })
+            .await?;
+        tokio::fs::remove_file(self.user_dir(user_id).join(&entry.keystore)).await?;
+        log::info!("Deleted wallet {:#x} of user {}", entry.address, user_id);
+        Ok(entry)
+    }
+
+    /// Encrypts the hex encoded private key into a new keystore
+    pub(crate) async fn import(
+        &self,
+        user_id: UserId,
+        private_key: &str,
+    ) -> anyhow::Result<WalletEntry> {
+        let key = hex::decode(private_key.trim().trim_start_matches("0x"))
+            .ok()
+            // from_bytes panics on keys of another length
+            .filter(|key| key.len() == 32)
+            .ok_or_else(|| anyhow::anyhow!("Invalid private key"))?;
+        let address = LocalWallet::from_bytes(&key)
+            .map_err(|_| anyhow::anyhow!("Invalid private key"))?
+            .address();
+
+        let _lock = self.lock.lock().await;
+        let mut index = self.read_index(user_id).await?;
+        if index.wallets.len() >= MAX_WALLETS {
+            return Err(anyhow::anyhow!(
+                "You already have {} wallets, delete one first",
+                MAX_WALLETS
+            ));
+        }
+        if index.position(address).is_ok() {
+            return Err(anyhow::anyhow!("Wallet {:#x} already imported", address));
+        }
+
+        let dir = self.user_dir(user_id);
+        tokio::fs::create_dir_all(&dir).await?;
+        let passphrase = self.passphrase(user_id);
+        let (_, keystore) = tokio::task::spawn_blocking(move || {
+            LocalWallet::encrypt_keystore(&dir, &mut thread_rng(), key, passphrase, None)
+        })
+        .await??;
+
+        let entry = WalletEntry {
+            name: format!("Wallet {}", index.wallets.len() + 1),
+            address,
+            keystore,
+        };
+        index.wallets.push(entry.clone());
+        self.write_index(user_id, &index).await?;
+        log::info!("Imported wallet {:#x} for user {}", address, user_id);
+        Ok(entry)
+    }
+
+    /// Decrypts the wallet and returns its hex encoded private key
+    pub(crate) async fn export(&self, user_id: UserId, address: Address) -> anyhow::Result<String> {
+        let wallet = self.signer(user_id, address).await?;
+        Ok(format!("0x{}", hex::encode(wallet.signer().to_bytes())))
+    }
+
+    /// Decrypts the wallet of the address to sign transactions
+    pub(crate) async fn signer(
+        &self,
+        user_id: UserId,
+        address: Address,
+    ) -> anyhow::Result<LocalWallet> {
+        let entry = self
+            .find(user_id, address)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Wallet {:#x} not found", address))?;
+        let path = self.user_dir(user_id).join(&entry.keystore);
+        let passphrase = self.passphrase(user_id);
+        let wallet = tokio::task::spawn_blocking(move || decrypt(&path, passphrase)).await??;
+        if wallet.address() != entry.address {
+            return Err(anyhow::anyhow!(
+                "Keystore {} doesn't match the address {:#x}",
+                entry.keystore,
+                entry.address
+            ));
+        }
+        Ok(wallet)
+    }
+}
+
+impl WalletIndex {
+    fn position(&self, address: Address) -> anyhow::Result<usize> {
+        self.wallets
+            .iter()
+            .position(|wallet| wallet.address == address)
+            .ok_or_else(|| anyhow::anyhow!("Wallet {:#x} not found", address))
+    }
+}
+
+fn decrypt(path: &Path, passphrase: String) -> anyhow::Result<LocalWallet> {
+    Ok(LocalWallet::decrypt_keystore(path, passphrase)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS: [&str; 3] = [
+        "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        "0x1111111111111111111111111111111111111111111111111111111111111111",
+        "0x2222222222222222222222222222222222222222222222222222222222222222",
+    ];
+    const USER: UserId = UserId(7);
+
+    /// Store in a directory of its own under the temp dir, removed once dropped
+    struct TestStore(WalletStore);
+
+    impl TestStore {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("koi-wallets-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            let config = WalletConfig {
+                keystore_dir: dir.to_string_lossy().into_owned(),
+                master_passphrase: "master".to_string(),
+            };
+            Self(WalletStore::new(config).unwrap())
+        }
+    }
+
+    impl Drop for TestStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0.dir);
+        }
+    }
+
+    fn address(key: &str) -> Address {
+        key.parse::<LocalWallet>().unwrap().address()
+    }
+
+    #[tokio::test]
+    async fn generates_at_most_the_wallet_limit() {
+        let store = TestStore::new("generate");
+        let wallets = store.0.ensure_wallets(USER, MAX_WALLETS + 2).await.unwrap();
+        assert_eq!(wallets.len(), MAX_WALLETS);
+        assert_eq!(wallets[2].name, "Wallet 3");
+
+        // the user has enough wallets already, none is generated
+        let again = store.0.ensure_wallets(USER, 1).await.unwrap();
+        let addresses = |wallets: &[WalletEntry]| -> Vec<Address> {
+            wallets.iter().map(|wallet| wallet.address).collect()
+        };
+        assert_eq!(addresses(&again), addresses(&wallets));
+        assert_eq!(store.0.default_wallet(USER).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn encrypts_the_keys_with_a_passphrase_per_user() {
+        let store = TestStore::new("passphrase");
+        let entry = store.0.import(USER, KEYS[0]).await.unwrap();
+        assert_eq!(entry.address, address(KEYS[0]));
+        assert_eq!(store.0.export(USER, entry.address).await.unwrap(), KEYS[0]);
+
+        assert_ne!(store.0.passphrase(USER), store.0.passphrase(UserId(8)));
+        let path = store.0.user_dir(USER).join(&entry.keystore);
+        assert!(decrypt(&path, store.0.passphrase(UserId(8))).is_err());
+        // another user can't use the wallet
+        assert!(store.0.export(UserId(8), entry.address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_and_duplicate_keys() {
+        let store = TestStore::new("import");
+        for key in ["0xzz", "0x1234", &format!("0x{}", "0".repeat(64))] {
+            let err = store.0.import(USER, key).await.unwrap_err();
+            assert_eq!(err.to_string(), "Invalid private key");
+        }
+
+        store.0.import(USER, KEYS[0]).await.unwrap();
+        // the 0x prefix and the surrounding spaces don't make it another key
+        let duplicate = format!(" {} ", KEYS[0].trim_start_matches("0x"));
+        let err = store.0.import(USER, &duplicate).await.unwrap_err();
+        assert!(err.to_string().ends_with("already imported"));
+        assert_eq!(store.0.wallets(USER).await.unwrap().len(), 1);
+
+        store.0.import(USER, KEYS[1]).await.unwrap();
+        store.0.import(USER, KEYS[2]).await.unwrap();
+        let err = store
+            .0
+            .import(
+                USER,
+                "0x3333333333333333333333333333333333333333333333333333333333333333",
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("You already have 3 wallets"));
+    }
+
+    #[tokio::test]
+    async fn moves_the_default_along_the_deleted_wallets() {
+        let store = TestStore::new("delete");
+        for key in KEYS {
+            store.0.import(USER, key).await.unwrap();
+        }
+        store.0.set_default(USER, address(KEYS[2])).await.unwrap();
+        assert_eq!(store.0.default_wallet(USER).await.unwrap(), 2);
+
+        // the default follows its wallet
+        let deleted = store.0.delete(USER, address(KEYS[0])).await.unwrap();
+        assert!(!store.0.user_dir(USER).join(&deleted.keystore).exists());
+        assert_eq!(store.0.default_wallet(USER).await.unwrap(), 1);
+
+        // deleting the default falls back to the first wallet
+        store.0.delete(USER, address(KEYS[2])).await.unwrap();
+        assert_eq!(store.0.default_wallet(USER).await.unwrap(), 0);
+        let wallets = store.0.wallets(USER).await.unwrap();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets[0].address, address(KEYS[1]));
+        assert!(store
+            .0
+            .find(USER, address(KEYS[2]))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}